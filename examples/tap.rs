@@ -0,0 +1,62 @@
+//! Prints what every session, or the ones named, sends over a crab_trap control socket
+//!
+//! cargo run --example tap -- /path/to/control.sock [session ...]
+
+use std::env;
+use std::process::exit;
+
+use crab_trap::socket::control::{render_message, ControlMessage, ControlRequest};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+
+async fn send(write: &mut tokio::net::unix::OwnedWriteHalf, request: &ControlRequest) {
+    let mut line = serde_json::to_string(request).unwrap();
+    line += "\n";
+    write.write_all(line.as_bytes()).await.unwrap();
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = env::args().skip(1);
+    let path = match args.next() {
+        Some(val) => val,
+        None => {
+            eprintln!("Usage: tap <control socket> [session ...]");
+            exit(1);
+        }
+    };
+    let sessions: Vec<String> = args.collect();
+    let stream = match UnixStream::connect(&path).await {
+        Ok(val) => val,
+        Err(err) => {
+            eprintln!("Couldn't connect to {path}: {err}");
+            exit(1);
+        }
+    };
+    let (read, mut write) = stream.into_split();
+    match sessions.is_empty() {
+        true => send(&mut write, &ControlRequest::List).await,
+        false => {
+            for session in sessions {
+                send(&mut write, &ControlRequest::Subscribe { session }).await;
+            }
+        }
+    }
+    let mut lines = BufReader::new(read).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let message: ControlMessage = match serde_json::from_str(&line) {
+            Ok(val) => val,
+            Err(_) => continue,
+        };
+        // with no sessions named, take every one there is
+        if let ControlMessage::Sessions { names } = &message {
+            for session in names {
+                let session = session.clone();
+                send(&mut write, &ControlRequest::Subscribe { session }).await;
+            }
+        }
+        for shown in render_message(&message) {
+            println!("{shown}");
+        }
+    }
+}
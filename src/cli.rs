@@ -0,0 +1,163 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{CommandFactory, Parser, Subcommand, ValueHint};
+use clap_complete::{generate, Shell};
+use crab_trap::socket::pipe::parse_pipe_arg;
+
+#[derive(Parser, Debug)]
+#[command(name = "crab_trap", about = "A lightweight reverse shell manager")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    /// Restore a reconnecting host's closed shell as soon as it connects
+    #[arg(long, global = true)]
+    pub auto_restore: bool,
+
+    /// How many closed shells to keep
+    #[arg(long, value_name = "COUNT", global = true)]
+    pub keep_closed: Option<usize>,
+
+    /// How long to keep closed shells for
+    #[arg(long, value_name = "MINUTES", global = true)]
+    pub keep_closed_mins: Option<u64>,
+
+    /// Config file to use instead of ~/.config/crab_trap/config.toml
+    #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Keep config, logs, loot and state apart in a named workspace
+    #[arg(long, value_name = "NAME", global = true, conflicts_with = "config")]
+    pub workspace: Option<String>,
+
+    /// Drive a shell another tool relays through a pair of named pipes
+    #[arg(long, value_name = "IN:OUT", value_parser = parse_pipe_arg, global = true)]
+    pub pipe: Option<(PathBuf, PathBuf)>,
+
+    /// Greet every shell with this device profile instead of matching its banner
+    #[arg(long, value_name = "NAME", global = true)]
+    pub profile: Option<String>,
+
+    /// Serve a read-only tap of every session's output and events on this unix socket
+    #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath, global = true)]
+    pub control: Option<PathBuf>,
+
+    /// Keep nothing on disk: no transcripts, loot, captures, state or config changes
+    #[arg(long, conflicts_with = "workspace", global = true)]
+    pub ephemeral: bool,
+
+    /// Address to listen for shells on
+    #[arg(requires = "port", value_hint = ValueHint::Hostname)]
+    pub address: Option<String>,
+
+    /// Port to listen for shells on
+    pub port: Option<u16>,
+
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Create a config file
+    Init {
+        /// Write the defaults without asking
+        #[arg(long)]
+        defaults: bool,
+    },
+    /// Check the terminal, config, directories and listener for problems
+    Doctor,
+    /// Print a shell completion script
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Listen for shells and open the dashboard instead of the prompt
+    Ui {
+        /// Address to listen for shells on
+        #[arg(requires = "port", value_hint = ValueHint::Hostname)]
+        address: Option<String>,
+        /// Port to listen for shells on
+        port: Option<u16>,
+    },
+    /// Replay a `capture burst` bundle and print what the terminal was sent
+    RenderDebug {
+        #[arg(value_hint = ValueHint::FilePath)]
+        bundle: PathBuf,
+        /// Write the rendered bytes as they are instead of escaped, one frame a line
+        #[arg(long)]
+        raw: bool,
+    },
+}
+
+/// Writes the completion script for `shell`, this never needs a config
+pub fn write_completions<W: Write>(shell: Shell, out: &mut W) {
+    let mut cmd = Cli::command();
+    // clap_complete panics on write errors, buffer it so `| head` doesn't blow up
+    let mut script = Vec::new();
+    generate(shell, &mut cmd, "crab_trap", &mut script);
+    out.write_all(&script).unwrap_or_default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_parse_args() {
+        let cli = Cli::parse_from(["crab_trap", "--auto-restore", "10.0.0.1", "9001"]);
+        assert!(cli.auto_restore);
+        assert_eq!(cli.address, Some(String::from("10.0.0.1")));
+        assert_eq!(cli.port, Some(9001));
+        assert!(Cli::try_parse_from(["crab_trap", "10.0.0.1"]).is_err());
+        let cli = Cli::parse_from(["crab_trap", "init", "--defaults"]);
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Init { defaults: true })
+        ));
+        let cli = Cli::parse_from(["crab_trap", "doctor", "--config", "/tmp/config.toml"]);
+        assert!(matches!(cli.command, Some(Commands::Doctor)));
+        assert_eq!(cli.config, Some(PathBuf::from("/tmp/config.toml")));
+        let cli = Cli::parse_from(["crab_trap", "ui", "10.0.0.1", "9001"]);
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Ui {
+                port: Some(9001),
+                ..
+            })
+        ));
+        assert!(Cli::try_parse_from(["crab_trap", "ui", "10.0.0.1"]).is_err());
+        let cli = Cli::parse_from(["crab_trap", "ui", "--ephemeral", "--auto-restore"]);
+        assert!(cli.ephemeral && cli.auto_restore);
+        let cli = Cli::parse_from(["crab_trap", "--workspace", "acme", "10.0.0.1", "9001"]);
+        assert_eq!(cli.workspace.as_deref(), Some("acme"));
+        assert!(Cli::try_parse_from(["crab_trap", "--workspace", "a", "--config", "b"]).is_err());
+        let cli = Cli::parse_from(["crab_trap", "--pipe", "/tmp/in:/tmp/out"]);
+        assert_eq!(
+            cli.pipe,
+            Some((PathBuf::from("/tmp/in"), PathBuf::from("/tmp/out")))
+        );
+        assert!(Cli::try_parse_from(["crab_trap", "--pipe", "/tmp/in"]).is_err());
+        let cli = Cli::parse_from(["crab_trap", "--profile", "cisco", "0.0.0.0", "23"]);
+        assert_eq!(cli.profile.as_deref(), Some("cisco"));
+        let cli = Cli::parse_from(["crab_trap", "--control", "/tmp/ct.sock"]);
+        assert_eq!(cli.control, Some(PathBuf::from("/tmp/ct.sock")));
+        let cli = Cli::parse_from(["crab_trap", "render-debug", "burst.json"]);
+        assert!(matches!(
+            cli.command,
+            Some(Commands::RenderDebug { ref bundle, raw: false }) if bundle == &PathBuf::from("burst.json")
+        ));
+    }
+
+    #[test]
+    fn test_bash_completions_parse() {
+        let mut script = Vec::new();
+        write_completions(Shell::Bash, &mut script);
+        let path = std::env::temp_dir().join("crab_trap_test_completions.bash");
+        std::fs::write(&path, &script).unwrap();
+        let status = Command::new("bash").arg("-n").arg(&path).status().unwrap();
+        std::fs::remove_file(&path).unwrap_or_default();
+        assert!(status.success());
+        assert!(String::from_utf8_lossy(&script).contains("--keep-closed-mins"));
+    }
+}
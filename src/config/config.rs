@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::settings;
+use crate::error::error::CrabTrapError;
+use crate::input::chord::DEFAULT_CHORD_PREFIX;
+
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// under log_dir, where sessions spill output that doesn't fit in memory
+pub const SPILL_DIR: &str = "spill";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    Default,
+    Light,
+    /// no colours at all
+    Plain,
+}
+
+impl Theme {
+    pub fn parse(name: &str) -> Option<Theme> {
+        return match name.trim().to_lowercase().as_str() {
+            "default" => Some(Theme::Default),
+            "light" => Some(Theme::Light),
+            "plain" => Some(Theme::Plain),
+            _ => None,
+        };
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Theme::Default => "default",
+            Theme::Light => "light",
+            Theme::Plain => "plain",
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen_address: String,
+    pub listen_port: u16,
+    pub log_dir: PathBuf,
+    /// where `loot add` keeps artifacts and their index
+    pub loot_dir: PathBuf,
+    /// key that starts a raw mode chord like `ctrl-b d` back to the menu, written like `ctrl-]`
+    pub escape_key: String,
+    pub theme: Theme,
+    pub transcripts: bool,
+    /// any other global settings saved with `set --save`
+    pub settings: BTreeMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Config {
+            listen_address: String::from("0.0.0.0"),
+            listen_port: 4545,
+            log_dir: data_dir().join("logs"),
+            loot_dir: data_dir().join("loot"),
+            escape_key: String::from(DEFAULT_CHORD_PREFIX),
+            theme: Theme::Default,
+            transcripts: false,
+            settings: BTreeMap::new(),
+        };
+    }
+}
+
+/// Where crab_trap keeps its config, `~/.config/crab_trap` on linux
+pub fn config_dir() -> PathBuf {
+    return dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("crab_trap");
+}
+
+/// Where crab_trap keeps logs, `~/.local/share/crab_trap` on linux
+pub fn data_dir() -> PathBuf {
+    return dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("crab_trap");
+}
+
+pub fn config_path() -> PathBuf {
+    return config_dir().join(CONFIG_FILE);
+}
+
+/// Checks an escape key is a single ctrl combination like `ctrl-]`
+pub fn valid_escape_key(key: &str) -> bool {
+    return match key.strip_prefix("ctrl-") {
+        Some(rest) => rest.chars().count() == 1 && rest.is_ascii(),
+        None => false,
+    };
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), CrabTrapError> {
+        if self.listen_address.parse::<IpAddr>().is_err() {
+            return Err(CrabTrapError::InvalidConfig {
+                reason: format!("{} is not an ip address", self.listen_address),
+            });
+        }
+        if self.listen_port == 0 {
+            return Err(CrabTrapError::InvalidConfig {
+                reason: String::from("listen_port can't be 0"),
+            });
+        }
+        if self.log_dir.as_os_str().is_empty() {
+            return Err(CrabTrapError::InvalidConfig {
+                reason: String::from("log_dir can't be empty"),
+            });
+        }
+        if self.loot_dir.as_os_str().is_empty() {
+            return Err(CrabTrapError::InvalidConfig {
+                reason: String::from("loot_dir can't be empty"),
+            });
+        }
+        if !valid_escape_key(&self.escape_key) {
+            return Err(CrabTrapError::InvalidConfig {
+                reason: format!("{} is not a key like ctrl-]", self.escape_key),
+            });
+        }
+        for (key, value) in &self.settings {
+            settings::lookup(key)?.normalize(value)?;
+        }
+        return Ok(());
+    }
+
+    /// Parses and validates the contents of a config file
+    pub fn parse(content: &str) -> Result<Config, CrabTrapError> {
+        let config: Config =
+            toml::from_str(content).map_err(|err| CrabTrapError::InvalidConfig {
+                reason: err.message().to_string(),
+            })?;
+        config.validate()?;
+        return Ok(config);
+    }
+
+    /// Loads the config at `path`, None if there isn't one yet
+    pub fn load(path: &Path) -> Result<Option<Config>, CrabTrapError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path).map_err(|err| CrabTrapError::InvalidConfig {
+            reason: format!("can't read {}: {err}", path.display()),
+        })?;
+        return Config::parse(&content).map(Some);
+    }
+
+    /// Renders the config as toml with a comment explaining each setting
+    pub fn to_commented_toml(&self) -> String {
+        let quote = |val: &str| toml::Value::String(String::from(val)).to_string();
+        let mut saved_settings = String::new();
+        for (key, value) in &self.settings {
+            saved_settings += &format!("{key} = {}\n", quote(value));
+        }
+        return format!(
+            "# crab_trap config, regenerate it with `crab_trap init`\n\
+             \n\
+             # address and port to listen for shells on, the command line overrides these\n\
+             listen_address = {address}\n\
+             listen_port = {port}\n\
+             \n\
+             # directory session logs are written to\n\
+             log_dir = {log_dir}\n\
+             \n\
+             # directory loot is stored in\n\
+             loot_dir = {loot_dir}\n\
+             \n\
+             # key that starts a raw mode chord, then d returns from a shell to the menu\n\
+             escape_key = {escape_key}\n\
+             \n\
+             # colour theme: default, light or plain\n\
+             theme = {theme}\n\
+             \n\
+             # record a transcript of every session in log_dir\n\
+             transcripts = {transcripts}\n\
+             \n\
+             # other settings, see `show` in the menu for the full list\n\
+             [settings]\n\
+             {saved_settings}",
+            address = quote(&self.listen_address),
+            port = self.listen_port,
+            log_dir = quote(&self.log_dir.to_string_lossy()),
+            loot_dir = quote(&self.loot_dir.to_string_lossy()),
+            escape_key = quote(&self.escape_key),
+            theme = quote(self.theme.name()),
+            transcripts = self.transcripts,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_round_trip() {
+        let config = Config {
+            listen_address: String::from("127.0.0.1"),
+            listen_port: 9001,
+            log_dir: PathBuf::from("/tmp/crab \"logs\""),
+            loot_dir: PathBuf::from("/tmp/crab loot"),
+            escape_key: String::from("ctrl-a"),
+            theme: Theme::Plain,
+            transcripts: true,
+            settings: BTreeMap::from([(String::from("keep_closed"), String::from("5"))]),
+        };
+        assert_eq!(Config::parse(&config.to_commented_toml()), Ok(config));
+        assert_eq!(
+            Config::parse(&Config::default().to_commented_toml()),
+            Ok(Config::default())
+        );
+        // missing keys fall back to the defaults
+        assert_eq!(Config::parse("listen_port = 4545"), Ok(Config::default()));
+        assert!(Config::parse("listen_address = \"nowhere\"").is_err());
+        assert!(Config::parse("escape_key = \"q\"").is_err());
+        assert!(Config::parse("colour = \"red\"").is_err());
+        assert!(Config::parse("[settings]\nkeep_closd = \"5\"").is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use std::fs;
+use std::io::{stdin, stdout};
+use std::net::TcpListener;
+use std::path::Path;
+
+use termion::raw::IntoRawMode;
+
+use crate::config::config::Config;
+use crate::config::state::state_path;
+use crate::socket::history::now_secs;
+
+/// 2024-01-01, a clock earlier than this is wrong and so is every timestamp
+const EARLIEST_SANE_CLOCK: u64 = 1_704_067_200;
+
+/// shells that can't cd or redirect, they break local commands
+const RESTRICTED_SHELLS: [&str; 4] = ["rbash", "rksh", "rzsh", "rsh"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            CheckStatus::Pass => "pass",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "fail",
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// what to do about it, for anything that didn't pass
+    pub hint: Option<&'static str>,
+}
+
+fn pass(name: &'static str, detail: String) -> CheckResult {
+    return CheckResult {
+        name,
+        status: CheckStatus::Pass,
+        detail,
+        hint: None,
+    };
+}
+
+fn problem(
+    name: &'static str,
+    status: CheckStatus,
+    detail: String,
+    hint: &'static str,
+) -> CheckResult {
+    return CheckResult {
+        name,
+        status,
+        detail,
+        hint: Some(hint),
+    };
+}
+
+/// stdin and stdout are a terminal that can move the cursor
+pub fn check_terminal() -> CheckResult {
+    if !termion::is_tty(&stdin()) || !termion::is_tty(&stdout()) {
+        return problem(
+            "terminal",
+            CheckStatus::Fail,
+            String::from("stdin or stdout isn't a terminal"),
+            "run crab_trap directly in a terminal, not through a pipe",
+        );
+    }
+    return match std::env::var("TERM") {
+        Ok(term) if !term.is_empty() && term != "dumb" => pass("terminal", format!("TERM={term}")),
+        _ => problem(
+            "terminal",
+            CheckStatus::Warn,
+            String::from("TERM is unset or dumb"),
+            "export TERM=xterm-256color",
+        ),
+    };
+}
+
+/// The terminal can go into raw mode and back, which raw mode shells need
+pub fn check_raw_mode() -> CheckResult {
+    if !termion::is_tty(&stdout()) {
+        return problem(
+            "raw mode",
+            CheckStatus::Warn,
+            String::from("skipped, stdout isn't a terminal"),
+            "raw mode shells need a terminal",
+        );
+    }
+    return match stdout().into_raw_mode() {
+        Ok(raw) => match raw.suspend_raw_mode() {
+            Ok(_) => pass("raw mode", String::from("entered and left raw mode")),
+            Err(err) => problem(
+                "raw mode",
+                CheckStatus::Fail,
+                format!("couldn't leave raw mode: {err}"),
+                "run `stty sane` to fix the terminal",
+            ),
+        },
+        Err(err) => problem(
+            "raw mode",
+            CheckStatus::Fail,
+            format!("couldn't enter raw mode: {err}"),
+            "use line mode shells or another terminal",
+        ),
+    };
+}
+
+/// The login shell isn't a restricted one, `!` commands run through it
+pub fn check_shell() -> CheckResult {
+    let shell = std::env::var("SHELL").unwrap_or_default();
+    let name = shell.rsplit('/').next().unwrap_or_default();
+    if RESTRICTED_SHELLS.contains(&name) {
+        return problem(
+            "shell",
+            CheckStatus::Warn,
+            format!("{shell} is a restricted shell"),
+            "local commands may not be able to cd or redirect",
+        );
+    }
+    return pass("shell", format!("SHELL={shell}"));
+}
+
+/// The config parses, a missing one means the defaults
+pub fn check_config(path: &Path) -> (CheckResult, Option<Config>) {
+    return match Config::load(path) {
+        Ok(Some(config)) => (
+            pass("config", format!("{} is valid", path.display())),
+            Some(config),
+        ),
+        Ok(None) => (
+            problem(
+                "config",
+                CheckStatus::Warn,
+                format!("{} doesn't exist, using the defaults", path.display()),
+                "run `crab_trap init` to write one",
+            ),
+            Some(Config::default()),
+        ),
+        Err(err) => (
+            problem(
+                "config",
+                CheckStatus::Fail,
+                format!("{err} in {}", path.display()),
+                "fix the file or move it aside and run `crab_trap init`",
+            ),
+            None,
+        ),
+    };
+}
+
+/// `dir` exists or can be made, and crab_trap can write in it
+pub fn check_dir(name: &'static str, dir: &Path) -> CheckResult {
+    let probe = dir.join(format!(".crab_trap_doctor_{}", std::process::id()));
+    let result = fs::create_dir_all(dir).and_then(|_| fs::write(&probe, b""));
+    fs::remove_file(&probe).unwrap_or_default();
+    return match result {
+        Ok(_) => pass(name, format!("{} is writable", dir.display())),
+        Err(err) => problem(
+            name,
+            CheckStatus::Fail,
+            format!("can't write to {}: {err}", dir.display()),
+            "fix the directory's permissions or point the config somewhere else",
+        ),
+    };
+}
+
+/// Nothing else is listening where shells are meant to connect
+pub fn check_port(address: &str, port: u16) -> CheckResult {
+    return match TcpListener::bind((address, port)) {
+        Ok(_) => pass("listener", format!("{address}:{port} is free")),
+        Err(err) => problem(
+            "listener",
+            CheckStatus::Fail,
+            format!("can't listen on {address}:{port}: {err}"),
+            "another crab_trap may already be running, or pick another port",
+        ),
+    };
+}
+
+/// The clock is set, timestamps in history, notes and transcripts come from it
+pub fn check_clock() -> CheckResult {
+    let now = now_secs();
+    if now < EARLIEST_SANE_CLOCK {
+        return problem(
+            "clock",
+            CheckStatus::Warn,
+            format!("the clock says {now} seconds since the epoch"),
+            "set the system time, timestamps will be wrong",
+        );
+    }
+    return pass("clock", String::from("the system time looks right"));
+}
+
+/// Every check in order, the ones that need a config are skipped without one
+pub fn run_checks(config_path: &Path) -> Vec<CheckResult> {
+    let mut results = vec![check_terminal(), check_raw_mode(), check_shell()];
+    let (config_result, config) = check_config(config_path);
+    results.push(config_result);
+    let config_dir = config_path.parent().unwrap_or(Path::new("."));
+    results.push(check_dir("config dir", config_dir));
+    if let Some(config) = config {
+        results.push(check_dir("log dir", &config.log_dir));
+        results.push(check_dir("loot dir", &config.loot_dir));
+        results.push(check_port(&config.listen_address, config.listen_port));
+    }
+    let state = state_path(config_path);
+    if state.exists() && fs::read_to_string(&state).is_err() {
+        results.push(problem(
+            "state",
+            CheckStatus::Warn,
+            format!("can't read {}", state.display()),
+            "lost sessions from the last run won't be listed",
+        ));
+    }
+    results.push(check_clock());
+    return results;
+}
+
+/// One result as `[pass] name: detail`, with the hint under it
+pub fn render_check(result: &CheckResult) -> String {
+    let mut line = format!(
+        "[{}] {}: {}\n",
+        result.status.name(),
+        result.name,
+        result.detail
+    );
+    if let Some(hint) = result.hint {
+        line += &format!("       {hint}\n");
+    }
+    return line;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_dir() {
+        let dir = std::env::temp_dir().join("crab_trap_test_doctor");
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        assert_eq!(
+            check_dir("log dir", &dir.join("logs")).status,
+            CheckStatus::Pass
+        );
+        assert_eq!(fs::read_dir(dir.join("logs")).unwrap().count(), 0);
+        // a file is in the way
+        fs::write(dir.join("file"), b"").unwrap();
+        let result = check_dir("log dir", &dir.join("file").join("logs"));
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert!(result.hint.is_some());
+        fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+
+    #[test]
+    fn test_check_config() {
+        let dir = std::env::temp_dir().join("crab_trap_test_doctor_config");
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        let (result, config) = check_config(&path);
+        assert_eq!(result.status, CheckStatus::Warn);
+        assert!(config.is_some());
+        fs::write(&path, "listen_port = \"nope\"\n").unwrap();
+        let (result, config) = check_config(&path);
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert!(config.is_none());
+        fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+
+    #[test]
+    fn test_check_port() {
+        assert_eq!(check_port("127.0.0.1", 32458).status, CheckStatus::Pass);
+        let _listener = TcpListener::bind("127.0.0.1:32458").unwrap();
+        assert_eq!(check_port("127.0.0.1", 32458).status, CheckStatus::Fail);
+    }
+
+    #[test]
+    fn test_render_check() {
+        assert_eq!(check_clock().status, CheckStatus::Pass);
+        let result = problem(
+            "terminal",
+            CheckStatus::Warn,
+            String::from("TERM is unset or dumb"),
+            "export TERM=xterm-256color",
+        );
+        assert_eq!(
+            render_check(&result),
+            "[warn] terminal: TERM is unset or dumb\n       export TERM=xterm-256color\n"
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// set by `--ephemeral`, nothing about the engagement is kept on disk
+static EPHEMERAL: AtomicBool = AtomicBool::new(false);
+
+/// files that couldn't be avoided, shredded on the way out
+static SCRUB_LIST: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+#[cfg(test)]
+thread_local! {
+    // tests run side by side in one process, each turns it on for its own thread
+    static TEST_EPHEMERAL: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+pub const EPHEMERAL_WARNING: &str = "[!] ephemeral mode: transcripts, loot, captures, marks, the session state and config changes aren't written, anything not copied off the screen is gone when crab_trap exits";
+
+pub fn enable() {
+    EPHEMERAL.store(true, Ordering::SeqCst);
+}
+
+pub fn is_ephemeral() -> bool {
+    #[cfg(test)]
+    if TEST_EPHEMERAL.with(|on| on.get()) {
+        return true;
+    }
+    return EPHEMERAL.load(Ordering::SeqCst);
+}
+
+/// Why `what` wasn't written, for the paths that report errors as text
+pub fn refusal(what: &str) -> String {
+    return format!("{what} isn't written in ephemeral mode");
+}
+
+/// Fails when nothing should touch the disk
+pub fn check_write(what: &str) -> io::Result<()> {
+    return match is_ephemeral() {
+        true => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            refusal(what),
+        )),
+        false => Ok(()),
+    };
+}
+
+/// Remembers a file that has to exist for a while, like the block `note edit` opens
+pub fn track(path: &Path) {
+    if let Ok(mut list) = SCRUB_LIST.lock() {
+        list.push(PathBuf::from(path));
+    }
+}
+
+pub fn untrack(path: &Path) {
+    if let Ok(mut list) = SCRUB_LIST.lock() {
+        list.retain(|tracked| tracked != path);
+    }
+}
+
+/// Overwrites a file with zeros before removing it. On journaling and copy on write
+/// filesystems the old blocks may survive, it still beats a plain unlink
+pub fn shred(path: &Path) -> io::Result<()> {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(val) => val,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    if meta.is_file() {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        let zeros = vec![0u8; 64 * 1024];
+        let mut left = meta.len();
+        while left > 0 {
+            let n = left.min(zeros.len() as u64) as usize;
+            file.write_all(&zeros[..n])?;
+            left -= n as u64;
+        }
+        file.sync_all()?;
+    }
+    return fs::remove_file(path);
+}
+
+/// Shreds every tracked file, run on exit, from the panic hook and on a signal
+pub fn scrub() {
+    let tracked: Vec<PathBuf> = match SCRUB_LIST.lock() {
+        Ok(mut list) => list.drain(..).collect(),
+        Err(_) => return,
+    };
+    for path in tracked {
+        if let Err(err) = shred(&path) {
+            eprintln!("[-] Couldn't shred {}: {err}", path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config::Config;
+    use crate::config::init::write_config;
+    use crate::config::loot::LootStore;
+    use crate::config::settings::Settings;
+    use crate::config::state::StateFile;
+    use crate::socket::connection::Handle;
+    use crate::socket::marks::{parse_save_args, save_marked};
+    use crate::socket::transcript::DEFAULT_FSYNC_INTERVAL;
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn snapshot(dir: &Path) -> Vec<(PathBuf, u64)> {
+        let mut found = Vec::new();
+        let mut dirs = vec![PathBuf::from(dir)];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir).unwrap().flatten() {
+                let path = entry.path();
+                let meta = entry.metadata().unwrap();
+                if meta.is_dir() {
+                    dirs.push(path.clone());
+                }
+                found.push((path, meta.len()));
+            }
+        }
+        found.sort();
+        return found;
+    }
+
+    #[tokio::test]
+    async fn test_ephemeral_writes_nothing() {
+        let dir = std::env::temp_dir().join("crab_trap_test_ephemeral");
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("state.json"), "{ not json").unwrap();
+        let before = snapshot(&dir);
+        TEST_EPHEMERAL.with(|on| on.set(true));
+
+        let listener = TcpListener::bind("127.0.0.1:32475").await.unwrap();
+        let client = tokio::spawn(async { TcpStream::connect("127.0.0.1:32475").await.unwrap() });
+        let (soc, _) = listener.accept().await.unwrap();
+        let _remote = client.await.unwrap();
+        let (read, write) = soc.into_split();
+        let mut handle = Handle::new_headless(read, write);
+
+        // the state file, where a corrupt one would be moved aside
+        let mut state = StateFile::load(&dir.join("state.json"));
+        state
+            .save(
+                &HashMap::new(),
+                &Settings::from_config(&Config::default()),
+                10,
+            )
+            .unwrap();
+        // `set --save`, `init` and new workspaces
+        let config = Config {
+            log_dir: dir.join("logs"),
+            ..Config::default()
+        };
+        assert!(write_config(&dir.join("config").join("config.toml"), &config).is_err());
+        // transcripts and spilled output
+        assert!(handle
+            .transcribe_to(dir.join("logs/web.jsonl"), "web", DEFAULT_FSYNC_INTERVAL)
+            .is_err());
+        handle.spill_to(dir.join("logs/spill/web.out"), 1024);
+        assert!(handle.spill.is_none());
+        // loot, captures and saved marks
+        let mut store = LootStore::open(&dir.join("loot")).unwrap();
+        assert!(store.add("shadow", "web", "cat", b"root:x").is_err());
+        assert!(handle
+            .start_capture(Duration::from_secs(5), dir.join("capture.json"))
+            .is_err());
+        let args = format!("web --since start {}", dir.join("out.txt").display());
+        let args = parse_save_args(&args).unwrap();
+        assert!(save_marked(&dir.join("logs/web.jsonl"), &[], &args).is_err());
+        // what couldn't be avoided is gone afterwards
+        let staged = dir.join("notes.txt");
+        fs::write(&staged, "a note").unwrap();
+        track(&staged);
+        scrub();
+
+        TEST_EPHEMERAL.with(|on| on.set(false));
+        assert_eq!(snapshot(&dir), before);
+        fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+}
@@ -0,0 +1,206 @@
+use std::fs::{self, DirBuilder};
+use std::io::{self, BufRead, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::config::config::{valid_escape_key, Config, Theme};
+use crate::config::ephemeral::check_write;
+
+/// Asks a question until `parse` accepts the answer, an empty answer keeps the default
+fn ask<R, W, T>(
+    input: &mut R,
+    output: &mut W,
+    question: &str,
+    default: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> io::Result<T>
+where
+    R: BufRead,
+    W: Write,
+{
+    loop {
+        write!(output, "{question} [{default}]: ")?;
+        output.flush()?;
+        let mut line = String::new();
+        let answer = match input.read_line(&mut line)? {
+            // out of input, take the default
+            0 => default,
+            _ if line.trim().is_empty() => default,
+            _ => line.trim(),
+        };
+        if let Some(val) = parse(answer) {
+            return Ok(val);
+        }
+        writeln!(output, "[-] Invalid answer {answer}")?;
+    }
+}
+
+/// Asks a yes or no question, anything but yes is a no
+pub fn confirm<R, W>(input: &mut R, output: &mut W, question: &str) -> io::Result<bool>
+where
+    R: BufRead,
+    W: Write,
+{
+    return ask(input, output, question, "y/N", |answer| {
+        Some(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+    });
+}
+
+/// Walks through each setting, offering the current value as the default
+pub fn run_wizard<R, W>(input: &mut R, output: &mut W, defaults: Config) -> io::Result<Config>
+where
+    R: BufRead,
+    W: Write,
+{
+    writeln!(
+        output,
+        "Setting up crab_trap, press enter to keep a default"
+    )?;
+    let listen_address = ask(
+        input,
+        output,
+        "Listen address",
+        &defaults.listen_address,
+        |answer| {
+            answer
+                .parse::<std::net::IpAddr>()
+                .ok()
+                .map(|_| String::from(answer))
+        },
+    )?;
+    let listen_port = ask(
+        input,
+        output,
+        "Listen port",
+        &defaults.listen_port.to_string(),
+        |answer| answer.parse::<u16>().ok().filter(|port| *port != 0),
+    )?;
+    let log_dir = ask(
+        input,
+        output,
+        "Log directory",
+        &defaults.log_dir.to_string_lossy(),
+        |answer| Some(PathBuf::from(answer)),
+    )?;
+    let escape_key = ask(
+        input,
+        output,
+        "Escape key",
+        &defaults.escape_key,
+        |answer| valid_escape_key(answer).then(|| String::from(answer)),
+    )?;
+    let theme = ask(
+        input,
+        output,
+        "Theme (default, light, plain)",
+        defaults.theme.name(),
+        Theme::parse,
+    )?;
+    let transcripts = ask(
+        input,
+        output,
+        "Record session transcripts (yes/no)",
+        if defaults.transcripts { "yes" } else { "no" },
+        |answer| match answer.to_lowercase().as_str() {
+            "y" | "yes" => Some(true),
+            "n" | "no" => Some(false),
+            _ => None,
+        },
+    )?;
+    return Ok(Config {
+        listen_address,
+        listen_port,
+        log_dir,
+        loot_dir: defaults.loot_dir,
+        escape_key,
+        theme,
+        transcripts,
+        settings: defaults.settings,
+    });
+}
+
+/// Creates a directory only the current user can get into
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    DirBuilder::new().recursive(true).mode(0o700).create(path)?;
+    return fs::set_permissions(path, fs::Permissions::from_mode(0o700));
+}
+
+/// Writes the config and creates the directories it points at
+pub fn write_config(path: &Path, config: &Config) -> io::Result<()> {
+    check_write("the config")?;
+    if let Some(parent) = path.parent() {
+        create_private_dir(parent)?;
+    }
+    create_private_dir(&config.log_dir)?;
+    fs::write(path, config.to_commented_toml())?;
+    return fs::set_permissions(path, fs::Permissions::from_mode(0o600));
+}
+
+/// Runs `crab_trap init`, returning the config that was written or None if the
+/// user kept their existing one
+pub fn init<R, W>(
+    input: &mut R,
+    output: &mut W,
+    path: &Path,
+    use_defaults: bool,
+) -> io::Result<Option<Config>>
+where
+    R: BufRead,
+    W: Write,
+{
+    // start from whatever is there so rerunning init only changes what you answer
+    let existing = Config::load(path).ok().flatten();
+    if path.exists() {
+        let question = format!("{} already exists, overwrite it?", path.display());
+        if !confirm(input, output, &question)? {
+            return Ok(None);
+        }
+    }
+    let defaults = existing.unwrap_or_default();
+    let config = match use_defaults {
+        true => defaults,
+        false => run_wizard(input, output, defaults)?,
+    };
+    write_config(path, &config)?;
+    writeln!(output, "[+] Wrote {}", path.display())?;
+    return Ok(Some(config));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_init() {
+        let dir = std::env::temp_dir().join("crab_trap_test_init");
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        let path = dir.join("config.toml");
+        let log_dir = dir.join("logs");
+
+        let answers = format!(
+            "10.0.0.1\nnot a port\n9001\n{}\n\nplain\nyes\n",
+            log_dir.display()
+        );
+        let mut output = Vec::new();
+        let written = init(&mut Cursor::new(answers), &mut output, &path, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(written.listen_address, "10.0.0.1");
+        assert_eq!(written.listen_port, 9001);
+        assert_eq!(written.escape_key, Config::default().escape_key);
+        assert_eq!(written.theme, Theme::Plain);
+        assert!(written.transcripts);
+        assert!(String::from_utf8_lossy(&output).contains("Invalid answer not a port"));
+        assert_eq!(Config::load(&path), Ok(Some(written.clone())));
+        let mode = fs::metadata(&log_dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // an existing config is only replaced after confirming
+        let kept = init(&mut Cursor::new("n\n"), &mut Vec::new(), &path, true).unwrap();
+        assert_eq!(kept, None);
+        let replaced = init(&mut Cursor::new("y\n"), &mut Vec::new(), &path, true).unwrap();
+        assert_eq!(replaced, Some(written));
+        fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+}
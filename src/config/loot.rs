@@ -0,0 +1,317 @@
+use std::fs::{self, DirBuilder};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha256::digest;
+
+use crate::config::ephemeral::{is_ephemeral, refusal};
+use crate::menu::render::Column;
+use crate::menu::timeline::format_clock;
+use crate::socket::history::now_secs;
+
+/// in loot_dir, next to the artifacts named by their hash
+pub const LOOT_INDEX: &str = "index.json";
+
+pub const LOOT_USAGE: &str =
+    "usage: loot add <shell> <name> [--from-last-output | --file <path>] | loot list | loot show <name>";
+
+/// Something captured during the engagement, the content is stored once per hash
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LootEntry {
+    pub name: String,
+    pub session: String,
+    /// the command whose output it is, or the file it was copied from
+    pub source: String,
+    /// seconds since the unix epoch
+    pub at: u64,
+    pub sha256: String,
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LootFrom {
+    LastOutput,
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LootCommand {
+    Add {
+        session: String,
+        name: String,
+        from: LootFrom,
+    },
+    List,
+    Show(String),
+}
+
+pub fn parse_loot_args(args: &str) -> Option<LootCommand> {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let (session, name, from) = match words.as_slice() {
+        ["list"] => return Some(LootCommand::List),
+        ["show", name] => return Some(LootCommand::Show(String::from(*name))),
+        ["add", session, name] | ["add", session, name, "--from-last-output"] => {
+            (session, name, LootFrom::LastOutput)
+        }
+        ["add", session, name, "--file", path] => {
+            (session, name, LootFrom::File(PathBuf::from(path)))
+        }
+        _ => return None,
+    };
+    return Some(LootCommand::Add {
+        session: String::from(*session),
+        name: String::from(*name),
+        from,
+    });
+}
+
+/// The last command in a jsonl transcript and everything that came back for it
+pub fn last_output(transcript: &str) -> Option<(String, String)> {
+    let records: Vec<serde_json::Value> = transcript
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    let command = records
+        .iter()
+        .rev()
+        .find(|record| record["kind"].as_str() == Some("command"))?;
+    let seq = command["seq"].as_u64()?;
+    let output: String = records
+        .iter()
+        .filter(|record| record["kind"].as_str() == Some("output"))
+        .filter(|record| record["reply_to"].as_u64() == Some(seq))
+        .filter_map(|record| record["text"].as_str())
+        .collect();
+    let cmd = command["text"].as_str().unwrap_or_default().trim_end();
+    return Some((String::from(cmd), output));
+}
+
+/// The loot directory and its index
+pub struct LootStore {
+    pub dir: PathBuf,
+    pub entries: Vec<LootEntry>,
+}
+
+impl LootStore {
+    /// Reads the index in `dir`, a missing one is an empty store
+    pub fn open(dir: &Path) -> Result<LootStore, String> {
+        let path = dir.join(LOOT_INDEX);
+        let entries = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|err| format!("{} is corrupt: {err}", path.display()))?,
+            Err(_) => Vec::new(),
+        };
+        return Ok(LootStore {
+            dir: dir.to_path_buf(),
+            entries,
+        });
+    }
+
+    pub fn find(&self, name: &str) -> Option<&LootEntry> {
+        return self.entries.iter().find(|entry| entry.name == name);
+    }
+
+    /// Where an entry's content is kept
+    pub fn path(&self, entry: &LootEntry) -> PathBuf {
+        return self.dir.join(&entry.sha256);
+    }
+
+    /// Stores `content` under `name`, content already in the store isn't written again.
+    /// Returns the new entry and the name of one that already had the same content
+    pub fn add(
+        &mut self,
+        name: &str,
+        session: &str,
+        source: &str,
+        content: &[u8],
+    ) -> Result<(LootEntry, Option<String>), String> {
+        if name.is_empty() || name.contains(|c: char| c == '/' || c.is_whitespace()) {
+            return Err(String::from("a loot name is one word without slashes"));
+        }
+        if self.find(name).is_some() {
+            return Err(format!("There's already loot called {name}"));
+        }
+        if is_ephemeral() {
+            return Err(refusal("Loot"));
+        }
+        // loot is whatever was worth taking, only the operator should read it
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.dir)
+            .and_then(|_| fs::set_permissions(&self.dir, fs::Permissions::from_mode(0o700)))
+            .map_err(|err| format!("Couldn't create {}: {err}", self.dir.display()))?;
+        let entry = LootEntry {
+            name: String::from(name),
+            session: String::from(session),
+            source: String::from(source),
+            at: now_secs(),
+            sha256: digest(content),
+            size: content.len(),
+        };
+        let duplicate = self
+            .entries
+            .iter()
+            .find(|other| other.sha256 == entry.sha256)
+            .map(|other| other.name.clone());
+        let path = self.path(&entry);
+        if !path.exists() {
+            fs::write(&path, content)
+                .map_err(|err| format!("Couldn't write {}: {err}", path.display()))?;
+        }
+        self.entries.push(entry.clone());
+        if let Err(err) = self.save() {
+            self.entries.pop();
+            return Err(err);
+        }
+        return Ok((entry, duplicate));
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = self.dir.join(LOOT_INDEX);
+        let content = serde_json::to_string_pretty(&self.entries).map_err(|err| err.to_string())?;
+        return fs::write(&path, content)
+            .map_err(|err| format!("Couldn't write {}: {err}", path.display()));
+    }
+}
+
+/// name, shell, size and source, the source is cut first
+pub const LOOT_COLUMNS: [Column; 4] = [
+    Column {
+        min: 8,
+        priority: 3,
+    },
+    Column {
+        min: 6,
+        priority: 2,
+    },
+    Column {
+        min: 4,
+        priority: 2,
+    },
+    Column {
+        min: 12,
+        priority: 0,
+    },
+];
+
+/// An entry's cells in the `loot list` table
+pub fn loot_row(entry: &LootEntry) -> Vec<String> {
+    return vec![
+        entry.name.clone(),
+        entry.session.clone(),
+        format!("{}B", entry.size),
+        entry.source.clone(),
+    ];
+}
+
+/// What `loot show` prints, the content itself when it's text
+pub fn show_loot(store: &LootStore, entry: &LootEntry) -> String {
+    let path = store.path(entry);
+    let mut text = format!(
+        "{name} from {session} at {at}\nsource: {source}\nsha256: {sha}\nstored: {path}\n",
+        name = entry.name,
+        session = entry.session,
+        at = format_clock(entry.at),
+        source = entry.source,
+        sha = entry.sha256,
+        path = path.display()
+    );
+    match fs::read(&path).map(String::from_utf8) {
+        Ok(Ok(content)) => {
+            text += "\n";
+            text += &content;
+            if !content.ends_with('\n') {
+                text += "\n";
+            }
+        }
+        Ok(Err(_)) => text += &format!("{} bytes of binary\n", entry.size),
+        Err(err) => text += &format!("Couldn't read it: {err}\n"),
+    }
+    return text;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_loot_args() {
+        assert_eq!(parse_loot_args("list"), Some(LootCommand::List));
+        assert_eq!(
+            parse_loot_args("show shadow"),
+            Some(LootCommand::Show(String::from("shadow")))
+        );
+        assert_eq!(
+            parse_loot_args("add web shadow"),
+            Some(LootCommand::Add {
+                session: String::from("web"),
+                name: String::from("shadow"),
+                from: LootFrom::LastOutput,
+            })
+        );
+        assert_eq!(
+            parse_loot_args("add web key --file id_rsa"),
+            Some(LootCommand::Add {
+                session: String::from("web"),
+                name: String::from("key"),
+                from: LootFrom::File(PathBuf::from("id_rsa")),
+            })
+        );
+        assert_eq!(parse_loot_args("add web"), None);
+        assert_eq!(parse_loot_args("add web key --file"), None);
+    }
+
+    #[test]
+    fn test_last_output() {
+        let transcript = "\
+{\"seq\":1,\"ts\":0,\"kind\":\"command\",\"session\":\"web\",\"text\":\"id\\n\"}
+{\"seq\":2,\"ts\":0,\"kind\":\"output\",\"session\":\"web\",\"text\":\"uid=0(root)\\n\",\"reply_to\":1}
+{\"seq\":3,\"ts\":0,\"kind\":\"command\",\"session\":\"web\",\"text\":\"cat /etc/hostname\\n\"}
+{\"seq\":4,\"ts\":0,\"kind\":\"output\",\"session\":\"web\",\"text\":\"box\",\"reply_to\":3}
+{\"seq\":5,\"ts\":0,\"kind\":\"note\",\"session\":\"web\",\"text\":\"detached\"}
+{\"seq\":6,\"ts\":0,\"kind\":\"output\",\"session\":\"web\",\"text\":\"01\\n\",\"reply_to\":3}
+";
+        assert_eq!(
+            last_output(transcript),
+            Some((String::from("cat /etc/hostname"), String::from("box01\n")))
+        );
+        assert_eq!(last_output(""), None);
+    }
+
+    #[test]
+    fn test_loot_store() {
+        let dir = std::env::temp_dir().join("crab_trap_test_loot");
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        let mut store = LootStore::open(&dir).unwrap();
+        assert!(store.entries.is_empty());
+
+        let (entry, duplicate) = store
+            .add("hostname", "web", "cat /etc/hostname", b"box01\n")
+            .unwrap();
+        assert_eq!(duplicate, None);
+        assert_eq!(entry.size, 6);
+        assert_eq!(fs::read(store.path(&entry)).unwrap(), b"box01\n");
+        let mode = fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // the same content again is only stored once
+        let (_, duplicate) = store
+            .add("hostname-again", "db", "hostname", b"box01\n")
+            .unwrap();
+        assert_eq!(duplicate.as_deref(), Some("hostname"));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        assert!(store.add("hostname", "web", "id", b"other").is_err());
+        assert!(store.add("../escape", "web", "id", b"other").is_err());
+
+        let shown = show_loot(&store, &entry);
+        assert!(shown.starts_with("hostname from web at "));
+        assert!(shown.ends_with("\nbox01\n"));
+
+        let reopened = LootStore::open(&dir).unwrap();
+        assert_eq!(reopened.entries, store.entries);
+        assert_eq!(reopened.find("hostname-again").unwrap().session, "db");
+        fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+}
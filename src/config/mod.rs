@@ -0,0 +1,9 @@
+pub mod config;
+pub mod doctor;
+pub mod ephemeral;
+pub mod init;
+pub mod loot;
+pub mod profiles;
+pub mod settings;
+pub mod state;
+pub mod workspace;
@@ -0,0 +1,263 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::config::config::config_dir;
+use crate::config::settings::lookup;
+use crate::menu::render::Column;
+
+/// under the config dir, one toml file per profile named after it
+pub const PROFILES_DIR: &str = "profiles";
+
+pub const PROFILES_USAGE: &str = "usage: profiles | profiles test <name>";
+
+/// how long a new shell gets to send its banner when there are profiles to match
+pub const BANNER_WAIT: Duration = Duration::from_secs(2);
+
+fn default_step_timeout() -> u64 {
+    return 5000;
+}
+
+/// Waits for the device to print something, then answers it
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileStep {
+    /// a regex, matched against everything received since the last step
+    pub expect: String,
+    pub send: String,
+    #[serde(default = "default_step_timeout")]
+    pub timeout_ms: u64,
+}
+
+/// What a kind of device needs before its shell is usable
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceProfile {
+    /// from the file name
+    #[serde(skip)]
+    pub name: String,
+    /// a regex for the banner, profiles without one are only used with `--profile`
+    #[serde(default, rename = "match")]
+    pub matches: Option<String>,
+    #[serde(default)]
+    pub steps: Vec<ProfileStep>,
+    /// per session settings for shells it's used on
+    #[serde(default)]
+    pub settings: BTreeMap<String, String>,
+    /// attach to it in raw mode
+    #[serde(default)]
+    pub raw: bool,
+}
+
+/// `~/.config/crab_trap/profiles` on linux, shared by every workspace
+pub fn profiles_dir() -> PathBuf {
+    return config_dir().join(PROFILES_DIR);
+}
+
+fn compile(pattern: &str) -> Result<Regex, String> {
+    return Regex::new(pattern).map_err(|err| format!("{pattern} isn't a valid regex: {err}"));
+}
+
+/// Reads a profile and checks its regexes and settings
+pub fn parse_profile(name: &str, content: &str) -> Result<DeviceProfile, String> {
+    let mut profile: DeviceProfile = toml::from_str(content).map_err(|err| err.to_string())?;
+    profile.name = String::from(name);
+    if let Some(pattern) = &profile.matches {
+        compile(pattern)?;
+    }
+    for step in &profile.steps {
+        compile(&step.expect)?;
+    }
+    for (key, value) in &profile.settings {
+        let def = lookup(key).map_err(|err| err.to_string())?;
+        if !def.per_session {
+            return Err(format!("{key} can't be set per session"));
+        }
+        def.normalize(value).map_err(|err| err.to_string())?;
+    }
+    return Ok(profile);
+}
+
+/// Every profile in `dir` sorted by name, and what was wrong with the ones that
+/// couldn't be loaded
+pub fn load_profiles(dir: &Path) -> (Vec<DeviceProfile>, Vec<String>) {
+    let mut profiles = Vec::new();
+    let mut errors = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(val) => val,
+        Err(_) => return (profiles, errors),
+    };
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        let name = match (path.file_stem(), path.extension()) {
+            (Some(stem), Some(ext)) if ext == "toml" => stem.to_string_lossy().into_owned(),
+            _ => continue,
+        };
+        let parsed = fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|content| parse_profile(&name, &content));
+        match parsed {
+            Ok(profile) => profiles.push(profile),
+            Err(err) => errors.push(format!("{}: {err}", path.display())),
+        }
+    }
+    profiles.sort_by(|a, b| a.name.cmp(&b.name));
+    return (profiles, errors);
+}
+
+/// The first profile whose `match` finds something in the banner
+pub fn match_profile<'a>(profiles: &'a [DeviceProfile], banner: &str) -> Option<&'a DeviceProfile> {
+    return profiles.iter().find(|profile| {
+        profile
+            .matches
+            .as_deref()
+            .and_then(|pattern| Regex::new(pattern).ok())
+            .is_some_and(|re| re.is_match(banner))
+    });
+}
+
+/// name, steps, match
+pub const PROFILE_COLUMNS: [Column; 3] = [
+    Column {
+        min: 8,
+        priority: 3,
+    },
+    Column {
+        min: 7,
+        priority: 1,
+    },
+    Column {
+        min: 12,
+        priority: 2,
+    },
+];
+
+pub fn profile_row(profile: &DeviceProfile) -> Vec<String> {
+    let mut steps = format!("{} steps", profile.steps.len());
+    if profile.raw {
+        steps += ", raw";
+    }
+    return vec![
+        profile.name.clone(),
+        steps,
+        profile
+            .matches
+            .clone()
+            .unwrap_or_else(|| String::from("--profile only")),
+    ];
+}
+
+/// Where `expect` first matches in `content`, the end of the match
+pub fn find_expect(expect: &str, content: &str) -> Option<usize> {
+    return Regex::new(expect)
+        .ok()?
+        .find(content)
+        .map(|found| found.end());
+}
+
+/// What `profiles test` prints, whether the profile would be picked for the banner and
+/// how far its steps get on it. Steps waiting for replies to earlier sends can't be
+/// checked against a banner and show as waiting
+pub fn test_profile(profile: &DeviceProfile, banner: &str) -> String {
+    let mut report = match &profile.matches {
+        Some(pattern) => match find_expect(pattern, banner) {
+            Some(_) => format!("{} matches the banner\n", profile.name),
+            None => format!("{} doesn't match the banner ({pattern})\n", profile.name),
+        },
+        None => format!(
+            "{} has no match, it's only used with --profile\n",
+            profile.name
+        ),
+    };
+    let mut rest = banner;
+    for (i, step) in profile.steps.iter().enumerate() {
+        match find_expect(&step.expect, rest) {
+            Some(end) => {
+                report += &format!(
+                    "  step {}: found {}, sends {:?}\n",
+                    i + 1,
+                    step.expect,
+                    step.send
+                );
+                rest = &rest[end..];
+            }
+            None => {
+                report += &format!("  step {}: waits for {}\n", i + 1, step.expect);
+                break;
+            }
+        }
+    }
+    return report;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SWITCH: &str = "match = 'Cisco IOS'\n\
+        raw = true\n\
+        [settings]\n\
+        max_line_width = '200'\n\
+        [[steps]]\n\
+        expect = 'Press RETURN'\n\
+        send = \"\\r\"\n\
+        [[steps]]\n\
+        expect = '>\\s*$'\n\
+        send = \"terminal length 0\\r\"\n\
+        timeout_ms = 2000\n";
+
+    #[test]
+    fn test_parse_profile() {
+        let profile = parse_profile("cisco", SWITCH).unwrap();
+        assert_eq!(profile.name, "cisco");
+        assert!(profile.raw);
+        assert_eq!(profile.steps.len(), 2);
+        assert_eq!(profile.steps[0].send, "\r");
+        assert_eq!(profile.steps[0].timeout_ms, 5000);
+        assert!(parse_profile("bad", "match = '('").is_err());
+        assert!(parse_profile("bad", "[settings]\ntheme = 'plain'").is_err());
+        assert!(parse_profile("bad", "[settings]\nmax_line_width = 'wide'").is_err());
+        assert!(parse_profile("bad", "prompt = '>'").is_err());
+    }
+
+    #[test]
+    fn test_load_and_match_profiles() {
+        let dir = std::env::temp_dir().join("crab_trap_test_profiles");
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("cisco.toml"), SWITCH).unwrap();
+        fs::write(dir.join("manual.toml"), "raw = true\n").unwrap();
+        fs::write(dir.join("broken.toml"), "match = '['\n").unwrap();
+        fs::write(dir.join("notes.txt"), "not a profile").unwrap();
+        let (profiles, errors) = load_profiles(&dir);
+        let names: Vec<&str> = profiles
+            .iter()
+            .map(|profile| profile.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["cisco", "manual"]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("broken.toml"));
+
+        let banner = "\r\nCisco IOS Software, C2960\r\nPress RETURN to get started!\r\n";
+        assert_eq!(match_profile(&profiles, banner).unwrap().name, "cisco");
+        assert!(match_profile(&profiles, "Ubuntu 22.04").is_none());
+        fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+
+    #[test]
+    fn test_test_profile() {
+        let profile = parse_profile("cisco", SWITCH).unwrap();
+        let report = test_profile(&profile, "Cisco IOS\nPress RETURN to get started\n");
+        assert_eq!(
+            report,
+            "cisco matches the banner\n  step 1: found Press RETURN, sends \"\\r\"\n  step 2: waits for >\\s*$\n"
+        );
+        assert!(test_profile(&profile, "Juniper").starts_with("cisco doesn't match"));
+    }
+}
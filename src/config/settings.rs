@@ -0,0 +1,547 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::config::{valid_escape_key, Config, Theme};
+use crate::error::error::CrabTrapError;
+use crate::input::chord::DEFAULT_CHORD_PREFIX;
+use crate::input::suggest::closest_match;
+use crate::remote::upload::ChunkOptions;
+use crate::socket::retention::RetentionPolicy;
+use crate::socket::write::Pace;
+
+/// Where a setting's effective value came from, narrowest last
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    Default,
+    Global,
+    /// the listener crab_trap was started with, set from the command line
+    Listener,
+    Session,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(
+            f,
+            "{}",
+            match self {
+                Scope::Default => "default",
+                Scope::Global => "global",
+                Scope::Listener => "listener",
+                Scope::Session => "session",
+            }
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingKind {
+    Bool,
+    Number,
+    Choice(&'static [&'static str]),
+    /// a ctrl key combination like `ctrl-]`
+    Key,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SettingDef {
+    pub key: &'static str,
+    pub kind: SettingKind,
+    pub default: &'static str,
+    pub help: &'static str,
+    /// whether it can also be set for a single session
+    pub per_session: bool,
+}
+
+/// Every setting crab_trap knows about, new toggles go here
+pub const SETTINGS: &[SettingDef] = &[
+    SettingDef {
+        key: "auto_restore",
+        kind: SettingKind::Bool,
+        default: "false",
+        help: "restore a reconnecting host's closed shell as soon as it connects",
+        per_session: false,
+    },
+    SettingDef {
+        key: "chord_timeout_ms",
+        kind: SettingKind::Number,
+        default: "1000",
+        help: "how long to wait after the escape key before sending it to the remote",
+        per_session: true,
+    },
+    SettingDef {
+        key: "escape_key",
+        kind: SettingKind::Key,
+        default: DEFAULT_CHORD_PREFIX,
+        help: "key that starts a raw mode chord, then d returns from a shell to the menu",
+        per_session: true,
+    },
+    SettingDef {
+        key: "input_newline_delay_ms",
+        kind: SettingKind::Number,
+        default: "0",
+        help: "extra wait after each line sent to the remote, 0 for none",
+        per_session: true,
+    },
+    SettingDef {
+        key: "input_pace",
+        kind: SettingKind::Number,
+        default: "0",
+        help: "most characters per second sent to the remote, for consoles that drop input, 0 for no limit",
+        per_session: true,
+    },
+    SettingDef {
+        key: "intercept_typos",
+        kind: SettingKind::Bool,
+        default: "true",
+        help: "catch mistyped menu commands instead of running them as local commands",
+        per_session: false,
+    },
+    SettingDef {
+        key: "keep_closed",
+        kind: SettingKind::Number,
+        default: "20",
+        help: "how many closed shells to keep",
+        per_session: false,
+    },
+    SettingDef {
+        key: "keep_closed_mins",
+        kind: SettingKind::Number,
+        default: "60",
+        help: "how long closed shells are kept for",
+        per_session: false,
+    },
+    SettingDef {
+        key: "max_line_width",
+        kind: SettingKind::Number,
+        default: "16384",
+        help: "lines longer than this are truncated outside raw mode",
+        per_session: true,
+    },
+    SettingDef {
+        key: "meta_commands",
+        kind: SettingKind::Choice(&["bare", "prefix"]),
+        default: "bare",
+        help: "bare: `back` is handled locally and `\\back` sends it, prefix: only `%back` is",
+        per_session: true,
+    },
+    SettingDef {
+        key: "protocol",
+        kind: SettingKind::Choice(&["auto", "shell", "http", "ssh", "tls", "unknown-binary"]),
+        default: "auto",
+        help: "what a session speaks, auto goes by its first bytes. Anything but a shell is shown as hex",
+        per_session: true,
+    },
+    SettingDef {
+        key: "sniff_timeout_ms",
+        kind: SettingKind::Number,
+        default: "300",
+        help: "how long a new connection has to show whether it's TLS before it's taken as a plain shell, 0 to skip the check",
+        per_session: false,
+    },
+    SettingDef {
+        key: "spill_max_kb",
+        kind: SettingKind::Number,
+        default: "10240",
+        help: "how much spilled output to keep on disk for each session",
+        per_session: false,
+    },
+    SettingDef {
+        key: "spill_output",
+        kind: SettingKind::Bool,
+        default: "false",
+        help: "write output that overflows a detached session's buffer to log_dir instead of dropping it",
+        per_session: false,
+    },
+    SettingDef {
+        key: "terminal_queries",
+        kind: SettingKind::Choice(&["answer", "terminal", "ignore"]),
+        default: "answer",
+        help: "cursor position, device attribute and capability queries from the remote: answer them here, leave them to your terminal or drop them",
+        per_session: true,
+    },
+    SettingDef {
+        key: "theme",
+        kind: SettingKind::Choice(&["default", "light", "plain"]),
+        default: "default",
+        help: "colours of crab_trap's own output, light for light backgrounds, plain for none",
+        per_session: false,
+    },
+    SettingDef {
+        key: "timing",
+        kind: SettingKind::Bool,
+        default: "false",
+        help: "show how long each line mode command took once its prompt comes back",
+        per_session: true,
+    },
+    SettingDef {
+        key: "title",
+        kind: SettingKind::Bool,
+        default: "true",
+        help: "show the attached shell, or how many shells there are, in the terminal's title",
+        per_session: false,
+    },
+    SettingDef {
+        key: "transcript_fsync_ms",
+        kind: SettingKind::Number,
+        default: "1000",
+        help: "how often transcripts are synced to disk",
+        per_session: false,
+    },
+    SettingDef {
+        key: "transcripts",
+        kind: SettingKind::Bool,
+        default: "false",
+        help: "record a transcript of every session",
+        per_session: true,
+    },
+    SettingDef {
+        key: "transfer_chunk_max",
+        kind: SettingKind::Number,
+        default: "3072",
+        help: "largest base64 chunk an upload grows to, in characters",
+        per_session: true,
+    },
+    SettingDef {
+        key: "transfer_chunk_min",
+        kind: SettingKind::Number,
+        default: "64",
+        help: "smallest base64 chunk an upload shrinks to after a chunk arrives mangled",
+        per_session: true,
+    },
+    SettingDef {
+        key: "transfer_verify_every",
+        kind: SettingKind::Number,
+        default: "1",
+        help: "how many upload chunks are sent between checks that they arrived intact",
+        per_session: true,
+    },
+];
+
+/// Finds a setting, suggesting the closest known key when it doesn't exist
+pub fn lookup(key: &str) -> Result<&'static SettingDef, CrabTrapError> {
+    if let Some(def) = SETTINGS.iter().find(|def| def.key == key) {
+        return Ok(def);
+    }
+    return Err(CrabTrapError::UnknownSetting {
+        key: String::from(key),
+        suggestion: closest_match(key, SETTINGS.iter().map(|def| def.key)).map(String::from),
+    });
+}
+
+impl SettingDef {
+    /// Checks a value and puts it in its canonical form
+    pub fn normalize(&self, value: &str) -> Result<String, CrabTrapError> {
+        let value = value.trim();
+        let invalid = |expected: String| CrabTrapError::InvalidSetting {
+            key: String::from(self.key),
+            reason: format!("expected {expected}, got {value}"),
+        };
+        return match self.kind {
+            SettingKind::Bool => match value.to_lowercase().as_str() {
+                "true" | "on" | "yes" => Ok(String::from("true")),
+                "false" | "off" | "no" => Ok(String::from("false")),
+                _ => Err(invalid(String::from("on or off"))),
+            },
+            SettingKind::Number => match value.parse::<u64>() {
+                // a zero interval would have the transcript writer syncing without a break
+                Ok(0) if self.key == "transcript_fsync_ms" => {
+                    Err(invalid(String::from("at least 1")))
+                }
+                Ok(num) => Ok(num.to_string()),
+                Err(_) => Err(invalid(String::from("a whole number"))),
+            },
+            SettingKind::Choice(choices) => match choices.contains(&value) {
+                true => Ok(String::from(value)),
+                false => Err(invalid(format!("one of {}", choices.join(", ")))),
+            },
+            SettingKind::Key => match valid_escape_key(value) {
+                true => Ok(String::from(value)),
+                false => Err(invalid(String::from("a key like ctrl-]"))),
+            },
+        };
+    }
+}
+
+/// Settings shared between the menu and the listener
+pub type SharedSettings = Arc<std::sync::Mutex<Settings>>;
+
+/// Values set at each scope, lookups fall back from session to listener to global
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    global: BTreeMap<String, String>,
+    listener: BTreeMap<String, String>,
+    sessions: HashMap<String, BTreeMap<String, String>>,
+}
+
+impl Settings {
+    /// Starts from the values in the config file
+    pub fn from_config(config: &Config) -> Settings {
+        let mut settings = Settings::default();
+        let defaults = Config::default();
+        let mut from_file = config.settings.clone();
+        if config.escape_key != defaults.escape_key {
+            from_file.insert(String::from("escape_key"), config.escape_key.clone());
+        }
+        if config.theme != defaults.theme {
+            from_file.insert(String::from("theme"), String::from(config.theme.name()));
+        }
+        if config.transcripts != defaults.transcripts {
+            from_file.insert(String::from("transcripts"), config.transcripts.to_string());
+        }
+        for (key, value) in from_file {
+            if let Err(err) = settings.set(Scope::Global, None, &key, &value) {
+                eprintln!("Ignoring {key} from the config: {err}");
+            }
+        }
+        return settings;
+    }
+
+    /// Writes the global values back into the config so they can be saved
+    pub fn apply_to_config(&self, config: &mut Config) {
+        for (key, value) in &self.global {
+            match key.as_str() {
+                "escape_key" => config.escape_key = value.clone(),
+                "theme" => config.theme = Theme::parse(value).unwrap_or(config.theme),
+                "transcripts" => config.transcripts = value == "true",
+                _ => {
+                    config.settings.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+
+    /// Validates and stores a value, returning it in its canonical form
+    pub fn set(
+        &mut self,
+        scope: Scope,
+        session: Option<&str>,
+        key: &str,
+        value: &str,
+    ) -> Result<String, CrabTrapError> {
+        let def = lookup(key)?;
+        let value = def.normalize(value)?;
+        let values = match (scope, session) {
+            (Scope::Global, _) | (Scope::Default, _) => &mut self.global,
+            (Scope::Listener, _) => &mut self.listener,
+            (Scope::Session, Some(name)) if def.per_session => {
+                self.sessions.entry(String::from(name)).or_default()
+            }
+            (Scope::Session, Some(_)) => {
+                return Err(CrabTrapError::InvalidSetting {
+                    key: String::from(key),
+                    reason: String::from("it can't be set per session"),
+                })
+            }
+            (Scope::Session, None) => {
+                return Err(CrabTrapError::InvalidSetting {
+                    key: String::from(key),
+                    reason: String::from("no session given"),
+                })
+            }
+        };
+        values.insert(String::from(key), value.clone());
+        return Ok(value);
+    }
+
+    /// The effective value of a setting and the scope it came from
+    pub fn get(&self, key: &str, session: Option<&str>) -> Result<(String, Scope), CrabTrapError> {
+        let def = lookup(key)?;
+        let session_value = session
+            .and_then(|name| self.sessions.get(name))
+            .and_then(|values| values.get(key));
+        if let Some(value) = session_value {
+            return Ok((value.clone(), Scope::Session));
+        }
+        if let Some(value) = self.listener.get(key) {
+            return Ok((value.clone(), Scope::Listener));
+        }
+        if let Some(value) = self.global.get(key) {
+            return Ok((value.clone(), Scope::Global));
+        }
+        return Ok((String::from(def.default), Scope::Default));
+    }
+
+    /// Typed lookups for known settings, the registry guarantees these parse
+    pub fn get_bool(&self, key: &str, session: Option<&str>) -> bool {
+        return matches!(self.get(key, session), Ok((value, _)) if value == "true");
+    }
+
+    pub fn get_theme(&self) -> Theme {
+        return match self.get("theme", None) {
+            Ok((value, _)) => Theme::parse(&value).unwrap_or(Theme::Default),
+            Err(_) => Theme::Default,
+        };
+    }
+
+    pub fn get_number(&self, key: &str, session: Option<&str>) -> u64 {
+        return match self.get(key, session) {
+            Ok((value, _)) => value.parse::<u64>().unwrap_or_default(),
+            Err(_) => 0,
+        };
+    }
+
+    /// The settings a session overrides
+    pub fn session_overrides(&self, session: &str) -> Vec<(String, String)> {
+        return match self.sessions.get(session) {
+            Some(values) => values.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            None => Vec::new(),
+        };
+    }
+
+    /// Forgets a session's overrides, called once it's gone
+    pub fn remove_session(&mut self, session: &str) {
+        self.sessions.remove(session);
+    }
+
+    pub fn retention(&self) -> RetentionPolicy {
+        return RetentionPolicy {
+            max_age: Duration::from_secs(self.get_number("keep_closed_mins", None) * 60),
+            max_closed: self.get_number("keep_closed", None) as usize,
+        };
+    }
+
+    pub fn pace(&self, session: Option<&str>) -> Pace {
+        return Pace {
+            chars_per_sec: self.get_number("input_pace", session),
+            newline_delay: Duration::from_millis(
+                self.get_number("input_newline_delay_ms", session),
+            ),
+        };
+    }
+
+    pub fn chunk_options(&self, session: Option<&str>) -> ChunkOptions {
+        return ChunkOptions {
+            min: self.get_number("transfer_chunk_min", session).max(1) as usize,
+            max: self.get_number("transfer_chunk_max", session) as usize,
+            verify_every: self.get_number("transfer_verify_every", session).max(1) as usize,
+        };
+    }
+}
+
+/// A parsed `set [--save] [global|listener|session <name>] <key> <value>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetCommand {
+    pub scope: Scope,
+    pub session: Option<String>,
+    pub key: String,
+    pub value: String,
+    /// write global settings back to the config file
+    pub save: bool,
+}
+
+pub const SET_USAGE: &str = "Usage: set [--save] [global|listener|session <name>] <key> <value>";
+
+pub fn parse_set_args(args: &str) -> Option<SetCommand> {
+    let mut words: Vec<&str> = args.split_whitespace().collect();
+    let save = words.first() == Some(&"--save");
+    if save {
+        words.remove(0);
+    }
+    let (scope, session, rest) = match words.as_slice() {
+        ["global", rest @ ..] => (Scope::Global, None, rest),
+        ["listener", rest @ ..] => (Scope::Listener, None, rest),
+        ["session", name, rest @ ..] => (Scope::Session, Some(String::from(*name)), rest),
+        rest => (Scope::Global, None, rest),
+    };
+    return match rest {
+        [key, value @ ..] if !value.is_empty() => Some(SetCommand {
+            scope,
+            session,
+            key: String::from(*key),
+            value: value.join(" "),
+            save,
+        }),
+        _ => None,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_resolution() {
+        let mut settings = Settings::default();
+        assert_eq!(
+            settings.get("max_line_width", Some("web")),
+            Ok((String::from("16384"), Scope::Default))
+        );
+        settings
+            .set(Scope::Global, None, "max_line_width", "100")
+            .unwrap();
+        settings
+            .set(Scope::Session, Some("web"), "max_line_width", "80")
+            .unwrap();
+        assert_eq!(
+            settings.get("max_line_width", Some("web")),
+            Ok((String::from("80"), Scope::Session))
+        );
+        assert_eq!(
+            settings.get("max_line_width", Some("db")),
+            Ok((String::from("100"), Scope::Global))
+        );
+        assert_eq!(
+            settings.set(Scope::Global, None, "auto_restore", "on"),
+            Ok(String::from("true"))
+        );
+        assert!(settings.get_bool("auto_restore", None));
+        assert!(settings
+            .set(Scope::Session, Some("web"), "theme", "plain")
+            .is_err());
+        assert!(settings.set(Scope::Global, None, "theme", "neon").is_err());
+        assert!(settings
+            .set(Scope::Global, None, "transcript_fsync_ms", "0")
+            .is_err());
+        assert_eq!(
+            settings.get("thme", None),
+            Err(CrabTrapError::UnknownSetting {
+                key: String::from("thme"),
+                suggestion: Some(String::from("theme"))
+            })
+        );
+    }
+
+    #[test]
+    fn test_config_settings_round_trip() {
+        let mut settings = Settings::default();
+        settings.set(Scope::Global, None, "theme", "plain").unwrap();
+        settings
+            .set(Scope::Global, None, "keep_closed", "5")
+            .unwrap();
+        settings
+            .set(Scope::Listener, None, "keep_closed", "2")
+            .unwrap();
+        let mut config = Config::default();
+        settings.apply_to_config(&mut config);
+        let config = Config::parse(&config.to_commented_toml()).unwrap();
+        assert_eq!(config.theme, Theme::Plain);
+        // only global values are saved
+        let loaded = Settings::from_config(&config);
+        assert_eq!(loaded.get_number("keep_closed", None), 5);
+        assert_eq!(
+            loaded.get("theme", None),
+            Ok((String::from("plain"), Scope::Global))
+        );
+    }
+
+    #[test]
+    fn test_parse_set_args() {
+        assert_eq!(
+            parse_set_args("session web max_line_width 80"),
+            Some(SetCommand {
+                scope: Scope::Session,
+                session: Some(String::from("web")),
+                key: String::from("max_line_width"),
+                value: String::from("80"),
+                save: false,
+            })
+        );
+        let cmd = parse_set_args("--save theme plain").unwrap();
+        assert!(cmd.save);
+        assert_eq!(cmd.scope, Scope::Global);
+        assert_eq!(parse_set_args("theme"), None);
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ephemeral::is_ephemeral;
+use crate::config::settings::{Settings, SharedSettings};
+use crate::menu::render::Column;
+use crate::menu::timeline::format_clock;
+use crate::socket::close::CloseReason;
+use crate::socket::connection::Handle;
+use crate::socket::history::now_secs;
+use crate::socket::marks::SessionMark;
+use crate::socket::notes::SessionNote;
+use crate::socket::sniff::ProtocolHint;
+
+/// kept next to the config file
+pub const STATE_FILE: &str = "state.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStatus {
+    Open,
+    Closed,
+    /// closed and waiting to redial the bind shell it came from
+    Reconnecting,
+    /// from an earlier run, the connection died with it
+    Lost,
+}
+
+impl SessionStatus {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            SessionStatus::Open => "open",
+            SessionStatus::Closed => "closed",
+            SessionStatus::Reconnecting => "reconnecting",
+            SessionStatus::Lost => "lost",
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListenerRecord {
+    pub address: String,
+    pub port: u16,
+}
+
+/// What's remembered about a session after crab_trap exits
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub name: String,
+    pub peer_addr: Option<String>,
+    /// seconds since the unix epoch
+    pub connected_at: u64,
+    pub status: SessionStatus,
+    #[serde(default)]
+    pub restored_from: Option<String>,
+    /// settings set for just this session
+    #[serde(default)]
+    pub settings: Vec<(String, String)>,
+    #[serde(default)]
+    pub notes: Vec<SessionNote>,
+    #[serde(default)]
+    pub marks: Vec<SessionMark>,
+    /// where its transcript was written, so marks can still be saved from it
+    #[serde(default)]
+    pub transcript: Option<PathBuf>,
+    /// where its relative local paths were resolved from
+    #[serde(default)]
+    pub local_dir: Option<PathBuf>,
+    /// where the remote shell was last seen to be
+    #[serde(default)]
+    pub remote_cwd: Option<String>,
+    /// why it ended, lost sessions without one went down with crab_trap
+    #[serde(default)]
+    pub close_reason: Option<CloseReason>,
+    /// what it speaks when that isn't a shell
+    #[serde(default)]
+    pub protocol: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct SavedState {
+    #[serde(default)]
+    listener: Option<ListenerRecord>,
+    #[serde(default)]
+    sessions: Vec<SessionRecord>,
+}
+
+/// The state file and what was loaded from it
+pub struct StateFile {
+    pub path: PathBuf,
+    pub listener: Option<ListenerRecord>,
+    /// sessions from earlier runs, newest first
+    pub lost: Vec<SessionRecord>,
+}
+
+pub type SharedState = Arc<std::sync::Mutex<StateFile>>;
+
+pub fn state_path(config_path: &Path) -> PathBuf {
+    return config_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join(STATE_FILE);
+}
+
+impl SessionRecord {
+    pub fn from_handle(name: &str, handle: &Handle, settings: &Settings) -> SessionRecord {
+        return SessionRecord {
+            name: String::from(name),
+            peer_addr: handle.peer_addr.map(|addr| addr.to_string()),
+            connected_at: handle
+                .history()
+                .first()
+                .map_or_else(now_secs, |event| event.at),
+            status: match (handle.is_closed(), handle.is_redialing()) {
+                (true, true) => SessionStatus::Reconnecting,
+                (true, false) => SessionStatus::Closed,
+                (false, _) => SessionStatus::Open,
+            },
+            restored_from: handle.restored_from.clone(),
+            settings: settings.session_overrides(name),
+            notes: handle.notes(),
+            marks: handle.marks(),
+            transcript: handle.transcript_path(),
+            local_dir: Some(handle.local_dir()),
+            remote_cwd: handle.remote_cwd(),
+            close_reason: handle.close_reason(),
+            protocol: match handle.protocol() {
+                ProtocolHint::Shell => None,
+                other => Some(String::from(other.name())),
+            },
+        };
+    }
+}
+
+impl StateFile {
+    /// Loads the state file, everything in it is from a run that has ended so its
+    /// sessions come back as lost. A file that can't be read is moved aside and
+    /// started over, it never stops crab_trap from starting
+    pub fn load(path: &Path) -> StateFile {
+        let saved = match fs::read_to_string(path) {
+            Ok(content) => match serde_json::from_str::<SavedState>(&content) {
+                Ok(val) => val,
+                Err(err) if is_ephemeral() => {
+                    eprintln!("[-] {} is corrupt ({err}), starting over", path.display());
+                    SavedState::default()
+                }
+                Err(err) => {
+                    let backup = path.with_extension(format!("json.bak-{}", now_secs()));
+                    eprintln!(
+                        "[-] {} is corrupt ({err}), moved it to {}",
+                        path.display(),
+                        backup.display()
+                    );
+                    fs::rename(path, &backup).unwrap_or_default();
+                    SavedState::default()
+                }
+            },
+            Err(_) => SavedState::default(),
+        };
+        let mut lost = saved.sessions;
+        for session in lost.iter_mut() {
+            session.status = SessionStatus::Lost;
+        }
+        lost.sort_by_key(|record| std::cmp::Reverse(record.connected_at));
+        return StateFile {
+            path: PathBuf::from(path),
+            listener: saved.listener,
+            lost,
+        };
+    }
+
+    /// The newest lost session with this name
+    pub fn lost_mut(&mut self, name: &str) -> Option<&mut SessionRecord> {
+        return self.lost.iter_mut().find(|record| record.name == name);
+    }
+
+    /// Writes the current shells and the lost sessions, keeping at most `max_lost`
+    /// lost ones
+    pub fn save(
+        &mut self,
+        shells: &HashMap<String, Handle>,
+        settings: &Settings,
+        max_lost: usize,
+    ) -> io::Result<()> {
+        self.lost.truncate(max_lost);
+        // kept in memory for `sessions`, just never written
+        if is_ephemeral() {
+            return Ok(());
+        }
+        let mut sessions: Vec<SessionRecord> = shells
+            .iter()
+            .map(|(name, handle)| SessionRecord::from_handle(name, handle, settings))
+            .collect();
+        sessions.sort_by_key(|record| std::cmp::Reverse(record.connected_at));
+        sessions.extend(self.lost.iter().cloned());
+        let saved = SavedState {
+            listener: self.listener.clone(),
+            sessions,
+        };
+        let content = serde_json::to_string_pretty(&saved).map_err(io::Error::other)?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // a crash mid write leaves the old file in place
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, content)?;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+        return fs::rename(&tmp, &self.path);
+    }
+}
+
+/// Saves the state, keeping as many lost sessions as closed ones are kept. Failing to
+/// save is only worth a warning
+pub fn save_state(
+    state: &SharedState,
+    shells: &HashMap<String, Handle>,
+    settings: &SharedSettings,
+) {
+    let settings = match settings.lock() {
+        Ok(val) => val,
+        Err(_) => return,
+    };
+    let max_lost = settings.retention().max_closed;
+    if let Ok(mut state) = state.lock() {
+        if let Err(err) = state.save(shells, &settings, max_lost) {
+            eprintln!("[-] Couldn't save {}: {err}", state.path.display());
+        }
+    }
+}
+
+/// name, status and address give way last when `sessions` is squeezed
+pub const SESSION_COLUMNS: [Column; 4] = [
+    Column {
+        min: 8,
+        priority: 3,
+    },
+    Column {
+        min: 4,
+        priority: 2,
+    },
+    Column {
+        min: 8,
+        priority: 1,
+    },
+    Column {
+        min: 12,
+        priority: 0,
+    },
+];
+
+/// A session's cells in the `sessions` table
+pub fn session_row(record: &SessionRecord) -> Vec<String> {
+    let mut details = format!("connected {}", format_clock(record.connected_at));
+    if let Some(cwd) = &record.remote_cwd {
+        details += &format!(" in {cwd}");
+    }
+    if let Some(protocol) = &record.protocol {
+        details += &format!(" speaking {protocol}");
+    }
+    if let Some(reason) = record.close_reason {
+        details += &format!(" closed by {}", reason.code());
+    }
+    if let Some(previous) = &record.restored_from {
+        details += &format!(" (restored from {previous})");
+    }
+    match record.notes.len() {
+        0 => {}
+        1 => details += ", 1 note",
+        n => details += &format!(", {n} notes"),
+    }
+    match record.marks.len() {
+        0 => {}
+        1 => details += ", 1 mark",
+        n => details += &format!(", {n} marks"),
+    }
+    return vec![
+        record.name.clone(),
+        String::from(record.status.name()),
+        String::from(record.peer_addr.as_deref().unwrap_or("-")),
+        details,
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::menu::render::render_table;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    #[tokio::test]
+    async fn test_state_file() {
+        let dir = std::env::temp_dir().join("crab_trap_test_state");
+        std::fs::remove_dir_all(&dir).unwrap_or_default();
+        let path = dir.join(STATE_FILE);
+
+        let mut state = StateFile::load(&path);
+        assert!(state.lost.is_empty());
+        state.listener = Some(ListenerRecord {
+            address: String::from("0.0.0.0"),
+            port: 4444,
+        });
+        let mut settings = Settings::default();
+        let mut shells = HashMap::new();
+        shells.insert(String::from("web"), spawn_shell_session(32449).await);
+        settings
+            .set(
+                crate::config::settings::Scope::Session,
+                Some("web"),
+                "max_line_width",
+                "200",
+            )
+            .unwrap();
+        state.save(&shells, &settings, 20).unwrap();
+
+        let state = StateFile::load(&path);
+        assert_eq!(state.listener.as_ref().unwrap().port, 4444);
+        assert_eq!(state.lost.len(), 1);
+        assert_eq!(state.lost[0].name, "web");
+        assert_eq!(state.lost[0].status, SessionStatus::Lost);
+        assert_eq!(
+            state.lost[0].settings,
+            vec![(String::from("max_line_width"), String::from("200"))]
+        );
+
+        std::fs::write(&path, "{ not json").unwrap();
+        let state = StateFile::load(&path);
+        assert!(state.lost.is_empty());
+        assert!(!path.exists());
+        let backups = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(backups, 1);
+        std::fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+
+    #[test]
+    fn test_session_row() {
+        let record = SessionRecord {
+            name: String::from("web"),
+            peer_addr: Some(String::from("10.0.0.5:50122")),
+            connected_at: 3600 * 14 + 62,
+            status: SessionStatus::Lost,
+            restored_from: Some(String::from("web~1")),
+            settings: Vec::new(),
+            notes: Vec::new(),
+            marks: Vec::new(),
+            transcript: None,
+            local_dir: None,
+            remote_cwd: Some(String::from("/var/www")),
+            close_reason: Some(CloseReason::ListenerShutdown),
+            protocol: None,
+        };
+        let row = session_row(&record);
+        assert_eq!(
+            row,
+            vec![
+                "web",
+                "lost",
+                "10.0.0.5:50122",
+                "connected 14:01:02 in /var/www closed by listener_shutdown (restored from web~1)"
+            ]
+        );
+        assert_eq!(
+            render_table(std::slice::from_ref(&row), &SESSION_COLUMNS, 200),
+            "web  lost  10.0.0.5:50122  connected 14:01:02 in /var/www closed by listener_shutdown (restored from web~1)\n"
+        );
+        let crawler = SessionRecord {
+            protocol: Some(String::from("http")),
+            ..record.clone()
+        };
+        assert!(
+            session_row(&crawler)[3].starts_with("connected 14:01:02 in /var/www speaking http")
+        );
+        // the details are cut before anything else
+        assert_eq!(
+            render_table(&[row], &SESSION_COLUMNS, 40),
+            "web  lost  10.0.0.5:50122  connected 14…\n"
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::config::{data_dir, Config, CONFIG_FILE};
+use crate::config::init::write_config;
+
+/// under the data dir, one directory per workspace
+pub const WORKSPACES_DIR: &str = "workspaces";
+
+pub const WORKSPACE_USAGE: &str =
+    "usage: workspace list | workspace new <name> | workspace use <name>";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorkspaceCommand {
+    List,
+    New(String),
+    Use(String),
+}
+
+pub fn parse_workspace_args(args: &str) -> Option<WorkspaceCommand> {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    return match words.as_slice() {
+        ["list"] | [] => Some(WorkspaceCommand::List),
+        ["new", name] => Some(WorkspaceCommand::New(String::from(*name))),
+        ["use", name] => Some(WorkspaceCommand::Use(String::from(*name))),
+        _ => None,
+    };
+}
+
+/// `~/.local/share/crab_trap/workspaces` on linux
+pub fn workspaces_root() -> PathBuf {
+    return data_dir().join(WORKSPACES_DIR);
+}
+
+/// Names are used as directory names, so letters, digits, `.`, `_` and `-` only
+pub fn valid_workspace_name(name: &str) -> bool {
+    return !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+}
+
+/// The workspace's config, its state file and history sit next to it
+pub fn workspace_config(root: &Path, name: &str) -> PathBuf {
+    return root.join(name).join(CONFIG_FILE);
+}
+
+/// The workspace a config file belongs to, none for a config outside `root`
+pub fn workspace_of(config_path: &Path, root: &Path) -> Option<String> {
+    let dir = config_path.parent()?;
+    if dir.parent()? != root {
+        return None;
+    }
+    return dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+}
+
+/// Every workspace under `root`, sorted
+pub fn list_workspaces(root: &Path) -> Vec<String> {
+    let mut names: Vec<String> = match fs::read_dir(root) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| workspace_config(root, name).exists())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    names.sort();
+    return names;
+}
+
+/// Makes a workspace whose config starts as a copy of `base`, with the logs,
+/// transcripts and loot moved inside the workspace. Returns its config's path
+pub fn create_workspace(root: &Path, name: &str, base: &Config) -> Result<PathBuf, String> {
+    if !valid_workspace_name(name) {
+        return Err(format!(
+            "{name} isn't a workspace name, use letters, digits, ., _ and -"
+        ));
+    }
+    let path = workspace_config(root, name);
+    if path.exists() {
+        return Err(format!("Workspace {name} already exists"));
+    }
+    let dir = root.join(name);
+    let config = Config {
+        log_dir: dir.join("logs"),
+        loot_dir: dir.join("loot"),
+        ..base.clone()
+    };
+    write_config(&path, &config)
+        .map_err(|err| format!("Couldn't create {}: {err}", dir.display()))?;
+    return Ok(path);
+}
+
+/// The config for `name`, creating the workspace from the config at `base` the first
+/// time it's used
+pub fn open_workspace(root: &Path, name: &str, base: &Path) -> Result<PathBuf, String> {
+    let path = workspace_config(root, name);
+    if path.exists() {
+        return Ok(path);
+    }
+    let base = match Config::load(base) {
+        Ok(config) => config.unwrap_or_default(),
+        Err(err) => return Err(format!("{err} in {}", base.display())),
+    };
+    return create_workspace(root, name, &base);
+}
+
+/// The command line arguments to start crab_trap again in `name`, in place of
+/// whichever workspace or config it was started with
+pub fn relaunch_args(args: &[String], name: &str) -> Vec<String> {
+    let mut kept = Vec::new();
+    let mut skip_value = false;
+    for arg in args {
+        if std::mem::take(&mut skip_value) {
+            continue;
+        }
+        match arg.as_str() {
+            "--workspace" | "--config" => skip_value = true,
+            _ if arg.starts_with("--workspace=") || arg.starts_with("--config=") => {}
+            _ => kept.push(arg.clone()),
+        }
+    }
+    kept.push(String::from("--workspace"));
+    kept.push(String::from(name));
+    return kept;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_workspace_args() {
+        assert_eq!(parse_workspace_args(""), Some(WorkspaceCommand::List));
+        assert_eq!(
+            parse_workspace_args("use acme-2024"),
+            Some(WorkspaceCommand::Use(String::from("acme-2024")))
+        );
+        assert_eq!(parse_workspace_args("new"), None);
+        assert!(valid_workspace_name("acme-2024"));
+        assert!(!valid_workspace_name("../acme"));
+        assert!(!valid_workspace_name(".hidden"));
+    }
+
+    #[test]
+    fn test_workspaces() {
+        let dir = std::env::temp_dir().join("crab_trap_test_workspaces");
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        let root = dir.join(WORKSPACES_DIR);
+        assert!(list_workspaces(&root).is_empty());
+
+        let base = Config {
+            listen_port: 9001,
+            ..Config::default()
+        };
+        let path = create_workspace(&root, "acme", &base).unwrap();
+        let config = Config::load(&path).unwrap().unwrap();
+        // the listener carries over, everything written to disk stays inside
+        assert_eq!(config.listen_port, 9001);
+        assert_eq!(config.log_dir, root.join("acme").join("logs"));
+        assert_eq!(config.loot_dir, root.join("acme").join("loot"));
+        assert!(create_workspace(&root, "acme", &base).is_err());
+        assert!(create_workspace(&root, "a/b", &base).is_err());
+
+        // opening one that doesn't exist yet makes it from the global config
+        let global = dir.join(CONFIG_FILE);
+        let opened = open_workspace(&root, "globex", &global).unwrap();
+        assert_eq!(open_workspace(&root, "globex", &global).unwrap(), opened);
+        assert_eq!(list_workspaces(&root), vec!["acme", "globex"]);
+        assert_eq!(workspace_of(&opened, &root), Some(String::from("globex")));
+        assert_eq!(workspace_of(&global, &root), None);
+        fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+
+    #[test]
+    fn test_relaunch_args() {
+        let args: Vec<String> = [
+            "--workspace",
+            "acme",
+            "--auto-restore",
+            "--config=x.toml",
+            "10.0.0.1",
+            "9001",
+        ]
+        .iter()
+        .map(|arg| String::from(*arg))
+        .collect();
+        assert_eq!(
+            relaunch_args(&args, "globex"),
+            vec![
+                "--auto-restore",
+                "10.0.0.1",
+                "9001",
+                "--workspace",
+                "globex"
+            ]
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CrabTrapError {
+    /// the session closed or the command didn't finish in time
+    NoResponse,
+    RemoteCopyFailed {
+        reason: String,
+    },
+    RemoteCommandFailed {
+        reason: String,
+    },
+    InvalidConfig {
+        reason: String,
+    },
+    UnknownSetting {
+        key: String,
+        suggestion: Option<String>,
+    },
+    InvalidSetting {
+        key: String,
+        reason: String,
+    },
+    InvalidScanTarget {
+        target: String,
+        reason: String,
+    },
+    SocketOption {
+        option: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for CrabTrapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            CrabTrapError::NoResponse => write!(f, "no response from the remote"),
+            CrabTrapError::RemoteCopyFailed { reason } => {
+                write!(f, "remote copy failed: {reason}")
+            }
+            CrabTrapError::RemoteCommandFailed { reason } => {
+                write!(f, "remote command failed: {reason}")
+            }
+            CrabTrapError::InvalidConfig { reason } => write!(f, "invalid config: {reason}"),
+            CrabTrapError::UnknownSetting { key, suggestion } => match suggestion {
+                Some(suggestion) => write!(f, "unknown setting {key}, did you mean {suggestion}?"),
+                None => write!(f, "unknown setting {key}"),
+            },
+            CrabTrapError::InvalidSetting { key, reason } => {
+                write!(f, "invalid value for {key}: {reason}")
+            }
+            CrabTrapError::InvalidScanTarget { target, reason } => {
+                write!(f, "invalid scan target {target}: {reason}")
+            }
+            CrabTrapError::SocketOption { option, reason } => {
+                write!(f, "couldn't set {option}: {reason}")
+            }
+        };
+    }
+}
+
+impl Error for CrabTrapError {}
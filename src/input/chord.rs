@@ -0,0 +1,188 @@
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, RepeatCount};
+use termion::event::Key;
+
+/// keys pressed after the prefix in raw mode and the session command they run
+pub const CHORD_BINDINGS: &[(char, &str)] = &[
+    ('d', "back"),
+    ('n', "next"),
+    ('p', "prev"),
+    ('c', "discard"),
+    ('?', "keys"),
+];
+
+pub const DEFAULT_CHORD_PREFIX: &str = "ctrl-b";
+
+pub const DEFAULT_CHORD_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// The byte a terminal sends for a key like `ctrl-b`
+pub fn ctrl_code(key: &str) -> Option<u8> {
+    let rest = key.strip_prefix("ctrl-")?;
+    let mut chars = rest.chars();
+    let c = chars.next()?;
+    if chars.next().is_some() || !c.is_ascii() {
+        return None;
+    }
+    return match c.to_ascii_uppercase() as u8 {
+        code @ b'@'..=b'_' => Some(code & 0x1f),
+        _ => None,
+    };
+}
+
+/// Turns a control byte back into its `ctrl-b` name
+pub fn ctrl_label(code: u8) -> String {
+    return format!("ctrl-{}", ((code | 0x40) as char).to_ascii_lowercase());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChordConfig {
+    pub prefix: u8,
+    /// how long after the prefix before it's sent to the remote as is
+    pub timeout: Duration,
+}
+
+impl Default for ChordConfig {
+    fn default() -> Self {
+        return ChordConfig {
+            prefix: ctrl_code(DEFAULT_CHORD_PREFIX).unwrap_or(0x02),
+            timeout: DEFAULT_CHORD_TIMEOUT,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chord {
+    /// send these bytes to the remote
+    Pass(Vec<u8>),
+    /// the prefix was pressed, waiting on the next key
+    Pending,
+    /// run this session command
+    Action(&'static str),
+}
+
+/// Watches raw mode keystrokes for the prefix key and the binding after it
+pub struct ChordReader {
+    config: ChordConfig,
+    pending_since: Option<Instant>,
+}
+
+impl ChordReader {
+    pub fn new(config: ChordConfig) -> ChordReader {
+        return ChordReader {
+            config,
+            pending_since: None,
+        };
+    }
+
+    pub fn feed(&mut self, key: &Key, bytes: &[u8], now: Instant) -> Chord {
+        let prefix = self.config.prefix;
+        if let Some(prefix_bytes) = self.expire(now) {
+            return Chord::Pass([prefix_bytes.as_slice(), bytes].concat());
+        }
+        if self.pending_since.take().is_none() {
+            if bytes == [prefix] {
+                self.pending_since = Some(now);
+                return Chord::Pending;
+            }
+            return Chord::Pass(bytes.to_vec());
+        }
+        // pressing the prefix twice sends it to the remote
+        if bytes == [prefix] {
+            return Chord::Pass(vec![prefix]);
+        }
+        if let Key::Char(c) = key {
+            if let Some((_, action)) = CHORD_BINDINGS.iter().find(|(bound, _)| bound == c) {
+                return Chord::Action(action);
+            }
+        }
+        return Chord::Pass([&[prefix], bytes].concat());
+    }
+
+    /// When a pending prefix runs out of time
+    pub fn deadline(&self) -> Option<Instant> {
+        return self.pending_since.map(|since| since + self.config.timeout);
+    }
+
+    /// Gives back the prefix byte once it has waited too long for a binding
+    pub fn expire(&mut self, now: Instant) -> Option<Vec<u8>> {
+        match self.deadline() {
+            Some(deadline) if now >= deadline => {
+                self.pending_since = None;
+                return Some(vec![self.config.prefix]);
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Line mode key like alt-n that asks to switch shells. Readline can't run a command
+/// itself so the key records the step and interrupts the line being read
+pub struct SwitchKey {
+    pub request: Arc<AtomicIsize>,
+    pub step: isize,
+}
+
+impl ConditionalEventHandler for SwitchKey {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        self.request.store(self.step, Ordering::SeqCst);
+        return Some(Cmd::Interrupt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ctrl_code() {
+        assert_eq!(ctrl_code("ctrl-b"), Some(0x02));
+        assert_eq!(ctrl_code("ctrl-]"), Some(0x1d));
+        assert_eq!(ctrl_code("ctrl-bb"), None);
+        assert_eq!(ctrl_code("b"), None);
+        assert_eq!(ctrl_label(0x02), "ctrl-b");
+        assert_eq!(ctrl_label(0x1d), "ctrl-]");
+    }
+
+    #[test]
+    fn test_chord_reader() {
+        let mut reader = ChordReader::new(ChordConfig::default());
+        let start = Instant::now();
+        let ctrl_b = Key::Ctrl('b');
+        assert_eq!(
+            reader.feed(&Key::Char('l'), b"l", start),
+            Chord::Pass(b"l".to_vec())
+        );
+        assert_eq!(reader.feed(&ctrl_b, &[0x02], start), Chord::Pending);
+        assert_eq!(
+            reader.feed(&Key::Char('d'), b"d", start),
+            Chord::Action("back")
+        );
+        // double press
+        reader.feed(&ctrl_b, &[0x02], start);
+        assert_eq!(
+            reader.feed(&ctrl_b, &[0x02], start),
+            Chord::Pass(vec![0x02])
+        );
+        // unbound keys go through with the prefix
+        reader.feed(&ctrl_b, &[0x02], start);
+        assert_eq!(
+            reader.feed(&Key::Char('x'), b"x", start),
+            Chord::Pass(vec![0x02, b'x'])
+        );
+        // timing out
+        reader.feed(&ctrl_b, &[0x02], start);
+        assert_eq!(reader.expire(start), None);
+        let later = start + DEFAULT_CHORD_TIMEOUT;
+        assert_eq!(reader.expire(later), Some(vec![0x02]));
+        assert_eq!(reader.deadline(), None);
+        // a key that shows up after the timeout but before it was noticed
+        reader.feed(&ctrl_b, &[0x02], start);
+        assert_eq!(
+            reader.feed(&Key::Char('d'), b"d", later),
+            Chord::Pass(vec![0x02, b'd'])
+        );
+    }
+}
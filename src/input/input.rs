@@ -25,9 +25,13 @@ use tokio::{
     task,
 };
 
+use crate::menu::terminal;
+
 #[derive(Helper, Hinter, Validator)]
 pub struct InputHelper {
     completer: Option<FilenameCompleter>,
+    /// completed as the first word of the line
+    commands: Vec<&'static str>,
     #[rustyline(Validator)]
     validator: MatchingBracketValidator,
     #[rustyline(Hinter)]
@@ -38,8 +42,8 @@ impl Highlighter for InputHelper {
     fn highlight_hint<'h>(&self, hint: &'h str) -> std::borrow::Cow<'h, str> {
         return format!(
             "{grey}{hint}{reset}",
-            grey = color::Fg(color::Rgb(100, 100, 100)),
-            reset = color::Fg(color::Reset)
+            grey = terminal::fg(color::Rgb(100, 100, 100), color::Rgb(150, 150, 150)),
+            reset = terminal::fg_reset()
         )
         .into();
     }
@@ -53,6 +57,21 @@ impl Completer for InputHelper {
         pos: usize,
         ctx: &rustyline::Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Self::Candidate>)> {
+        let word = &line[..pos];
+        if !word.is_empty() && !word.contains(char::is_whitespace) {
+            let matches: Vec<Pair> = self
+                .commands
+                .iter()
+                .filter(|cmd| cmd.starts_with(word))
+                .map(|cmd| Pair {
+                    display: String::from(*cmd),
+                    replacement: String::from(*cmd),
+                })
+                .collect();
+            if !matches.is_empty() {
+                return Ok((0, matches));
+            }
+        }
         return match &self.completer {
             Some(completer) => completer.complete(line, pos, ctx),
             None => Ok((0, Vec::new())),
@@ -60,18 +79,32 @@ impl Completer for InputHelper {
     }
 }
 
+impl Default for InputHelper {
+    fn default() -> Self {
+        return InputHelper::new();
+    }
+}
+
 impl InputHelper {
     pub fn new() -> InputHelper {
         let helper: InputHelper = InputHelper {
             completer: Some(FilenameCompleter::new()),
+            commands: Vec::new(),
             hinter: HistoryHinter {},
             validator: MatchingBracketValidator::new(),
         };
         return helper;
     }
+    /// Completes menu command names as well as files
+    pub fn with_commands(commands: Vec<&'static str>) -> InputHelper {
+        let mut helper = InputHelper::new();
+        helper.commands = commands;
+        return helper;
+    }
     pub fn new_only_hinter() -> InputHelper {
         let helper: InputHelper = InputHelper {
             completer: None,
+            commands: Vec::new(),
             hinter: HistoryHinter {},
             validator: MatchingBracketValidator::new(),
         };
@@ -81,25 +114,30 @@ impl InputHelper {
 
 pub fn display_notification(text: String) {
     let mut stdout = stdout();
+    // there's no top line to draw on, it goes where the output is
+    if !terminal::escapes() {
+        writeln!(stdout, "\n{text}").unwrap_or_default();
+        return;
+    }
     let notification = format!(
         "{goto}{clear}{success_bg}{success}{text}{reset}{reset_bg}",
         goto = cursor::Goto(1, 1),
         clear = clear::CurrentLine,
-        success = color::Fg(color::Red),
-        success_bg = color::Bg(color::Rgb(255, 200, 0)),
-        reset = color::Fg(color::Reset),
-        reset_bg = color::Bg(color::Reset),
+        success = terminal::fg(color::Red, color::Red),
+        success_bg = terminal::bg(color::Rgb(255, 200, 0), color::Rgb(255, 200, 0)),
+        reset = terminal::fg_reset(),
+        reset_bg = terminal::bg_reset(),
     );
 
     // save cursor position
-    stdout.write_all(&"\x1B7".as_bytes()).unwrap();
+    stdout.write_all("\x1B7".as_bytes()).unwrap();
     stdout.flush().unwrap();
 
-    stdout.write_all(&notification.as_bytes()).unwrap();
+    stdout.write_all(notification.as_bytes()).unwrap();
     stdout.flush().unwrap();
 
     // restore cursor position
-    stdout.write_all(&"\x1B8".as_bytes()).unwrap();
+    stdout.write_all("\x1B8".as_bytes()).unwrap();
     stdout.flush().unwrap();
 }
 
@@ -121,10 +159,7 @@ where
 
         let raw_content = reader.readline(&input_prompt);
 
-        let content = match raw_content {
-            Ok(line) => line,
-            Err(_) => String::from(""),
-        };
+        let content = raw_content.unwrap_or_default();
         reader.add_history_entry(content.clone()).unwrap();
         tx.send(content + "\n")
             .unwrap_or_else(|err| eprintln!("Error from readline handler: {err}"));
@@ -132,6 +167,23 @@ where
     rx.await
 }
 
+/// Reads a line from stdin without an editor, for when rustyline can't start. None
+/// once stdin is closed
+pub async fn read_plain_line(prompt: &str) -> Option<String> {
+    print!("{prompt}");
+    stdout().flush().unwrap_or_default();
+    return task::spawn_blocking(|| {
+        let mut line = String::new();
+        return match stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line),
+        };
+    })
+    .await
+    .ok()
+    .flatten();
+}
+
 pub async fn handle_key_input() -> Result<Option<(Key, Vec<u8>)>, RecvError> {
     let (tx, rx) = oneshot::channel::<Option<(Key, Vec<u8>)>>();
     task::spawn(async move {
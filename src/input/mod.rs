@@ -1 +1,3 @@
-pub mod input;
\ No newline at end of file
+pub mod chord;
+pub mod input;
+pub mod suggest;
@@ -0,0 +1,43 @@
+/// Levenshtein distance between two words
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    return prev[b.len()];
+}
+
+/// Picks the candidate closest to `word`, if any is close enough to be a likely typo
+pub fn closest_match<'a, I>(word: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    // allow roughly one mistake per three characters
+    let max_distance = (word.chars().count() / 3).max(1);
+    return candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_closest_match() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        let words = ["theme", "transcripts", "escape_key"];
+        assert_eq!(closest_match("thme", words), Some("theme"));
+        assert_eq!(closest_match("transcript", words), Some("transcripts"));
+        assert_eq!(closest_match("colour", words), None);
+    }
+}
@@ -0,0 +1,9 @@
+#![allow(clippy::needless_return, clippy::module_inception)]
+
+pub mod config;
+pub mod error;
+pub mod input;
+pub mod menu;
+pub mod recon;
+pub mod remote;
+pub mod socket;
@@ -1,29 +1,61 @@
+#![allow(clippy::needless_return, clippy::module_inception)]
+
+mod cli;
+
 use std::collections::HashMap;
 use std::env::{self, set_current_dir};
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
-use input::input::{read_line, InputHelper};
-use menu::menu_list::clear;
+use clap::Parser;
+use cli::{write_completions, Cli, Commands};
+use crab_trap::config::config::{self as app_config, config_path, SPILL_DIR};
+use crab_trap::config::doctor::{render_check, run_checks, CheckStatus};
+use crab_trap::config::ephemeral::{self, is_ephemeral, EPHEMERAL_WARNING};
+use crab_trap::config::init::{confirm, init};
+use crab_trap::config::workspace::{open_workspace, workspaces_root};
+use crab_trap::input::input::{read_line, read_plain_line, InputHelper};
+use crab_trap::menu::menu_list::clear;
 use rustyline::history::MemHistory;
 use rustyline::{CompletionType, Config, Editor};
 use std::process::{exit, Command};
 use termion::raw::IntoRawMode;
 
-use crate::input::input::display_notification;
-use crate::menu::menu_list;
-use crate::socket::{connection, listener};
 use connection::{handle_new_shell, Handle};
-use futures_util::pin_mut;
-use futures_util::stream::StreamExt;
-use std::io::stdout;
+use crab_trap::config::profiles::{load_profiles, profiles_dir, DeviceProfile};
+use crab_trap::config::settings::{Scope, Settings, SharedSettings};
+use crab_trap::config::state::{save_state, state_path, ListenerRecord, SharedState, StateFile};
+use crab_trap::input::input::display_notification;
+use crab_trap::menu::commands::{command_names, suggest_command};
+use crab_trap::menu::menu_list;
+use crab_trap::menu::output::DEFAULT_MAX_LINE_WIDTH;
+use crab_trap::menu::terminal;
+use crab_trap::menu::title::{menu_title, set_title};
+use crab_trap::socket::capture::{load_bundle, render_bundle};
+use crab_trap::socket::close::CloseReason;
+use crab_trap::socket::connection;
+use crab_trap::socket::control::serve_control;
+use crab_trap::socket::dial::{DialTarget, Dialed, RedialStatus};
+use crab_trap::socket::exec::shell_quote;
+use crab_trap::socket::greet::Greeting;
+use crab_trap::socket::history::EventKind;
+use crab_trap::socket::listener::{self, AuthDecision, Listener};
+use crab_trap::socket::pipe::{adopt_pipe, PIPE_REOPEN_WINDOW};
+use crab_trap::socket::reconnect::{find_previous_session, restore_session};
+use crab_trap::socket::retention::{sweep, SWEEP_INTERVAL};
+use crab_trap::socket::sniff::{guess_protocol, reject_tls, ProtocolHint};
+use crab_trap::socket::transcript::flush_open_transcripts;
+use std::io::{stdin, stdout, Write};
 use termion::{self, color};
+use tokio::net::TcpStream;
+use tokio::select;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tokio::sync::Mutex;
+use tokio::time::sleep;
 
-mod input;
-mod menu;
-mod socket;
-
-fn get_prompt() -> (String, String) {
+fn get_prompt(workspace: Option<&str>) -> (String, String) {
     let mut pwd = match env::current_dir() {
         Ok(path) => String::from(path.to_str().unwrap_or("")),
         Err(_) => String::from(""),
@@ -33,61 +65,127 @@ fn get_prompt() -> (String, String) {
         None => String::from(""),
     };
     pwd = pwd.replace(&home, "~");
+    let workspace = match workspace {
+        Some(name) => format!(" [{name}]"),
+        None => String::new(),
+    };
     let prompt = format!(
-        "{red}crab_trap 🦀:{pwd} #{reset} ",
-        red = color::Fg(color::LightRed),
-        reset = color::Fg(color::Reset)
+        "{red}crab_trap 🦀{workspace}:{pwd} #{reset} ",
+        red = terminal::fg(color::LightRed, color::Red),
+        reset = terminal::fg_reset()
     );
     return (prompt, home);
 }
 
+/// Checks whether the local shell knows a command, so typos of menu commands can be caught
+fn is_local_command(name: &str) -> bool {
+    return Command::new("sh")
+        .arg("-c")
+        .arg(format!("command -v {} >/dev/null 2>&1", shell_quote(name)))
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false);
+}
+
+/// The menu command a line was probably meant to be, unless it's a real local command
+fn typo_suggestion(name: &str, settings: &SharedSettings) -> Option<&'static str> {
+    let intercept = match settings.lock() {
+        Ok(settings) => settings.get_bool("intercept_typos", None),
+        Err(_) => false,
+    };
+    if !intercept {
+        return None;
+    }
+    let suggestion = suggest_command(name)?;
+    if is_local_command(name) {
+        return None;
+    }
+    return Some(suggestion);
+}
+
 fn input_loop(
     shells: Arc<Mutex<HashMap<String, Handle>>>,
     menu: menu_list::MenuList,
+    settings: SharedSettings,
+    workspace: Option<String>,
     init_message: Option<String>,
+    dashboard: bool,
 ) {
     tokio::spawn(async move {
         let history = MemHistory::new();
         let mut builder = Config::builder();
         builder = builder.completion_type(CompletionType::Circular);
         let config = builder.build();
-        let mut rl = Editor::with_history(config, history).unwrap();
-        let helper = InputHelper::new();
-        rl.set_helper(Some(helper));
-        let menu_rl = Arc::new(Mutex::new(rl));
+        // without an editor the menu still reads plain lines, like a piped stdin
+        let menu_rl = match Editor::with_history(config, history) {
+            Ok(mut rl) => {
+                rl.set_helper(Some(InputHelper::with_commands(command_names())));
+                Some(Arc::new(Mutex::new(rl)))
+            }
+            Err(err) => {
+                eprintln!("Error starting the line editor, reading plain lines instead: {err}");
+                None
+            }
+        };
         clear();
-        if init_message.is_some() {
-            let msg = init_message.unwrap();
+        if let Some(msg) = init_message {
             println!("{msg}\n");
         }
 
+        // quitting the dashboard leaves the classic prompt underneath
+        if let Some(entry) = menu.get("ui").filter(|_| dashboard) {
+            if let Some(join_handle) = entry(shells.clone(), String::new()) {
+                join_handle.await.unwrap_or_default();
+            }
+        }
         menu_list::help();
         loop {
-            let stdout = stdout().into_raw_mode().unwrap();
-            stdout.suspend_raw_mode().unwrap();
-            let (prompt, home) = get_prompt();
-            let content = match read_line(menu_rl.clone(), Some(&prompt)).await {
-                Ok(line) => line,
-                Err(_) => continue,
+            // puts the terminal back in cooked mode if a shell left it raw
+            if let Ok(stdout) = stdout().into_raw_mode() {
+                stdout.suspend_raw_mode().unwrap_or_default();
+            }
+            let open = shells
+                .lock()
+                .await
+                .values()
+                .filter(|handle| !handle.is_closed())
+                .count();
+            set_title(&settings, &menu_title(open));
+            let (prompt, home) = get_prompt(workspace.as_deref());
+            let content = match &menu_rl {
+                Some(rl) => match read_line(rl.clone(), Some(&prompt)).await {
+                    Ok(line) => line,
+                    Err(_) => continue,
+                },
+                None => match read_plain_line(&prompt).await {
+                    Some(line) => line,
+                    None => {
+                        eprintln!("The menu's input closed, shells are still accepted");
+                        return;
+                    }
+                },
             };
 
             let key = String::from(content.trim_end());
+            let (name, entry_args) = key.split_once(' ').unwrap_or((&key, ""));
 
-            let entry = match menu.get(&*key) {
+            let entry = match menu.get(name) {
                 Some(val) => val,
                 None => {
                     // need to handle cd differently
                     if key.starts_with("cd ") {
                         let mut dir = String::from(key.split(" ").last().unwrap_or_default());
-                        if dir.len() <= 0 {
+                        if dir.is_empty() {
                             continue;
                         }
                         dir = dir.replace("~", &home);
-                        let err = set_current_dir(dir).err();
-                        if err.is_some() {
-                            let display_err = err.unwrap();
+                        if let Err(display_err) = set_current_dir(dir) {
                             println!("error changing directories: {display_err}");
                         }
+                    } else if let Some(suggestion) = typo_suggestion(name, &settings) {
+                        println!(
+                            "Unknown command {name}, did you mean {suggestion}? Enter help to list the commands"
+                        );
                     } else {
                         Command::new("sh")
                             .arg("-c")
@@ -101,70 +199,597 @@ fn input_loop(
                 }
             };
 
-            let handle_opt = entry(shells.clone());
-            if handle_opt.is_some() {
-                let join_handle = handle_opt.unwrap();
+            if let Some(join_handle) = entry(shells.clone(), String::from(entry_args)) {
                 join_handle.await.unwrap_or_default();
             }
         }
     });
 }
 
+/// Loads the config, offering to create one when running for the first time
+fn load_config(path: &Path) -> Result<app_config::Config, String> {
+    match app_config::Config::load(path) {
+        Ok(Some(config)) => return Ok(config),
+        Ok(None) => {}
+        Err(err) => return Err(format!("{err} in {}", path.display())),
+    }
+    if !termion::is_tty(&stdin()) || is_ephemeral() {
+        return Ok(app_config::Config::default());
+    }
+    let mut input = stdin().lock();
+    let mut output = stdout();
+    let question = "No config found, run the setup now?";
+    if !confirm(&mut input, &mut output, question).unwrap_or(false) {
+        return Ok(app_config::Config::default());
+    }
+    return match init(&mut input, &mut output, path, false) {
+        Ok(config) => Ok(config.unwrap_or_default()),
+        Err(err) => Err(format!("couldn't write {}: {err}", path.display())),
+    };
+}
+
+/// What the accept loop picked up next
+enum Arrival {
+    /// a connection nothing has been checked on yet, with whether to skip the echo
+    /// check, the target it was dialed from, what its listener admitted it with and
+    /// what its first bytes looked like
+    Fresh(
+        TcpStream,
+        Option<bool>,
+        Option<(DialTarget, Option<String>)>,
+        Option<String>,
+        ProtocolHint,
+    ),
+    /// a session whose greeting finished off the loop
+    Greeted(Greeted),
+}
+
+/// A session in the trap, ready for its settings and notification
+struct Greeted {
+    soc_key: String,
+    greeting: Option<Greeting>,
+    dialed: Option<(DialTarget, Option<String>)>,
+    admitted: Option<String>,
+    protocol: ProtocolHint,
+}
+
+/// Works out what a new session is in the background, so a slow banner or profile
+/// doesn't hold up other connections. It comes back to the accept loop when it's done
+fn spawn_greeting(
+    handle: Handle,
+    mut greeted: Greeted,
+    connected_shells: Arc<Mutex<HashMap<String, Handle>>>,
+    profiles: Arc<Vec<DeviceProfile>>,
+    selected_profile: Option<DeviceProfile>,
+    greeted_tx: UnboundedSender<Greeted>,
+) {
+    tokio::spawn(async move {
+        let soc_key = greeted.soc_key.clone();
+        greeted.greeting = handle
+            .greet(&soc_key, &profiles, selected_profile.as_ref())
+            .await;
+        if greeted.greeting.is_none() {
+            handle.mark_closed(CloseReason::HandshakeFailure);
+            connected_shells.lock().await.remove(&soc_key);
+            if let Some((target, _)) = &greeted.dialed {
+                display_notification(format!(
+                    "{}:{} didn't answer like a shell",
+                    target.host, target.port
+                ));
+            }
+            return;
+        }
+        greeted_tx.send(greeted).unwrap_or_default();
+    });
+}
+
 #[tokio::main]
 async fn main() {
-    let args: Vec<String> = env::args().collect();
-    let bound_addr: String;
-    let bound_port: u16;
-    if args.len() == 3 {
-        bound_addr = args[1].clone();
-        bound_port = match args[2].parse::<u16>() {
+    let cli = Cli::parse();
+    let path = match &cli.workspace {
+        Some(name) => match open_workspace(&workspaces_root(), name, &config_path()) {
             Ok(val) => val,
-            Err(_) => {
-                println!("[-] Invalid port {}", args[2]);
-                println!("Usage: {0} OR {0} <address> <port>", args[0]);
-                return;
+            Err(err) => {
+                println!("[-] {err}");
+                exit(1);
             }
-        };
-    } else if args.len() == 1 {
-        bound_addr = String::from("0.0.0.0");
-        bound_port = 4545;
-    } else {
-        println!("Usage: {0} OR {0} <address> <port>", args[0]);
-        return;
+        },
+        None => cli.config.clone().unwrap_or_else(config_path),
+    };
+    let (mut cli_address, mut cli_port) = (cli.address.clone(), cli.port);
+    let mut dashboard = false;
+    match cli.command {
+        Some(Commands::Init { defaults }) => {
+            if let Err(err) = init(&mut stdin().lock(), &mut stdout(), &path, defaults) {
+                println!("[-] Couldn't write {}: {err}", path.display());
+                exit(1);
+            }
+            return;
+        }
+        Some(Commands::Doctor) => {
+            let results = run_checks(&path);
+            for result in &results {
+                print!("{}", render_check(result));
+            }
+            if results
+                .iter()
+                .any(|result| result.status == CheckStatus::Fail)
+            {
+                exit(1);
+            }
+            return;
+        }
+        Some(Commands::Completions { shell }) => {
+            write_completions(shell, &mut stdout());
+            return;
+        }
+        Some(Commands::RenderDebug { bundle, raw }) => {
+            let loaded = match load_bundle(&bundle) {
+                Ok(val) => val,
+                Err(err) => {
+                    println!("[-] Couldn't read {}: {err}", bundle.display());
+                    exit(1);
+                }
+            };
+            let mut out = stdout();
+            for (at_ms, shown) in render_bundle(&loaded) {
+                match raw {
+                    true => out.write_all(shown.as_bytes()).unwrap_or_default(),
+                    false => writeln!(out, "{at_ms:>8}ms {shown:?}").unwrap_or_default(),
+                }
+            }
+            return;
+        }
+        Some(Commands::Ui { address, port }) => {
+            dashboard = true;
+            (cli_address, cli_port) = (address, port);
+        }
+        None => {}
+    }
+    if cli.ephemeral {
+        ephemeral::enable();
+        eprintln!(
+            "{red}{EPHEMERAL_WARNING}{reset}",
+            red = terminal::fg(color::LightRed, color::Red),
+            reset = terminal::fg_reset()
+        );
+        // a kill or a closed terminal skips the exit path, the scrub still has to run
+        tokio::spawn(async {
+            let (mut term, mut hup) = match (
+                signal(SignalKind::terminate()),
+                signal(SignalKind::hangup()),
+            ) {
+                (Ok(term), Ok(hup)) => (term, hup),
+                _ => return,
+            };
+            select! {
+                _ = term.recv() => {}
+                _ = hup.recv() => {}
+            }
+            ephemeral::scrub();
+            exit(1);
+        });
+    }
+    let config = match load_config(&path) {
+        Ok(val) => val,
+        Err(err) => {
+            println!("[-] {err}");
+            return;
+        }
+    };
+    // keep what the transcripts already have if crab_trap goes down
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if !listener::panic_is_caught() {
+            flush_open_transcripts();
+            ephemeral::scrub();
+        }
+        default_hook(info);
+    }));
+    // the command line sets the listener scope, over the config's global values
+    let mut settings = Settings::from_config(&config);
+    let mut cli_settings = Vec::new();
+    if cli.auto_restore {
+        cli_settings.push(("auto_restore", String::from("true")));
+    }
+    if let Some(count) = cli.keep_closed {
+        cli_settings.push(("keep_closed", count.to_string()));
+    }
+    if let Some(mins) = cli.keep_closed_mins {
+        cli_settings.push(("keep_closed_mins", mins.to_string()));
+    }
+    if cli.ephemeral {
+        cli_settings.push(("transcripts", String::from("false")));
+        cli_settings.push(("spill_output", String::from("false")));
+    }
+    for (key, value) in cli_settings {
+        settings
+            .set(Scope::Listener, None, key, &value)
+            .unwrap_or_default();
+    }
+    terminal::set_theme(settings.get_theme());
+    let settings: SharedSettings = Arc::new(std::sync::Mutex::new(settings));
+    let (profiles, profile_errors) = load_profiles(&profiles_dir());
+    for err in profile_errors {
+        println!("[-] skipping profile {err}");
+    }
+    let selected_profile = match &cli.profile {
+        Some(name) => match profiles.iter().find(|profile| &profile.name == name) {
+            Some(val) => Some(val.clone()),
+            None => {
+                println!(
+                    "[-] no profile named {name} in {}",
+                    profiles_dir().display()
+                );
+                exit(1);
+            }
+        },
+        None => None,
+    };
+    let profiles = Arc::new(profiles);
+    // spill files belong to sessions, nothing left over from the last run is wanted
+    if !is_ephemeral() {
+        std::fs::remove_dir_all(config.log_dir.join(SPILL_DIR)).unwrap_or_default();
     }
+    let bound_addr = cli_address.unwrap_or(config.listen_address);
+    let bound_port = cli_port.unwrap_or(config.listen_port);
     let connected_shells = Arc::new(Mutex::new(HashMap::<String, Handle>::new()));
-    let menu = menu_list::new();
+    // sessions from the last run can't be reconnected but what's known about them is kept
+    let mut state = StateFile::load(&state_path(&path));
+    let lost = state.lost.len();
+    state.listener = Some(ListenerRecord {
+        address: bound_addr.clone(),
+        port: bound_port,
+    });
+    let state: SharedState = Arc::new(std::sync::Mutex::new(state));
+    save_state(&state, &HashMap::new(), &settings);
+    // shells dialed from the menu come back to the loop below to be set up
+    let (dial_tx, mut dial_rx) = unbounded_channel::<Dialed>();
+    // and so do ones greeted in the background
+    let (greeted_tx, mut greeted_rx) = unbounded_channel::<Greeted>();
+    let menu = menu_list::new(
+        settings.clone(),
+        path.clone(),
+        config.loot_dir.clone(),
+        state.clone(),
+        dial_tx.clone(),
+    );
+
+    if let Some(control) = cli.control.clone() {
+        let control_shells = connected_shells.clone();
+        tokio::spawn(async move {
+            if let Err(err) = serve_control(&control, control_shells).await {
+                eprintln!("\nError serving {}: {err}", control.display());
+            }
+        });
+    }
+
+    // sweep closed shells in the background
+    let sweep_shells = connected_shells.clone();
+    let sweep_settings = settings.clone();
+    let sweep_state = state.clone();
+    tokio::spawn(async move {
+        loop {
+            sleep(SWEEP_INTERVAL).await;
+            let retention = match sweep_settings.lock() {
+                Ok(settings) => settings.retention(),
+                Err(_) => continue,
+            };
+            let mut shells = sweep_shells.lock().await;
+            sweep(&mut shells, &retention).await;
+            save_state(&sweep_state, &shells, &sweep_settings);
+        }
+    });
 
     // get user input
-    let init_message = format!(
+    let caps = terminal::init();
+    let mut init_message = format!(
         "{red}listening on {bound_addr}:{bound_port}{reset}",
-        red = color::Fg(color::LightRed),
-        reset = color::Fg(color::Reset)
+        red = terminal::fg(color::LightRed, color::Red),
+        reset = terminal::fg_reset()
     );
-    input_loop(connected_shells.clone(), menu, Some(init_message));
-    let socket_stream = listener::catch_sockets(bound_addr.clone(), bound_port);
-    pin_mut!(socket_stream);
-
-    loop {
-        let soc = match socket_stream.next().await.unwrap() {
-            Ok(val) => val,
-            Err(_) => {
-                eprintln!("\nError address already in use {bound_addr}:{bound_port}");
+    if let Some(name) = &cli.workspace {
+        init_message += &format!(" in workspace {name}");
+    }
+    if lost > 0 {
+        init_message += &format!("\n{lost} sessions were lost when crab_trap last stopped, enter sessions --all to list them");
+    }
+    if !caps.raw {
+        init_message += "\nraw mode isn't available in this terminal, shells attach in line mode";
+    }
+    input_loop(
+        connected_shells.clone(),
+        menu,
+        settings.clone(),
+        cli.workspace.clone(),
+        Some(init_message),
+        dashboard,
+    );
+    let mut socket_listener = match Listener::bind(&bound_addr, bound_port).await {
+        Ok(val) => val,
+        Err(_) => {
+            eprintln!("\nError address already in use {bound_addr}:{bound_port}");
+            exit(1)
+        }
+    };
+    // a stager expecting TLS would only fill a session with handshake bytes
+    socket_listener.set_authenticator(|info, bytes| {
+        let decision = reject_tls(info, bytes);
+        if let AuthDecision::Reject(_) = decision {
+            display_notification(format!(
+                "{} started a TLS handshake, only plaintext shells are taken",
+                info.peer
+            ));
+        }
+        return decision;
+    });
+    let mut adopted = match &cli.pipe {
+        Some((input, output)) => match adopt_pipe(input, output, PIPE_REOPEN_WINDOW).await {
+            Ok(val) => Some(val),
+            Err(err) => {
+                eprintln!("\nError opening {}: {err}", input.display());
                 exit(1)
             }
+        },
+        None => None,
+    };
+
+    loop {
+        // the other tool already has a shell, the echo check would only end up in its pipe
+        if let Ok(settings) = settings.lock() {
+            let wait = settings.get_number("sniff_timeout_ms", None);
+            socket_listener.set_auth_window(Duration::from_millis(wait));
+        }
+        let arrival = match adopted.take() {
+            Some(val) => Arrival::Fresh(val, Some(true), None, None, ProtocolHint::Shell),
+            None => select! {
+                soc = socket_listener.accept() => match soc {
+                    Ok(val) => {
+                        let protocol = guess_protocol(&val.first_bytes);
+                        Arrival::Fresh(val.soc, None, None, val.metadata, protocol)
+                    }
+                    Err(err) => {
+                        eprintln!("\nError accepting on {bound_addr}:{bound_port}: {err}");
+                        exit(1)
+                    }
+                },
+                Some(dialed) = dial_rx.recv() => {
+                    let target = Some((dialed.target, dialed.previous));
+                    Arrival::Fresh(dialed.soc, None, target, None, ProtocolHint::Shell)
+                }
+                Some(greeted) = greeted_rx.recv() => Arrival::Greeted(greeted),
+            },
         };
+        let greeted = match arrival {
+            Arrival::Greeted(greeted) => greeted,
+            Arrival::Fresh(soc, skip_validation, dialed, admitted, protocol) => {
+                // the echo check and profiles would only spray shell commands at a web crawler
+                let skip_validation = match protocol {
+                    ProtocolHint::Shell => skip_validation,
+                    _ => Some(true),
+                };
 
-        let soc_added = handle_new_shell(soc, connected_shells.clone(), None).await;
+                // with profiles about, the banner decides whether the echo check or a profile runs
+                let greet = skip_validation.is_none() && !profiles.is_empty();
+                let skip_validation = match greet {
+                    true => Some(true),
+                    false => skip_validation,
+                };
+                let shells = connected_shells.clone();
+                let soc_key = match handle_new_shell(soc, shells, skip_validation).await {
+                    Some(val) => val,
+                    None => {
+                        if let Some((target, _)) = &dialed {
+                            display_notification(format!(
+                                "{}:{} didn't answer like a shell",
+                                target.host, target.port
+                            ));
+                        }
+                        continue;
+                    }
+                };
+                let greeted = Greeted {
+                    soc_key,
+                    greeting: None,
+                    dialed,
+                    admitted,
+                    protocol,
+                };
+                let handle = connected_shells.lock().await.get(&greeted.soc_key).cloned();
+                match handle {
+                    // profile steps can take a while, other shells are taken meanwhile
+                    Some(handle) if greet => {
+                        spawn_greeting(
+                            handle,
+                            greeted,
+                            connected_shells.clone(),
+                            profiles.clone(),
+                            selected_profile.clone(),
+                            greeted_tx.clone(),
+                        );
+                        continue;
+                    }
+                    _ => greeted,
+                }
+            }
+        };
+        let Greeted {
+            soc_key,
+            greeting,
+            dialed,
+            admitted,
+            protocol,
+        } = greeted;
+        let profile_message = match &greeting {
+            Some(Greeting::Ready(profile)) => Some(format!(" {} profile ran", profile.name)),
+            Some(Greeting::Manual(profile, reason)) => Some(format!(
+                " {} profile stopped, {reason}, attach to finish by hand",
+                profile.name
+            )),
+            _ => None,
+        };
+        if let Some(Greeting::Ready(profile) | Greeting::Manual(profile, _)) = &greeting {
+            if let Ok(mut settings) = settings.lock() {
+                for (key, value) in &profile.settings {
+                    settings
+                        .set(Scope::Session, Some(&soc_key), key, value)
+                        .unwrap_or_default();
+                }
+            }
+        }
 
-        if soc_added {
-            let num_shells = connected_shells.lock().await.len();
-            display_notification(format!(
-                "{num_shells} shell{plural} in trap!",
-                plural = match num_shells {
-                    1 => "",
-                    _ => "s",
+        let mut shells = connected_shells.lock().await;
+        let (auto_restore, max_line_width, spill_kb, transcripts, fsync_ms) = match settings.lock()
+        {
+            Ok(settings) => (
+                settings.get_bool("auto_restore", Some(&soc_key)),
+                settings.get_number("max_line_width", Some(&soc_key)) as usize,
+                match settings.get_bool("spill_output", None) {
+                    true => Some(settings.get_number("spill_max_kb", None)),
+                    false => None,
+                },
+                settings.get_bool("transcripts", Some(&soc_key)),
+                settings.get_number("transcript_fsync_ms", None),
+            ),
+            Err(_) => (false, DEFAULT_MAX_LINE_WIDTH, None, false, 1000),
+        };
+        if let Some(handle) = shells.get_mut(&soc_key) {
+            handle.max_line_width = max_line_width;
+            match &greeting {
+                Some(Greeting::Ready(profile)) => {
+                    handle.raw_mode = profile.raw;
+                    handle.record(EventKind::Note, &format!("{} profile ran", profile.name));
                 }
-            ));
+                // whatever the device wants next goes to it as typed
+                Some(Greeting::Manual(profile, reason)) => {
+                    handle.raw_mode = true;
+                    handle.record(
+                        EventKind::Note,
+                        &format!("{} profile stopped: {reason}", profile.name),
+                    );
+                }
+                _ => {}
+            }
+            if let Some(metadata) = &admitted {
+                handle.record(EventKind::Note, &format!("admitted: {metadata}"));
+            }
+            handle.sniffed_protocol = protocol;
+            if protocol != ProtocolHint::Shell {
+                handle.record(
+                    EventKind::Note,
+                    &format!("first bytes look like {}", protocol.name()),
+                );
+            }
+            handle.dial_target = dialed.as_ref().map(|(target, _)| target.clone());
+            if let Some(kb) = spill_kb {
+                let path = config
+                    .log_dir
+                    .join(SPILL_DIR)
+                    .join(format!("{soc_key}.out"));
+                handle.spill_to(path, kb * 1024);
+            }
+            if transcripts {
+                let path = config.log_dir.join(format!("{soc_key}.jsonl"));
+                let fsync_interval = Duration::from_millis(fsync_ms);
+                if let Err(err) = handle.transcribe_to(path, &soc_key, fsync_interval) {
+                    display_notification(format!(
+                        "couldn't open a transcript for {soc_key}: {err}"
+                    ));
+                }
+            }
+        }
+        let num_shells = shells.values().filter(|shell| !shell.is_closed()).count();
+        let mut notification = format!(
+            "{num_shells} shell{plural} in trap!",
+            plural = match num_shells {
+                1 => "",
+                _ => "s",
+            }
+        );
+        if let Some(message) = profile_message {
+            notification += &message;
+        }
+        if protocol != ProtocolHint::Shell {
+            notification += &format!(
+                " {soc_key} looks like {} rather than a shell, nothing was sent to it and it's shown as hex",
+                protocol.name()
+            );
+        }
+        let mut session_key = soc_key.clone();
+        if let Some((_, Some(previous))) = &dialed {
+            // a redial always carries on as the session it was made for
+            if let Some(name) = restore_session(&mut shells, &soc_key, previous).await {
+                notification += &format!(" {name} was redialed");
+                session_key = name;
+            }
+        } else if protocol != ProtocolHint::Shell {
+            // a crawler from the same address isn't the shell that was lost
+        } else if let Some(previous) = find_previous_session(&shells, &soc_key).await {
+            if auto_restore {
+                restore_session(&mut shells, &soc_key, &previous).await;
+                notification += &format!(" {previous} reconnected and was restored");
+            } else {
+                notification += &format!(" {previous} reconnected, enter restore to resume it");
+            }
+        }
+        save_state(&state, &shells, &settings);
+        display_notification(notification);
+        if let Some(handle) = shells.get(&session_key).cloned() {
+            let watch_shells = connected_shells.clone();
+            let dial_tx = dial_tx.clone();
+            tokio::spawn(async move {
+                handle.soc_kill_token.cancelled().await;
+                handle.flush_transcript().await;
+                let reason = match handle.close_reason() {
+                    Some(val) => val,
+                    None => return,
+                };
+                // it may have been restored under another name since
+                let name = watch_shells
+                    .lock()
+                    .await
+                    .iter()
+                    .find(|(_, other)| other.same_session(&handle))
+                    .map(|(name, _)| name.clone());
+                let name = match name {
+                    Some(val) => val,
+                    None => return,
+                };
+                // a killed session stays closed, anything else it was dialed for is tried again
+                let redial = match &handle.dial_target {
+                    Some(target) if reason != CloseReason::OperatorKill => {
+                        target.redial.map(|redial| (target.clone(), redial))
+                    }
+                    _ => None,
+                };
+                let (target, redial) = match redial {
+                    Some(val) => val,
+                    None => {
+                        display_notification(format!("{name} closed: {reason}"));
+                        return;
+                    }
+                };
+                display_notification(format!(
+                    "{name} closed: {reason}, redialing {}:{}",
+                    target.host, target.port
+                ));
+                match handle.redial(&target, &redial).await {
+                    Some(soc) => dial_tx
+                        .send(Dialed {
+                            soc,
+                            target,
+                            previous: Some(name),
+                        })
+                        .unwrap_or_default(),
+                    None => {
+                        if let RedialStatus::GaveUp { attempts } = handle.redial_status() {
+                            display_notification(format!(
+                                "gave up redialing {name} after {attempts} attempts"
+                            ));
+                        }
+                    }
+                }
+            });
         }
     }
 }
@@ -175,9 +800,11 @@ mod tests {
 
     #[test]
     fn test_prompt() {
-        let (prompt, home) = get_prompt();
+        let (prompt, home) = get_prompt(None);
         assert!(prompt
             .starts_with(format!("{red}crab_trap 🦀:", red = color::Fg(color::LightRed)).as_str()));
         assert_ne!(home, "");
+        let (prompt, _) = get_prompt(Some("acme"));
+        assert!(prompt.contains("crab_trap 🦀 [acme]:"));
     }
 }
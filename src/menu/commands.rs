@@ -0,0 +1,1531 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{stdin, stdout, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use termion::clear;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::config::Config;
+use crate::config::ephemeral;
+use crate::config::init::write_config;
+use crate::config::loot::{
+    last_output, loot_row, parse_loot_args, show_loot, LootCommand, LootFrom, LootStore,
+    LOOT_COLUMNS, LOOT_USAGE,
+};
+use crate::config::profiles::{
+    load_profiles, profile_row, profiles_dir, test_profile, PROFILES_USAGE, PROFILE_COLUMNS,
+};
+use crate::config::settings::{lookup, parse_set_args, Scope, SharedSettings, SETTINGS, SET_USAGE};
+use crate::config::state::{save_state, session_row, SessionRecord, SharedState, SESSION_COLUMNS};
+use crate::config::workspace::{
+    create_workspace, list_workspaces, parse_workspace_args, relaunch_args, workspace_config,
+    workspace_of, workspaces_root, WorkspaceCommand, WORKSPACE_USAGE,
+};
+use crate::input::suggest::closest_match;
+use crate::menu::dashboard;
+use crate::menu::render::{hanging, render_table, terminal_width};
+use crate::menu::terminal;
+use crate::menu::timeline::{
+    filter_origin, filter_since, format_clock, parse_timeline_args, render_timeline, TIMELINE_USAGE,
+};
+use crate::remote::batch::{
+    batch_json, parse_run_all_args, render_batch, run_batch, RUN_ALL_USAGE,
+};
+use crate::remote::upload::{parse_upload_args, UPLOAD_USAGE};
+use crate::remote::watch::{parse_watch_args, DiffLine, WatchCommand, WatchReport, WATCH_USAGE};
+use crate::socket::connection::Handle;
+use crate::socket::dial::{dial, parse_connect_args, DialSender, Dialed, CONNECT_USAGE};
+use crate::socket::history::{now_secs, EventKind};
+use crate::socket::local_dir::startup_dir;
+use crate::socket::marks::{
+    parse_save_args, save_marked, SaveArgs, SessionMark, MARK_USAGE, SAVE_USAGE,
+};
+use crate::socket::notes::{edit_notes, parse_note_args, NoteAction, SessionNote, NOTE_USAGE};
+use crate::socket::reconnect::restore_all;
+use crate::socket::retention::purge_closed;
+use crate::socket::sniff::ProtocolHint;
+use crate::socket::tags::normalize_tag;
+
+/// A menu command, the menu, help and tab completion are all built from these
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub category: &'static str,
+    pub summary: &'static str,
+    pub usage: &'static str,
+    /// argument name and what it does
+    pub args: &'static [(&'static str, &'static str)],
+    pub examples: &'static [&'static str],
+}
+
+pub const CATEGORIES: [&str; 3] = ["Shells", "Settings", "Menu"];
+
+pub const COMMANDS: &[CommandInfo] = &[
+    CommandInfo {
+        name: "l",
+        aliases: &[],
+        category: "Shells",
+        summary: "list the connected shells",
+        usage: "l [<name>]",
+        args: &[("<name>", "attach to this shell without the picker")],
+        examples: &[],
+    },
+    CommandInfo {
+        name: "ui",
+        aliases: &[],
+        category: "Shells",
+        summary: "open the dashboard, a full screen view of every shell",
+        usage: "ui",
+        args: &[],
+        examples: &[],
+    },
+    CommandInfo {
+        name: "restore",
+        aliases: &[],
+        category: "Shells",
+        summary: "resume closed shells whose host has reconnected",
+        usage: "restore",
+        args: &[],
+        examples: &[],
+    },
+    CommandInfo {
+        name: "purge",
+        aliases: &[],
+        category: "Shells",
+        summary: "remove a closed shell, or all of them",
+        usage: "purge <name> | purge --closed",
+        args: &[
+            ("<name>", "the closed shell to remove"),
+            ("--closed", "remove every closed shell"),
+        ],
+        examples: &["purge web~1", "purge --closed"],
+    },
+    CommandInfo {
+        name: "status",
+        aliases: &[],
+        category: "Shells",
+        summary: "show how many shells are connected and kept after closing, and how transcripts are keeping up",
+        usage: "status",
+        args: &[],
+        examples: &[],
+    },
+    CommandInfo {
+        name: "connect",
+        aliases: &[],
+        category: "Shells",
+        summary: "connect out to a bind shell, optionally redialing it when it drops",
+        usage: "connect <host> <port> [--redial <seconds>[:<max-attempts>]]",
+        args: &[
+            ("<host> <port>", "where the bind shell listens"),
+            (
+                "--redial",
+                "dial again when it drops, waiting twice as long each time, and keep its name",
+            ),
+        ],
+        examples: &["connect 10.0.0.5 4444", "connect 10.0.0.5 4444 --redial 5:10"],
+    },
+    CommandInfo {
+        name: "profiles",
+        aliases: &[],
+        category: "Shells",
+        summary: "list the device profiles new shells are greeted with, or try one on a banner",
+        usage: "profiles | profiles test <name>",
+        args: &[(
+            "test <name>",
+            "paste a banner and see whether the profile picks it and which steps it finds",
+        )],
+        examples: &["profiles test cisco"],
+    },
+    CommandInfo {
+        name: "kill",
+        aliases: &[],
+        category: "Shells",
+        summary: "close a shell's connection or stop it redialing, it stays in the list as closed",
+        usage: "kill <name>",
+        args: &[("<name>", "the shell to close")],
+        examples: &[],
+    },
+    CommandInfo {
+        name: "sessions",
+        aliases: &[],
+        category: "Shells",
+        summary: "list shells with where they came from and when",
+        usage: "sessions [--all]",
+        args: &[("--all", "include shells lost when crab_trap last stopped")],
+        examples: &["sessions --all"],
+    },
+    CommandInfo {
+        name: "note",
+        aliases: &[],
+        category: "Shells",
+        summary: "write down notes about a shell, they're never sent to it",
+        usage: "note <name> [text] | note edit <name>",
+        args: &[
+            ("<name>", "the shell, or a session lost in the last run"),
+            ("[text]", "the note to add, leave it out to list the notes"),
+            ("edit", "open all of the shell's notes in $EDITOR"),
+        ],
+        examples: &["note web~1 creds in /opt/app/.env", "note edit web~1"],
+    },
+    CommandInfo {
+        name: "mark",
+        aliases: &[],
+        category: "Shells",
+        summary: "mark where a shell's transcript is up to, to save the output after it later",
+        usage: "mark <name> <label>",
+        args: &[
+            ("<name>", "the shell"),
+            ("<label>", "one word, unique in the shell"),
+        ],
+        examples: &["mark web~1 creds-found"],
+    },
+    CommandInfo {
+        name: "marks",
+        aliases: &[],
+        category: "Shells",
+        summary: "list a shell's marks",
+        usage: "marks <name>",
+        args: &[("<name>", "the shell, or a session lost in the last run")],
+        examples: &[],
+    },
+    CommandInfo {
+        name: "save",
+        aliases: &[],
+        category: "Shells",
+        summary: "save the commands and output between two marks to a file",
+        usage: "save <name> --between <from> <to> <file> | save <name> --since <from> <file>",
+        args: &[
+            ("<name>", "the shell, or a session lost in the last run"),
+            ("--between <from> <to>", "everything after one mark and before another"),
+            ("--since <from>", "everything after a mark"),
+            ("<file>", "where to write it"),
+        ],
+        examples: &[
+            "save web~1 --between creds-found done-dumping loot.txt",
+            "save web~1 --since creds-found loot.txt",
+        ],
+    },
+    CommandInfo {
+        name: "loot",
+        aliases: &[],
+        category: "Shells",
+        summary: "keep command output or files in the loot directory with where they came from",
+        usage: "loot add <shell> <name> [--from-last-output | --file <path>] | loot list | loot show <name>",
+        args: &[
+            ("<shell>", "the shell it came from, or a session lost in the last run"),
+            ("<name>", "one word to find it by"),
+            ("--from-last-output", "the output of the shell's last command, the default"),
+            ("--file <path>", "a local file, relative to the shell's local directory"),
+        ],
+        examples: &[
+            "loot add web~1 shadow",
+            "loot add web~1 app-key --file id_rsa",
+            "loot show shadow",
+        ],
+    },
+    CommandInfo {
+        name: "workspace",
+        aliases: &[],
+        category: "Menu",
+        summary: "list, create or switch to the workspaces that keep engagements apart",
+        usage: "workspace list | workspace new <name> | workspace use <name>",
+        args: &[
+            ("list", "the workspaces, * marks the one in use"),
+            ("new <name>", "make a workspace starting from the current config"),
+            ("use <name>", "restart crab_trap in another workspace, only with no shells connected"),
+        ],
+        examples: &["workspace new acme-2024", "workspace use acme-2024"],
+    },
+    CommandInfo {
+        name: "watch-remote",
+        aliases: &[],
+        category: "Shells",
+        summary: "re-run a command on a shell and show what changed",
+        usage: "watch-remote [--full] <name> <interval> <command> | watch-remote stop <id>",
+        args: &[
+            ("<interval>", "time between runs, like 30s, 5m or 500ms"),
+            ("--full", "show the whole output each time it changes"),
+            (
+                "stop <id>",
+                "stop a watch, watch-remote on its own lists them",
+            ),
+        ],
+        examples: &["watch-remote web 10s ls -la /tmp", "watch-remote stop 1"],
+    },
+    CommandInfo {
+        name: "run-all",
+        aliases: &[],
+        category: "Shells",
+        summary: "run a command on every open shell and compare what came back",
+        usage: "run-all '<command>' [--tag <tag>] [--timeout <duration>] [--json <path>] [--loot <name>]",
+        args: &[
+            ("--tag <tag>", "only shells with this tag"),
+            ("--timeout <duration>", "how long each shell gets, 10s by default"),
+            ("--json <path>", "also write every shell's result as json"),
+            ("--loot <name>", "keep the json in the loot directory"),
+        ],
+        examples: &[
+            "run-all 'uname -a' --tag linux",
+            "run-all 'id' --timeout 20s --loot ids",
+        ],
+    },
+    CommandInfo {
+        name: "upload",
+        aliases: &[],
+        category: "Shells",
+        summary: "send a local file to a shell as base64, checking each chunk arrives intact",
+        usage: "upload <name> <local path> <remote path>",
+        args: &[
+            ("<local path>", "relative to the shell's local directory"),
+            (
+                "<remote path>",
+                "where it ends up, chunks are staged next to it with .b64 and .part",
+            ),
+        ],
+        examples: &["upload web linpeas.sh /tmp/l.sh"],
+    },
+    CommandInfo {
+        name: "timeline",
+        aliases: &[],
+        category: "Shells",
+        summary: "show what happened in a shell, in order",
+        usage: "timeline <name> [--since HH:MM] [--origin <origin>] [--json|--csv]",
+        args: &[
+            ("<name>", "the shell to report on"),
+            ("--since HH:MM", "only events after this time (utc)"),
+            (
+                "--origin",
+                "only commands that were typed (operator), injected, or run by crab_trap (internal)",
+            ),
+            ("--json", "print the events as json"),
+            ("--csv", "print the events as csv"),
+        ],
+        examples: &[
+            "timeline web~1",
+            "timeline web --since 14:00 --csv",
+            "timeline web --origin injected",
+        ],
+    },
+    CommandInfo {
+        name: "set",
+        aliases: &[],
+        category: "Settings",
+        summary: "change a setting",
+        usage: "set [--save] [global|listener|session <name>] <key> <value>",
+        args: &[
+            ("--save", "also write global settings to the config file"),
+            ("session <name>", "only change it for one shell"),
+        ],
+        examples: &["set theme plain", "set session web max_line_width 200"],
+    },
+    CommandInfo {
+        name: "show",
+        aliases: &[],
+        category: "Settings",
+        summary: "show settings and where their values come from",
+        usage: "show [key]",
+        args: &[("[key]", "only show this setting")],
+        examples: &["show", "show max_line_width"],
+    },
+    CommandInfo {
+        name: "help",
+        aliases: &["h"],
+        category: "Menu",
+        summary: "list commands, or show how to use one",
+        usage: "help [command]",
+        args: &[("[command]", "the command to explain")],
+        examples: &["help purge"],
+    },
+    CommandInfo {
+        name: "clear",
+        aliases: &[],
+        category: "Menu",
+        summary: "clear the display",
+        usage: "clear",
+        args: &[],
+        examples: &[],
+    },
+    CommandInfo {
+        name: "exit",
+        aliases: &[],
+        category: "Menu",
+        summary: "quit the program",
+        usage: "exit",
+        args: &[],
+        examples: &[],
+    },
+];
+
+/// Finds a command by its name or an alias
+pub fn find_command(name: &str) -> Option<&'static CommandInfo> {
+    return COMMANDS
+        .iter()
+        .find(|cmd| cmd.name == name || cmd.aliases.contains(&name));
+}
+
+/// Every name a command can be run by
+pub fn command_names() -> Vec<&'static str> {
+    let mut names = Vec::new();
+    for cmd in COMMANDS {
+        names.push(cmd.name);
+        names.extend_from_slice(cmd.aliases);
+    }
+    return names;
+}
+
+/// The command a mistyped name was probably meant to be
+pub fn suggest_command(name: &str) -> Option<&'static str> {
+    return closest_match(name, command_names());
+}
+
+/// Commands grouped by category with their one line summaries
+pub fn help_text() -> String {
+    return render_help(terminal_width());
+}
+
+/// The command list wrapped to fit in `width`
+pub fn render_help(width: usize) -> String {
+    let mut text = String::new();
+    for category in CATEGORIES {
+        text += &format!("{category}:\n");
+        for cmd in COMMANDS.iter().filter(|cmd| cmd.category == category) {
+            // long names push their summary along rather than running into it
+            let label = match cmd.name.len() {
+                0..=8 => format!("  {:<10}", cmd.name),
+                _ => format!("  {}  ", cmd.name),
+            };
+            text += &hanging(&label, cmd.summary, width, 12);
+        }
+    }
+    text += &hanging(
+        "",
+        "Anything else runs as a local command, `help <command>` explains a command",
+        width,
+        0,
+    );
+    return text;
+}
+
+/// Usage, arguments and examples for one command
+pub fn command_help(name: &str) -> String {
+    return render_command_help(name, terminal_width());
+}
+
+pub fn render_command_help(name: &str, width: usize) -> String {
+    let cmd = match find_command(name) {
+        Some(val) => val,
+        None => {
+            return match suggest_command(name) {
+                Some(suggestion) => format!("No command {name}, did you mean {suggestion}?\n"),
+                None => format!("No command {name}, enter help to list them\n"),
+            };
+        }
+    };
+    let mut text = hanging(&format!("{} - ", cmd.name), cmd.summary, width, 2);
+    text += &hanging("Usage: ", cmd.usage, width, 7);
+    if !cmd.aliases.is_empty() {
+        text += &format!("Aliases: {}\n", cmd.aliases.join(", "));
+    }
+    if !cmd.args.is_empty() {
+        text += "Arguments:\n";
+        for (arg, about) in cmd.args {
+            let label = match arg.chars().count() {
+                0..=14 => format!("  {arg:<16}"),
+                _ => format!("  {arg}  "),
+            };
+            text += &hanging(&label, about, width, 18);
+        }
+    }
+    if !cmd.examples.is_empty() {
+        text += "Examples:\n";
+        for example in cmd.examples {
+            text += &format!("  {example}\n");
+        }
+    }
+    return text;
+}
+
+/// The shells every menu command gets
+pub type Shells = Arc<Mutex<HashMap<String, Handle>>>;
+
+/// running watches, id -> (session, command, stop)
+pub type Watches = BTreeMap<u64, (String, String, CancellationToken)>;
+
+/// What the menu's commands share besides the shells
+#[derive(Clone)]
+pub struct MenuContext {
+    pub settings: SharedSettings,
+    pub config_path: PathBuf,
+    pub loot_dir: PathBuf,
+    pub state: SharedState,
+    pub dialer: DialSender,
+    pub watches: Arc<std::sync::Mutex<Watches>>,
+    pub next_watch: Arc<AtomicU64>,
+}
+
+/// Runs a menu command with whatever was typed after its name
+pub type Handler = fn(&MenuContext, Shells, String) -> Option<JoinHandle<()>>;
+
+/// The commands the menu runs from here, the rest need the menu's own terminal state
+pub const HANDLERS: &[(&str, Handler)] = &[
+    ("ui", ui),
+    ("restore", restore),
+    ("purge", purge),
+    ("status", status),
+    ("connect", connect),
+    ("profiles", profiles),
+    ("kill", kill),
+    ("sessions", sessions),
+    ("note", note),
+    ("mark", mark),
+    ("marks", marks),
+    ("save", save),
+    ("workspace", workspace),
+    ("loot", loot),
+    ("watch-remote", watch_remote),
+    ("run-all", run_all),
+    ("upload", upload),
+    ("timeline", timeline),
+    ("set", set),
+    ("show", show),
+];
+
+/// `ui`, open the dashboard, a full screen view of every shell
+pub fn ui(context: &MenuContext, connected_shells: Shells, _: String) -> Option<JoinHandle<()>> {
+    let settings = context.settings.clone();
+    return Some(tokio::spawn(async move {
+        dashboard::run(connected_shells, settings).await;
+    }));
+}
+
+/// `restore`, resume closed shells whose host has reconnected
+pub fn restore(_: &MenuContext, connected_shells: Shells, _: String) -> Option<JoinHandle<()>> {
+    return Some(tokio::spawn(async move {
+        let mut shells = connected_shells.lock().await;
+        let restored = restore_all(&mut shells).await;
+        if restored.is_empty() {
+            println!("No reconnected shells to restore");
+        }
+        for name in restored {
+            println!("Restored {name}");
+        }
+    }));
+}
+
+/// `purge`, remove a closed shell, or all of them
+pub fn purge(_: &MenuContext, connected_shells: Shells, args: String) -> Option<JoinHandle<()>> {
+    return Some(tokio::spawn(async move {
+        let mut shells = connected_shells.lock().await;
+        let target = args.trim();
+        if target == "--closed" {
+            let purged = purge_closed(&mut shells);
+            println!("Purged {} closed shell(s)", purged.len());
+            return;
+        }
+        match shells.get(target) {
+            Some(handle) if handle.is_closed() => {
+                shells.remove(target);
+                println!("Purged {target}");
+            }
+            Some(_) => {
+                println!("{target} is still connected, delete it from the list instead")
+            }
+            None => println!("Usage: purge <name> | purge --closed"),
+        }
+    }));
+}
+
+/// `status`, show how many shells are connected and kept after closing, and how
+/// transcripts are keeping up
+pub fn status(
+    context: &MenuContext,
+    connected_shells: Shells,
+    _: String,
+) -> Option<JoinHandle<()>> {
+    let retention = match context.settings.lock() {
+        Ok(settings) => settings.retention(),
+        Err(_) => return None,
+    };
+    let workspace = workspace_of(&context.config_path, &workspaces_root());
+    return Some(tokio::spawn(async move {
+        if let Some(name) = workspace {
+            println!("workspace {name}");
+        }
+        let shells = connected_shells.lock().await;
+        let closed = shells.values().filter(|handle| handle.is_closed()).count();
+        println!(
+            "{live} connected, {closed} closed (closed shells are kept for {mins} minutes, at most {max})",
+            live = shells.len() - closed,
+            mins = retention.max_age.as_secs() / 60,
+            max = retention.max_closed
+        );
+        let mut names: Vec<&String> = shells.keys().collect();
+        names.sort();
+        for name in names {
+            match shells[name].pending_operations() {
+                0 => {}
+                1 => println!("{name:<16} 1 operation pending"),
+                n => println!("{name:<16} {n} operations pending"),
+            }
+            for (id, target) in shells[name].tees() {
+                println!("{name:<16} tee {id}: {target}");
+            }
+            let stats = match shells[name].transcript_stats() {
+                Some(val) => val,
+                None => continue,
+            };
+            println!(
+                "{name:<16} transcript: {written} written, {backlog} queued, {dropped} dropped",
+                written = stats.written.load(Ordering::SeqCst),
+                backlog = stats.backlog(),
+                dropped = stats.dropped.load(Ordering::SeqCst)
+            );
+        }
+    }));
+}
+
+/// `connect`, connect out to a bind shell, optionally redialing it when it drops
+pub fn connect(context: &MenuContext, _: Shells, args: String) -> Option<JoinHandle<()>> {
+    let target = match parse_connect_args(&args) {
+        Some(val) => val,
+        None => {
+            println!("{CONNECT_USAGE}");
+            return None;
+        }
+    };
+    let dialer = context.dialer.clone();
+    return Some(tokio::spawn(async move {
+        match dial(&target).await {
+            // the listener loop checks it's a shell and announces it
+            Ok(soc) => dialer
+                .send(Dialed {
+                    soc,
+                    target,
+                    previous: None,
+                })
+                .unwrap_or_default(),
+            Err(err) => {
+                println!("Couldn't connect to {}:{}: {err}", target.host, target.port)
+            }
+        }
+    }));
+}
+
+/// `profiles`, list the device profiles new shells are greeted with, or try one on a banner
+pub fn profiles(_: &MenuContext, _: Shells, args: String) -> Option<JoinHandle<()>> {
+    let words: Vec<String> = args.split_whitespace().map(String::from).collect();
+    let name = match words.as_slice() {
+        [] => None,
+        [test, name] if test == "test" => Some(name.clone()),
+        _ => {
+            println!("{PROFILES_USAGE}");
+            return None;
+        }
+    };
+    return Some(tokio::spawn(async move {
+        // read fresh so edits can be tried without a restart
+        let dir = profiles_dir();
+        let (profiles, errors) = load_profiles(&dir);
+        for err in errors {
+            println!("[-] {err}");
+        }
+        let name = match name {
+            Some(val) => val,
+            None => {
+                if profiles.is_empty() {
+                    println!("No profiles in {}", dir.display());
+                }
+                let rows: Vec<Vec<String>> = profiles.iter().map(profile_row).collect();
+                print!(
+                    "{}",
+                    render_table(&rows, &PROFILE_COLUMNS, terminal_width())
+                );
+                return;
+            }
+        };
+        let profile = match profiles.iter().find(|profile| profile.name == name) {
+            Some(val) => val,
+            None => {
+                println!("No profile named {name}");
+                return;
+            }
+        };
+        println!("Paste the banner, end it with an empty line");
+        let mut banner = String::new();
+        for line in stdin().lines() {
+            match line {
+                Ok(line) if !line.is_empty() => banner += &(line + "\n"),
+                _ => break,
+            }
+        }
+        print!("{}", test_profile(profile, &banner));
+    }));
+}
+
+/// `kill`, close a shell's connection or stop it redialing, it stays in the list as closed
+pub fn kill(_: &MenuContext, connected_shells: Shells, args: String) -> Option<JoinHandle<()>> {
+    let name = String::from(args.trim());
+    if name.is_empty() {
+        println!("usage: kill <name>");
+        return None;
+    }
+    return Some(tokio::spawn(async move {
+        match connected_shells.lock().await.get(&name) {
+            Some(handle) if handle.stop_redial() => println!("stopped redialing {name}"),
+            Some(handle) if handle.is_closed() => println!("{name} is already closed"),
+            Some(handle) => handle.kill().await,
+            None => println!("No shell called {name}"),
+        }
+    }));
+}
+
+/// `sessions`, list shells with where they came from and when
+pub fn sessions(
+    context: &MenuContext,
+    connected_shells: Shells,
+    args: String,
+) -> Option<JoinHandle<()>> {
+    let all = match args.trim() {
+        "" => false,
+        "--all" => true,
+        _ => {
+            println!("usage: sessions [--all]");
+            return None;
+        }
+    };
+    let settings = context.settings.clone();
+    let state = context.state.clone();
+    return Some(tokio::spawn(async move {
+        let shells = connected_shells.lock().await;
+        let mut records: Vec<SessionRecord> = match settings.lock() {
+            Ok(settings) => shells
+                .iter()
+                .map(|(name, handle)| SessionRecord::from_handle(name, handle, &settings))
+                .collect(),
+            Err(_) => return,
+        };
+        records.sort_by_key(|record| std::cmp::Reverse(record.connected_at));
+        if all {
+            if let Ok(state) = state.lock() {
+                records.extend(state.lost.iter().cloned());
+            }
+        }
+        if records.is_empty() {
+            println!("No sessions");
+        }
+        let rows: Vec<Vec<String>> = records.iter().map(session_row).collect();
+        print!(
+            "{}",
+            render_table(&rows, &SESSION_COLUMNS, terminal_width())
+        );
+    }));
+}
+
+/// `note`, write down notes about a shell, they're never sent to it
+pub fn note(
+    context: &MenuContext,
+    connected_shells: Shells,
+    args: String,
+) -> Option<JoinHandle<()>> {
+    let cmd = match parse_note_args(&args) {
+        Some(val) => val,
+        None => {
+            println!("{NOTE_USAGE}");
+            return None;
+        }
+    };
+    let settings = context.settings.clone();
+    let state = context.state.clone();
+    return Some(tokio::spawn(async move {
+        let name = cmd.session;
+        // lost sessions from the last run only live in the state file
+        let handle = connected_shells.lock().await.get(&name).cloned();
+        let old = match &handle {
+            Some(handle) => handle.notes(),
+            None => match state.lock() {
+                Ok(mut state) => match state.lost_mut(&name) {
+                    Some(record) => record.notes.clone(),
+                    None => {
+                        println!("No shell called {name}");
+                        return;
+                    }
+                },
+                Err(_) => return,
+            },
+        };
+        let notes = match cmd.action {
+            NoteAction::List => {
+                if old.is_empty() {
+                    println!("No notes for {name}");
+                }
+                for note in old {
+                    println!("{} {}", format_clock(note.at), note.text);
+                }
+                return;
+            }
+            NoteAction::Add(text) => match &handle {
+                Some(handle) => {
+                    handle.add_note(&text);
+                    handle.notes()
+                }
+                None => {
+                    let mut notes = old;
+                    notes.push(SessionNote {
+                        at: now_secs(),
+                        text,
+                    });
+                    notes
+                }
+            },
+            NoteAction::Edit => match edit_notes(&old) {
+                Ok(val) => val,
+                Err(err) => {
+                    println!("Couldn't edit the notes: {err}");
+                    return;
+                }
+            },
+        };
+        match &handle {
+            Some(handle) => handle.replace_notes(notes),
+            None => {
+                if let Ok(mut state) = state.lock() {
+                    if let Some(record) = state.lost_mut(&name) {
+                        record.notes = notes;
+                    }
+                }
+            }
+        }
+        save_state(&state, &*connected_shells.lock().await, &settings);
+    }));
+}
+
+/// `mark`, mark where a shell's transcript is up to, to save the output after it later
+pub fn mark(
+    context: &MenuContext,
+    connected_shells: Shells,
+    args: String,
+) -> Option<JoinHandle<()>> {
+    let (name, label) = match args.trim().split_once(' ') {
+        Some((name, label)) => (String::from(name), String::from(label.trim())),
+        None => {
+            println!("{MARK_USAGE}");
+            return None;
+        }
+    };
+    let settings = context.settings.clone();
+    let state = context.state.clone();
+    return Some(tokio::spawn(async move {
+        let shells = connected_shells.lock().await;
+        let handle = match shells.get(&name) {
+            Some(val) => val,
+            None => {
+                println!("No shell called {name}");
+                return;
+            }
+        };
+        match handle.add_mark(&label) {
+            Ok(mark) if mark.seq.is_none() => {
+                println!("Marked {label}, {name} has no transcript to save from")
+            }
+            Ok(_) => println!("Marked {label}"),
+            Err(err) => {
+                println!("{err}");
+                return;
+            }
+        }
+        save_state(&state, &shells, &settings);
+    }));
+}
+
+/// `marks`, list a shell's marks
+pub fn marks(
+    context: &MenuContext,
+    connected_shells: Shells,
+    args: String,
+) -> Option<JoinHandle<()>> {
+    let name = String::from(args.trim());
+    if name.is_empty() {
+        println!("usage: marks <name>");
+        return None;
+    }
+    let state = context.state.clone();
+    return Some(tokio::spawn(async move {
+        let marks = match lookup_marks(&connected_shells, &state, &name).await {
+            Some((marks, _, _)) => marks,
+            None => {
+                println!("No shell called {name}");
+                return;
+            }
+        };
+        if marks.is_empty() {
+            println!("No marks for {name}");
+        }
+        for mark in marks {
+            match mark.seq {
+                Some(seq) => {
+                    println!("{} {:<20} #{seq}", format_clock(mark.at), mark.label)
+                }
+                None => println!(
+                    "{} {:<20} (no transcript)",
+                    format_clock(mark.at),
+                    mark.label
+                ),
+            }
+        }
+    }));
+}
+
+/// `save`, save the commands and output between two marks to a file
+pub fn save(
+    context: &MenuContext,
+    connected_shells: Shells,
+    args: String,
+) -> Option<JoinHandle<()>> {
+    let args = match parse_save_args(&args) {
+        Some(val) => val,
+        None => {
+            println!("{SAVE_USAGE}");
+            return None;
+        }
+    };
+    let state = context.state.clone();
+    return Some(tokio::spawn(async move {
+        let name = &args.session;
+        let (marks, transcript, local_dir) =
+            match lookup_marks(&connected_shells, &state, name).await {
+                Some(val) => val,
+                None => {
+                    println!("No shell called {name}");
+                    return;
+                }
+            };
+        let transcript = match transcript {
+            Some(val) => val,
+            None => {
+                println!("{name} has no transcript, turn on transcripts to save between marks");
+                return;
+            }
+        };
+        // relative files go in the shell's local directory
+        let args = SaveArgs {
+            file: local_dir.join(&args.file),
+            ..args
+        };
+        match save_marked(&transcript, &marks, &args) {
+            Ok(len) => println!("Saved {len} bytes to {}", args.file.display()),
+            Err(err) => println!("{err}"),
+        }
+    }));
+}
+
+/// `workspace`, list, create or switch to the workspaces that keep engagements apart
+pub fn workspace(
+    context: &MenuContext,
+    connected_shells: Shells,
+    args: String,
+) -> Option<JoinHandle<()>> {
+    let cmd = match parse_workspace_args(&args) {
+        Some(val) => val,
+        None => {
+            println!("{WORKSPACE_USAGE}");
+            return None;
+        }
+    };
+    let config_path = context.config_path.clone();
+    let settings = context.settings.clone();
+    let state = context.state.clone();
+    return Some(tokio::spawn(async move {
+        let root = workspaces_root();
+        let active = workspace_of(&config_path, &root);
+        match cmd {
+            WorkspaceCommand::List => {
+                let names = list_workspaces(&root);
+                if names.is_empty() {
+                    println!("No workspaces yet, workspace new <name> makes one");
+                }
+                for name in names {
+                    let marker = match active.as_deref() == Some(name.as_str()) {
+                        true => "*",
+                        false => " ",
+                    };
+                    println!("{marker} {name}");
+                }
+            }
+            WorkspaceCommand::New(name) => {
+                let base = match Config::load(&config_path) {
+                    Ok(config) => config.unwrap_or_default(),
+                    Err(err) => {
+                        println!("{err} in {}", config_path.display());
+                        return;
+                    }
+                };
+                match create_workspace(&root, &name, &base) {
+                    Ok(_) => {
+                        println!("Created workspace {name}, workspace use {name} switches to it")
+                    }
+                    Err(err) => println!("{err}"),
+                }
+            }
+            WorkspaceCommand::Use(name) => {
+                if active.as_deref() == Some(name.as_str()) {
+                    println!("Already in workspace {name}");
+                    return;
+                }
+                if !workspace_config(&root, &name).exists() {
+                    println!("No workspace called {name}, workspace new {name} makes one");
+                    return;
+                }
+                let shells = connected_shells.lock().await;
+                let live = shells.values().filter(|handle| !handle.is_closed()).count();
+                if live > 0 {
+                    println!("{live} shells are connected to this workspace, kill them or exit before switching so their sessions don't mix");
+                    return;
+                }
+                // what's left of the closed shells stays with this workspace
+                for handle in shells.values() {
+                    handle.flush_transcript().await;
+                }
+                save_state(&state, &shells, &settings);
+                let args: Vec<String> = std::env::args().skip(1).collect();
+                let err = std::process::Command::new(std::env::current_exe().unwrap_or_default())
+                    .args(relaunch_args(&args, &name))
+                    .exec();
+                println!("Couldn't restart crab_trap in {name}: {err}");
+            }
+        }
+    }));
+}
+
+/// `loot`, keep command output or files in the loot directory with where they came from
+pub fn loot(
+    context: &MenuContext,
+    connected_shells: Shells,
+    args: String,
+) -> Option<JoinHandle<()>> {
+    let cmd = match parse_loot_args(&args) {
+        Some(val) => val,
+        None => {
+            println!("{LOOT_USAGE}");
+            return None;
+        }
+    };
+    let state = context.state.clone();
+    let loot_dir = context.loot_dir.clone();
+    return Some(tokio::spawn(async move {
+        let mut store = match LootStore::open(&loot_dir) {
+            Ok(val) => val,
+            Err(err) => {
+                println!("{err}");
+                return;
+            }
+        };
+        let (session, name, from) = match cmd {
+            LootCommand::List => {
+                if store.entries.is_empty() {
+                    println!("No loot in {}", store.dir.display());
+                }
+                let rows: Vec<Vec<String>> = store.entries.iter().map(loot_row).collect();
+                print!("{}", render_table(&rows, &LOOT_COLUMNS, terminal_width()));
+                return;
+            }
+            LootCommand::Show(name) => {
+                match store.find(&name) {
+                    Some(entry) => print!("{}", show_loot(&store, entry)),
+                    None => println!("No loot called {name}"),
+                }
+                return;
+            }
+            LootCommand::Add {
+                session,
+                name,
+                from,
+            } => (session, name, from),
+        };
+        let (_, transcript, local_dir) =
+            match lookup_marks(&connected_shells, &state, &session).await {
+                Some(val) => val,
+                None => {
+                    println!("No shell called {session}");
+                    return;
+                }
+            };
+        let (source, content) = match from {
+            LootFrom::LastOutput => {
+                let last = transcript
+                    .and_then(|path| std::fs::read_to_string(path).ok())
+                    .and_then(|content| last_output(&content));
+                match last {
+                    Some((cmd, output)) => (cmd, output.into_bytes()),
+                    None => {
+                        println!("{session} has no transcript with a command in it, turn on transcripts or use --file");
+                        return;
+                    }
+                }
+            }
+            LootFrom::File(path) => {
+                let path = local_dir.join(path);
+                match std::fs::read(&path) {
+                    Ok(content) => (format!("file {}", path.display()), content),
+                    Err(err) => {
+                        println!("Couldn't read {}: {err}", path.display());
+                        return;
+                    }
+                }
+            }
+        };
+        match store.add(&name, &session, &source, &content) {
+            Ok((entry, duplicate)) => {
+                println!("Stored {name}, {} bytes", entry.size);
+                if let Some(other) = duplicate {
+                    println!("It's the same as {other}, the content is only kept once");
+                }
+                if let Some(handle) = connected_shells.lock().await.get(&session) {
+                    handle.record(EventKind::Loot, &format!("{name} from {source}"));
+                }
+            }
+            Err(err) => println!("{err}"),
+        }
+    }));
+}
+
+/// `watch-remote`, re-run a command on a shell and show what changed
+pub fn watch_remote(
+    context: &MenuContext,
+    connected_shells: Shells,
+    args: String,
+) -> Option<JoinHandle<()>> {
+    let cmd = match parse_watch_args(&args) {
+        Some(val) => val,
+        None => {
+            println!("{WATCH_USAGE}");
+            return None;
+        }
+    };
+    let watches = context.watches.clone();
+    let next_watch = context.next_watch.clone();
+    return Some(tokio::spawn(async move {
+        let (session, interval, command, full) = match cmd {
+            WatchCommand::List => {
+                let watches = match watches.lock() {
+                    Ok(val) => val,
+                    Err(_) => return,
+                };
+                if watches.is_empty() {
+                    println!("No watches running");
+                }
+                for (id, (session, command, _)) in watches.iter() {
+                    println!("{id:<4}{session:<16}{command}");
+                }
+                return;
+            }
+            WatchCommand::Stop(id) => {
+                match watches.lock().ok().and_then(|mut w| w.remove(&id)) {
+                    Some((_, _, stop)) => stop.cancel(),
+                    None => println!("No watch {id}"),
+                }
+                return;
+            }
+            WatchCommand::Start {
+                session,
+                interval,
+                command,
+                full,
+            } => (session, interval, command, full),
+        };
+        let handle = match connected_shells.lock().await.get(&session) {
+            Some(handle) => handle.clone(),
+            None => {
+                println!("No shell called {session}");
+                return;
+            }
+        };
+        let mut watch = handle.watch_remote(&command, interval, full);
+        let id = next_watch.fetch_add(1, Ordering::SeqCst);
+        if let Ok(mut watches) = watches.lock() {
+            watches.insert(id, (session.clone(), command.clone(), watch.stop_token()));
+        }
+        println!("Watching {command} on {session} as watch {id}, watch-remote stop {id} ends it");
+        tokio::spawn(async move {
+            let tag = format!("[watch {id} {session}]");
+            while let Some(report) = watch.reports.recv().await {
+                match report {
+                    WatchReport::Output(output) => {
+                        println!("{tag}");
+                        print!("{output}");
+                    }
+                    WatchReport::Changed(diff) => {
+                        for line in diff {
+                            match line {
+                                DiffLine::Added(line) => println!("{tag} + {line}"),
+                                DiffLine::Removed(line) => println!("{tag} - {line}"),
+                            }
+                        }
+                    }
+                    WatchReport::Failed(count) => {
+                        println!("{tag} no answer ({count} in a row)")
+                    }
+                    WatchReport::Stopped(reason) => println!("{tag} stopped, {reason}"),
+                }
+            }
+            if let Ok(mut watches) = watches.lock() {
+                watches.remove(&id);
+            }
+        });
+    }));
+}
+
+/// `run-all`, run a command on every open shell and compare what came back
+pub fn run_all(
+    context: &MenuContext,
+    connected_shells: Shells,
+    args: String,
+) -> Option<JoinHandle<()>> {
+    let args = match parse_run_all_args(&args) {
+        Some(val) => val,
+        None => {
+            println!("{RUN_ALL_USAGE}");
+            return None;
+        }
+    };
+    let loot_dir = context.loot_dir.clone();
+    return Some(tokio::spawn(async move {
+        let tag = args.tag.as_deref().map(normalize_tag);
+        let shells: Vec<(String, Handle)> = connected_shells
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, handle)| !handle.is_closed())
+            .filter(|(_, handle)| match &tag {
+                Some(tag) => tag.as_ref().is_some_and(|tag| handle.tags().contains(tag)),
+                None => true,
+            })
+            .map(|(name, handle)| (name.clone(), handle.clone()))
+            .collect();
+        if shells.is_empty() {
+            println!("No open shells to run it on");
+            return;
+        }
+        println!("Running {} on {} shells", args.command, shells.len());
+        let results = run_batch(shells, &args.command, args.timeout).await;
+        print!("{}", render_batch(&results));
+        let json = batch_json(&args.command, &results);
+        if let Some(path) = &args.json {
+            let written = ephemeral::check_write("A run-all export")
+                .and_then(|_| std::fs::write(path, &json));
+            match written {
+                Ok(_) => println!("Wrote {}", path.display()),
+                Err(err) => println!("Couldn't write {}: {err}", path.display()),
+            }
+        }
+        if let Some(name) = &args.loot {
+            let stored = LootStore::open(&loot_dir)
+                .and_then(|mut store| store.add(name, "run-all", &args.command, json.as_bytes()));
+            match stored {
+                Ok((entry, _)) => println!("Stored {name}, {} bytes", entry.size),
+                Err(err) => println!("{err}"),
+            }
+        }
+    }));
+}
+
+/// `upload`, send a local file to a shell as base64, checking each chunk arrives intact
+pub fn upload(
+    context: &MenuContext,
+    connected_shells: Shells,
+    args: String,
+) -> Option<JoinHandle<()>> {
+    let (name, local, remote) = match parse_upload_args(&args) {
+        Some(val) => val,
+        None => {
+            println!("{UPLOAD_USAGE}");
+            return None;
+        }
+    };
+    let settings = context.settings.clone();
+    return Some(tokio::spawn(async move {
+        let handle = connected_shells.lock().await.get(&name).cloned();
+        let handle = match handle {
+            Some(val) => val,
+            None => {
+                println!("No shell called {name}");
+                return;
+            }
+        };
+        let options = match settings.lock() {
+            Ok(settings) => settings.chunk_options(Some(&name)),
+            Err(_) => return,
+        };
+        // relative to the session's local directory like lcd leaves it
+        let local = handle.local_dir().join(local);
+        let result = handle
+            .upload_file(&local, &remote, options, |progress| {
+                // redrawn in place, a dumb terminal would get a line per chunk
+                if terminal::escapes() {
+                    print!("\r{}{}", clear::CurrentLine, progress.line());
+                    stdout().flush().unwrap_or_default();
+                }
+            })
+            .await;
+        println!();
+        match result {
+            Ok(done) => println!(
+                "Uploaded {} to {remote} on {name}, {} chunks resent",
+                local.display(),
+                done.retries
+            ),
+            Err(err) => println!("{err}"),
+        }
+    }));
+}
+
+/// `timeline`, show what happened in a shell, in order
+pub fn timeline(_: &MenuContext, connected_shells: Shells, args: String) -> Option<JoinHandle<()>> {
+    return Some(tokio::spawn(async move {
+        let args = match parse_timeline_args(&args) {
+            Some(val) => val,
+            None => {
+                println!("{TIMELINE_USAGE}");
+                return;
+            }
+        };
+        let mut events = match connected_shells.lock().await.get(&args.session) {
+            Some(handle) => handle.history(),
+            None => {
+                println!("No shell called {}", args.session);
+                return;
+            }
+        };
+        if let Some(since) = args.since {
+            events = filter_since(events, since, now_secs());
+        }
+        if let Some(origin) = args.origin {
+            events = filter_origin(events, origin);
+        }
+        print!("{}", render_timeline(&events, args.format));
+    }));
+}
+
+/// `set`, change a setting
+pub fn set(
+    context: &MenuContext,
+    connected_shells: Shells,
+    args: String,
+) -> Option<JoinHandle<()>> {
+    let settings = context.settings.clone();
+    let config_path = context.config_path.clone();
+    return Some(tokio::spawn(async move {
+        let cmd = match parse_set_args(&args) {
+            Some(val) => val,
+            None => {
+                println!("{SET_USAGE}");
+                return;
+            }
+        };
+        let mut shells = connected_shells.lock().await;
+        if let Some(name) = &cmd.session {
+            if !shells.contains_key(name) {
+                println!("No shell called {name}");
+                return;
+            }
+        }
+        let result = match settings.lock() {
+            Ok(mut settings) => {
+                settings.set(cmd.scope, cmd.session.as_deref(), &cmd.key, &cmd.value)
+            }
+            Err(_) => return,
+        };
+        let value = match result {
+            Ok(val) => val,
+            Err(err) => {
+                println!("{err}");
+                return;
+            }
+        };
+        // session settings take effect on the shells straight away
+        if cmd.key == "max_line_width" {
+            for (name, handle) in shells.iter_mut() {
+                if let Ok(settings) = settings.lock() {
+                    handle.max_line_width =
+                        settings.get_number("max_line_width", Some(name)) as usize;
+                }
+            }
+        }
+        if cmd.key == "theme" {
+            if let Ok(settings) = settings.lock() {
+                terminal::set_theme(settings.get_theme());
+            }
+        }
+        if cmd.key == "protocol" {
+            for (name, handle) in shells.iter_mut() {
+                if let Ok(settings) = settings.lock() {
+                    handle.protocol_override = settings
+                        .get("protocol", Some(name))
+                        .ok()
+                        .and_then(|(protocol, _)| ProtocolHint::parse(&protocol));
+                }
+            }
+        }
+        println!("{} = {value} ({})", cmd.key, cmd.scope);
+        if cmd.save {
+            match save_settings(&settings, &config_path) {
+                Ok(_) => println!("Saved global settings to {}", config_path.display()),
+                Err(err) => println!("Couldn't save settings: {err}"),
+            }
+        }
+    }));
+}
+
+/// `show`, show settings and where their values come from
+pub fn show(
+    context: &MenuContext,
+    connected_shells: Shells,
+    args: String,
+) -> Option<JoinHandle<()>> {
+    let settings = context.settings.clone();
+    return Some(tokio::spawn(async move {
+        let mut sessions: Vec<String> = connected_shells.lock().await.keys().cloned().collect();
+        sessions.sort();
+        let key = args.trim();
+        if !key.is_empty() {
+            if let Ok(def) = lookup(key) {
+                println!("{}", def.help);
+            }
+            show_setting(key, &settings, &sessions);
+            return;
+        }
+        for def in SETTINGS {
+            show_setting(def.key, &settings, &sessions);
+        }
+    }));
+}
+
+/// Prints a setting's effective value and any per session overrides
+pub fn show_setting(key: &str, settings: &SharedSettings, sessions: &[String]) {
+    let settings = match settings.lock() {
+        Ok(val) => val,
+        Err(_) => return,
+    };
+    match settings.get(key, None) {
+        Ok((value, scope)) => println!("{key} = {value} ({scope})"),
+        Err(err) => {
+            println!("{err}");
+            return;
+        }
+    }
+    for session in sessions {
+        if let Ok((value, Scope::Session)) = settings.get(key, Some(session)) {
+            println!("  {session}: {value}");
+        }
+    }
+}
+
+/// Saves the global settings into the config file, keeping whatever else is in it
+pub fn save_settings(settings: &SharedSettings, config_path: &Path) -> Result<(), String> {
+    let mut config = match Config::load(config_path) {
+        Ok(val) => val.unwrap_or_default(),
+        Err(err) => return Err(err.to_string()),
+    };
+    if let Ok(settings) = settings.lock() {
+        settings.apply_to_config(&mut config);
+    }
+    return write_config(config_path, &config).map_err(|err| err.to_string());
+}
+
+/// A shell's marks, where its transcript is and its local directory, for live shells
+/// and ones lost in the last run
+pub async fn lookup_marks(
+    shells: &Mutex<HashMap<String, Handle>>,
+    state: &SharedState,
+    name: &str,
+) -> Option<(Vec<SessionMark>, Option<PathBuf>, PathBuf)> {
+    let live = shells.lock().await.get(name).cloned();
+    if let Some(handle) = live {
+        // records still in the writer's buffer belong in what's read back
+        handle.flush_transcript().await;
+        return Some((handle.marks(), handle.transcript_path(), handle.local_dir()));
+    }
+    let mut state = state.lock().ok()?;
+    let record = state.lost_mut(name)?;
+    return Some((
+        record.marks.clone(),
+        record.transcript.clone(),
+        record.local_dir.clone().unwrap_or_else(startup_dir),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::settings::Settings;
+    use crate::config::state::StateFile;
+    use crate::menu::menu_list;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::mpsc::unbounded_channel;
+
+    #[test]
+    fn test_registry_matches_menu() {
+        let settings = Arc::new(Mutex::new(Settings::default()));
+        let state = Arc::new(Mutex::new(StateFile::load(Path::new(
+            "/nonexistent/state.json",
+        ))));
+        let menu = menu_list::new(
+            settings,
+            PathBuf::from("config.toml"),
+            PathBuf::from("/nonexistent/loot"),
+            state,
+            unbounded_channel().0,
+        );
+        let mut names = command_names();
+        names.sort();
+        let mut entries: Vec<&str> = menu.keys().copied().collect();
+        entries.sort();
+        assert_eq!(names, entries);
+        for cmd in COMMANDS {
+            assert!(CATEGORIES.contains(&cmd.category));
+        }
+    }
+
+    #[test]
+    fn test_command_help() {
+        assert!(help_text().contains("  purge     remove a closed shell"));
+        let help = command_help("h");
+        assert!(help.starts_with("help - "));
+        assert!(help.contains("help purge"));
+        assert_eq!(
+            command_help("prge"),
+            "No command prge, did you mean purge?\n"
+        );
+        assert_eq!(suggest_command("stauts"), Some("status"));
+    }
+
+    #[test]
+    fn test_help_width() {
+        for width in [40, 80, 200] {
+            let help = render_help(width);
+            assert!(help.lines().all(|line| line.chars().count() <= width));
+            for cmd in COMMANDS {
+                let help = render_command_help(cmd.name, width);
+                // examples are copied as they are so they're never wrapped
+                for line in help
+                    .lines()
+                    .filter(|line| !cmd.examples.contains(&line.trim()))
+                {
+                    assert!(line.chars().count() <= width, "{line}");
+                }
+            }
+        }
+        let narrow = render_command_help("watch-remote", 40);
+        assert!(narrow.starts_with("watch-remote - re-run a command on a\n  shell"));
+        assert_eq!(
+            render_help(200),
+            render_help(80).replace("\n            ", " ")
+        );
+    }
+}
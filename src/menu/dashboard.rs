@@ -0,0 +1,651 @@
+use std::collections::HashMap;
+use std::io::{stdout, Stdout, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use termion::event::Key;
+use termion::raw::{IntoRawMode, RawTerminal};
+use termion::screen::{ToAlternateScreen, ToMainScreen};
+use termion::{clear, color, cursor, terminal_size};
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::sync::Mutex;
+use tokio::time::interval;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::settings::SharedSettings;
+use crate::input::input::handle_key_input;
+use crate::menu::menu_list::{attach, rename_shell};
+use crate::menu::render::{resize_events, resized, truncate};
+use crate::menu::terminal;
+use crate::menu::title::{menu_title, set_title};
+use crate::socket::connection::Handle;
+use crate::socket::history::{now_secs, EventKind, SessionEvent};
+use crate::socket::tags::normalize_tag;
+
+/// how much of each session's latest output is kept for the preview
+const PREVIEW_BYTES: usize = 16384;
+
+/// redraws this often with nothing else going on, so closed shells show up
+const TICK: Duration = Duration::from_secs(1);
+
+/// narrower than this and there's only the list
+const MIN_SPLIT_WIDTH: usize = 60;
+
+const DASHBOARD_HELP: &str =
+    "(ENTER attach) (j/k move) (x kill) (r rename) (t tag) (q back to the prompt)";
+
+/// One line in the session list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub name: String,
+    pub state: &'static str,
+    /// seconds since the unix epoch
+    pub last_activity: u64,
+    /// events other than commands and attaching since it was last attached from here
+    pub alerts: usize,
+    pub tags: Vec<String>,
+}
+
+/// What's on screen, drawn fresh each time something changes
+pub struct Frame<'a> {
+    pub rows: &'a [Row],
+    pub selected: usize,
+    /// seconds since the unix epoch, for how long ago each shell was active
+    pub now: u64,
+    /// the selected session's latest output, as the remote sent it
+    pub preview: &'a [u8],
+    pub status: &'a str,
+}
+
+/// What the remote's output looks like without its escape sequences, carriage
+/// returns and backspaces applied so progress bars and prompts end up as one line
+pub fn plain_text(output: &str) -> String {
+    let mut text = String::with_capacity(output.len());
+    let mut chars = output.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI ends on its final byte
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC ends on BEL or ST
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\r' => {
+                let start = text.rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+                text.truncate(start);
+            }
+            '\x08' => {
+                if !text.ends_with('\n') {
+                    text.pop();
+                }
+            }
+            '\t' => text.push(' '),
+            '\n' => text.push('\n'),
+            c if c.is_control() => {}
+            c => text.push(c),
+        }
+    }
+    return text;
+}
+
+/// Cuts or pads to exactly `width` characters
+fn fit(text: &str, width: usize) -> String {
+    let text = truncate(text, width);
+    let pad = width.saturating_sub(text.chars().count());
+    return text + &" ".repeat(pad);
+}
+
+/// The last `height` lines of output, each fit to `width`
+pub fn preview_lines(output: &[u8], width: usize, height: usize) -> Vec<String> {
+    let text = plain_text(&String::from_utf8_lossy(output));
+    let lines: Vec<&str> = text.trim_end_matches('\n').lines().collect();
+    let start = lines.len().saturating_sub(height);
+    let mut shown: Vec<String> = lines[start..].iter().map(|line| fit(line, width)).collect();
+    shown.resize(height, " ".repeat(width));
+    return shown;
+}
+
+/// How long ago `at` was, shortened to its largest unit
+pub fn ago(at: u64, now: u64) -> String {
+    if at == 0 {
+        return String::from("-");
+    }
+    let secs = now.saturating_sub(at);
+    return match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    };
+}
+
+/// The session list scrolled so the selected row is in view, each line fit to `width`
+pub fn list_lines(
+    rows: &[Row],
+    selected: usize,
+    now: u64,
+    width: usize,
+    height: usize,
+) -> Vec<String> {
+    let name_width = rows
+        .iter()
+        .map(|row| row.name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .min(width / 2);
+    let state_width = rows.iter().map(|row| row.state.len()).max().unwrap_or(0);
+    let top = (selected + 1).saturating_sub(height);
+    let mut lines: Vec<String> = rows
+        .iter()
+        .skip(top)
+        .take(height)
+        .map(|row| {
+            let alerts = match row.alerts {
+                0 => String::new(),
+                n => format!("!{n}"),
+            };
+            let tags: Vec<String> = row.tags.iter().map(|tag| format!("#{tag}")).collect();
+            let line = format!(
+                "{name:<name_width$} {state:<state_width$} {at:>3} {alerts:<3} {tags}",
+                name = truncate(&row.name, name_width),
+                state = row.state,
+                at = ago(row.last_activity, now),
+                tags = tags.join(" "),
+            );
+            fit(&line, width)
+        })
+        .collect();
+    if rows.is_empty() {
+        lines.push(fit("No shells yet, waiting for connections", width));
+    }
+    lines.resize(height, " ".repeat(width));
+    return lines;
+}
+
+impl Frame<'_> {
+    /// Lays out the header, list, preview and status line. `highlight` goes around
+    /// the selected row
+    pub fn render(&self, width: usize, height: usize, highlight: (&str, &str)) -> Vec<String> {
+        let body = height.saturating_sub(2);
+        let list_width = match width >= MIN_SPLIT_WIDTH {
+            true => (width / 3).clamp(24, 48),
+            false => width,
+        };
+        let list = list_lines(self.rows, self.selected, self.now, list_width, body);
+        let preview = match width >= MIN_SPLIT_WIDTH {
+            true => preview_lines(self.preview, width - list_width - 1, body),
+            false => Vec::new(),
+        };
+        let open = self.rows.iter().filter(|row| row.state != "closed").count();
+        let mut lines = vec![fit(&menu_title(open), width)];
+        let selected_line = (self.selected + 1).min(body).saturating_sub(1);
+        for (i, line) in list.into_iter().enumerate() {
+            let line = match i == selected_line && !self.rows.is_empty() {
+                true => format!("{}{line}{}", highlight.0, highlight.1),
+                false => line,
+            };
+            lines.push(match preview.get(i) {
+                Some(shown) => format!("{line}│{shown}"),
+                None => line,
+            });
+        }
+        lines.push(fit(self.status, width));
+        return lines;
+    }
+}
+
+fn session_state(handle: &Handle) -> &'static str {
+    if handle.is_redialing() {
+        return "reconnecting";
+    }
+    if handle.is_closed() {
+        return "closed";
+    }
+    return match handle.raw_mode {
+        true => "open (raw)",
+        false => "open",
+    };
+}
+
+fn is_alert(kind: EventKind) -> bool {
+    return !matches!(
+        kind,
+        EventKind::Connected | EventKind::Attached | EventKind::Detached | EventKind::Command
+    );
+}
+
+enum Update {
+    Event(u64, SessionEvent),
+    Output(u64, Vec<u8>),
+}
+
+/// A session the dashboard is following
+struct Tracked {
+    id: u64,
+    handle: Handle,
+    stop: CancellationToken,
+    tail: Vec<u8>,
+    alerts: usize,
+    last_activity: u64,
+}
+
+#[derive(Default)]
+struct Sessions {
+    next_id: u64,
+    tracked: Vec<Tracked>,
+}
+
+impl Sessions {
+    /// Starts following new shells and stops following removed ones, returning the
+    /// rows in name order with where each one is tracked
+    fn sync(
+        &mut self,
+        shells: &HashMap<String, Handle>,
+        updates: &UnboundedSender<Update>,
+    ) -> Vec<(Row, usize)> {
+        self.tracked.retain(|tracked| {
+            let kept = shells.values().any(|h| h.same_session(&tracked.handle));
+            if !kept {
+                tracked.stop.cancel();
+            }
+            kept
+        });
+        let mut names: Vec<&String> = shells.keys().collect();
+        names.sort();
+        let mut rows = Vec::new();
+        for name in names {
+            let handle = &shells[name];
+            let idx = match self
+                .tracked
+                .iter()
+                .position(|tracked| tracked.handle.same_session(handle))
+            {
+                Some(val) => val,
+                None => {
+                    self.follow(handle, updates);
+                    self.tracked.len() - 1
+                }
+            };
+            let tracked = &self.tracked[idx];
+            rows.push((
+                Row {
+                    name: name.clone(),
+                    state: session_state(handle),
+                    last_activity: tracked.last_activity,
+                    alerts: tracked.alerts,
+                    tags: handle.tags(),
+                },
+                idx,
+            ));
+        }
+        return rows;
+    }
+
+    /// Forwards the session's events and output to the dashboard until it stops
+    /// following it
+    fn follow(&mut self, handle: &Handle, updates: &UnboundedSender<Update>) {
+        self.next_id += 1;
+        let id = self.next_id;
+        let stop = CancellationToken::new();
+        let mut events = handle.subscribe_events();
+        let mut output = handle.subscribe_output();
+        let tail = match handle.pending_output.lock() {
+            Ok(pending) => pending[pending.len().saturating_sub(PREVIEW_BYTES)..].to_vec(),
+            Err(_) => Vec::new(),
+        };
+        self.tracked.push(Tracked {
+            id,
+            handle: handle.clone(),
+            stop: stop.clone(),
+            tail,
+            alerts: 0,
+            last_activity: handle.history().last().map(|e| e.at).unwrap_or(0),
+        });
+        let updates = updates.clone();
+        tokio::spawn(async move {
+            loop {
+                let update = select! {
+                    _ = stop.cancelled() => return,
+                    event = events.recv() => match event {
+                        Ok(event) => Update::Event(id, event),
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => return,
+                    },
+                    chunk = output.recv() => match chunk {
+                        Ok(chunk) => Update::Output(id, chunk),
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => return,
+                    },
+                };
+                if updates.send(update).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    fn apply(&mut self, update: Update) {
+        let (id, at) = match &update {
+            Update::Event(id, event) => (*id, event.at),
+            Update::Output(id, _) => (*id, now_secs()),
+        };
+        let tracked = match self.tracked.iter_mut().find(|tracked| tracked.id == id) {
+            Some(val) => val,
+            None => return,
+        };
+        tracked.last_activity = tracked.last_activity.max(at);
+        match update {
+            Update::Event(_, event) if is_alert(event.kind) => tracked.alerts += 1,
+            Update::Event(..) => {}
+            Update::Output(_, chunk) => {
+                tracked.tail.extend_from_slice(&chunk);
+                let over = tracked.tail.len().saturating_sub(PREVIEW_BYTES);
+                tracked.tail.drain(..over);
+            }
+        }
+    }
+
+    fn stop_all(&mut self) {
+        for tracked in self.tracked.drain(..) {
+            tracked.stop.cancel();
+        }
+    }
+}
+
+/// A line being typed into the status line
+enum Prompt {
+    Rename(String, String),
+    Tag(String, String),
+}
+
+enum Exit {
+    Prompt,
+    Attach(String),
+}
+
+fn draw(out: &mut RawTerminal<Stdout>, frame: &Frame) {
+    let (width, height) = terminal_size().unwrap_or((80, 24));
+    let highlight = (terminal::bg(color::Red, color::Red), terminal::bg_reset());
+    let lines = frame.render(
+        width as usize,
+        height as usize,
+        (&highlight.0, &highlight.1),
+    );
+    let mut screen = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        // the bottom right corner is left alone, writing it scrolls some terminals
+        let line = match i + 1 == lines.len() {
+            true => line.trim_end(),
+            false => line.as_str(),
+        };
+        screen += &format!("{}{line}", cursor::Goto(1, i as u16 + 1));
+    }
+    write!(out, "{screen}{}", clear::UntilNewline).unwrap_or_default();
+    out.flush().unwrap_or_default();
+}
+
+/// The full screen view of every shell. Attaching returns here on detach, `q` goes
+/// back to the prompt
+pub async fn run(shells: Arc<Mutex<HashMap<String, Handle>>>, settings: SharedSettings) {
+    if !terminal::detect().raw {
+        println!("Raw mode isn't available in this terminal, the dashboard needs it");
+        return;
+    }
+    let mut out = match stdout().into_raw_mode() {
+        Ok(val) => val,
+        Err(err) => {
+            println!("Couldn't start the dashboard: {err}");
+            return;
+        }
+    };
+    let (updates, mut update_rx) = unbounded_channel::<Update>();
+    let mut sessions = Sessions::default();
+    let mut selected_name: Option<String> = None;
+    let mut status = String::from(DASHBOARD_HELP);
+    let mut prompt: Option<Prompt> = None;
+    let mut resizes = resize_events();
+    let mut ticks = interval(TICK);
+    loop {
+        out.activate_raw_mode().unwrap_or_default();
+        write!(out, "{ToAlternateScreen}{}", cursor::Hide).unwrap_or_default();
+        let mut input_future = Box::pin(handle_key_input());
+        let exit = loop {
+            let rows = sessions.sync(&*shells.lock().await, &updates);
+            let mut selected = selected_name
+                .as_ref()
+                .and_then(|name| rows.iter().position(|(row, _)| &row.name == name))
+                .unwrap_or(0);
+            selected = selected.min(rows.len().saturating_sub(1));
+            selected_name = rows.get(selected).map(|(row, _)| row.name.clone());
+            let shown = match &prompt {
+                Some(Prompt::Rename(name, input)) => format!("rename {name} to: {input}"),
+                Some(Prompt::Tag(name, input)) => format!("tag {name} with: {input}"),
+                None => status.clone(),
+            };
+            {
+                let plain: Vec<Row> = rows.iter().map(|(row, _)| row.clone()).collect();
+                let preview = match rows.get(selected) {
+                    Some((_, idx)) => sessions.tracked[*idx].tail.as_slice(),
+                    None => &[],
+                };
+                let frame = Frame {
+                    rows: &plain,
+                    selected,
+                    now: now_secs(),
+                    preview,
+                    status: &shown,
+                };
+                draw(&mut out, &frame);
+            }
+            let key = select! {
+                res = &mut input_future => {
+                    input_future = Box::pin(handle_key_input());
+                    match res {
+                        Ok(Some((key, _))) => key,
+                        _ => continue,
+                    }
+                }
+                Some(update) = update_rx.recv() => {
+                    sessions.apply(update);
+                    continue;
+                }
+                _ = resized(&mut resizes) => continue,
+                _ = ticks.tick() => continue,
+            };
+            let current = selected_name.clone();
+            if let Some(typing) = prompt.take() {
+                let (name, mut input, is_tag) = match typing {
+                    Prompt::Rename(name, input) => (name, input, false),
+                    Prompt::Tag(name, input) => (name, input, true),
+                };
+                match key {
+                    Key::Esc => status = String::from(DASHBOARD_HELP),
+                    Key::Char('\n') | Key::Char('\r') if is_tag => {
+                        status = match (normalize_tag(&input), shells.lock().await.get(&name)) {
+                            (Some(tag), Some(handle)) => match handle.toggle_tag(&tag) {
+                                true => format!("Tagged {name} #{tag}"),
+                                false => format!("Took #{tag} off {name}"),
+                            },
+                            (None, _) => String::from("Tags are one word"),
+                            (_, None) => format!("No shell called {name}"),
+                        };
+                    }
+                    Key::Char('\n') | Key::Char('\r') => {
+                        status = match rename_shell(&mut *shells.lock().await, &name, &input) {
+                            Ok(_) => {
+                                selected_name = Some(input.clone());
+                                format!("Renamed {name} to {input}")
+                            }
+                            Err(err) => String::from(err),
+                        };
+                    }
+                    key => {
+                        match key {
+                            Key::Backspace | Key::Delete => {
+                                input.pop();
+                            }
+                            Key::Char(c) if !c.is_control() => input.push(c),
+                            _ => {}
+                        }
+                        prompt = Some(match is_tag {
+                            true => Prompt::Tag(name, input),
+                            false => Prompt::Rename(name, input),
+                        });
+                    }
+                }
+                continue;
+            }
+            match key {
+                Key::Char('q') | Key::Esc => break Exit::Prompt,
+                Key::Up | Key::Char('k') => {
+                    let idx = selected.saturating_sub(1);
+                    selected_name = rows.get(idx).map(|(row, _)| row.name.clone());
+                }
+                Key::Down | Key::Char('j') => {
+                    let idx = (selected + 1).min(rows.len().saturating_sub(1));
+                    selected_name = rows.get(idx).map(|(row, _)| row.name.clone());
+                }
+                Key::Char('\n') | Key::Char('\r') => {
+                    if let Some(name) = current {
+                        break Exit::Attach(name);
+                    }
+                }
+                Key::Char('x') => {
+                    if let Some(name) = current {
+                        let handle = shells.lock().await.get(&name).cloned();
+                        if let Some(handle) = handle {
+                            handle.kill().await;
+                            status = format!("Killed {name}");
+                        }
+                    }
+                }
+                Key::Char('r') => {
+                    if let Some(name) = current {
+                        prompt = Some(Prompt::Rename(name, String::new()));
+                    }
+                }
+                Key::Char('t') => {
+                    if let Some(name) = current {
+                        prompt = Some(Prompt::Tag(name, String::new()));
+                    }
+                }
+                _ => {}
+            }
+        };
+        write!(out, "{}{ToMainScreen}", cursor::Show).unwrap_or_default();
+        out.flush().unwrap_or_default();
+        out.suspend_raw_mode().unwrap_or_default();
+        let name = match exit {
+            Exit::Prompt => break,
+            Exit::Attach(name) => name,
+        };
+        {
+            let shells = shells.lock().await;
+            attach(&shells, name.clone(), &settings).await;
+        }
+        set_title(&settings, "crab_trap: dashboard");
+        // what happened while attached was seen there
+        while let Ok(update) = update_rx.try_recv() {
+            sessions.apply(update);
+        }
+        let shells = shells.lock().await;
+        if let Some(handle) = shells.get(&name) {
+            for tracked in &mut sessions.tracked {
+                if tracked.handle.same_session(handle) {
+                    tracked.alerts = 0;
+                }
+            }
+        }
+        status = String::from(DASHBOARD_HELP);
+    }
+    sessions.stop_all();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(name: &str, state: &'static str, last_activity: u64, alerts: usize) -> Row {
+        return Row {
+            name: String::from(name),
+            state,
+            last_activity,
+            alerts,
+            tags: Vec::new(),
+        };
+    }
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(
+            plain_text("\x1b[1;32muser@box\x1b[0m:~$ ls\r\na  b\r\n"),
+            "user@box:~$ ls\na  b\n"
+        );
+        assert_eq!(plain_text("10%\r50%\r100%\n"), "100%\n");
+        assert_eq!(plain_text("\x1b]0;title\x07ab\x08c"), "ac");
+        assert_eq!(plain_text("\x1b]2;t\x1b\\ok\tgo"), "ok go");
+        assert_eq!(ago(0, 500), "-");
+        assert_eq!(ago(440, 500), "1m");
+        assert_eq!(ago(500, 7700), "2h");
+    }
+
+    #[test]
+    fn test_frame() {
+        let now = 100_000;
+        let mut rows = vec![
+            row("db", "closed", now - 90_000, 0),
+            row("web", "open (raw)", now - 5, 2),
+            row("web~1", "open", 0, 0),
+        ];
+        rows[1].tags = vec![String::from("pivot")];
+        let frame = Frame {
+            rows: &rows,
+            selected: 1,
+            now,
+            preview: b"\x1b[0mroot@web:~# id\r\nuid=0(root)\r\nroot@web:~# ",
+            status: DASHBOARD_HELP,
+        };
+        let lines = frame.render(120, 6, ("[", "]"));
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0].trim_end(), "crab_trap: 2 sessions");
+        assert!(lines[1].starts_with("db    closed      1d     "));
+        let (list, preview) = lines[2].split_once('│').unwrap();
+        assert!(list.starts_with("[web   open (raw)  5s !2  #pivot  "));
+        assert_eq!(preview.trim_end(), "uid=0(root)");
+        assert_eq!(
+            lines[3].split_once('│').unwrap().1.trim_end(),
+            "root@web:~#"
+        );
+        assert!(lines[5].starts_with("(ENTER attach)"));
+        // every line fills the width, apart from the highlight
+        for line in &lines {
+            assert_eq!(line.replace(['[', ']'], "").chars().count(), 120);
+        }
+
+        // the selection stays in view and a narrow terminal drops the preview
+        let frame = Frame {
+            selected: 2,
+            ..frame
+        };
+        let lines = frame.render(40, 4, ("", ""));
+        assert_eq!(lines[1].trim_end(), "web   open (raw)  5s !2  #pivot");
+        assert_eq!(lines[2].trim_end(), "web~1 open         -");
+        assert!(!lines[1].contains('│'));
+    }
+}
@@ -0,0 +1,423 @@
+use crate::input::chord::CHORD_BINDINGS;
+use crate::input::suggest::closest_match;
+use crate::socket::capture::{parse_capture_args, CaptureAction};
+use crate::socket::tee::{parse_tee_args, TeeAction, TEE_USAGE};
+
+/// marks a meta-command in prefix mode, doubled to send it literally
+pub const META_PREFIX: char = '%';
+
+/// escapes a meta-command name in bare mode so it goes to the remote
+pub const META_ESCAPE: char = '\\';
+
+/// How lines typed into a shell are checked for meta-commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchMode {
+    /// `back` is a meta-command, `\back` sends the word to the remote
+    Bare,
+    /// only `%back` is a meta-command, everything else goes straight to the remote
+    Prefix,
+}
+
+impl DispatchMode {
+    pub fn parse(name: &str) -> DispatchMode {
+        return match name {
+            "prefix" => DispatchMode::Prefix,
+            _ => DispatchMode::Bare,
+        };
+    }
+}
+
+/// What the session loop should do after a meta-command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionAction {
+    /// go back to the menu
+    Detach,
+    /// move to the shell this many places along
+    Switch(isize),
+    Print(String),
+    /// attach a note to the session, it never goes to the remote
+    Note(String),
+    /// mark where the transcript is up to
+    Mark(String),
+    /// change the session's local directory, or show it with none
+    LocalDir(Option<String>),
+    /// start or stop recording the raw bytes
+    Capture(CaptureAction),
+    /// copy the output from now on to a file or process, or stop or list the copies
+    Tee(TeeAction),
+    /// throw away paced input that's still waiting to go out
+    Discard,
+}
+
+/// Why a session stopped reading input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionExit {
+    Menu,
+    Switch(isize),
+}
+
+pub struct SessionCommand {
+    pub name: &'static str,
+    pub summary: &'static str,
+    pub usage: &'static str,
+    /// gets everything after the name, errors are shown with the usage
+    pub run: fn(&str) -> Result<SessionAction, String>,
+}
+
+fn no_args(args: &str) -> Result<(), String> {
+    return match args.trim().is_empty() {
+        true => Ok(()),
+        false => Err(format!("unexpected arguments {}", args.trim())),
+    };
+}
+
+fn run_back(args: &str) -> Result<SessionAction, String> {
+    no_args(args)?;
+    return Ok(SessionAction::Detach);
+}
+
+fn run_next(args: &str) -> Result<SessionAction, String> {
+    no_args(args)?;
+    return Ok(SessionAction::Switch(1));
+}
+
+fn run_prev(args: &str) -> Result<SessionAction, String> {
+    no_args(args)?;
+    return Ok(SessionAction::Switch(-1));
+}
+
+fn run_note(args: &str) -> Result<SessionAction, String> {
+    return match args.trim() {
+        "" => Err(String::from("nothing to note")),
+        text => Ok(SessionAction::Note(String::from(text))),
+    };
+}
+
+fn run_mark(args: &str) -> Result<SessionAction, String> {
+    return match args.trim() {
+        "" => Err(String::from("missing a label")),
+        label => Ok(SessionAction::Mark(String::from(label))),
+    };
+}
+
+fn run_lcd(args: &str) -> Result<SessionAction, String> {
+    return match args.trim() {
+        "" => Err(String::from("missing a directory")),
+        path => Ok(SessionAction::LocalDir(Some(String::from(path)))),
+    };
+}
+
+fn run_lpwd(args: &str) -> Result<SessionAction, String> {
+    no_args(args)?;
+    return Ok(SessionAction::LocalDir(None));
+}
+
+fn run_capture(args: &str) -> Result<SessionAction, String> {
+    return Ok(SessionAction::Capture(parse_capture_args(args)?));
+}
+
+fn run_tee(args: &str) -> Result<SessionAction, String> {
+    return Ok(SessionAction::Tee(parse_tee_args(args)?));
+}
+
+fn run_discard(args: &str) -> Result<SessionAction, String> {
+    no_args(args)?;
+    return Ok(SessionAction::Discard);
+}
+
+fn run_commands(args: &str) -> Result<SessionAction, String> {
+    no_args(args)?;
+    let mut text = String::new();
+    for cmd in SESSION_COMMANDS {
+        text += &format!("  {:<10}{}\n", cmd.name, cmd.summary);
+    }
+    return Ok(SessionAction::Print(text));
+}
+
+fn run_keys(args: &str) -> Result<SessionAction, String> {
+    no_args(args)?;
+    let mut text = String::from("In raw mode press the escape_key, then:\n");
+    for (key, action) in CHORD_BINDINGS {
+        let summary = find_session_command(action).map_or("", |cmd| cmd.summary);
+        text += &format!("  {key:<4}{action:<10}{summary}\n");
+    }
+    text += "Press the prefix twice to send it to the remote\n";
+    return Ok(SessionAction::Print(text));
+}
+
+/// Commands handled locally while attached to a shell
+pub const SESSION_COMMANDS: &[SessionCommand] = &[
+    SessionCommand {
+        name: "back",
+        summary: "return to the menu",
+        usage: "back",
+        run: run_back,
+    },
+    SessionCommand {
+        name: "next",
+        summary: "switch to the next open shell",
+        usage: "next",
+        run: run_next,
+    },
+    SessionCommand {
+        name: "prev",
+        summary: "switch to the previous open shell",
+        usage: "prev",
+        run: run_prev,
+    },
+    SessionCommand {
+        name: "note",
+        summary: "write down a note about this shell",
+        usage: "note <text>",
+        run: run_note,
+    },
+    SessionCommand {
+        name: "mark",
+        summary: "mark this point in the transcript",
+        usage: "mark <label>",
+        run: run_mark,
+    },
+    SessionCommand {
+        name: "lcd",
+        summary: "change this shell's local directory",
+        usage: "lcd <path>",
+        run: run_lcd,
+    },
+    SessionCommand {
+        name: "lpwd",
+        summary: "show this shell's local directory",
+        usage: "lpwd",
+        run: run_lpwd,
+    },
+    SessionCommand {
+        name: "capture",
+        summary: "record the raw bytes both ways for a while, for a bug report",
+        usage: "capture burst <duration> [<path>] | capture stop",
+        run: run_capture,
+    },
+    SessionCommand {
+        name: "ltee",
+        summary: "copy the output from now on to a file or local command",
+        usage: TEE_USAGE,
+        run: run_tee,
+    },
+    SessionCommand {
+        name: "discard",
+        summary: "stop sending input held back by input_pace",
+        usage: "discard",
+        run: run_discard,
+    },
+    SessionCommand {
+        name: "commands",
+        summary: "list the commands handled locally while in a shell",
+        usage: "commands",
+        run: run_commands,
+    },
+    SessionCommand {
+        name: "keys",
+        summary: "list the raw mode key bindings",
+        usage: "keys",
+        run: run_keys,
+    },
+];
+
+pub fn find_session_command(name: &str) -> Option<&'static SessionCommand> {
+    return SESSION_COMMANDS.iter().find(|cmd| cmd.name == name);
+}
+
+/// Where a typed line should go
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dispatch {
+    /// send this to the remote
+    Send(String),
+    Run {
+        name: &'static str,
+        args: String,
+    },
+    /// a prefixed name that isn't a command, with the closest one if there is one
+    Unknown {
+        name: String,
+        suggestion: Option<&'static str>,
+    },
+}
+
+fn split_command(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    return line.split_once(char::is_whitespace).unwrap_or((line, ""));
+}
+
+/// Decides whether a typed line is a meta-command or goes to the remote
+pub fn dispatch(line: &str, mode: DispatchMode) -> Dispatch {
+    match mode {
+        DispatchMode::Bare => {
+            let (word, args) = split_command(line);
+            if let Some(cmd) = find_session_command(word) {
+                return Dispatch::Run {
+                    name: cmd.name,
+                    args: String::from(args.trim()),
+                };
+            }
+            // `\back` sends `back`, any other backslash is left for the remote
+            if let Some(escaped) = word.strip_prefix(META_ESCAPE) {
+                if find_session_command(escaped).is_some() {
+                    return Dispatch::Send(line.replacen(META_ESCAPE, "", 1));
+                }
+            }
+        }
+        DispatchMode::Prefix => {
+            let trimmed = line.trim_start();
+            if trimmed.starts_with(&format!("{META_PREFIX}{META_PREFIX}")) {
+                return Dispatch::Send(line.replacen(META_PREFIX, "", 1));
+            }
+            if let Some(rest) = trimmed.strip_prefix(META_PREFIX) {
+                let (word, args) = split_command(rest);
+                return match find_session_command(word) {
+                    Some(cmd) => Dispatch::Run {
+                        name: cmd.name,
+                        args: String::from(args.trim()),
+                    },
+                    None => Dispatch::Unknown {
+                        name: String::from(word),
+                        suggestion: closest_match(
+                            word,
+                            SESSION_COMMANDS.iter().map(|cmd| cmd.name),
+                        ),
+                    },
+                };
+            }
+        }
+    }
+    return Dispatch::Send(String::from(line));
+}
+
+/// Runs a dispatched meta-command, bad arguments come back as a printable message
+pub fn run_session_command(name: &str, args: &str) -> SessionAction {
+    let cmd = match find_session_command(name) {
+        Some(val) => val,
+        None => return SessionAction::Print(format!("No command {name}\n")),
+    };
+    return match (cmd.run)(args) {
+        Ok(action) => action,
+        Err(err) => SessionAction::Print(format!("{err}\nUsage: {}\n", cmd.usage)),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(name: &'static str, args: &str) -> Dispatch {
+        return Dispatch::Run {
+            name,
+            args: String::from(args),
+        };
+    }
+
+    #[test]
+    fn test_bare_dispatch() {
+        let mode = DispatchMode::Bare;
+        assert_eq!(dispatch("back\n", mode), run("back", ""));
+        assert_eq!(dispatch("  back  \n", mode), run("back", ""));
+        assert_eq!(
+            dispatch("ls -la\n", mode),
+            Dispatch::Send(String::from("ls -la\n"))
+        );
+        // only the first word counts
+        assert_eq!(
+            dispatch("echo back\n", mode),
+            Dispatch::Send(String::from("echo back\n"))
+        );
+        // the remote's own tee isn't shadowed, the local one is ltee
+        assert_eq!(
+            dispatch("tee -a /etc/x\n", mode),
+            Dispatch::Send(String::from("tee -a /etc/x\n"))
+        );
+        assert_eq!(
+            dispatch("backup.sh\n", mode),
+            Dispatch::Send(String::from("backup.sh\n"))
+        );
+        // escaping sends the word itself, other backslashes are untouched
+        assert_eq!(
+            dispatch("\\back\n", mode),
+            Dispatch::Send(String::from("back\n"))
+        );
+        assert_eq!(
+            dispatch("\\ls\n", mode),
+            Dispatch::Send(String::from("\\ls\n"))
+        );
+        // prefixed names aren't special in bare mode
+        assert_eq!(
+            dispatch("%back\n", mode),
+            Dispatch::Send(String::from("%back\n"))
+        );
+    }
+
+    #[test]
+    fn test_prefix_dispatch() {
+        let mode = DispatchMode::Prefix;
+        assert_eq!(
+            dispatch("back\n", mode),
+            Dispatch::Send(String::from("back\n"))
+        );
+        assert_eq!(dispatch("%back\n", mode), run("back", ""));
+        assert_eq!(
+            dispatch("%commands extra\n", mode),
+            run("commands", "extra")
+        );
+        assert_eq!(
+            dispatch("%%back\n", mode),
+            Dispatch::Send(String::from("%back\n"))
+        );
+        assert_eq!(
+            dispatch("%bak\n", mode),
+            Dispatch::Unknown {
+                name: String::from("bak"),
+                suggestion: Some("back")
+            }
+        );
+        assert_eq!(
+            dispatch("printf 100%\n", mode),
+            Dispatch::Send(String::from("printf 100%\n"))
+        );
+    }
+
+    #[test]
+    fn test_run_session_command() {
+        assert_eq!(run_session_command("back", ""), SessionAction::Detach);
+        assert_eq!(run_session_command("prev", ""), SessionAction::Switch(-1));
+        assert_eq!(
+            run_session_command("note", " sudo -l works "),
+            SessionAction::Note(String::from("sudo -l works"))
+        );
+        assert_eq!(
+            run_session_command("mark", "creds-found"),
+            SessionAction::Mark(String::from("creds-found"))
+        );
+        assert_eq!(
+            run_session_command("lcd", " ~/loot "),
+            SessionAction::LocalDir(Some(String::from("~/loot")))
+        );
+        assert_eq!(
+            run_session_command("lpwd", ""),
+            SessionAction::LocalDir(None)
+        );
+        assert_eq!(
+            run_session_command("back", "now"),
+            SessionAction::Print(String::from("unexpected arguments now\nUsage: back\n"))
+        );
+        match run_session_command("commands", "") {
+            SessionAction::Print(text) => assert!(text.contains("back")),
+            action => panic!("unexpected {action:?}"),
+        }
+        match run_session_command("keys", "") {
+            SessionAction::Print(text) => {
+                assert!(text.contains("  d   back      return to the menu\n"))
+            }
+            action => panic!("unexpected {action:?}"),
+        }
+        // every binding runs a real command
+        for (_, action) in CHORD_BINDINGS {
+            assert!(find_session_command(action).is_some());
+        }
+    }
+}
@@ -1,42 +1,77 @@
-use std::cmp::max;
 use std::collections::HashMap;
+use std::future::{pending, Future};
 
 use connection::Handle;
 use std::io::{stdin, stdout, Stdout, Write};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use termion::cursor::DetectCursorPos;
 use termion::event::Key;
 use termion::input::TermRead;
 use termion::raw::{IntoRawMode, RawTerminal};
-use termion::{clear, color, cursor, terminal_size};
+use termion::{clear, color, cursor, style, terminal_size};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::oneshot::error::RecvError;
 use tokio::sync::watch::{self, Receiver, Sender};
 use tokio::sync::{Mutex, MutexGuard};
 use tokio::task::JoinHandle;
+use tokio::time::sleep_until;
 use tokio::{join, select};
 use tokio_util::sync::CancellationToken;
 
+use crate::config::ephemeral;
+use crate::config::settings::{Settings, SharedSettings};
+use crate::config::state::{save_state, SharedState};
+use crate::input::chord::{ctrl_code, ctrl_label, Chord, ChordConfig, ChordReader};
 use crate::input::input::{self, read_line};
+use crate::menu::commands::{command_help, help_text, MenuContext, HANDLERS};
+use crate::menu::dispatch::{
+    dispatch, run_session_command, Dispatch, DispatchMode, SessionAction, SessionExit, META_PREFIX,
+};
+use crate::menu::output::{hex_dump, prompt_from_chunk, render_chunk, LineLimiter};
+use crate::menu::render::{resize_events, resized, truncate};
+use crate::menu::terminal;
+use crate::menu::title::{restore_title, session_title, set_title};
+use crate::socket::capture::Direction;
+use crate::socket::close::CloseReason;
 use crate::socket::connection;
+use crate::socket::dial::DialSender;
+use crate::socket::history::EventKind;
+use crate::socket::origin::InputOrigin;
+use crate::socket::queries::{QueryFilter, QueryMode};
+use crate::socket::sniff::ProtocolHint;
+use crate::socket::timing::format_took;
+use crate::socket::write::{
+    write_paced, write_sliced, Pace, Pacer, WriteOutcome, PACE_PROGRESS_MIN,
+};
 
+/// Menu entries get the shell list and whatever was typed after the command name
 pub type MenuListValue = Box<
-    dyn Fn(Arc<Mutex<HashMap<String, Handle>>>) -> Option<JoinHandle<()>> + Send + Sync + 'static,
+    dyn Fn(Arc<Mutex<HashMap<String, Handle>>>, String) -> Option<JoinHandle<()>>
+        + Send
+        + Sync
+        + 'static,
 >;
 
 pub type MenuList = HashMap<&'static str, MenuListValue>;
 
+/// A line being read at the session prompt
+type LineInput = Pin<Box<dyn Future<Output = Result<String, RecvError>> + Send>>;
+
 pub fn help() {
-    println!("l - list the connected shells");
-    println!("h - display this help message");
-    println!("clear - clear the display");
-    println!("exit - quit the program");
+    print!("{}", help_text());
 }
 
 pub fn clear() {
-    println!("{clear}", clear = clear::All);
+    println!("{clear}", clear = terminal::escape(clear::All));
 }
 
 pub fn exit() {
+    restore_title();
+    ephemeral::scrub();
     std::process::exit(0);
 }
 
@@ -45,32 +80,93 @@ async fn soc_read<W>(
     mut out_writer: W,
     cancel_token: CancellationToken,
     prompt_tx: Sender<String>,
+    timing: bool,
+    queries: QueryMode,
 ) where
     W: Write,
 {
     let mut read_soc = handle.read_stream.lock().await;
     let mut read_buf: [u8; 4096] = [0; 4096];
+    let mut limiter = LineLimiter::new(handle.max_line_width);
+    let mut filter = QueryFilter::default();
+    // whatever isn't a shell is shown byte for byte, counted from its first byte
+    let hex = handle.protocol() != ProtocolHint::Shell;
+    let mut offset = 0;
+    // show anything the background pump read while we weren't attached
+    let pending = handle.take_pending_output().await;
+    if hex {
+        out_writer
+            .write_all(hex_dump(&pending, offset).as_bytes())
+            .unwrap_or_default();
+        offset += pending.len();
+    } else if !pending.is_empty() {
+        out_writer.write_all(&pending).unwrap_or_default();
+    }
+    out_writer.flush().unwrap_or_default();
     loop {
         let reader = read_soc.read(&mut read_buf);
         let cancel_fut = cancel_token.cancelled();
         select! {
             bytes_read = reader => {
-                if bytes_read.is_err(){
-                    eprint!("Channel closed");
-                    cancel_token.cancel();
-                    return
+                // a zero length read means the remote hung up
+                let n = match bytes_read {
+                    Ok(n) if n > 0 => n,
+                    result => {
+                        handle.mark_closed(CloseReason::from_read(&result));
+                        cancel_token.cancel();
+                        return
+                    }
+                };
+                handle.capture(Direction::In, &read_buf[0..n]);
+                handle.publish_output(&read_buf[0..n]);
+                if hex {
+                    handle.transcribe_output(&String::from_utf8_lossy(&read_buf[0..n]), None);
+                    out_writer.write_all(hex_dump(&read_buf[0..n], offset).as_bytes()).unwrap_or_default();
+                    out_writer.flush().unwrap_or_default();
+                    offset += n;
+                    continue;
+                }
+                let shown = match queries {
+                    QueryMode::Terminal => read_buf[0..n].to_vec(),
+                    mode => {
+                        let (shown, found) = filter.feed(&read_buf[0..n]);
+                        if mode == QueryMode::Answer {
+                            for query in &found {
+                                handle.answer_query(query);
+                            }
+                        }
+                        shown
+                    }
+                };
+                // a chunk that was only a query has nothing to show
+                if shown.is_empty() {
+                    continue;
                 }
-                let n = bytes_read.unwrap();
-                let content = String::from_utf8_lossy(&read_buf[0..n]);
-                let prompt = content.lines().last().unwrap_or("");
+                let content = handle.route_output(&String::from_utf8_lossy(&shown));
+                // keystrokes in raw mode aren't commands, there's nothing to time
+                let took = match handle.raw_mode {
+                    false => handle.time_output(&content),
+                    true => None,
+                };
+                handle.transcribe_output(&content, took);
+                let prompt = prompt_from_chunk(&content);
                 prompt_tx.send(String::from(prompt)).unwrap_or_else(|_|{eprintln!("Prompt channel closed!")});
-                let send_content = match handle.raw_mode {
-                    false => String::from("\r")+&format!("{}",clear::CurrentLine)+&String::from_utf8_lossy(&read_buf[0..n]).into_owned(),
-                    true => content.into_owned()
+                let send_content = match (handle.raw_mode, took.filter(|_| timing)) {
+                    (false, Some(took)) => {
+                        // the timing goes on its own line between the output and the prompt
+                        let (body, prompt) = content.rsplit_once('\n').unwrap_or(("", &content));
+                        let mut shown = String::from("\r") + &format!("{}", clear::CurrentLine);
+                        if !body.is_empty() {
+                            shown += &limiter.feed(&format!("{body}\n"));
+                        }
+                        shown += &format!("{}{}{}\n", style::Faint, format_took(took), style::Reset);
+                        shown + &limiter.feed(prompt)
+                    }
+                    (raw, _) => render_chunk(&content, raw, &mut limiter),
                 };
 
-                out_writer.write_all(send_content.as_bytes()).unwrap();
-                out_writer.flush().unwrap();
+                out_writer.write_all(send_content.as_bytes()).unwrap_or_default();
+                out_writer.flush().unwrap_or_default();
             }
             _ = cancel_fut =>{
                 break;
@@ -79,101 +175,510 @@ async fn soc_read<W>(
     }
 }
 
-async fn soc_write(handle: Handle, cancel_token: CancellationToken, prompt_rx: Receiver<String>) {
+async fn soc_write(
+    handle: Handle,
+    cancel_token: CancellationToken,
+    prompt_rx: Receiver<String>,
+    mode: DispatchMode,
+    chord_config: ChordConfig,
+    pace: Pace,
+) -> SessionExit {
     let mut write_soc = handle.write_stream.lock().await;
+    let mut injected_rx = handle.input_rx.lock().await;
+    let mut replies_rx = handle.replies_rx.lock().await;
+    // kept across loops so a reply written mid line doesn't lose what's being typed
+    let mut line_input: Option<LineInput> = None;
     loop {
+        if handle.is_closed() {
+            cancel_token.cancel();
+            return SessionExit::Menu;
+        }
+        let kill_fut = handle.soc_kill_token.cancelled();
         if handle.raw_mode {
             let out = stdout();
             let raw_stdout = out.into_raw_mode().unwrap();
-
-            let cancel_fut = cancel_token.cancelled();
-            let input_future = input::handle_key_input();
-            select! {
-                Ok(Some((key, key_bytes))) = input_future =>{
-                    if key == Key::Ctrl('b'){
+            let mut chords = ChordReader::new(chord_config);
+            let mut pacer = Pacer::new(pace);
+            // kept across loops so a key read while the chord timer fires isn't lost
+            let mut input_future = Box::pin(input::handle_key_input());
+            let mut resizes = resize_events();
+            loop {
+                let deadline = chords.deadline();
+                let chord_timer = async {
+                    match deadline {
+                        Some(deadline) => sleep_until(deadline.into()).await,
+                        None => pending().await,
+                    }
+                };
+                select! {
+                    biased;
+                    _ = handle.soc_kill_token.cancelled() => {
                         raw_stdout.suspend_raw_mode().unwrap();
                         cancel_token.cancel();
-                        return
+                        return SessionExit::Menu;
+                    }
+                    Some(reply) = replies_rx.recv() => {
+                        if !send_raw(&handle, &mut *write_soc, &reply).await {
+                            break;
+                        }
+                    }
+                    res = &mut input_future => {
+                        input_future = Box::pin(input::handle_key_input());
+                        let (key, key_bytes) = match res {
+                            Ok(Some(val)) => val,
+                            _ => continue,
+                        };
+                        let bytes = match chords.feed(&key, &key_bytes, Instant::now()) {
+                            Chord::Pending => {
+                                show_chord_indicator(Some(chord_config.prefix));
+                                continue;
+                            }
+                            Chord::Pass(bytes) => bytes,
+                            Chord::Action(name) => match run_session_command(name, "") {
+                                SessionAction::Detach => {
+                                    show_chord_indicator(None);
+                                    raw_stdout.suspend_raw_mode().unwrap();
+                                    cancel_token.cancel();
+                                    return SessionExit::Menu;
+                                }
+                                SessionAction::Switch(step) => {
+                                    show_chord_indicator(None);
+                                    raw_stdout.suspend_raw_mode().unwrap();
+                                    cancel_token.cancel();
+                                    return SessionExit::Switch(step);
+                                }
+                                SessionAction::Print(text) => {
+                                    print!("\r\n{}", text.replace('\n', "\r\n"));
+                                    stdout().flush().unwrap_or_default();
+                                    Vec::new()
+                                }
+                                SessionAction::Note(text) => {
+                                    handle.add_note(&text);
+                                    Vec::new()
+                                }
+                                SessionAction::Mark(label) => {
+                                    if let Err(err) = handle.add_mark(&label) {
+                                        print!("\r\n{err}\r\n");
+                                        stdout().flush().unwrap_or_default();
+                                    }
+                                    Vec::new()
+                                }
+                                SessionAction::LocalDir(path) => {
+                                    print!("\r\n{}\r\n", handle.local_dir_command(path.as_deref()));
+                                    stdout().flush().unwrap_or_default();
+                                    Vec::new()
+                                }
+                                SessionAction::Capture(action) => {
+                                    print!("\r\n{}\r\n", handle.capture_command(action));
+                                    stdout().flush().unwrap_or_default();
+                                    Vec::new()
+                                }
+                                SessionAction::Tee(action) => {
+                                    let shown = handle.tee_command(action).replace('\n', "\r\n");
+                                    print!("\r\n{shown}\r\n");
+                                    stdout().flush().unwrap_or_default();
+                                    Vec::new()
+                                }
+                                SessionAction::Discard => {
+                                    let (_, total) = pacer.progress();
+                                    show_pace_indicator(total, total);
+                                    print!("\r\nDiscarded {} bytes\r\n", pacer.clear());
+                                    stdout().flush().unwrap_or_default();
+                                    Vec::new()
+                                }
+                            },
+                        };
+                        show_chord_indicator(None);
+                        match pacer.is_off() {
+                            true => {
+                                if !send_raw(&handle, &mut *write_soc, &bytes).await {
+                                    break;
+                                }
+                            }
+                            false => pacer.push(&bytes),
+                        }
+                    }
+                    piece = pacer.next_piece(), if !pacer.is_idle() => {
+                        if !send_raw(&handle, &mut *write_soc, &piece).await {
+                            break;
+                        }
+                        let (sent, total) = pacer.progress();
+                        show_pace_indicator(sent, total);
+                    }
+                    _ = resized(&mut resizes) => {
+                        // the indicator sits against the right edge, move it to the new one
+                        if chords.deadline().is_some() {
+                            show_chord_indicator(Some(chord_config.prefix));
+                        }
+                    }
+                    _ = chord_timer => {
+                        if let Some(bytes) = chords.expire(Instant::now()) {
+                            show_chord_indicator(None);
+                            match pacer.is_off() {
+                                true => {
+                                    if !send_raw(&handle, &mut *write_soc, &bytes).await {
+                                        break;
+                                    }
+                                }
+                                false => pacer.push(&bytes),
+                            }
+                        }
+                    }
+                    _ = cancel_token.cancelled() => {
+                        return SessionExit::Menu;
                     }
-                    write_soc.write_all(&key_bytes).await.unwrap();
-                    write_soc.flush().await.unwrap();
-                }
-                _ = cancel_fut =>{
-                    break;
                 }
-            };
+            }
+            // only a failed write gets here, the session is already marked closed
+            raw_stdout.suspend_raw_mode().unwrap_or_default();
+            cancel_token.cancel();
+            return SessionExit::Menu;
         } else {
+            let mut pacer = Pacer::new(pace);
             let cancel_fut = cancel_token.cancelled();
-            let prompt = prompt_rx.borrow().to_string();
-            let input_future = read_line(handle.readline.clone(), Some(prompt.as_str()));
+            let input_future = line_input.get_or_insert_with(|| {
+                let prompt = prompt_rx.borrow().to_string();
+                let readline = handle.readline.clone();
+                Box::pin(async move {
+                    match readline {
+                        Some(rl) => read_line(rl, Some(prompt.as_str())).await,
+                        // headless handles only take injected input
+                        None => pending().await,
+                    }
+                })
+            });
             select! {
+                biased;
+                _ = kill_fut => {
+                    cancel_token.cancel();
+                    return SessionExit::Menu;
+                }
+                Some(reply) = replies_rx.recv() => {
+                    if !send_raw(&handle, &mut *write_soc, &reply).await {
+                        cancel_token.cancel();
+                        return SessionExit::Menu;
+                    }
+                }
+                Some((origin, injected)) = injected_rx.recv() => {
+                    handle.record_input(origin, &injected);
+                    handle.time_command(&injected);
+                    if !handle.is_headless() {
+                        print!("{}", origin.prefix());
+                        stdout().flush().unwrap_or_default();
+                    }
+                    // injected input can be big, detaching or a kill shouldn't wait on it
+                    let tokens = [&cancel_token, &handle.soc_kill_token];
+                    let outcome = match pacer.is_off() {
+                        true => {
+                            handle.capture(Direction::Out, injected.as_bytes());
+                            write_sliced(&mut *write_soc, injected.as_bytes(), &tokens).await
+                        }
+                        false => {
+                            send_paced(&handle, &mut *write_soc, &mut pacer, injected.as_bytes(), &cancel_token).await
+                        }
+                    };
+                    match outcome {
+                        WriteOutcome::Done => {}
+                        WriteOutcome::Failed(_) => {
+                            handle.mark_closed(CloseReason::WriteError);
+                            cancel_token.cancel();
+                            return SessionExit::Menu;
+                        }
+                        WriteOutcome::Cancelled(_) => {
+                            cancel_token.cancel();
+                            return SessionExit::Menu;
+                        }
+                    }
+                }
                 res = input_future =>{
+                    line_input = None;
                     if res.is_err(){
                         println!("receiving input failed");
                         cancel_token.cancel();
-                        break;
+                        return SessionExit::Menu;
                     }
-                    let inp_string = res.unwrap();
-                    if inp_string.trim_end() == "back"{
+                    // alt-n and alt-p interrupt the line they were pressed on
+                    if let Some(step) = handle.take_switch_request() {
                         cancel_token.cancel();
-                        break;
+                        return SessionExit::Switch(step);
+                    }
+                    let inp_string = match dispatch(&res.unwrap(), mode) {
+                        Dispatch::Send(line) => {
+                            handle.record_input(InputOrigin::Operator, &line);
+                            handle.time_command(&line);
+                            line
+                        }
+                        Dispatch::Run { name, args } => match run_session_command(name, &args) {
+                            SessionAction::Detach => {
+                                cancel_token.cancel();
+                                return SessionExit::Menu;
+                            }
+                            SessionAction::Switch(step) => {
+                                cancel_token.cancel();
+                                return SessionExit::Switch(step);
+                            }
+                            SessionAction::Print(text) => {
+                                print!("{text}");
+                                // get the remote prompt back
+                                String::from("\n")
+                            }
+                            SessionAction::Note(text) => {
+                                handle.add_note(&text);
+                                println!("Noted");
+                                String::from("\n")
+                            }
+                            SessionAction::Mark(label) => {
+                                match handle.add_mark(&label) {
+                                    Ok(_) => println!("Marked {label}"),
+                                    Err(err) => println!("{err}"),
+                                }
+                                String::from("\n")
+                            }
+                            SessionAction::LocalDir(path) => {
+                                println!("{}", handle.local_dir_command(path.as_deref()));
+                                String::from("\n")
+                            }
+                            SessionAction::Capture(action) => {
+                                println!("{}", handle.capture_command(action));
+                                String::from("\n")
+                            }
+                            SessionAction::Tee(action) => {
+                                println!("{}", handle.tee_command(action));
+                                String::from("\n")
+                            }
+                            // line mode sends wait for the pace, nothing's left over to discard
+                            SessionAction::Discard => {
+                                println!("Nothing is waiting to be sent, ctrl-c stops a paced send");
+                                String::from("\n")
+                            }
+                        },
+                        Dispatch::Unknown { name, suggestion } => {
+                            match suggestion {
+                                Some(suggestion) => println!("No command {name}, did you mean {suggestion}?"),
+                                None => println!("No command {name}, {META_PREFIX}commands lists them"),
+                            }
+                            String::from("\n")
+                        }
+                    };
+                    let outcome = match pacer.is_off() {
+                        true => {
+                            handle.capture(Direction::Out, inp_string.as_bytes());
+                            write_sliced(&mut *write_soc, inp_string.as_bytes(), &[]).await
+                        }
+                        false => {
+                            send_paced(
+                                &handle,
+                                &mut *write_soc,
+                                &mut pacer,
+                                inp_string.as_bytes(),
+                                &cancel_token,
+                            )
+                            .await
+                        }
+                    };
+                    match outcome {
+                        WriteOutcome::Done => {}
+                        WriteOutcome::Failed(_) => {
+                            handle.mark_closed(CloseReason::WriteError);
+                            cancel_token.cancel();
+                            return SessionExit::Menu;
+                        }
+                        WriteOutcome::Cancelled(_) => {
+                            cancel_token.cancel();
+                            return SessionExit::Menu;
+                        }
                     }
-                    write_soc.write_all(inp_string.as_bytes()).await.unwrap();
-                    write_soc.flush().await.unwrap();
                 }
                 _ = cancel_fut =>{
-                    break;
+                    return SessionExit::Menu;
                 }
             }
         }
     }
 }
 
+/// Shows which chord prefix is waiting for its next key in the top right corner
+fn show_chord_indicator(prefix: Option<u8>) {
+    let (cols, _) = terminal_size().unwrap_or((80, 24));
+    let label = match prefix {
+        Some(code) => format!(" {} ", ctrl_label(code)),
+        None => String::from("        "),
+    };
+    let col = cols.saturating_sub(label.len() as u16).max(1);
+    print!(
+        "{save}{goto}{invert}{label}{reset}{restore}",
+        save = cursor::Save,
+        goto = cursor::Goto(col, 1),
+        invert = match prefix {
+            Some(_) => format!("{}", style::Invert),
+            None => String::new(),
+        },
+        reset = style::Reset,
+        restore = cursor::Restore
+    );
+    stdout().flush().unwrap_or_default();
+}
+
+/// Shows how much of a big paced send has gone out in the top right corner, and
+/// clears it once it's all out
+fn show_pace_indicator(sent: usize, total: usize) {
+    if total < PACE_PROGRESS_MIN || !terminal::escapes() {
+        return;
+    }
+    // redrawn every so often, not for every byte
+    if !sent.is_multiple_of(64) && sent < total {
+        return;
+    }
+    let label = match sent < total {
+        true => format!(" {:>3}% ", sent * 100 / total),
+        false => String::from("      "),
+    };
+    let (cols, _) = terminal_size().unwrap_or((80, 24));
+    let col = cols.saturating_sub(label.len() as u16).max(1);
+    print!(
+        "{save}{goto}{invert}{label}{reset}{restore}",
+        save = cursor::Save,
+        goto = cursor::Goto(col, 1),
+        invert = match sent < total {
+            true => format!("{}", style::Invert),
+            false => String::new(),
+        },
+        reset = style::Reset,
+        restore = cursor::Restore
+    );
+    stdout().flush().unwrap_or_default();
+}
+
+/// Writes bytes to the remote as they are. A write that fails closes the session
+/// and gives false
+async fn send_raw<W>(handle: &Handle, writer: &mut W, bytes: &[u8]) -> bool
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    handle.capture(Direction::Out, bytes);
+    if writer.write_all(bytes).await.is_err() || writer.flush().await.is_err() {
+        handle.mark_closed(CloseReason::WriteError);
+        return false;
+    }
+    return true;
+}
+
+/// Sends line mode input at the session's pace. Ctrl-c stops it and drops what
+/// hadn't gone out, detaching or a kill stop it like any other write
+async fn send_paced<W>(
+    handle: &Handle,
+    writer: &mut W,
+    pacer: &mut Pacer,
+    data: &[u8],
+    cancel_token: &CancellationToken,
+) -> WriteOutcome
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    pacer.push(data);
+    let interrupted = CancellationToken::new();
+    let watcher = {
+        let interrupted = interrupted.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                interrupted.cancel();
+            }
+        })
+    };
+    let tokens = [cancel_token, &handle.soc_kill_token, &interrupted];
+    let outcome = write_paced(writer, pacer, &tokens, show_pace_indicator).await;
+    watcher.abort();
+    let (sent, total) = pacer.progress();
+    handle.capture(Direction::Out, &data[..sent.min(data.len())]);
+    show_pace_indicator(total, total);
+    let dropped = pacer.clear();
+    if interrupted.is_cancelled() && !cancel_token.is_cancelled() {
+        println!("\nStopped sending, {dropped} bytes weren't sent");
+        return WriteOutcome::Done;
+    }
+    return outcome;
+}
+
 /// Sends the start signal to a handle, await until the handle is paused
-async fn start(handle: Handle) {
+async fn start(
+    name: &str,
+    handle: Handle,
+    mode: DispatchMode,
+    chord_config: ChordConfig,
+    timing: bool,
+    pace: Pace,
+    queries: QueryMode,
+) -> SessionExit {
+    let mut handle = handle;
+    // checked each time, the terminal can change between attaches under tmux or screen
+    if handle.raw_mode && !terminal::detect().raw {
+        println!("Raw mode isn't available in this terminal, attaching to {name} in line mode");
+        handle.raw_mode = false;
+    }
     //start handler
-    println!("{clear}", clear = clear::BeforeCursor);
+    println!("{clear}", clear = terminal::escape(clear::BeforeCursor));
 
     let quit_token = CancellationToken::new();
+    handle.record(
+        EventKind::Attached,
+        match handle.raw_mode {
+            true => "raw mode",
+            false => "line mode",
+        },
+    );
+    if handle.raw_mode {
+        handle.transcribe_note("attached in raw mode, keystrokes aren't split into commands");
+    } else {
+        handle.transcribe_note("attached");
+    }
 
     match handle.raw_mode {
         true => {
             println!(
-                "\r\n{guide}type \"CTRL + b\" to return to menu{reset}\r\n",
-                guide = color::Fg(color::Red),
-                reset = color::Fg(color::Reset)
+                "\r\n{guide}attached to {name}, type \"{prefix} d\" to return to menu, \"{prefix} ?\" for key bindings{reset}\r\n",
+                prefix = ctrl_label(chord_config.prefix),
+                guide = terminal::fg(color::Red, color::Red),
+                reset = terminal::fg_reset()
             );
         }
         false => {
+            let cwd = match handle.remote_cwd() {
+                Some(cwd) => format!(" in {cwd}"),
+                None => String::new(),
+            };
             println!(
-                "\r\n{guide}type \"back\" to return to menu{reset}\r\n",
-                guide = color::Fg(color::Red),
-                reset = color::Fg(color::Reset)
+                "\r\n{guide}attached to {name}{cwd}, type \"{prefix}back\" to return to menu{reset}\r\n",
+                prefix = match mode {
+                    DispatchMode::Bare => String::new(),
+                    DispatchMode::Prefix => String::from(META_PREFIX),
+                },
+                guide = terminal::fg(color::Red, color::Red),
+                reset = terminal::fg_reset()
             );
         }
     }
 
     {
-        if handle.raw_mode {
-            let size = termion::terminal_size();
-            if size.is_ok() {
-                let (cols, rows) = size.unwrap();
+        // only a shell knows what to do with stty
+        if handle.raw_mode && handle.protocol() == ProtocolHint::Shell {
+            if let Ok((cols, rows)) = termion::terminal_size() {
                 let mut write_soc = handle.write_stream.lock().await;
-                write_soc
-                    .write_all(format!("\nstty rows {rows} cols {cols}\n").as_bytes())
-                    .await
-                    .unwrap();
-                write_soc.flush().await.unwrap();
+                let stty = format!("\nstty rows {rows} cols {cols}\n");
+                // a failure closes the session, soc_write sees that and goes back to the menu
+                if write_soc.write_all(stty.as_bytes()).await.is_err()
+                    || write_soc.flush().await.is_err()
+                {
+                    handle.mark_closed(CloseReason::WriteError);
+                }
             }
         }
     }
 
     let out_writer: Box<dyn Write + Send> = match handle.raw_mode {
         true => {
-            let tty_res = termion::get_tty();
             let mut out: Box<dyn Write + Send> = Box::new(stdout());
-            if tty_res.is_ok() {
-                out = Box::new(tty_res.unwrap());
+            if let Ok(tty) = termion::get_tty() {
+                out = Box::new(tty);
             }
             out
         }
@@ -181,11 +686,114 @@ async fn start(handle: Handle) {
     };
 
     let (prompt_tx, prompt_rx) = watch::channel(String::from(""));
-    let reader_handle = soc_read(handle.clone(), out_writer, quit_token.clone(), prompt_tx);
+    let reader_handle = soc_read(
+        handle.clone(),
+        out_writer,
+        quit_token.clone(),
+        prompt_tx,
+        timing,
+        queries,
+    );
 
     // start write to socket thread
-    let writer_handle = soc_write(handle.clone(), quit_token.clone(), prompt_rx);
-    join!(reader_handle, writer_handle);
+    let writer_handle = soc_write(
+        handle.clone(),
+        quit_token.clone(),
+        prompt_rx,
+        mode,
+        chord_config,
+        pace,
+    );
+    let (_, exit) = join!(reader_handle, writer_handle);
+    handle.record(
+        EventKind::Detached,
+        match exit {
+            SessionExit::Menu => "back to the menu",
+            SessionExit::Switch(_) => "switched shells",
+        },
+    );
+    handle.transcribe_note("detached");
+    // the remote is idle now, a good time to look where it is
+    handle.refresh_remote_cwd().await;
+
+    if handle.is_closed() {
+        println!(
+            "\r\n{guide}session closed by remote, returning to menu{reset}\r",
+            guide = terminal::fg(color::Red, color::Red),
+            reset = terminal::fg_reset()
+        );
+        return SessionExit::Menu;
+    }
+    return exit;
+}
+
+/// Attaches to `key`, then to whichever shell it's switched to until one goes back to
+/// the menu
+pub(crate) async fn attach(
+    shells: &HashMap<String, Handle>,
+    key: String,
+    settings: &SharedSettings,
+) {
+    let mut key = key;
+    while let Some(handle) = shells.get(&key).cloned() {
+        let (mode, chord_config, timing, pace, queries) = match settings.lock() {
+            Ok(settings) => {
+                let (mode, chord_config, timing) = session_input(&settings, &key);
+                let queries = settings
+                    .get("terminal_queries", Some(&key))
+                    .map(|(mode, _)| QueryMode::parse(&mode))
+                    .unwrap_or(QueryMode::Answer);
+                (
+                    mode,
+                    chord_config,
+                    timing,
+                    settings.pace(Some(&key)),
+                    queries,
+                )
+            }
+            Err(_) => (
+                DispatchMode::Bare,
+                ChordConfig::default(),
+                false,
+                Pace::default(),
+                QueryMode::Answer,
+            ),
+        };
+        set_title(settings, &session_title(&key, handle.peer_addr));
+        let step = match start(&key, handle, mode, chord_config, timing, pace, queries).await {
+            SessionExit::Menu => break,
+            SessionExit::Switch(step) => step,
+        };
+        // the target's own raw or line mode applies when it starts
+        key = match adjacent_session(shells, &key, step) {
+            Some(val) => val,
+            None => {
+                println!("No other open shells, returning to menu");
+                break;
+            }
+        };
+    }
+}
+
+/// The open shell `step` places from `current` in name order, wrapping around
+fn adjacent_session(
+    shells: &HashMap<String, Handle>,
+    current: &str,
+    step: isize,
+) -> Option<String> {
+    let mut names: Vec<&String> = shells
+        .iter()
+        .filter(|(name, handle)| !handle.is_closed() || name.as_str() == current)
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+    let idx = names.iter().position(|name| name.as_str() == current)? as isize;
+    let len = names.len() as isize;
+    let next = names[(idx + step).rem_euclid(len) as usize];
+    if next == current {
+        return None;
+    }
+    return Some(next.clone());
 }
 
 async fn delete(key: String, connected_shells: &mut MutexGuard<'_, HashMap<String, Handle>>) {
@@ -196,7 +804,35 @@ async fn delete(key: String, connected_shells: &mut MutexGuard<'_, HashMap<Strin
             return;
         }
     };
-    handle.write_stream.lock().await.flush().await.unwrap();
+    handle
+        .write_stream
+        .lock()
+        .await
+        .flush()
+        .await
+        .unwrap_or_default();
+}
+
+/// Gives a shell a new name, keeping restored shells pointing at it
+pub(crate) fn rename_shell(
+    shells: &mut HashMap<String, Handle>,
+    old: &str,
+    new: &str,
+) -> Result<(), &'static str> {
+    if new.is_empty() {
+        return Err("Alias cannot be empty");
+    }
+    if shells.contains_key(new) {
+        return Err("Alias already exists");
+    }
+    let shell = shells.remove(old).ok_or("No such shell")?;
+    for other in shells.values_mut() {
+        if other.restored_from.as_deref() == Some(old) {
+            other.restored_from = Some(String::from(new));
+        }
+    }
+    shells.insert(String::from(new), shell);
+    return Ok(());
 }
 
 fn alias(
@@ -238,24 +874,19 @@ fn alias(
         match key.unwrap() {
             Key::Char(c) => {
                 if c == '\n' || c == '\r' {
-                    if input.is_empty() {
-                        prompt = String::from("❌ Alias cannot be empty, please try again: ")
-                    } else if connected_shells.contains_key(&input) {
-                        input = String::new();
-                        prompt = String::from("❌ Alias already exists, please try again: ")
-                    } else {
-                        let shell = connected_shells.remove(&shell_key).unwrap();
-                        connected_shells.insert(input, shell);
-                        return;
+                    match rename_shell(connected_shells, &shell_key, &input) {
+                        Ok(_) => return,
+                        Err(err) => {
+                            input = String::new();
+                            prompt = format!("❌ {err}, please try again: ");
+                        }
                     }
                 } else {
                     input += &c.to_string();
                 }
             }
             Key::Backspace | Key::Delete => {
-                if input.len() > 0 {
-                    input = String::from(&input[..input.len() - 1]);
-                }
+                input.pop();
             }
             Key::Esc => {
                 return;
@@ -285,19 +916,14 @@ fn list_menu_help(stdout: &mut RawTerminal<Stdout>) {
         String::from("(a - rename shell) (r - enter tty (raw) mode)"),
     ];
     for msg in msgs {
-        let mut display_msg = msg.clone();
-        if msg.len() > width.into() {
-            let split = max(width - 3, 0).into();
-            display_msg = String::from(&msg.as_str()[..split]);
-            display_msg = display_msg + "...";
-        }
+        let display_msg = truncate(&msg, width.into());
         write!(
             stdout,
             "\r\n{goto}{select}{msg}{reset}",
             goto = cursor::Goto(0, start_pos),
             msg = display_msg,
-            select = color::Bg(color::LightBlack),
-            reset = color::Bg(color::Reset)
+            select = terminal::bg(color::LightBlack, color::Rgb(210, 210, 210)),
+            reset = terminal::bg_reset()
         )
         .unwrap();
         stdout.flush().unwrap();
@@ -312,35 +938,45 @@ fn refresh_list_display(
     write!(
         stdout,
         "{goto}{clear}{clear_before}",
-        goto = cursor::Goto(0, 2 as u16),
+        goto = cursor::Goto(0, 2),
         clear = clear::AfterCursor,
         clear_before = clear::BeforeCursor
     )
     .unwrap();
     stdout.flush().unwrap();
     for (i, key) in keys.clone().into_iter().enumerate() {
-        let raw_mode = match key.1.raw_mode {
+        let mut raw_mode = String::from(match key.1.raw_mode {
             true => " (raw)",
             false => "",
-        };
-        let selection: String;
-        if i == cur_idx {
-            selection = format!(
+        });
+        if key.1.is_redialing() {
+            raw_mode += " (reconnecting)";
+        } else if key.1.is_closed() {
+            raw_mode += " (closed)";
+        }
+        if let Some(previous) = &key.1.restored_from {
+            raw_mode += &format!(" (restored from {previous})");
+        }
+        if key.1.protocol() != ProtocolHint::Shell {
+            raw_mode += &format!(" ({})", key.1.protocol().name());
+        }
+        let selection = if i == cur_idx {
+            format!(
                 "{select}{key}{raw}{reset}{hide}",
                 key = key.0,
                 raw = raw_mode,
-                select = color::Bg(color::Red),
+                select = terminal::bg(color::Red, color::Red),
                 hide = cursor::Hide,
-                reset = color::Bg(color::Reset),
-            );
+                reset = terminal::bg_reset(),
+            )
         } else {
-            selection = format!(
+            format!(
                 "{key}{raw}{hide}",
                 key = key.0,
                 raw = raw_mode,
                 hide = cursor::Hide,
-            );
-        }
+            )
+        };
         write!(stdout, "{}", selection).unwrap();
         if i < &keys.len() - 1 {
             write!(stdout, "\r\n{clear}", clear = clear::AfterCursor).unwrap();
@@ -350,11 +986,61 @@ fn refresh_list_display(
     list_menu_help(stdout);
 }
 
-pub fn new() -> MenuList {
+/// How a session's input is read, from its settings
+fn session_input(settings: &Settings, session: &str) -> (DispatchMode, ChordConfig, bool) {
+    let mode = settings
+        .get("meta_commands", Some(session))
+        .map(|(mode, _)| DispatchMode::parse(&mode))
+        .unwrap_or(DispatchMode::Bare);
+    let defaults = ChordConfig::default();
+    let prefix = settings
+        .get("escape_key", Some(session))
+        .ok()
+        .and_then(|(key, _)| ctrl_code(&key))
+        .unwrap_or(defaults.prefix);
+    let chord_config = ChordConfig {
+        prefix,
+        timeout: Duration::from_millis(settings.get_number("chord_timeout_ms", Some(session))),
+    };
+    let timing = settings.get_bool("timing", Some(session));
+    return (mode, chord_config, timing);
+}
+
+pub fn new(
+    settings: SharedSettings,
+    config_path: PathBuf,
+    loot_dir: PathBuf,
+    state: SharedState,
+    dialer: DialSender,
+) -> MenuList {
     let mut menu: MenuList = HashMap::new();
 
-    let list = |connected_shells: Arc<Mutex<HashMap<String, Handle>>>| -> Option<JoinHandle<()>> {
+    let list_settings_shared = settings.clone();
+    let list = move |connected_shells: Arc<Mutex<HashMap<String, Handle>>>,
+                     args: String|
+          -> Option<JoinHandle<()>> {
+        let list_settings = list_settings_shared.clone();
         Some(tokio::spawn(async move {
+            let name = args.trim();
+            if !name.is_empty() {
+                let shells = connected_shells.lock().await;
+                match shells.contains_key(name) {
+                    true => attach(&shells, String::from(name), &list_settings).await,
+                    false => println!("No shell called {name}"),
+                }
+                return;
+            }
+            // the picker reads single keys, without raw mode it's just a list
+            if !terminal::detect().raw {
+                let shells = connected_shells.lock().await;
+                let mut names: Vec<&String> = shells.keys().collect();
+                names.sort();
+                for name in names {
+                    println!("{name}");
+                }
+                println!("Raw mode isn't available in this terminal, attach with `l <name>`");
+                return;
+            }
             let stdin = stdin();
             let mut stdout = stdout().into_raw_mode().unwrap();
             let mut shell_list: Vec<(String, Handle)>;
@@ -366,7 +1052,7 @@ pub fn new() -> MenuList {
                     .map(|item| (item.0.to_owned(), item.1.to_owned()))
                     .collect::<Vec<(String, Handle)>>()
             }
-            if shell_list.len() > 0 {
+            if !shell_list.is_empty() {
                 let (_, start_pos) = stdout.cursor_pos().unwrap();
                 let mut cur_idx = 0;
                 let mut keys: Vec<String>;
@@ -384,31 +1070,26 @@ pub fn new() -> MenuList {
                                 return;
                             }
                             Key::Up => {
-                                if cur_idx > 0 {
-                                    cur_idx -= 1;
-                                }
+                                cur_idx = cur_idx.saturating_sub(1);
                             }
-                            Key::Down => {
-                                if cur_idx < keys.len() - 1 {
-                                    cur_idx += 1;
-                                }
+                            Key::Down if cur_idx < keys.len() - 1 => {
+                                cur_idx += 1;
                             }
                             Key::Char('\n') | Key::Char('\r') => {
                                 let key = keys[cur_idx].to_owned();
-                                let handle_opt = shells.get(&key);
-                                if handle_opt.is_some() {
-                                    let handle = handle_opt.unwrap().clone();
-                                    println!(
-                                        "\r\n{show}{blink}{clear}",
-                                        show = cursor::Show,
-                                        blink = cursor::BlinkingBlock,
-                                        clear = clear::AfterCursor
-                                    );
-                                    // drop the mutex guard so we're not holding and waiting
-                                    // drop(shells);
-                                    stdout.suspend_raw_mode().unwrap();
-                                    start(handle).await;
+                                if !shells.contains_key(&key) {
+                                    return;
                                 }
+                                println!(
+                                    "\r\n{show}{blink}{clear}",
+                                    show = cursor::Show,
+                                    blink = cursor::BlinkingBlock,
+                                    clear = clear::AfterCursor
+                                );
+                                // drop the mutex guard so we're not holding and waiting
+                                // drop(shells);
+                                stdout.suspend_raw_mode().unwrap();
+                                attach(&shells, key, &list_settings).await;
                                 return;
                             }
                             Key::Char('r') => {
@@ -423,9 +1104,7 @@ pub fn new() -> MenuList {
                             Key::Delete | Key::Backspace => {
                                 let key: String = keys[cur_idx].to_owned();
                                 delete(key, &mut shells).await;
-                                if cur_idx > 0 {
-                                    cur_idx -= 1;
-                                }
+                                cur_idx = cur_idx.saturating_sub(1);
                                 write!(stdout, "{}", clear::CurrentLine).unwrap();
                                 stdout.flush().unwrap();
 
@@ -460,26 +1139,56 @@ pub fn new() -> MenuList {
     };
     menu.insert("l", Box::new(list));
 
-    let clear = |_| {
+    let context = MenuContext {
+        settings: settings.clone(),
+        config_path,
+        loot_dir,
+        state: state.clone(),
+        dialer,
+        watches: Arc::default(),
+        next_watch: Arc::new(AtomicU64::new(1)),
+    };
+    for (name, handler) in HANDLERS {
+        let context = context.clone();
+        menu.insert(
+            name,
+            Box::new(move |shells, args| handler(&context, shells, args)),
+        );
+    }
+
+    let exit_settings = settings.clone();
+    let clear = |_, _| {
         clear();
         None
     };
 
     menu.insert("clear", Box::new(clear));
 
-    menu.insert(
-        "h",
-        Box::new(|_| {
-            help();
-            None
-        }),
-    );
+    let help_entry = |_, args: String| {
+        match args.trim() {
+            "" => help(),
+            name => print!("{}", command_help(name)),
+        }
+        None
+    };
+    menu.insert("help", Box::new(help_entry));
+    menu.insert("h", Box::new(help_entry));
 
     menu.insert(
         "exit",
-        Box::new(|_| {
-            exit();
-            None
+        Box::new(move |connected_shells, _| {
+            let settings = exit_settings.clone();
+            let state = state.clone();
+            Some(tokio::spawn(async move {
+                // a clean shutdown, the sessions are saved as they are now
+                let shells = connected_shells.lock().await;
+                for handle in shells.values() {
+                    handle.mark_closed(CloseReason::ListenerShutdown);
+                    handle.flush_transcript().await;
+                }
+                save_state(&state, &shells, &settings);
+                exit();
+            }))
         }),
     );
 
@@ -488,14 +1197,14 @@ pub fn new() -> MenuList {
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
-
     use tokio::{
         net::{TcpListener, TcpStream},
         time::sleep,
     };
 
+    use crate::menu::output::{DEFAULT_MAX_LINE_WIDTH, PROMPT_WINDOW};
     use crate::socket::connection::handle_new_shell;
+    use crate::socket::mock_shell::spawn_shell_session;
 
     use super::*;
 
@@ -508,7 +1217,7 @@ mod tests {
             let (soc, _) = listener.accept().await.unwrap();
             let connected_shells = Arc::new(Mutex::new(HashMap::<String, Handle>::new()));
             handle_new_shell(soc, connected_shells.clone(), Some(true)).await;
-            assert!(connected_shells.lock().await.len() > 0);
+            assert!(!connected_shells.lock().await.is_empty());
             let cancel_token = CancellationToken::new();
             let cancel_token_copy = cancel_token.clone();
 
@@ -524,16 +1233,322 @@ mod tests {
             let handle_copy = handle.clone();
             let write_handle = tokio::spawn(async move {
                 let mut write_half = handle.write_stream.lock().await;
-                write_half.write("hello\n".as_bytes()).await.unwrap();
+                write_half.write_all("hello\n".as_bytes()).await.unwrap();
                 write_half.flush().await.unwrap();
                 sleep(Duration::from_millis(500)).await;
                 cancel_token_copy.cancel();
             });
             let (prompt_tx, _) = watch::channel(String::from(""));
-            soc_read(handle_copy, &mut buf, cancel_token, prompt_tx).await;
+            soc_read(
+                handle_copy,
+                &mut buf,
+                cancel_token,
+                prompt_tx,
+                false,
+                QueryMode::Answer,
+            )
+            .await;
             write_handle.await.unwrap();
         });
         TcpStream::connect("127.0.0.1:32425").await.unwrap();
         init_handle.await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_timing_display() {
+        let listener = TcpListener::bind("127.0.0.1:32457").await.unwrap();
+        let client = tokio::spawn(TcpStream::connect("127.0.0.1:32457"));
+        let (soc, _) = listener.accept().await.unwrap();
+        let mut remote = client.await.unwrap().unwrap();
+        let (read, write) = soc.into_split();
+        let handle = Handle::new_headless(read, write);
+        let cancel_token = CancellationToken::new();
+        let stopper = cancel_token.clone();
+        let timed = handle.clone();
+        tokio::spawn(async move {
+            timed.time_command("sleep 1");
+            sleep(Duration::from_millis(150)).await;
+            remote.write_all(b"done\n$ ").await.unwrap();
+            sleep(Duration::from_millis(100)).await;
+            // a prompt nobody asked for isn't timed
+            remote.write_all(b"\n$ ").await.unwrap();
+            sleep(Duration::from_millis(100)).await;
+            stopper.cancel();
+        });
+        let mut buf: Vec<u8> = Vec::new();
+        let (prompt_tx, _) = watch::channel(String::from(""));
+        soc_read(
+            handle,
+            &mut buf,
+            cancel_token,
+            prompt_tx,
+            true,
+            QueryMode::Answer,
+        )
+        .await;
+        let shown = String::from_utf8_lossy(&buf);
+        assert_eq!(shown.matches("[took").count(), 1);
+        let took = shown.split_once("[took ").unwrap().1;
+        assert!(took.starts_with("0.1s]") || took.starts_with("0.2s]"));
+        assert!(shown.find("done").unwrap() < shown.find("[took").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_answers_terminal_queries() {
+        let listener = TcpListener::bind("127.0.0.1:32481").await.unwrap();
+        let client = tokio::spawn(TcpStream::connect("127.0.0.1:32481"));
+        let (soc, _) = listener.accept().await.unwrap();
+        let mut remote = client.await.unwrap().unwrap();
+        let (read, write) = soc.into_split();
+        let handle = Handle::new_headless(read, write);
+        let cancel_token = CancellationToken::new();
+        let (prompt_tx, prompt_rx) = watch::channel(String::from(""));
+        let writer = tokio::spawn(soc_write(
+            handle.clone(),
+            cancel_token.clone(),
+            prompt_rx,
+            DispatchMode::Bare,
+            ChordConfig::default(),
+            Pace::default(),
+        ));
+        let stopper = cancel_token.clone();
+        let asker = tokio::spawn(async move {
+            remote.write_all(b"a\x1b[6").await.unwrap();
+            sleep(Duration::from_millis(50)).await;
+            remote.write_all(b"nb\x1b[1m$ ").await.unwrap();
+            let mut reply = [0; 6];
+            remote.read_exact(&mut reply).await.unwrap();
+            stopper.cancel();
+            return reply;
+        });
+        let mut buf: Vec<u8> = Vec::new();
+        soc_read(
+            handle,
+            &mut buf,
+            cancel_token,
+            prompt_tx,
+            false,
+            QueryMode::Answer,
+        )
+        .await;
+        assert_eq!(&asker.await.unwrap(), b"\x1b[1;1R");
+        writer.await.unwrap();
+        let shown = String::from_utf8_lossy(&buf);
+        assert!(shown.contains('a') && shown.contains("b\x1b[1m$ "));
+        assert!(!shown.contains("[6n"));
+    }
+
+    #[tokio::test]
+    async fn test_soc_write_exits_on_session_close() {
+        let listener_res = TcpListener::bind("127.0.0.1:32426").await;
+        assert!(listener_res.is_ok());
+        let listener = listener_res.unwrap();
+        let init_handle = tokio::spawn(async move {
+            let (soc, _) = listener.accept().await.unwrap();
+            let (read, write) = soc.into_split();
+            // headless, so the writer stays parked waiting for injected input
+            let handle = Handle::new_headless(read, write);
+            let cancel_token = CancellationToken::new();
+            let (_, prompt_rx) = watch::channel(String::from(""));
+            let writer = tokio::spawn(soc_write(
+                handle.clone(),
+                cancel_token.clone(),
+                prompt_rx,
+                DispatchMode::Bare,
+                ChordConfig::default(),
+                Pace::default(),
+            ));
+
+            sleep(Duration::from_millis(200)).await;
+            assert!(!writer.is_finished());
+            handle.soc_kill_token.cancel();
+            let res = tokio::time::timeout(Duration::from_secs(2), writer).await;
+            assert!(res.is_ok());
+            assert!(cancel_token.is_cancelled());
+        });
+        let _client = TcpStream::connect("127.0.0.1:32426").await.unwrap();
+        init_handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_soc_closes_session_on_eof() {
+        let listener_res = TcpListener::bind("127.0.0.1:32427").await;
+        assert!(listener_res.is_ok());
+        let listener = listener_res.unwrap();
+        let init_handle = tokio::spawn(async move {
+            let (soc, _) = listener.accept().await.unwrap();
+            let (read, write) = soc.into_split();
+            let handle = Handle::new_headless(read, write);
+            let cancel_token = CancellationToken::new();
+            let (prompt_tx, _) = watch::channel(String::from(""));
+            let mut buf: Vec<u8> = Vec::new();
+            let res = tokio::time::timeout(
+                Duration::from_secs(2),
+                soc_read(
+                    handle.clone(),
+                    &mut buf,
+                    cancel_token.clone(),
+                    prompt_tx,
+                    false,
+                    QueryMode::Answer,
+                ),
+            )
+            .await;
+            assert!(res.is_ok());
+            assert!(handle.is_closed());
+            assert!(cancel_token.is_cancelled());
+        });
+        drop(TcpStream::connect("127.0.0.1:32427").await.unwrap());
+        init_handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_headless_session_injection() {
+        let handle = spawn_shell_session(32432).await;
+        let mut output_rx = handle.subscribe_output();
+        let cancel_token = CancellationToken::new();
+        let (prompt_tx, prompt_rx) = watch::channel(String::from(""));
+        let reader = tokio::spawn(soc_read(
+            handle.clone(),
+            Vec::<u8>::new(),
+            cancel_token.clone(),
+            prompt_tx,
+            false,
+            QueryMode::Answer,
+        ));
+        let writer = tokio::spawn(soc_write(
+            handle.clone(),
+            cancel_token.clone(),
+            prompt_rx,
+            DispatchMode::Bare,
+            ChordConfig::default(),
+            Pace::default(),
+        ));
+
+        assert!(handle.inject_input("echo injected''_line"));
+        let mut output = String::new();
+        while !output.contains("injected_line") {
+            let chunk = tokio::time::timeout(Duration::from_secs(2), output_rx.recv()).await;
+            output += &String::from_utf8_lossy(&chunk.unwrap().unwrap());
+        }
+        cancel_token.cancel();
+        reader.await.unwrap();
+        writer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_headless_paced_injection() {
+        let handle = spawn_shell_session(32474).await;
+        let mut output_rx = handle.subscribe_output();
+        let cancel_token = CancellationToken::new();
+        let (prompt_tx, prompt_rx) = watch::channel(String::from(""));
+        let reader = tokio::spawn(soc_read(
+            handle.clone(),
+            Vec::<u8>::new(),
+            cancel_token.clone(),
+            prompt_tx,
+            false,
+            QueryMode::Answer,
+        ));
+        let pace = Pace {
+            chars_per_sec: 200,
+            newline_delay: Duration::from_millis(50),
+        };
+        let writer = tokio::spawn(soc_write(
+            handle.clone(),
+            cancel_token.clone(),
+            prompt_rx,
+            DispatchMode::Bare,
+            ChordConfig::default(),
+            pace,
+        ));
+
+        let started = Instant::now();
+        assert!(handle.inject_input("echo paced''_line\n"));
+        let mut output = String::new();
+        while !output.contains("paced_line") {
+            let chunk = tokio::time::timeout(Duration::from_secs(2), output_rx.recv()).await;
+            output += &String::from_utf8_lossy(&chunk.unwrap().unwrap());
+        }
+        // 18 bytes at 5ms each, the line only ends with the last one
+        assert!(started.elapsed() >= Duration::from_millis(85));
+        cancel_token.cancel();
+        reader.await.unwrap();
+        writer.await.unwrap();
+    }
+
+    struct CountingWriter {
+        bytes: usize,
+    }
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.bytes += buf.len();
+            return Ok(buf.len());
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_soc_huge_line() {
+        let listener = TcpListener::bind("127.0.0.1:32433").await.unwrap();
+        let (sent_tx, sent_rx) = tokio::sync::oneshot::channel::<()>();
+        let (checked_tx, checked_rx) = tokio::sync::oneshot::channel::<()>();
+        let remote = tokio::spawn(async move {
+            let mut soc = TcpStream::connect("127.0.0.1:32433").await.unwrap();
+            let block = vec![b'A'; 1024 * 1024];
+            for _ in 0..100 {
+                soc.write_all(&block).await.unwrap();
+            }
+            sent_tx.send(()).unwrap();
+            checked_rx.await.unwrap();
+            soc.write_all("\nuser@box:~$ ".as_bytes()).await.unwrap();
+        });
+        let (soc, _) = listener.accept().await.unwrap();
+        let (read, write) = soc.into_split();
+        let handle = Handle::new_headless(read, write);
+        let mut writer = CountingWriter { bytes: 0 };
+        let (prompt_tx, prompt_rx) = watch::channel(String::from(""));
+        let mid_line = prompt_rx.clone();
+        let check = async move {
+            // 100 MB of one line with no newline yet, what's held for the prompt is capped
+            sent_rx.await.unwrap();
+            let held = mid_line.borrow().len();
+            assert!(held > 0 && held <= PROMPT_WINDOW);
+            checked_tx.send(()).unwrap();
+        };
+        let read = soc_read(
+            handle,
+            &mut writer,
+            CancellationToken::new(),
+            prompt_tx,
+            false,
+            QueryMode::Answer,
+        );
+        tokio::join!(read, check);
+        remote.await.unwrap();
+
+        // only the width limit of the line plus the per chunk line resets make it out
+        assert!(writer.bytes < DEFAULT_MAX_LINE_WIDTH + 1024 * 1024);
+        assert_eq!(*prompt_rx.borrow(), "user@box:~$ ");
+    }
+
+    #[tokio::test]
+    async fn test_adjacent_session() {
+        let mut shells = HashMap::new();
+        shells.insert(String::from("a"), spawn_shell_session(32446).await);
+        shells.insert(String::from("b"), spawn_shell_session(32447).await);
+        shells.insert(String::from("c"), spawn_shell_session(32448).await);
+        assert_eq!(adjacent_session(&shells, "a", 1), Some(String::from("b")));
+        assert_eq!(adjacent_session(&shells, "a", -1), Some(String::from("c")));
+        // closed shells are skipped
+        shells["b"].mark_closed(CloseReason::RemoteEof);
+        assert_eq!(adjacent_session(&shells, "a", 1), Some(String::from("c")));
+        assert_eq!(adjacent_session(&shells, "c", 1), Some(String::from("a")));
+        shells["c"].mark_closed(CloseReason::RemoteEof);
+        assert_eq!(adjacent_session(&shells, "a", 1), None);
+        assert_eq!(adjacent_session(&shells, "gone", 1), None);
+    }
 }
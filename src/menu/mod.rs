@@ -1 +1,9 @@
-pub mod menu_list;
\ No newline at end of file
+pub mod commands;
+pub mod dashboard;
+pub mod dispatch;
+pub mod menu_list;
+pub mod output;
+pub mod render;
+pub mod terminal;
+pub mod timeline;
+pub mod title;
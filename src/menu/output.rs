@@ -0,0 +1,140 @@
+use termion::clear;
+
+use crate::menu::terminal;
+
+/// how much of the latest output is kept when looking for the prompt
+pub const PROMPT_WINDOW: usize = 256;
+
+/// longest line shown in non raw mode before the rest of it is dropped
+pub const DEFAULT_MAX_LINE_WIDTH: usize = 16384;
+
+/// Returns at most the last `max` bytes of `content`, cut on a char boundary
+pub fn tail(content: &str, max: usize) -> &str {
+    if content.len() <= max {
+        return content;
+    }
+    let mut start = content.len() - max;
+    while !content.is_char_boundary(start) {
+        start += 1;
+    }
+    return &content[start..];
+}
+
+/// Picks the prompt out of a chunk of output, bounded to the prompt window
+pub fn prompt_from_chunk(content: &str) -> &str {
+    return tail(content.lines().last().unwrap_or(""), PROMPT_WINDOW);
+}
+
+/// Truncates lines longer than the configured width as output streams through,
+/// only the current column is tracked so memory stays bounded
+pub struct LineLimiter {
+    width: usize,
+    column: usize,
+    dropped: usize,
+}
+
+impl LineLimiter {
+    pub fn new(width: usize) -> LineLimiter {
+        return LineLimiter {
+            width,
+            column: 0,
+            dropped: 0,
+        };
+    }
+
+    fn take_line_piece(&mut self, piece: &str, out: &mut String) {
+        let budget = self.width.saturating_sub(self.column);
+        let mut take = piece.len().min(budget);
+        while !piece.is_char_boundary(take) {
+            take -= 1;
+        }
+        out.push_str(&piece[..take]);
+        self.column += take;
+        self.dropped += piece.len() - take;
+    }
+
+    fn end_line(&mut self, out: &mut String) {
+        if self.dropped > 0 {
+            out.push_str(&format!(" ... [{} bytes truncated]", self.dropped));
+        }
+        out.push('\n');
+        self.column = 0;
+        self.dropped = 0;
+    }
+
+    /// Returns the part of the chunk that should be displayed
+    pub fn feed(&mut self, chunk: &str) -> String {
+        let mut out = String::with_capacity(chunk.len().min(self.width + 64));
+        let mut pieces = chunk.split('\n').peekable();
+        while let Some(piece) = pieces.next() {
+            self.take_line_piece(piece, &mut out);
+            if pieces.peek().is_some() {
+                self.end_line(&mut out);
+            }
+        }
+        return out;
+    }
+}
+
+/// What's written to the terminal for a chunk of a shell's output, outside of the
+/// timing line. Line mode starts over on the current line and truncates long lines
+pub fn render_chunk(content: &str, raw: bool, limiter: &mut LineLimiter) -> String {
+    return match raw {
+        true => String::from(content),
+        false => terminal::escape(format!("\r{}", clear::CurrentLine)) + &limiter.feed(content),
+    };
+}
+
+/// Bytes as hex and printable ascii, 16 to a line. `offset` is where the first one
+/// falls in the stream, so dumps of later chunks carry on counting
+pub fn hex_dump(bytes: &[u8], offset: usize) -> String {
+    let mut text = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{b:02x}")).collect();
+        let ascii: String = line
+            .iter()
+            .map(|b| match b {
+                0x20..=0x7e => *b as char,
+                _ => '.',
+            })
+            .collect();
+        text += &format!(
+            "{:08x}  {:<47}  {ascii}\r\n",
+            offset + i * 16,
+            hex.join(" ")
+        );
+    }
+    return text;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_from_chunk() {
+        assert_eq!(prompt_from_chunk("output\nuser@box:~$ "), "user@box:~$ ");
+        let long_line = "A".repeat(PROMPT_WINDOW * 4);
+        assert_eq!(prompt_from_chunk(&long_line).len(), PROMPT_WINDOW);
+        assert_eq!(tail("🦀🦀", 5), "🦀");
+    }
+
+    #[test]
+    fn test_line_limiter() {
+        let mut limiter = LineLimiter::new(4);
+        assert_eq!(limiter.feed("ab"), "ab");
+        assert_eq!(limiter.feed("cdef"), "cd");
+        assert_eq!(limiter.feed("g\nok\n"), " ... [3 bytes truncated]\nok\n");
+        assert_eq!(limiter.feed("$ "), "$ ");
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        assert_eq!(
+            hex_dump(b"GET / HTTP/1.1\r\nHost", 32),
+            "00000020  47 45 54 20 2f 20 48 54 54 50 2f 31 2e 31 0d 0a  GET / HTTP/1.1..\r\n\
+             00000030  48 6f 73 74                                      Host\r\n"
+        );
+        assert_eq!(hex_dump(b"", 0), "");
+    }
+}
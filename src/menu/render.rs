@@ -0,0 +1,254 @@
+use std::future::pending;
+
+use termion::terminal_size;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+/// used when stdout isn't a terminal
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// gap between table columns
+const COLUMN_GAP: &str = "  ";
+
+pub fn terminal_width() -> usize {
+    return match terminal_size() {
+        Ok((cols, _)) if cols > 0 => cols as usize,
+        _ => DEFAULT_WIDTH,
+    };
+}
+
+/// Cuts `text` to `width` characters, ending in an ellipsis when anything was cut
+pub fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return String::from(text);
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let mut cut: String = text.chars().take(width - 1).collect();
+    cut.push('…');
+    return cut;
+}
+
+/// Wraps `text` on spaces to fit in `width`, lines after the first start with
+/// `indent` spaces. Words longer than a line are split
+pub fn wrap(text: &str, width: usize, indent: usize) -> Vec<String> {
+    return wrap_after(text, width, 0, indent);
+}
+
+/// Wraps like `wrap` for text that starts `first` columns into its first line
+fn wrap_after(text: &str, width: usize, first: usize, indent: usize) -> Vec<String> {
+    let pad = " ".repeat(indent);
+    // never squeeze a line to nothing on a tiny terminal
+    let width = width.max(first.max(indent) + 10);
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    let mut len = first;
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        loop {
+            let start = match lines.is_empty() {
+                true => first,
+                false => indent,
+            };
+            let space = usize::from(len > start);
+            if len + space + word.len() <= width {
+                if space == 1 {
+                    line.push(' ');
+                }
+                line.extend(word.iter());
+                len += space + word.len();
+                break;
+            }
+            if len == start {
+                // alone on its line and still too long
+                let rest = word.split_off(width - len);
+                line.extend(word.iter());
+                word = rest;
+            }
+            lines.push(std::mem::take(&mut line));
+            line = pad.clone();
+            len = indent;
+        }
+    }
+    let start = match lines.is_empty() {
+        true => first,
+        false => indent,
+    };
+    if len > start || lines.is_empty() {
+        lines.push(line);
+    }
+    return lines;
+}
+
+/// `label` followed by `text` wrapped to `width`, with the lines after the first
+/// lined up under column `indent`
+pub fn hanging(label: &str, text: &str, width: usize, indent: usize) -> String {
+    let lines = wrap_after(text, width, label.chars().count(), indent);
+    return format!("{label}{}\n", lines.join("\n"));
+}
+
+/// How a table column gives way when the terminal is narrow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Column {
+    /// narrowest it's cut to
+    pub min: usize,
+    /// lower priority columns are cut first
+    pub priority: u8,
+}
+
+/// Works out how wide each column can be to fit in `width`
+fn fit_columns(natural: &[usize], columns: &[Column], width: usize) -> Vec<usize> {
+    let mut widths = natural.to_vec();
+    let gaps = COLUMN_GAP.len() * natural.len().saturating_sub(1);
+    let mut over = (widths.iter().sum::<usize>() + gaps).saturating_sub(width);
+    let mut order: Vec<usize> = (0..widths.len()).collect();
+    order.sort_by_key(|idx| columns[*idx].priority);
+    for idx in order {
+        if over == 0 {
+            break;
+        }
+        let give = widths[idx].saturating_sub(columns[idx].min).min(over);
+        widths[idx] -= give;
+        over -= give;
+    }
+    return widths;
+}
+
+/// Lines up rows in columns, cutting low priority columns with an ellipsis until the
+/// table fits in `width`
+pub fn render_table(rows: &[Vec<String>], columns: &[Column], width: usize) -> String {
+    let mut natural = vec![0; columns.len()];
+    for row in rows {
+        for (idx, cell) in row.iter().enumerate().take(columns.len()) {
+            natural[idx] = natural[idx].max(cell.chars().count());
+        }
+    }
+    let widths = fit_columns(&natural, columns, width);
+    let mut text = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", truncate(cell, *width)))
+            .collect();
+        text += cells.join(COLUMN_GAP).trim_end();
+        text += "\n";
+    }
+    return text;
+}
+
+/// Listens for the terminal being resized, none when the signal can't be caught
+pub fn resize_events() -> Option<Signal> {
+    return signal(SignalKind::window_change()).ok();
+}
+
+/// Resolves on the next resize
+pub async fn resized(events: &mut Option<Signal>) {
+    match events {
+        Some(events) => {
+            events.recv().await;
+        }
+        None => pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Vec<Vec<String>> {
+        return [
+            [
+                "web",
+                "open",
+                "10.0.0.5:50122",
+                "connected 14:01:02, 2 notes",
+            ],
+            ["db~1", "lost", "10.0.0.17:4444", "connected 09:30:00"],
+        ]
+        .iter()
+        .map(|row| row.iter().map(|cell| String::from(*cell)).collect())
+        .collect();
+    }
+
+    const COLUMNS: [Column; 4] = [
+        Column {
+            min: 8,
+            priority: 3,
+        },
+        Column {
+            min: 4,
+            priority: 2,
+        },
+        Column {
+            min: 6,
+            priority: 1,
+        },
+        Column {
+            min: 6,
+            priority: 0,
+        },
+    ];
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("session", 10), "session");
+        assert_eq!(truncate("session", 5), "sess…");
+        assert_eq!(truncate("session", 0), "");
+    }
+
+    #[test]
+    fn test_wrap() {
+        let text = "re-run a command on a shell every interval and print what changed";
+        let at_40 = wrap(text, 40, 4);
+        assert_eq!(
+            at_40,
+            vec![
+                "re-run a command on a shell every",
+                "    interval and print what changed"
+            ]
+        );
+        assert!(at_40.iter().all(|line| line.chars().count() <= 40));
+        assert_eq!(wrap(text, 80, 4), vec![text]);
+        assert_eq!(wrap(text, 200, 4), vec![text]);
+        // a word too long for any line is split
+        assert_eq!(
+            wrap("aaaaaaaaaaaaaaaaaaaaaaaa", 12, 2),
+            vec!["aaaaaaaaaaaa", "  aaaaaaaaaa", "  aa"]
+        );
+    }
+
+    #[test]
+    fn test_hanging() {
+        let summary = "re-run a command on a shell every interval and print what changed";
+        assert_eq!(
+            hanging("  watch-remote  ", summary, 40, 12),
+            "  watch-remote  re-run a command on a\n            shell every interval and\n            print what changed\n"
+        );
+        assert_eq!(
+            hanging("  purge     ", "remove a closed shell", 80, 12),
+            "  purge     remove a closed shell\n"
+        );
+    }
+
+    #[test]
+    fn test_render_table() {
+        let at_200 = render_table(&rows(), &COLUMNS, 200);
+        assert_eq!(
+            at_200,
+            "web   open  10.0.0.5:50122  connected 14:01:02, 2 notes\n\
+             db~1  lost  10.0.0.17:4444  connected 09:30:00\n"
+        );
+        assert_eq!(render_table(&rows(), &COLUMNS, 80), at_200);
+        // the details give way first, then the address
+        let at_40 = render_table(&rows(), &COLUMNS, 40);
+        assert_eq!(
+            at_40,
+            "web   open  10.0.0.5:50122  connected 1…\n\
+             db~1  lost  10.0.0.17:4444  connected 0…\n"
+        );
+        let at_30 = render_table(&rows(), &COLUMNS, 30);
+        assert!(at_30.lines().all(|line| line.chars().count() <= 30));
+        assert!(at_30.starts_with("web   open  10.0.0.5:…  conne…\n"));
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt::Display;
+use std::io::{self, stdout};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use termion::color::{self, Color};
+use termion::raw::IntoRawMode;
+use termion::style;
+
+use crate::config::config::Theme;
+
+/// cleared at startup when the terminal can't take cursor and color escapes
+static ESCAPES: AtomicBool = AtomicBool::new(true);
+
+/// the `theme` setting, as its place in THEMES
+static THEME: AtomicU8 = AtomicU8::new(0);
+
+const THEMES: [Theme; 3] = [Theme::Default, Theme::Light, Theme::Plain];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// raw mode could be entered and left again
+    pub raw: bool,
+    /// cursor movement, clearing and colors show as intended
+    pub escapes: bool,
+}
+
+/// Emacs' `M-x shell` and other line based frontends set TERM to dumb or not at all
+pub fn is_dumb_term(term: Option<&str>) -> bool {
+    return match term {
+        None => true,
+        Some(term) => term.trim().is_empty() || term == "dumb",
+    };
+}
+
+/// What the terminal can do. Raw mode is only tried on a tty whose TERM can show it,
+/// `enter_raw` enters and leaves it once
+pub fn probe<F>(term: Option<&str>, is_tty: bool, enter_raw: F) -> Capabilities
+where
+    F: FnOnce() -> io::Result<()>,
+{
+    let escapes = is_tty && !is_dumb_term(term);
+    return Capabilities {
+        raw: escapes && enter_raw().is_ok(),
+        escapes,
+    };
+}
+
+/// Probes the terminal crab_trap is running in
+pub fn detect() -> Capabilities {
+    let term = std::env::var("TERM").ok();
+    return probe(term.as_deref(), termion::is_tty(&stdout()), || {
+        return stdout().into_raw_mode()?.suspend_raw_mode();
+    });
+}
+
+/// Probes the terminal once at startup and keeps whether it takes escapes
+pub fn init() -> Capabilities {
+    let caps = detect();
+    ESCAPES.store(caps.escapes, Ordering::SeqCst);
+    return caps;
+}
+
+pub fn escapes() -> bool {
+    return ESCAPES.load(Ordering::SeqCst);
+}
+
+/// The sequence as text, or nothing on a terminal that would print it literally
+pub fn escape<T: Display>(seq: T) -> String {
+    return match escapes() {
+        true => seq.to_string(),
+        false => String::new(),
+    };
+}
+
+pub fn set_theme(theme: Theme) {
+    let index = THEMES.iter().position(|known| *known == theme).unwrap_or(0);
+    THEME.store(index as u8, Ordering::SeqCst);
+}
+
+pub fn theme() -> Theme {
+    return THEMES[THEME.load(Ordering::SeqCst) as usize % THEMES.len()];
+}
+
+/// `dark` on the default theme, `light` on the light one, nothing on plain
+fn themed<D: Display, L: Display>(theme: Theme, dark: D, light: L) -> String {
+    return match theme {
+        Theme::Default => dark.to_string(),
+        Theme::Light => light.to_string(),
+        Theme::Plain => String::new(),
+    };
+}
+
+/// A text colour for the current theme
+pub fn fg<D: Color, L: Color>(dark: D, light: L) -> String {
+    return escape(themed(theme(), color::Fg(dark), color::Fg(light)));
+}
+
+pub fn fg_reset() -> String {
+    return fg(color::Reset, color::Reset);
+}
+
+/// A background for the current theme. Plain has no colours, so it's reverse video
+/// there and a selection still shows
+pub fn bg<D: Color, L: Color>(dark: D, light: L) -> String {
+    return escape(match theme() {
+        Theme::Plain => style::Invert.to_string(),
+        theme => themed(theme, color::Bg(dark), color::Bg(light)),
+    });
+}
+
+pub fn bg_reset() -> String {
+    return escape(match theme() {
+        Theme::Plain => style::NoInvert.to_string(),
+        theme => themed(theme, color::Bg(color::Reset), color::Bg(color::Reset)),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe() {
+        let rejects = || Err(io::Error::other("not a terminal"));
+        assert_eq!(
+            probe(Some("xterm-256color"), true, rejects),
+            Capabilities {
+                raw: false,
+                escapes: true
+            }
+        );
+        assert_eq!(
+            probe(Some("xterm-256color"), true, || Ok(())),
+            Capabilities {
+                raw: true,
+                escapes: true
+            }
+        );
+        // never tried when it couldn't be shown anyway
+        let untried = || -> io::Result<()> { panic!("raw mode tried on a dumb terminal") };
+        assert!(!probe(Some("dumb"), true, untried).escapes);
+        assert!(!probe(None, true, untried).raw);
+        assert!(!probe(Some("xterm"), false, untried).escapes);
+    }
+
+    #[test]
+    fn test_themed() {
+        let red = || color::Fg(color::LightRed);
+        let dark_red = || color::Fg(color::Red);
+        assert_eq!(themed(Theme::Default, red(), dark_red()), red().to_string());
+        assert_eq!(
+            themed(Theme::Light, red(), dark_red()),
+            dark_red().to_string()
+        );
+        assert_eq!(themed(Theme::Plain, red(), dark_red()), "");
+    }
+}
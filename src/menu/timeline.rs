@@ -0,0 +1,232 @@
+use crate::socket::history::SessionEvent;
+use crate::socket::origin::InputOrigin;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+pub const TIMELINE_USAGE: &str =
+    "Usage: timeline <name> [--since HH:MM] [--origin operator|injected|internal] [--json|--csv]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimelineFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimelineArgs {
+    pub session: String,
+    /// seconds after midnight utc
+    pub since: Option<u64>,
+    /// only commands from here
+    pub origin: Option<InputOrigin>,
+    pub format: TimelineFormat,
+}
+
+/// Parses `HH:MM` or `HH:MM:SS` into seconds after midnight
+pub fn parse_time_of_day(time: &str) -> Option<u64> {
+    let parts: Vec<u64> = time
+        .split(':')
+        .map(|part| part.parse::<u64>().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    let (hours, mins, secs) = match parts.as_slice() {
+        [hours, mins] => (*hours, *mins, 0),
+        [hours, mins, secs] => (*hours, *mins, *secs),
+        _ => return None,
+    };
+    if hours > 23 || mins > 59 || secs > 59 {
+        return None;
+    }
+    return Some(hours * 3600 + mins * 60 + secs);
+}
+
+/// Parses everything typed after `timeline`
+pub fn parse_timeline_args(args: &str) -> Option<TimelineArgs> {
+    let mut session = None;
+    let mut since = None;
+    let mut origin = None;
+    let mut format = TimelineFormat::Table;
+    let mut words = args.split_whitespace();
+    while let Some(word) = words.next() {
+        match word {
+            "--since" => since = Some(parse_time_of_day(words.next()?)?),
+            "--origin" => origin = Some(InputOrigin::parse(words.next()?)?),
+            "--json" => format = TimelineFormat::Json,
+            "--csv" => format = TimelineFormat::Csv,
+            _ if word.starts_with("--") || session.is_some() => return None,
+            _ => session = Some(String::from(word)),
+        }
+    }
+    return Some(TimelineArgs {
+        session: session?,
+        since,
+        origin,
+        format,
+    });
+}
+
+/// Keeps the events from the most recent time the clock read `since`
+pub fn filter_since(events: Vec<SessionEvent>, since: u64, now: u64) -> Vec<SessionEvent> {
+    let mut start = now - now % SECS_PER_DAY + since;
+    // a time later than now means yesterday
+    if start > now {
+        start = start.saturating_sub(SECS_PER_DAY);
+    }
+    return events
+        .into_iter()
+        .filter(|event| event.at >= start)
+        .collect();
+}
+
+/// Keeps the commands that came from `origin`
+pub fn filter_origin(events: Vec<SessionEvent>, origin: InputOrigin) -> Vec<SessionEvent> {
+    return events
+        .into_iter()
+        .filter(|event| event.origin == Some(origin))
+        .collect();
+}
+
+/// Formats a unix timestamp as a utc wall clock time
+pub fn format_clock(at: u64) -> String {
+    let secs = at % SECS_PER_DAY;
+    return format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs % 3600) / 60,
+        secs % 60
+    );
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        return format!("\"{}\"", field.replace('"', "\"\""));
+    }
+    return String::from(field);
+}
+
+/// Renders a session's events as a table, json or csv
+pub fn render_timeline(events: &[SessionEvent], format: TimelineFormat) -> String {
+    match format {
+        TimelineFormat::Json => {
+            return serde_json::to_string_pretty(events).unwrap_or_default() + "\n";
+        }
+        TimelineFormat::Csv => {
+            let mut text = String::from("at,kind,detail,origin\n");
+            for event in events {
+                text += &format!(
+                    "{},{},{},{}\n",
+                    event.at,
+                    event.kind.name(),
+                    csv_field(&event.detail),
+                    event.origin.map_or("", |origin| origin.name())
+                );
+            }
+            return text;
+        }
+        TimelineFormat::Table => {}
+    }
+    if events.is_empty() {
+        return String::from("Nothing recorded in that time\n");
+    }
+    let mut text = format!("{:<10}{:<11}{}\n", "TIME", "EVENT", "DETAIL");
+    for event in events {
+        // operator commands are the usual case, the others say where they came from
+        let from = match event.origin {
+            Some(origin) if origin != InputOrigin::Operator => format!("[{}] ", origin.name()),
+            _ => String::new(),
+        };
+        text += &format!(
+            "{:<10}{:<11}{from}{}\n",
+            format_clock(event.at),
+            event.kind.name(),
+            event.detail
+        );
+    }
+    return text;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::history::EventKind;
+
+    fn event(at: u64, kind: EventKind, detail: &str) -> SessionEvent {
+        return SessionEvent {
+            at,
+            kind,
+            detail: String::from(detail),
+            origin: None,
+        };
+    }
+
+    #[test]
+    fn test_parse_timeline_args() {
+        assert_eq!(
+            parse_timeline_args("web --since 14:00 --csv"),
+            Some(TimelineArgs {
+                session: String::from("web"),
+                since: Some(14 * 3600),
+                origin: None,
+                format: TimelineFormat::Csv,
+            })
+        );
+        assert_eq!(
+            parse_timeline_args("--json web").map(|args| args.format),
+            Some(TimelineFormat::Json)
+        );
+        assert_eq!(parse_timeline_args(""), None);
+        assert_eq!(parse_timeline_args("web db"), None);
+        assert_eq!(parse_timeline_args("web --since 25:00"), None);
+        assert_eq!(parse_timeline_args("web --since"), None);
+        assert_eq!(
+            parse_timeline_args("web --origin injected").and_then(|args| args.origin),
+            Some(InputOrigin::Injected)
+        );
+        assert_eq!(parse_timeline_args("web --origin macro"), None);
+        assert_eq!(parse_time_of_day("09:30:15"), Some(9 * 3600 + 30 * 60 + 15));
+    }
+
+    #[test]
+    fn test_filter_since() {
+        let day = 20_000 * SECS_PER_DAY;
+        let events = vec![
+            event(day - 3600, EventKind::Connected, ""),
+            event(day + 600, EventKind::Command, ""),
+            event(day + 7200, EventKind::Closed, ""),
+        ];
+        // 01:00 today
+        assert_eq!(filter_since(events.clone(), 3600, day + 7300).len(), 1);
+        // 23:00 is still in the future so it means yesterday
+        assert_eq!(filter_since(events, 23 * 3600, day + 7300).len(), 3);
+    }
+
+    #[test]
+    fn test_render_timeline() {
+        let mut events = vec![
+            event(3661, EventKind::Connected, "from 10.0.0.5:4444"),
+            event(3700, EventKind::Command, "echo \"a, b\" -> a, b"),
+        ];
+        events[1].origin = Some(InputOrigin::Internal);
+        let table = render_timeline(&events, TimelineFormat::Table);
+        assert!(table.starts_with("TIME      EVENT      DETAIL\n"));
+        assert!(table.contains("01:01:01  connected  from 10.0.0.5:4444\n"));
+        assert!(table.contains("01:01:40  command    [internal] echo"));
+        assert_eq!(
+            render_timeline(&events, TimelineFormat::Csv),
+            "at,kind,detail,origin\n3661,connected,from 10.0.0.5:4444,\n3700,command,\"echo \"\"a, b\"\" -> a, b\",internal\n"
+        );
+        assert_eq!(
+            filter_origin(events.clone(), InputOrigin::Internal).len(),
+            1
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render_timeline(&events, TimelineFormat::Json)).unwrap();
+        assert_eq!(json[1]["kind"], "command");
+        assert_eq!(json[0]["at"], 3661);
+        assert_eq!(json[1]["origin"], "internal");
+        assert_eq!(
+            render_timeline(&[], TimelineFormat::Table),
+            "Nothing recorded in that time\n"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use std::io::{stdout, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::settings::SharedSettings;
+use crate::menu::terminal;
+
+/// set once crab_trap has saved the terminal's own title and replaced it
+static TITLE_SET: AtomicBool = AtomicBool::new(false);
+
+/// xterm's title stack, terminals without one ignore these
+const PUSH_TITLE: &str = "\x1b[22;0t";
+const POP_TITLE: &str = "\x1b[23;0t";
+
+/// OSC 2, with anything that could end the sequence early taken out
+pub fn title_sequence(title: &str) -> String {
+    let title: String = title.chars().filter(|c| !c.is_control()).collect();
+    return format!("\x1b]2;{title}\x07");
+}
+
+pub fn session_title(name: &str, peer: Option<SocketAddr>) -> String {
+    return match peer {
+        Some(addr) => format!("crab_trap: {name} ({})", addr.ip()),
+        None => format!("crab_trap: {name}"),
+    };
+}
+
+pub fn menu_title(sessions: usize) -> String {
+    return match sessions {
+        1 => String::from("crab_trap: 1 session"),
+        n => format!("crab_trap: {n} sessions"),
+    };
+}
+
+/// Sets the terminal's title, saving the one it had the first time. Nothing is sent
+/// when the title setting is off or stdout isn't a terminal that can show it
+pub fn set_title(settings: &SharedSettings, title: &str) {
+    let enabled = match settings.lock() {
+        Ok(settings) => settings.get_bool("title", None),
+        Err(_) => false,
+    };
+    if !enabled || !terminal::escapes() || !termion::is_tty(&stdout()) {
+        return;
+    }
+    let mut out = String::new();
+    if !TITLE_SET.swap(true, Ordering::SeqCst) {
+        out += PUSH_TITLE;
+    }
+    out += &title_sequence(title);
+    print!("{out}");
+    stdout().flush().unwrap_or_default();
+}
+
+/// Puts back the title the terminal had before crab_trap changed it
+pub fn restore_title() {
+    if !TITLE_SET.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    // terminals without a title stack are at least left without a stale one
+    print!("{}{POP_TITLE}", title_sequence(""));
+    stdout().flush().unwrap_or_default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_titles() {
+        assert_eq!(
+            session_title("web01", Some("10.0.0.5:4444".parse().unwrap())),
+            "crab_trap: web01 (10.0.0.5)"
+        );
+        assert_eq!(session_title("pipe", None), "crab_trap: pipe");
+        assert_eq!(menu_title(1), "crab_trap: 1 session");
+        assert_eq!(menu_title(3), "crab_trap: 3 sessions");
+        // a name can't end the sequence and run what comes after it
+        assert_eq!(title_sequence("web\x07\x1b[2Jx"), "\x1b]2;web[2Jx\x07");
+    }
+}
@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::socket::connection::Handle;
+use crate::socket::exec::{RemoteOs, ShellKind};
+
+/// a big domain has a lot of users and ldap pages through them a thousand at a time
+const AD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// groups whose members can take over the domain
+pub const PRIVILEGED_GROUPS: &[&str] = &["Domain Admins", "Enterprise Admins", "Schema Admins"];
+
+/// useraccountcontrol's ACCOUNTDISABLE bit
+const ACCOUNT_DISABLED: u32 = 0x2;
+
+/// Reads the domain over ldap with System.DirectoryServices, so it works without the
+/// RSAT modules the Get-AD* cmdlets need. Every attribute comes back as a list of strings
+const AD_PROBE: &str = "powershell -NoProfile -NonInteractive -Command \
+     \"$s = New-Object DirectoryServices.DirectorySearcher; $s.PageSize = 1000; \
+     function q($f, $p) { $s.Filter = $f; $s.PropertiesToLoad.Clear(); \
+     $p | ForEach-Object { [void]$s.PropertiesToLoad.Add($_) }; \
+     $s.FindAll() | ForEach-Object { $r = $_.Properties; $o = [ordered]@{}; \
+     foreach ($n in $p) { $o[$n] = @($r[$n] | ForEach-Object { [string]$_ }) }; [pscustomobject]$o } }; \
+     $d = [DirectoryServices.ActiveDirectory.Domain]::GetCurrentDomain(); \
+     ConvertTo-Json -Compress -Depth 4 -InputObject ([pscustomobject]@{ domain = $d.Name; \
+     users = @(q '(&(objectCategory=person)(objectClass=user))' @('samaccountname', 'memberof', 'useraccountcontrol')); \
+     groups = @(q '(objectClass=group)' @('samaccountname', 'member')); \
+     computers = @(q '(objectClass=computer)' @('dnshostname', 'operatingsystem')); \
+     gpos = @(q '(objectClass=groupPolicyContainer)' @('displayname', 'gpcfilesyspath')); \
+     trusts = @($d.GetAllTrustRelationships() | ForEach-Object { [pscustomobject]@{ \
+     target = $_.TargetName; direction = [string]$_.TrustDirection; type = [string]$_.TrustType } }) })\"";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdUser {
+    pub name: String,
+    /// the groups it's directly in, nested membership isn't followed
+    pub groups: Vec<String>,
+    pub disabled: bool,
+    /// directly in one of `PRIVILEGED_GROUPS`
+    pub privileged: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdGroup {
+    pub name: String,
+    pub members: Vec<String>,
+    pub privileged: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdComputer {
+    pub name: String,
+    pub os: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdGpo {
+    pub name: String,
+    /// the policy's folder in SYSVOL
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AdTrust {
+    pub target: String,
+    /// `Bidirectional`, `Inbound` or `Outbound`
+    pub direction: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdReport {
+    pub domain: String,
+    pub users: Vec<AdUser>,
+    pub groups: Vec<AdGroup>,
+    pub computers: Vec<AdComputer>,
+    pub gpos: Vec<AdGpo>,
+    pub trusts: Vec<AdTrust>,
+}
+
+impl AdReport {
+    pub fn privileged_users(&self) -> Vec<&AdUser> {
+        return self.users.iter().filter(|user| user.privileged).collect();
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RawEntry {
+    samaccountname: Vec<String>,
+    memberof: Vec<String>,
+    useraccountcontrol: Vec<String>,
+    member: Vec<String>,
+    dnshostname: Vec<String>,
+    operatingsystem: Vec<String>,
+    displayname: Vec<String>,
+    gpcfilesyspath: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawReport {
+    domain: String,
+    #[serde(default)]
+    users: Vec<RawEntry>,
+    #[serde(default)]
+    groups: Vec<RawEntry>,
+    #[serde(default)]
+    computers: Vec<RawEntry>,
+    #[serde(default)]
+    gpos: Vec<RawEntry>,
+    #[serde(default)]
+    trusts: Vec<AdTrust>,
+}
+
+fn first(values: &[String]) -> String {
+    return values.first().cloned().unwrap_or_default();
+}
+
+/// `CN=Domain Admins,CN=Users,DC=corp,DC=local` is `Domain Admins`
+fn common_name(dn: &str) -> String {
+    let first = dn.split(',').next().unwrap_or(dn);
+    return String::from(first.strip_prefix("CN=").unwrap_or(first));
+}
+
+fn is_privileged(group: &str) -> bool {
+    return PRIVILEGED_GROUPS
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(group));
+}
+
+/// The report in the probe's json, None when the box isn't in a domain or powershell
+/// couldn't run
+pub fn parse_ad_output(output: &str) -> Option<AdReport> {
+    let json = output[output.find('{')?..].trim();
+    let raw: RawReport = serde_json::from_str(json).ok()?;
+    let users = raw
+        .users
+        .iter()
+        .map(|entry| {
+            let groups: Vec<String> = entry.memberof.iter().map(|dn| common_name(dn)).collect();
+            let flags: u32 = first(&entry.useraccountcontrol).parse().unwrap_or(0);
+            AdUser {
+                name: first(&entry.samaccountname),
+                privileged: groups.iter().any(|group| is_privileged(group)),
+                groups,
+                disabled: flags & ACCOUNT_DISABLED != 0,
+            }
+        })
+        .collect();
+    let groups = raw
+        .groups
+        .iter()
+        .map(|entry| {
+            let name = first(&entry.samaccountname);
+            AdGroup {
+                privileged: is_privileged(&name),
+                name,
+                members: entry.member.iter().map(|dn| common_name(dn)).collect(),
+            }
+        })
+        .collect();
+    let computers = raw
+        .computers
+        .iter()
+        .map(|entry| AdComputer {
+            name: first(&entry.dnshostname),
+            os: first(&entry.operatingsystem),
+        })
+        .collect();
+    let gpos = raw
+        .gpos
+        .iter()
+        .map(|entry| AdGpo {
+            name: first(&entry.displayname),
+            path: first(&entry.gpcfilesyspath),
+        })
+        .collect();
+    return Some(AdReport {
+        domain: raw.domain,
+        users,
+        groups,
+        computers,
+        gpos,
+        trusts: raw.trusts,
+    });
+}
+
+impl Handle {
+    /// Lists the users, groups, computers, GPOs and trusts of the domain a windows
+    /// remote is joined to, as the session user sees them. None anywhere else
+    pub async fn enumerate_active_directory(&self) -> Option<AdReport> {
+        if self.detect_os().await != Some(RemoteOs::Windows) {
+            return None;
+        }
+        let output = self.exec_with(ShellKind::Cmd, AD_PROBE, AD_TIMEOUT).await?;
+        return parse_ad_output(&output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ad_output() {
+        let output = r#"
+{"domain":"corp.local","users":[{"samaccountname":["alice"],"memberof":["CN=Domain Admins,CN=Users,DC=corp,DC=local","CN=IT,OU=Groups,DC=corp,DC=local"],"useraccountcontrol":["512"]},{"samaccountname":["old.svc"],"memberof":[],"useraccountcontrol":["514"]}],"groups":[{"samaccountname":["Domain Admins"],"member":["CN=Alice Smith,CN=Users,DC=corp,DC=local"]},{"samaccountname":["IT"],"member":[]}],"computers":[{"dnshostname":["dc01.corp.local"],"operatingsystem":["Windows Server 2019 Standard"]}],"gpos":[{"displayname":["Default Domain Policy"],"gpcfilesyspath":["\\\\corp.local\\sysvol\\corp.local\\Policies\\{31B2F340-016D-11D2-945F-00C04FB984F9}"]}],"trusts":[{"target":"partner.local","direction":"Bidirectional","type":"Forest"}]}
+"#;
+        let report = parse_ad_output(output).unwrap();
+        assert_eq!(report.domain, "corp.local");
+        assert_eq!(
+            report.users[0],
+            AdUser {
+                name: String::from("alice"),
+                groups: vec![String::from("Domain Admins"), String::from("IT")],
+                disabled: false,
+                privileged: true,
+            }
+        );
+        assert!(report.users[1].disabled);
+        assert_eq!(report.privileged_users().len(), 1);
+        assert!(report.groups[0].privileged);
+        assert_eq!(report.groups[0].members, vec![String::from("Alice Smith")]);
+        assert!(!report.groups[1].privileged);
+        assert_eq!(report.computers[0].os, "Windows Server 2019 Standard");
+        assert_eq!(report.gpos[0].name, "Default Domain Policy");
+        assert_eq!(report.trusts[0].kind, "Forest");
+
+        let not_joined = "Exception calling \"GetCurrentDomain\" with \"0\" argument(s): \"Current security context is not associated with an Active Directory domain or forest.\"";
+        assert_eq!(parse_ad_output(not_joined), None);
+    }
+}
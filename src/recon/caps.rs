@@ -0,0 +1,348 @@
+use std::time::Duration;
+
+use crate::socket::connection::Handle;
+use crate::socket::exec::EXEC_TIMEOUT;
+
+/// getcap walks the whole filesystem
+const CAPS_SCAN_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// separates the shell's capability sets from the file capabilities
+const FILES_MARKER: &str = "--files--";
+
+/// capsh isn't always installed, the kernel reports the same sets in /proc
+const CAPS_PROBE: &str =
+    "grep '^Cap' /proc/$$/status 2>/dev/null; echo --fi''les--; getcap -r / 2>/dev/null";
+
+/// ambient capabilities are kept across exec, so grep's own set is the shell's
+const AMBIENT_PROBE: &str = "grep '^CapAmb' /proc/self/status 2>/dev/null";
+
+/// capability names in bit order, as in linux/capability.h
+const CAP_NAMES: &[&str] = &[
+    "cap_chown",
+    "cap_dac_override",
+    "cap_dac_read_search",
+    "cap_fowner",
+    "cap_fsetid",
+    "cap_kill",
+    "cap_setgid",
+    "cap_setuid",
+    "cap_setpcap",
+    "cap_linux_immutable",
+    "cap_net_bind_service",
+    "cap_net_broadcast",
+    "cap_net_admin",
+    "cap_net_raw",
+    "cap_ipc_lock",
+    "cap_ipc_owner",
+    "cap_sys_module",
+    "cap_sys_rawio",
+    "cap_sys_chroot",
+    "cap_sys_ptrace",
+    "cap_sys_pacct",
+    "cap_sys_admin",
+    "cap_sys_boot",
+    "cap_sys_nice",
+    "cap_sys_resource",
+    "cap_sys_time",
+    "cap_sys_tty_config",
+    "cap_mknod",
+    "cap_lease",
+    "cap_audit_write",
+    "cap_audit_control",
+    "cap_setfcap",
+    "cap_mac_override",
+    "cap_mac_admin",
+    "cap_syslog",
+    "cap_wake_alarm",
+    "cap_block_suspend",
+    "cap_audit_read",
+    "cap_perfmon",
+    "cap_bpf",
+    "cap_checkpoint_restore",
+];
+
+/// capabilities that are as good as root, and what they give
+const CAP_IMPACTS: &[(&str, &str)] = &[
+    ("cap_setuid", "can change uid to 0"),
+    ("cap_setgid", "can join any group, like shadow or docker"),
+    (
+        "cap_dac_override",
+        "can write any file regardless of permissions",
+    ),
+    ("cap_dac_read_search", "can read any file, like /etc/shadow"),
+    ("cap_fowner", "can chmod any file"),
+    ("cap_chown", "can take ownership of any file"),
+    (
+        "cap_sys_admin",
+        "can mount filesystems and much more, close to full root",
+    ),
+    ("cap_sys_ptrace", "can attach to root processes"),
+    ("cap_sys_module", "can load kernel modules"),
+    ("cap_sys_rawio", "can read and write raw memory and devices"),
+    ("cap_setfcap", "can give any binary more capabilities"),
+    (
+        "cap_setpcap",
+        "can add capabilities to its own bounding set",
+    ),
+    ("cap_bpf", "can load bpf programs into the kernel"),
+];
+
+/// ambient capabilities worth knowing about, and what having them means here
+const AMBIENT_IMPACTS: &[(&str, &str)] = &[
+    (
+        "cap_net_bind_service",
+        "anything run can listen on ports below 1024",
+    ),
+    (
+        "cap_net_raw",
+        "raw socket operations are available without root, to anything run",
+    ),
+    (
+        "cap_sys_admin",
+        "anything run can mount filesystems and much more, close to full root",
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileCapability {
+    pub path: String,
+    /// the capabilities set on it, from getcap
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapabilityFinding {
+    pub capability: String,
+    /// `process` for the session's own sets or the path of the binary that has it
+    pub source: String,
+    pub impact: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CapabilityReport {
+    pub effective: Vec<String>,
+    pub permitted: Vec<String>,
+    pub inheritable: Vec<String>,
+    /// every capability the kernel allows is effective, usually because the session is root
+    pub full: bool,
+    pub files: Vec<FileCapability>,
+    pub findings: Vec<CapabilityFinding>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AmbientCapReport {
+    /// every capability in the session's ambient set
+    pub ambient: Vec<String>,
+    /// the dangerous ones among them, with `process` as the source
+    pub findings: Vec<CapabilityFinding>,
+    /// cap_net_raw is ambient
+    pub raw_sockets: bool,
+}
+
+/// Names the bits set in a hex mask like `0000003fffffffff`
+pub fn decode_caps(hex: &str) -> Vec<String> {
+    let mask = u64::from_str_radix(hex.trim(), 16).unwrap_or_default();
+    return CAP_NAMES
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| mask & (1 << bit) != 0)
+        .map(|(_, name)| String::from(*name))
+        .collect();
+}
+
+/// Reads a getcap line, both `path cap_a,cap_b=ep` and the older `path = cap_a+ep`
+fn parse_getcap_line(line: &str) -> Option<FileCapability> {
+    let line = line.trim();
+    let (path, caps) = match line.split_once(" = ") {
+        Some(val) => val,
+        None => line.split_once(' ')?,
+    };
+    if !path.starts_with('/') {
+        return None;
+    }
+    let mut capabilities = Vec::new();
+    for clause in caps.split_whitespace() {
+        let (names, flags) = match clause.find(['=', '+', '-']) {
+            Some(idx) => clause.split_at(idx),
+            None => continue,
+        };
+        // only effective or permitted capabilities can be used
+        if !flags.contains('e') && !flags.contains('p') {
+            continue;
+        }
+        capabilities.extend(names.split(',').map(String::from));
+    }
+    return Some(FileCapability {
+        path: String::from(path),
+        capabilities,
+    });
+}
+
+fn impact(capability: &str) -> Option<&'static str> {
+    return CAP_IMPACTS
+        .iter()
+        .find(|(name, _)| *name == capability)
+        .map(|(_, impact)| *impact);
+}
+
+pub fn parse_caps_output(output: &str) -> CapabilityReport {
+    let (sets, files) = output.split_once(FILES_MARKER).unwrap_or((output, ""));
+    let mut report = CapabilityReport::default();
+    let mut bounding = Vec::new();
+    for line in sets.lines() {
+        let (name, hex) = match line.split_once(':') {
+            Some(val) => val,
+            None => continue,
+        };
+        match name {
+            "CapEff" => report.effective = decode_caps(hex),
+            "CapPrm" => report.permitted = decode_caps(hex),
+            "CapInh" => report.inheritable = decode_caps(hex),
+            "CapBnd" => bounding = decode_caps(hex),
+            _ => {}
+        }
+    }
+    // older kernels know fewer capabilities, the bounding set is the most there can be
+    report.full = !report.effective.is_empty() && report.effective == bounding;
+    report.files = files.lines().filter_map(parse_getcap_line).collect();
+    // a root shell has them all, listing each one says nothing
+    if !report.full {
+        for capability in &report.effective {
+            if let Some(impact) = impact(capability) {
+                report.findings.push(CapabilityFinding {
+                    capability: capability.clone(),
+                    source: String::from("process"),
+                    impact: String::from(impact),
+                });
+            }
+        }
+    }
+    for file in &report.files {
+        for capability in &file.capabilities {
+            if let Some(impact) = impact(capability) {
+                report.findings.push(CapabilityFinding {
+                    capability: capability.clone(),
+                    source: file.path.clone(),
+                    impact: String::from(impact),
+                });
+            }
+        }
+    }
+    return report;
+}
+
+pub fn parse_ambient_output(output: &str) -> AmbientCapReport {
+    let ambient = output
+        .lines()
+        .find_map(|line| line.strip_prefix("CapAmb:"))
+        .map(decode_caps)
+        .unwrap_or_default();
+    let findings = ambient
+        .iter()
+        .filter_map(|capability| {
+            let (_, impact) = AMBIENT_IMPACTS
+                .iter()
+                .find(|(name, _)| name == capability)?;
+            return Some(CapabilityFinding {
+                capability: capability.clone(),
+                source: String::from("process"),
+                impact: String::from(*impact),
+            });
+        })
+        .collect();
+    return AmbientCapReport {
+        raw_sockets: ambient.iter().any(|cap| cap == "cap_net_raw"),
+        ambient,
+        findings,
+    };
+}
+
+impl Handle {
+    /// Reads the session's capability sets and the file capabilities on the remote,
+    /// flagging the ones that lead to root
+    pub async fn check_capabilities(&self) -> CapabilityReport {
+        return match self.exec(CAPS_PROBE, CAPS_SCAN_TIMEOUT).await {
+            Some(output) => parse_caps_output(&output),
+            None => CapabilityReport::default(),
+        };
+    }
+
+    /// Reads the session's ambient capabilities, the ones every program it runs gets
+    /// without needing file capabilities
+    pub async fn check_capabilities_ambient(&self) -> AmbientCapReport {
+        return match self.exec(AMBIENT_PROBE, EXEC_TIMEOUT).await {
+            Some(output) => parse_ambient_output(&output),
+            None => AmbientCapReport::default(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_caps_output() {
+        let output = "\
+CapInh:\t0000000000000000
+CapPrm:\t0000000000000080
+CapEff:\t0000000000000080
+CapBnd:\t000001ffffffffff
+--files--
+/usr/bin/ping cap_net_raw=ep
+/usr/bin/python3.11 cap_setuid,cap_net_bind_service+ep
+/opt/tool = cap_dac_read_search+i
+";
+        let report = parse_caps_output(output);
+        assert_eq!(report.effective, vec!["cap_setuid"]);
+        assert!(report.inheritable.is_empty());
+        assert!(!report.full);
+        assert_eq!(report.files.len(), 3);
+        assert_eq!(
+            report.files[1].capabilities,
+            vec!["cap_setuid", "cap_net_bind_service"]
+        );
+        // inheritable only, nothing it can use itself
+        assert!(report.files[2].capabilities.is_empty());
+        let sources: Vec<(&str, &str)> = report
+            .findings
+            .iter()
+            .map(|f| (f.capability.as_str(), f.source.as_str()))
+            .collect();
+        assert_eq!(
+            sources,
+            vec![
+                ("cap_setuid", "process"),
+                ("cap_setuid", "/usr/bin/python3.11")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_ambient_output() {
+        // cap_net_raw, cap_net_bind_service and cap_chown
+        let report = parse_ambient_output("CapAmb:\t0000000000002401\n");
+        assert_eq!(
+            report.ambient,
+            vec!["cap_chown", "cap_net_bind_service", "cap_net_raw"]
+        );
+        let found: Vec<&str> = report
+            .findings
+            .iter()
+            .map(|f| f.capability.as_str())
+            .collect();
+        assert_eq!(found, vec!["cap_net_bind_service", "cap_net_raw"]);
+        assert!(report.raw_sockets);
+        let report = parse_ambient_output("CapAmb:\t0000000000000000\n");
+        assert!(report.ambient.is_empty() && !report.raw_sockets);
+        assert_eq!(parse_ambient_output(""), AmbientCapReport::default());
+    }
+
+    #[test]
+    fn test_full_caps() {
+        let report =
+            parse_caps_output("CapEff:\t0000003fffffffff\nCapBnd:\t0000003fffffffff\n--files--\n");
+        assert!(report.full);
+        assert!(report.findings.is_empty());
+    }
+}
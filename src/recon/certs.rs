@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::socket::connection::Handle;
+use crate::socket::exec::{RemoteOs, ShellKind};
+
+/// powershell takes a few seconds to start and the stores can be large
+const CERTS_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Lists the machine's certificates from cmd through powershell. Only whether a key is
+/// marked exportable is read, never the key itself. CNG keys don't answer the CAPI
+/// property and are reported as not exportable
+const CERTS_PROBE: &str = "powershell -NoProfile -NonInteractive -Command \
+     \"ConvertTo-Json -Compress -InputObject @(Get-ChildItem Cert:\\LocalMachine -Recurse \
+     | Where-Object { $_.Thumbprint } | ForEach-Object { $e = $false; \
+     if ($_.HasPrivateKey) { try { $e = [bool]$_.PrivateKey.CspKeyContainerInfo.Exportable } catch {} }; \
+     [pscustomobject]@{ Subject = $_.Subject; Issuer = $_.Issuer; NotAfter = $_.NotAfter.ToString('u'); \
+     Store = (Split-Path $_.PSParentPath -Leaf); Exportable = $e } })\"";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CertEntry {
+    #[serde(rename = "Subject")]
+    pub subject: String,
+    #[serde(rename = "Issuer")]
+    pub issuer: String,
+    /// `2031-01-01 00:00:00Z`
+    #[serde(rename = "NotAfter")]
+    pub expiry: String,
+    /// like `My` or `Root`
+    #[serde(rename = "Store")]
+    pub store: String,
+    /// the private key is on the box and can be exported with it
+    #[serde(rename = "Exportable")]
+    pub exportable: bool,
+}
+
+/// The certificates in the probe's json, powershell's banner or errors around it are skipped
+pub fn parse_certs_output(output: &str) -> Vec<CertEntry> {
+    let json = match output.find(['[', '{']) {
+        Some(start) => output[start..].trim(),
+        None => return Vec::new(),
+    };
+    // an older powershell unwraps a one item array
+    return match serde_json::from_str::<Vec<CertEntry>>(json) {
+        Ok(certs) => certs,
+        Err(_) => match serde_json::from_str::<CertEntry>(json) {
+            Ok(cert) => vec![cert],
+            Err(_) => Vec::new(),
+        },
+    };
+}
+
+impl Handle {
+    /// The certificates in the LocalMachine stores of a windows remote, empty on anything else
+    pub async fn enumerate_installed_certs(&self) -> Vec<CertEntry> {
+        if self.detect_os().await != Some(RemoteOs::Windows) {
+            return Vec::new();
+        }
+        return match self
+            .exec_with(ShellKind::Cmd, CERTS_PROBE, CERTS_TIMEOUT)
+            .await
+        {
+            Some(output) => parse_certs_output(&output),
+            None => Vec::new(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    #[test]
+    fn test_parse_certs_output() {
+        let output = "\r\n[{\"Subject\":\"CN=web01\",\"Issuer\":\"CN=corp-ca\",\"NotAfter\":\"2031-01-01 00:00:00Z\",\"Store\":\"My\",\"Exportable\":true},\
+{\"Subject\":\"CN=Root\",\"Issuer\":\"CN=Root\",\"NotAfter\":\"2040-06-01 00:00:00Z\",\"Store\":\"Root\",\"Exportable\":false}]\r\n";
+        let certs = parse_certs_output(output);
+        assert_eq!(certs.len(), 2);
+        assert_eq!(certs[0].subject, "CN=web01");
+        assert_eq!(certs[0].store, "My");
+        assert!(certs[0].exportable);
+        assert!(!certs[1].exportable);
+
+        let single = "{\"Subject\":\"CN=a\",\"Issuer\":\"CN=b\",\"NotAfter\":\"x\",\"Store\":\"CA\",\"Exportable\":false}";
+        assert_eq!(parse_certs_output(single).len(), 1);
+        assert!(parse_certs_output("'powershell' is not recognized").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_enumerate_installed_certs_off_windows() {
+        let handle = spawn_shell_session(32462).await;
+        assert!(handle.enumerate_installed_certs().await.is_empty());
+    }
+}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use crate::recon::field;
+use crate::socket::connection::Handle;
+
+/// every request gets 2 seconds, off a cloud they all time out
+const CLOUD_PROBE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Asks each provider's metadata service for the instance id and the name of the
+/// identity attached to it. AWS gets an IMDSv2 token first, IMDSv1 still works
+/// without one. Nothing under the credential endpoints is read
+const CLOUD_PROBE: &str = "m='curl -sf -m 2'; \
+     t=$($m -X PUT -H 'X-aws-ec2-metadata-token-ttl-seconds: 60' http://169.254.169.254/latest/api/token); \
+     a=\"X-aws-ec2-metadata-token: $t\"; \
+     echo aws-id:$($m -H \"$a\" http://169.254.169.254/latest/meta-data/instance-id); \
+     echo aws-role:$($m -H \"$a\" http://169.254.169.254/latest/meta-data/iam/security-credentials/ | head -n 1); \
+     g='Metadata-Flavor: Google'; \
+     echo gcp-id:$($m -H \"$g\" http://metadata.google.internal/computeMetadata/v1/instance/id); \
+     echo gcp-identity:$($m -H \"$g\" http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/email); \
+     echo azure-id:$($m -H 'Metadata: true' 'http://169.254.169.254/metadata/instance/compute/vmId?api-version=2021-02-01&format=text')";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloudProvider {
+    Aws,
+    Gcp,
+    Azure,
+}
+
+impl CloudProvider {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            CloudProvider::Aws => "aws",
+            CloudProvider::Gcp => "gcp",
+            CloudProvider::Azure => "azure",
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloudMetadata {
+    pub provider: CloudProvider,
+    pub instance_id: String,
+    /// the IAM role or service account the instance runs as. Anything on the box can
+    /// get its credentials from the metadata service, so it's worth reporting
+    pub identity: Option<String>,
+}
+
+/// The first provider that answered with an instance id, none off a cloud
+pub fn parse_cloud_output(output: &str) -> Option<CloudMetadata> {
+    let providers = [
+        (CloudProvider::Aws, "aws-id", Some("aws-role")),
+        (CloudProvider::Gcp, "gcp-id", Some("gcp-identity")),
+        (CloudProvider::Azure, "azure-id", None),
+    ];
+    for (provider, id, identity) in providers {
+        // a captive portal or proxy can answer with a page instead of an id
+        let instance_id = match field(output, id) {
+            Some(val) if !val.contains(char::is_whitespace) => val,
+            _ => continue,
+        };
+        return Some(CloudMetadata {
+            provider,
+            instance_id: String::from(instance_id),
+            identity: identity
+                .and_then(|name| field(output, name))
+                .map(String::from),
+        });
+    }
+    return None;
+}
+
+impl Handle {
+    /// Checks whether the remote is a cloud instance by asking the AWS, GCP and Azure
+    /// metadata services for its instance id, and names the identity attached to it
+    pub async fn enumerate_cloud_metadata(&self) -> Option<CloudMetadata> {
+        let output = self.exec(CLOUD_PROBE, CLOUD_PROBE_TIMEOUT).await?;
+        return parse_cloud_output(&output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cloud_output() {
+        let output =
+            "aws-id:i-0abc123def456\naws-role:web-server\ngcp-id:\ngcp-identity:\nazure-id:\n";
+        assert_eq!(
+            parse_cloud_output(output),
+            Some(CloudMetadata {
+                provider: CloudProvider::Aws,
+                instance_id: String::from("i-0abc123def456"),
+                identity: Some(String::from("web-server")),
+            })
+        );
+
+        let output = "aws-id:\naws-role:\ngcp-id:\ngcp-identity:\nazure-id:02aab8a4-74ef-476e-8182-f6d2ba4166a6\n";
+        let metadata = parse_cloud_output(output).unwrap();
+        assert_eq!(metadata.provider, CloudProvider::Azure);
+        assert_eq!(metadata.identity, None);
+
+        // a proxy's error page isn't an instance id
+        let output = "aws-id:<html><body>Access denied</body></html>\ngcp-id:\nazure-id:\n";
+        assert_eq!(parse_cloud_output(output), None);
+        assert_eq!(parse_cloud_output(""), None);
+    }
+}
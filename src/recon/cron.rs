@@ -0,0 +1,548 @@
+use std::collections::BTreeSet;
+
+use crate::socket::connection::Handle;
+use crate::socket::exec::{shell_quote, EXEC_TIMEOUT};
+
+/// what cron runs with when a crontab doesn't set PATH
+const DEFAULT_CRON_PATH: &str = "/usr/bin:/bin";
+
+/// starts each crontab in the probe output, followed by where it came from
+const FILE_MARKER: &str = "--cron-file:";
+
+/// Every crontab the session user can read, and their own
+const CRONTABS_PROBE: &str =
+    "for f in /etc/crontab /etc/cron.d/* /var/spool/cron/crontabs/* /var/spool/cron/*; do \
+     [ -f \"$f\" ] && [ -r \"$f\" ] && { echo \"--cron-file:$f\"; cat \"$f\"; }; done 2>/dev/null; \
+     echo '--cron-file:crontab -l'; crontab -l 2>/dev/null";
+
+/// builtins and keywords at the start of a command, they aren't looked up in PATH
+const NOT_LOOKED_UP: &[&str] = &[
+    ".", "[", "cd", "echo", "eval", "exec", "exit", "export", "false", "if", "printf", "read",
+    "set", "source", "test", "then", "true", "umask", "while",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronEntry {
+    pub cron_file: String,
+    /// the user field system crontabs have, user crontabs run as their owner
+    pub user: Option<String>,
+    pub command: String,
+    /// the PATH in effect for this line, in search order
+    pub path: Vec<String>,
+}
+
+/// A command a cron job runs by name, resolved through a PATH where the session user
+/// can put something of their own first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronInjectionTarget {
+    pub cron_file: String,
+    pub command: String,
+    /// the writable directory searched before the real command, or the command itself
+    pub writable_path: String,
+}
+
+/// A script a cron job runs that the session user can write to, whatever is put in
+/// it runs as `run_as` next time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WritableCronScript {
+    /// the crontab it's in and the command, `<file>: <command>`
+    pub cron_entry: String,
+    pub script_path: String,
+    pub run_as: String,
+    /// it runs as root
+    pub high_severity: bool,
+}
+
+/// /etc/crontab and the files in /etc/cron.d have a user field before the command
+fn is_system_crontab(file: &str) -> bool {
+    return file == "/etc/crontab" || file.starts_with("/etc/cron.d/");
+}
+
+/// `NAME=value` at the start of a line, cron takes these as environment settings
+fn env_assignment(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+    return Some((name, value.trim().trim_matches(|c| c == '"' || c == '\'')));
+}
+
+/// The schedule and user fields taken off, `@daily root cmd` or `0 3 * * * root cmd`
+fn split_entry(line: &str, system: bool) -> Option<(Option<&str>, &str)> {
+    let schedule = match line.starts_with('@') {
+        true => 1,
+        false => 5,
+    };
+    let mut rest = line;
+    for _ in 0..schedule {
+        rest = rest.trim_start().split_once(char::is_whitespace)?.1;
+    }
+    let rest = rest.trim_start();
+    if !system {
+        return Some((None, rest.trim()));
+    }
+    let (user, command) = rest.split_once(char::is_whitespace)?;
+    return Some((Some(user), command.trim()));
+}
+
+/// Splits the probe output into each crontab's entries, with the PATH each one runs with
+pub fn parse_crontabs(output: &str) -> Vec<CronEntry> {
+    let mut entries = Vec::new();
+    let mut file = String::new();
+    let mut path = DEFAULT_CRON_PATH;
+    for line in output.lines() {
+        if let Some(name) = line.strip_prefix(FILE_MARKER) {
+            file = String::from(name.trim());
+            path = DEFAULT_CRON_PATH;
+            continue;
+        }
+        let line = line.trim();
+        if file.is_empty() || line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some((name, value)) = env_assignment(line) {
+            if name == "PATH" {
+                path = value;
+            }
+            continue;
+        }
+        let (user, command) = match split_entry(line, is_system_crontab(&file)) {
+            Some(val) => val,
+            None => continue,
+        };
+        entries.push(CronEntry {
+            cron_file: file.clone(),
+            user: user.map(String::from),
+            command: String::from(command),
+            path: path
+                .split(':')
+                .filter(|dir| !dir.is_empty())
+                .map(String::from)
+                .collect(),
+        });
+    }
+    return entries;
+}
+
+/// The commands a line runs by name, the first word of each part of a pipeline or list
+pub fn bare_commands(command: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    // `2>&1` is a redirection, not the end of a command
+    let command = command.replace(">&", "> ").replace("<&", "< ");
+    for part in command.split(['|', ';', '&']) {
+        let name = match part.split_whitespace().next() {
+            Some(val) => val,
+            None => continue,
+        };
+        // cron takes % as a newline, what follows is the command's input
+        let name = name.split('%').next().unwrap_or(name);
+        if name.is_empty()
+            || name.contains(['/', '=', '$', '`', '(', '>', '<'])
+            || NOT_LOOKED_UP.contains(&name)
+            || names.iter().any(|seen| seen == name)
+        {
+            continue;
+        }
+        names.push(String::from(name));
+    }
+    return names;
+}
+
+/// Splits a line into words the way sh would for plain quoting, with `|`, `;` and
+/// `&` ending a word too
+fn words(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    for c in command.chars() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, c) if c.is_whitespace() || "|;&".contains(c) => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            (None, c) => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    return words;
+}
+
+/// Absolute paths a line runs or hands to what it runs, leaving out redirections and
+/// device files
+pub fn script_paths(command: &str) -> Vec<String> {
+    let mut paths: Vec<String> = Vec::new();
+    // cron takes % as a newline, what follows is the command's input
+    let command = command.split('%').next().unwrap_or(command);
+    let command = command.replace(">&", "> ").replace("<&", "< ");
+    let mut redirected = false;
+    for word in words(&command) {
+        let target = word.trim_start_matches(|c: char| c.is_ascii_digit());
+        if target.starts_with(['>', '<']) {
+            // `>file` names its target, `>` alone leaves it to the next word
+            redirected = target.trim_start_matches(['>', '<']).is_empty();
+            continue;
+        }
+        if std::mem::take(&mut redirected) {
+            continue;
+        }
+        if !word.starts_with('/')
+            || word.starts_with("/dev/")
+            || word.starts_with("/proc/")
+            || paths.contains(&word)
+        {
+            continue;
+        }
+        paths.push(word);
+    }
+    return paths;
+}
+
+/// Who a crontab's jobs run as, the user field for system crontabs and the owner
+/// the spool file is named for otherwise
+fn run_as(entry: &CronEntry, session_user: &str) -> String {
+    if let Some(user) = &entry.user {
+        return user.clone();
+    }
+    if let Some(name) = entry.cron_file.strip_prefix("/var/spool/cron/") {
+        return String::from(name.rsplit('/').next().unwrap_or(name));
+    }
+    return String::from(session_user);
+}
+
+/// `u:<user>` for the session user, then `wf:<file>` or `f:<file>` for each script
+/// or PATH lookup that exists, and for the files in directories run-parts is given
+fn script_probe(entries: &[CronEntry]) -> String {
+    let mut files = BTreeSet::new();
+    let mut dirs = BTreeSet::new();
+    for entry in entries {
+        let names = bare_commands(&entry.command);
+        for path in script_paths(&entry.command) {
+            if names.iter().any(|name| name == "run-parts") {
+                dirs.insert(shell_quote(&path));
+            }
+            files.insert(shell_quote(&path));
+        }
+        for name in names {
+            for dir in &entry.path {
+                files.insert(shell_quote(&format!("{dir}/{name}")));
+            }
+        }
+    }
+    let files: Vec<String> = files.into_iter().collect();
+    let dirs: Vec<String> = dirs.into_iter().collect();
+    return format!(
+        "echo \"u:$(id -un)\"; for p in {} $(for d in {}; do [ -d \"$d\" ] && ls -d \"$d\"/*; done); do \
+         [ -f \"$p\" ] || continue; \
+         if [ -w \"$p\" ]; then echo \"wf:$p\"; else echo \"f:$p\"; fi; done 2>/dev/null",
+        files.join(" "),
+        dirs.join(" ")
+    );
+}
+
+/// The scripts each entry runs that the probe found writable. Commands run by name
+/// are the first match along the entry's PATH, and run-parts runs what's in its
+/// directory
+pub fn find_writable_scripts(entries: &[CronEntry], lookup: &str) -> Vec<WritableCronScript> {
+    let mut session_user = "";
+    let mut writable = Vec::new();
+    let mut found = Vec::new();
+    for line in lookup.lines().map(|line| line.trim()) {
+        if let Some(user) = line.strip_prefix("u:") {
+            session_user = user;
+        } else if let Some(file) = line.strip_prefix("wf:") {
+            writable.push(file);
+            found.push(file);
+        } else if let Some(file) = line.strip_prefix("f:") {
+            found.push(file);
+        }
+    }
+    let mut scripts: Vec<WritableCronScript> = Vec::new();
+    for entry in entries {
+        let names = bare_commands(&entry.command);
+        let run_parts = names.iter().any(|name| name == "run-parts");
+        let mut paths = Vec::new();
+        for path in script_paths(&entry.command) {
+            if run_parts {
+                let prefix = format!("{}/", path.trim_end_matches('/'));
+                paths.extend(
+                    writable
+                        .iter()
+                        .filter(|file| {
+                            file.strip_prefix(&prefix)
+                                .is_some_and(|rest| !rest.contains('/'))
+                        })
+                        .map(|file| String::from(*file)),
+                );
+            }
+            paths.push(path);
+        }
+        for name in names {
+            let resolved = entry
+                .path
+                .iter()
+                .map(|dir| format!("{dir}/{name}"))
+                .find(|file| found.contains(&file.as_str()));
+            paths.extend(resolved);
+        }
+        let run_as = run_as(entry, session_user);
+        for path in paths {
+            if !writable.contains(&path.as_str()) {
+                continue;
+            }
+            let script = WritableCronScript {
+                cron_entry: format!("{}: {}", entry.cron_file, entry.command),
+                script_path: path,
+                high_severity: run_as == "root",
+                run_as: run_as.clone(),
+            };
+            if !scripts.contains(&script) {
+                scripts.push(script);
+            }
+        }
+    }
+    return scripts;
+}
+
+/// `wd:<dir>` for writable PATH directories, and `wf:<file>` or `f:<file>` for each
+/// place a command could be found, written or not
+fn lookup_probe(entries: &[CronEntry]) -> String {
+    let mut dirs = BTreeSet::new();
+    let mut files = BTreeSet::new();
+    for entry in entries {
+        for name in bare_commands(&entry.command) {
+            for dir in &entry.path {
+                dirs.insert(shell_quote(dir));
+                files.insert(shell_quote(&format!("{dir}/{name}")));
+            }
+        }
+    }
+    let dirs: Vec<String> = dirs.into_iter().collect();
+    let files: Vec<String> = files.into_iter().collect();
+    return format!(
+        "for d in {}; do [ -d \"$d\" ] && [ -w \"$d\" ] && echo \"wd:$d\"; done; \
+         for p in {}; do [ -f \"$p\" ] || continue; \
+         if [ -w \"$p\" ]; then echo \"wf:$p\"; else echo \"f:$p\"; fi; done",
+        dirs.join(" "),
+        files.join(" ")
+    );
+}
+
+/// Walks each command's PATH in order. Whatever is writable before the real command
+/// would be run in its place, and so would the real command if it's writable
+pub fn find_injection_targets(entries: &[CronEntry], lookup: &str) -> Vec<CronInjectionTarget> {
+    let mut writable_dirs = Vec::new();
+    let mut writable_files = Vec::new();
+    let mut files = Vec::new();
+    for line in lookup.lines().map(|line| line.trim()) {
+        if let Some(dir) = line.strip_prefix("wd:") {
+            writable_dirs.push(dir);
+        } else if let Some(file) = line.strip_prefix("wf:") {
+            writable_files.push(file);
+        } else if let Some(file) = line.strip_prefix("f:") {
+            files.push(file);
+        }
+    }
+    let mut targets: Vec<CronInjectionTarget> = Vec::new();
+    for entry in entries {
+        for name in bare_commands(&entry.command) {
+            let mut writable_path = None;
+            for dir in &entry.path {
+                let file = format!("{dir}/{name}");
+                if writable_files.contains(&file.as_str()) {
+                    writable_path = Some(file);
+                    break;
+                }
+                if files.contains(&file.as_str()) {
+                    break;
+                }
+                if writable_dirs.contains(&dir.as_str()) {
+                    writable_path = Some(dir.clone());
+                    break;
+                }
+            }
+            let target = match writable_path {
+                Some(path) => CronInjectionTarget {
+                    cron_file: entry.cron_file.clone(),
+                    command: name,
+                    writable_path: path,
+                },
+                None => continue,
+            };
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+    }
+    return targets;
+}
+
+impl Handle {
+    /// Every crontab the session user can read, parsed into its entries
+    pub async fn read_crontabs(&self) -> Vec<CronEntry> {
+        return match self.exec(CRONTABS_PROBE, EXEC_TIMEOUT).await {
+            Some(output) => parse_crontabs(&output),
+            None => Vec::new(),
+        };
+    }
+
+    /// Finds cron jobs that run a command by name through a PATH the session user can
+    /// get in front of, by writing to a directory searched first or the command itself
+    pub async fn check_cronjob_path_injection(&self) -> Vec<CronInjectionTarget> {
+        let entries = self.read_crontabs().await;
+        if entries
+            .iter()
+            .all(|entry| bare_commands(&entry.command).is_empty())
+        {
+            return Vec::new();
+        }
+        return match self.exec(&lookup_probe(&entries), EXEC_TIMEOUT).await {
+            Some(lookup) => find_injection_targets(&entries, &lookup),
+            None => Vec::new(),
+        };
+    }
+
+    /// Finds the scripts cron jobs run that the session user can write to, by full
+    /// path or looked up through the job's PATH. Root's are high severity
+    pub async fn check_writeable_cronjobs(&self) -> Vec<WritableCronScript> {
+        let entries = self.read_crontabs().await;
+        if entries.is_empty() {
+            return Vec::new();
+        }
+        return match self.exec(&script_probe(&entries), EXEC_TIMEOUT).await {
+            Some(lookup) => find_writable_scripts(&entries, &lookup),
+            None => Vec::new(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CRONTABS: &str = "\
+--cron-file:/etc/crontab
+SHELL=/bin/sh
+PATH=/usr/local/sbin:/usr/local/bin:/sbin:/bin:/usr/sbin:/usr/bin
+# m h dom mon dow user  command
+17 *    * * *   root    cd / && run-parts --report /etc/cron.hourly
+*/5 * * * * root backup.sh > /dev/null 2>&1
+--cron-file:/etc/cron.d/cleanup
+@daily www-data /usr/bin/find /tmp -mtime +7 -delete
+--cron-file:crontab -l
+0 3 * * * tar czf /tmp/home.tgz /home/bob | logger%done
+";
+
+    #[test]
+    fn test_parse_crontabs() {
+        let entries = parse_crontabs(CRONTABS);
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[1].user.as_deref(), Some("root"));
+        assert_eq!(entries[1].command, "backup.sh > /dev/null 2>&1");
+        assert_eq!(entries[1].path[0], "/usr/local/sbin");
+        assert_eq!(entries[2].cron_file, "/etc/cron.d/cleanup");
+        assert_eq!(entries[2].user.as_deref(), Some("www-data"));
+        // a new file starts over with cron's own PATH
+        assert_eq!(entries[2].path, vec!["/usr/bin", "/bin"]);
+        assert_eq!(entries[3].user, None);
+
+        assert_eq!(bare_commands(&entries[0].command), vec!["run-parts"]);
+        assert_eq!(bare_commands(&entries[2].command), Vec::<String>::new());
+        assert_eq!(bare_commands(&entries[3].command), vec!["tar", "logger"]);
+    }
+
+    #[test]
+    fn test_find_injection_targets() {
+        let entries = parse_crontabs(CRONTABS);
+        let probe = lookup_probe(&entries);
+        assert!(probe.contains("'/usr/local/bin/backup.sh'"));
+        let lookup = "wd:/usr/local/sbin\nf:/bin/run-parts\nwf:/usr/bin/tar\nf:/usr/bin/logger\n";
+        let targets = find_injection_targets(&entries, lookup);
+        assert_eq!(
+            targets,
+            vec![
+                CronInjectionTarget {
+                    cron_file: String::from("/etc/crontab"),
+                    command: String::from("run-parts"),
+                    writable_path: String::from("/usr/local/sbin"),
+                },
+                CronInjectionTarget {
+                    cron_file: String::from("/etc/crontab"),
+                    command: String::from("backup.sh"),
+                    writable_path: String::from("/usr/local/sbin"),
+                },
+                CronInjectionTarget {
+                    cron_file: String::from("crontab -l"),
+                    command: String::from("tar"),
+                    writable_path: String::from("/usr/bin/tar"),
+                },
+            ]
+        );
+        // found before anything writable, it's the one that runs
+        let lookup = "f:/usr/local/sbin/run-parts\nwd:/usr/local/bin\n";
+        let targets = find_injection_targets(&entries[..1], lookup);
+        assert_eq!(targets, Vec::new());
+    }
+
+    #[test]
+    fn test_find_writable_scripts() {
+        assert_eq!(
+            script_paths("/usr/bin/python3 /opt/jobs/sync.py >> /var/log/sync.log 2>&1"),
+            vec!["/usr/bin/python3", "/opt/jobs/sync.py"]
+        );
+        assert_eq!(
+            script_paths("sh '/opt/a b.sh' >/tmp/out < /etc/input%/etc/passwd"),
+            vec!["/opt/a b.sh"]
+        );
+        let crontabs = "\
+--cron-file:/etc/crontab
+17 * * * * root cd / && run-parts --report /etc/cron.hourly
+*/5 * * * * root /usr/bin/python3 /opt/jobs/sync.py >> /var/log/sync.log 2>&1
+--cron-file:/var/spool/cron/crontabs/bob
+@reboot cleanup.sh
+--cron-file:crontab -l
+0 * * * * /home/www/rotate.sh
+";
+        let entries = parse_crontabs(crontabs);
+        let probe = script_probe(&entries);
+        assert!(probe.contains("'/opt/jobs/sync.py'"));
+        assert!(probe.contains("'/bin/cleanup.sh'"));
+        let lookup = "u:www-data\nf:/usr/bin/python3\nwf:/opt/jobs/sync.py\nf:/bin/run-parts\n\
+                      wf:/etc/cron.hourly/logrotate\nf:/usr/bin/cleanup.sh\nwf:/bin/cleanup.sh\n\
+                      wf:/home/www/rotate.sh\nwf:/var/log/sync.log\n";
+        let scripts = find_writable_scripts(&entries, lookup);
+        assert_eq!(
+            scripts,
+            vec![
+                WritableCronScript {
+                    cron_entry: String::from(
+                        "/etc/crontab: cd / && run-parts --report /etc/cron.hourly"
+                    ),
+                    script_path: String::from("/etc/cron.hourly/logrotate"),
+                    run_as: String::from("root"),
+                    high_severity: true,
+                },
+                WritableCronScript {
+                    cron_entry: String::from(
+                        "/etc/crontab: /usr/bin/python3 /opt/jobs/sync.py >> /var/log/sync.log 2>&1"
+                    ),
+                    script_path: String::from("/opt/jobs/sync.py"),
+                    run_as: String::from("root"),
+                    high_severity: true,
+                },
+                WritableCronScript {
+                    cron_entry: String::from("crontab -l: /home/www/rotate.sh"),
+                    script_path: String::from("/home/www/rotate.sh"),
+                    run_as: String::from("www-data"),
+                    high_severity: false,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use crate::recon::field;
+use crate::socket::connection::Handle;
+use crate::socket::exec::EXEC_TIMEOUT;
+
+/// only a socket the session user can write to is any use
+const DOCKER_PROBE: &str = "echo groups:$(id -Gn 2>/dev/null); \
+     echo client:$(command -v docker 2>/dev/null); \
+     echo socket:$(for s in /var/run/docker.sock /run/docker.sock; do \
+     [ -S $s ] && [ -w $s ] && echo $s && break; done)";
+
+/// The session user can ask the docker daemon, which runs as root, for a container
+/// with the host's disk mounted in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DockerEscapePath {
+    /// the writable daemon socket
+    pub socket: String,
+    /// path to the docker client, without one the socket's http api still works
+    pub client: Option<String>,
+    /// whether access comes from the docker group rather than the socket's mode
+    pub docker_group: bool,
+    pub reference: String,
+}
+
+pub fn parse_docker_output(output: &str) -> Option<DockerEscapePath> {
+    let socket = field(output, "socket")?;
+    let docker_group = field(output, "groups")
+        .map(|groups| groups.split_whitespace().any(|group| group == "docker"))
+        .unwrap_or(false);
+    return Some(DockerEscapePath {
+        socket: String::from(socket),
+        client: field(output, "client").map(String::from),
+        docker_group,
+        reference: String::from(
+            "https://book.hacktricks.xyz/linux-hardening/privilege-escalation/docker-security/docker-breakout-privilege-escalation",
+        ),
+    });
+}
+
+impl Handle {
+    /// Checks whether the session user can write to the docker daemon's socket, which
+    /// is enough to mount the host filesystem in a container
+    pub async fn check_docker_socket_escape(&self) -> Option<DockerEscapePath> {
+        let output = self.exec(DOCKER_PROBE, EXEC_TIMEOUT).await?;
+        return parse_docker_output(&output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_docker_output() {
+        let output = "groups:bob adm docker\nclient:/usr/bin/docker\nsocket:/var/run/docker.sock\n";
+        let path = parse_docker_output(output).unwrap();
+        assert_eq!(path.socket, "/var/run/docker.sock");
+        assert_eq!(path.client.as_deref(), Some("/usr/bin/docker"));
+        assert!(path.docker_group);
+
+        // a world writable socket works without the group or the client
+        let path = parse_docker_output("groups:bob\nclient:\nsocket:/run/docker.sock\n").unwrap();
+        assert!(!path.docker_group);
+        assert_eq!(path.client, None);
+
+        // in the group but the daemon isn't running
+        assert_eq!(
+            parse_docker_output("groups:bob docker\nclient:/usr/bin/docker\nsocket:\n"),
+            None
+        );
+    }
+}
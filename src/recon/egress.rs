@@ -0,0 +1,136 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+
+use crate::socket::connection::Handle;
+
+/// seconds the remote gets to complete each connection attempt
+const CONNECT_TIMEOUT: u64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EgressResult {
+    Open,
+    Closed,
+    Filtered,
+}
+
+/// Parses the `<port>:<exit status>` lines printed by the egress probe
+pub fn parse_egress_output(output: &str) -> HashMap<u16, i32> {
+    let mut statuses = HashMap::new();
+    for line in output.lines() {
+        if let Some((port, status)) = line.trim().split_once(':') {
+            if let (Ok(port), Ok(status)) = (port.parse::<u16>(), status.parse::<i32>()) {
+                statuses.insert(port, status);
+            }
+        }
+    }
+    return statuses;
+}
+
+/// Combines what the remote reported with what our local listener saw
+pub fn classify(status: Option<i32>, listening: bool, accepted: bool) -> EgressResult {
+    if accepted {
+        return EgressResult::Open;
+    }
+    return match status {
+        // the remote connected to something, but it wasn't us
+        Some(0) if listening => EgressResult::Filtered,
+        Some(0) => EgressResult::Open,
+        // timeout exits with 124 when the connection hangs
+        Some(124) | None => EgressResult::Filtered,
+        Some(_) => EgressResult::Closed,
+    };
+}
+
+impl Handle {
+    /// Checks which outbound ports the remote can reach us on
+    pub async fn test_firewall_egress(&self, ports: &[u16]) -> HashMap<u16, EgressResult> {
+        let mut results = HashMap::new();
+        let lhost = match self.write_stream.lock().await.local_addr() {
+            Ok(addr) => addr.ip(),
+            Err(_) => return results,
+        };
+
+        let accepted = Arc::new(Mutex::new(HashSet::<u16>::new()));
+        let mut listening = HashSet::new();
+        let mut accept_tasks = Vec::new();
+        for port in ports {
+            let listener = match TcpListener::bind(format!("0.0.0.0:{port}")).await {
+                Ok(val) => val,
+                Err(_) => continue,
+            };
+            listening.insert(*port);
+            let accepted = accepted.clone();
+            let port = *port;
+            accept_tasks.push(tokio::spawn(async move {
+                if listener.accept().await.is_ok() {
+                    accepted.lock().await.insert(port);
+                }
+            }));
+        }
+
+        let probes = ports
+            .iter()
+            .map(|port| {
+                format!(
+                    "timeout {CONNECT_TIMEOUT} bash -c 'echo >/dev/tcp/{lhost}/{port}' >/dev/null 2>&1; echo {port}:$?"
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("; ");
+        let wait = Duration::from_secs(CONNECT_TIMEOUT * ports.len() as u64 + 5);
+        let statuses = match self.exec(&probes, wait).await {
+            Some(output) => parse_egress_output(&output),
+            None => HashMap::new(),
+        };
+
+        for task in accept_tasks {
+            task.abort();
+        }
+        let accepted = accepted.lock().await;
+        for port in ports {
+            let result = classify(
+                statuses.get(port).copied(),
+                listening.contains(port),
+                accepted.contains(port),
+            );
+            results.insert(*port, result);
+        }
+        return results;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    #[test]
+    fn test_parse_egress_output() {
+        let statuses = parse_egress_output("80:0\n443:1\n8080:124\ngarbage\n");
+        assert_eq!(statuses.get(&80), Some(&0));
+        assert_eq!(statuses.get(&443), Some(&1));
+        assert_eq!(statuses.get(&8080), Some(&124));
+        assert_eq!(statuses.len(), 3);
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(Some(0), true, true), EgressResult::Open);
+        assert_eq!(classify(Some(0), false, false), EgressResult::Open);
+        assert_eq!(classify(Some(0), true, false), EgressResult::Filtered);
+        assert_eq!(classify(Some(124), true, false), EgressResult::Filtered);
+        assert_eq!(classify(None, true, false), EgressResult::Filtered);
+        assert_eq!(classify(Some(1), true, false), EgressResult::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_firewall_egress() {
+        let handle = spawn_shell_session(32429).await;
+        let results = handle.test_firewall_egress(&[32430]).await;
+        assert_eq!(results.get(&32430), Some(&EgressResult::Open));
+    }
+}
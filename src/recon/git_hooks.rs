@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::socket::connection::Handle;
+
+/// finding every repository under the home directories can take a while
+const GIT_HOOKS_SCAN_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// the session user, then a hooks directory or hook file it can write with the owner
+/// of the repository it's in
+const GIT_HOOKS_PROBE: &str = "echo u:$(id -un); \
+     find /home /root /opt -name .git -type d 2>/dev/null | while read -r d; do \
+     o=$(stat -c %U \"$d\" 2>/dev/null); \
+     [ -w \"$d/hooks\" ] && echo \"w:$o $d/hooks\"; \
+     for f in \"$d\"/hooks/*; do [ -f \"$f\" ] && [ -w \"$f\" ] && echo \"f:$o $f\"; done; \
+     done";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitHookFinding {
+    /// the working tree, the directory .git is in
+    pub repo_path: String,
+    /// a hooks directory a new hook can be added to, or a hook that can be changed
+    pub hook_path: String,
+    pub repo_owner: String,
+    /// root owns the repository, so its own git commands run the hooks as root
+    pub high_severity: bool,
+}
+
+/// Parses the probe output, keeping hooks in repositories someone else owns. Git
+/// only runs hooks for whoever runs git in the repository, the session user's own
+/// ones give it nothing it doesn't have
+pub fn parse_git_hooks_output(output: &str) -> Vec<GitHookFinding> {
+    let user = output
+        .lines()
+        .find_map(|line| line.strip_prefix("u:"))
+        .map(str::trim)
+        .unwrap_or_default();
+    let mut found = BTreeMap::new();
+    for line in output.lines() {
+        let rest = match line.strip_prefix("w:").or(line.strip_prefix("f:")) {
+            Some(val) => val,
+            None => continue,
+        };
+        let (owner, hook_path) = match rest.split_once(' ') {
+            Some(val) => val,
+            None => continue,
+        };
+        let repo_path = match hook_path.rsplit_once("/.git/hooks") {
+            Some((repo, _)) => repo,
+            None => continue,
+        };
+        if owner.is_empty() || owner == user {
+            continue;
+        }
+        found.insert(
+            String::from(hook_path),
+            GitHookFinding {
+                repo_path: String::from(repo_path),
+                hook_path: String::from(hook_path),
+                repo_owner: String::from(owner),
+                high_severity: owner == "root",
+            },
+        );
+    }
+    return found.into_values().collect();
+}
+
+impl Handle {
+    /// Lists git hooks the session user can add or change in repositories under /home,
+    /// /root and /opt that belong to other users
+    pub async fn check_git_hooks(&self) -> Vec<GitHookFinding> {
+        return match self.exec(GIT_HOOKS_PROBE, GIT_HOOKS_SCAN_TIMEOUT).await {
+            Some(output) => parse_git_hooks_output(&output),
+            None => Vec::new(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_git_hooks_output() {
+        let output = "\
+u:www-data
+w:root /root/deploy/.git/hooks
+f:root /root/deploy/.git/hooks/pre-commit
+f:alice /home/alice/my repo/.git/hooks/post-merge
+w:www-data /home/www-data/site/.git/hooks
+f: /opt/gone/.git/hooks/pre-push
+";
+        let found = parse_git_hooks_output(output);
+        let paths: Vec<(&str, &str, bool)> = found
+            .iter()
+            .map(|f| (f.repo_path.as_str(), f.hook_path.as_str(), f.high_severity))
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                (
+                    "/home/alice/my repo",
+                    "/home/alice/my repo/.git/hooks/post-merge",
+                    false
+                ),
+                ("/root/deploy", "/root/deploy/.git/hooks", true),
+                ("/root/deploy", "/root/deploy/.git/hooks/pre-commit", true),
+            ]
+        );
+        assert_eq!(found[0].repo_owner, "alice");
+        assert!(parse_git_hooks_output("u:root\n").is_empty());
+    }
+}
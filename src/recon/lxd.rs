@@ -0,0 +1,86 @@
+use crate::recon::field;
+use crate::socket::connection::Handle;
+use crate::socket::exec::EXEC_TIMEOUT;
+
+/// members of these groups can talk to the lxd daemon, which runs as root
+const LXD_GROUPS: [&str; 2] = ["lxd", "lxc"];
+
+const LXD_PROBE: &str = "echo groups:$(id -Gn 2>/dev/null); \
+     echo client:$(command -v lxc 2>/dev/null); \
+     echo daemon:$(command -v lxd 2>/dev/null || ls -d /var/snap/lxd /var/lib/lxd 2>/dev/null | head -n 1); \
+     echo socket:$(ls /var/snap/lxd/common/lxd/unix.socket /var/lib/lxd/unix.socket 2>/dev/null | head -n 1)";
+
+/// The session user can ask lxd for a privileged container with the host's disk in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LxdEscapePath {
+    /// the group that gives access to the daemon
+    pub group: String,
+    /// path to the lxc client
+    pub client: String,
+    /// the daemon's unix socket, when it could be found
+    pub socket: Option<String>,
+    pub reference: String,
+}
+
+pub fn parse_lxd_output(output: &str) -> Option<LxdEscapePath> {
+    let groups = field(output, "groups")?;
+    let group = groups
+        .split_whitespace()
+        .find(|group| LXD_GROUPS.contains(group))?;
+    let client = field(output, "client")?;
+    // the client alone isn't enough, something has to be listening
+    let socket = field(output, "socket");
+    if socket.is_none() && field(output, "daemon").is_none() {
+        return None;
+    }
+    return Some(LxdEscapePath {
+        group: String::from(group),
+        client: String::from(client),
+        socket: socket.map(String::from),
+        reference: String::from(
+            "https://book.hacktricks.xyz/linux-hardening/privilege-escalation/interesting-groups-linux-pe/lxd-privilege-escalation",
+        ),
+    });
+}
+
+impl Handle {
+    /// Checks whether the session user is in the lxd group with lxd installed, which
+    /// is enough to mount the host filesystem in a privileged container
+    pub async fn check_lxc_lxd_escape(&self) -> Option<LxdEscapePath> {
+        let output = self.exec(LXD_PROBE, EXEC_TIMEOUT).await?;
+        return parse_lxd_output(&output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lxd_output() {
+        let output = "groups:bob adm lxd\nclient:/snap/bin/lxc\ndaemon:/snap/bin/lxd\nsocket:/var/snap/lxd/common/lxd/unix.socket\n";
+        let path = parse_lxd_output(output).unwrap();
+        assert_eq!(path.group, "lxd");
+        assert_eq!(path.client, "/snap/bin/lxc");
+        assert_eq!(
+            path.socket.as_deref(),
+            Some("/var/snap/lxd/common/lxd/unix.socket")
+        );
+
+        // `lxd-admins` isn't the lxd group
+        assert_eq!(
+            parse_lxd_output(
+                "groups:bob lxd-admins\nclient:/usr/bin/lxc\ndaemon:/usr/bin/lxd\nsocket:\n"
+            ),
+            None
+        );
+        assert_eq!(
+            parse_lxd_output("groups:bob lxd\nclient:\ndaemon:/usr/bin/lxd\nsocket:\n"),
+            None
+        );
+        assert_eq!(
+            parse_lxd_output("groups:bob lxd\nclient:/usr/bin/lxc\ndaemon:\nsocket:\n"),
+            None
+        );
+    }
+}
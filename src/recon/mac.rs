@@ -0,0 +1,113 @@
+use crate::error::error::CrabTrapError;
+use crate::socket::connection::Handle;
+use crate::socket::exec::EXEC_TIMEOUT;
+
+/// Prints `key:value` lines describing the mandatory access control setup
+const MAC_PROBE: &str = "echo \"label:$(tr -d '\\000' </proc/self/attr/current 2>/dev/null)\"; \
+    echo \"apparmor:$(cat /sys/module/apparmor/parameters/enabled 2>/dev/null)\"; \
+    echo \"getenforce:$(getenforce 2>/dev/null)\"; \
+    echo \"userns:$(cat /proc/sys/kernel/unprivileged_userns_clone 2>/dev/null)\"";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacSystem {
+    AppArmor,
+    SELinux,
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MandatoryAccessReport {
+    pub system: MacSystem,
+    /// whether the policy is actually enforced on the current process
+    pub enforcing: bool,
+    pub current_label: String,
+    /// weaknesses in the setup worth a closer look
+    pub bypass_vectors: Vec<String>,
+}
+
+pub fn parse_mac_output(output: &str) -> MandatoryAccessReport {
+    let mut values = std::collections::HashMap::new();
+    for line in output.lines() {
+        if let Some((key, value)) = line.split_once(':') {
+            values.insert(key, value.trim());
+        }
+    }
+    let label = values.get("label").copied().unwrap_or("").to_string();
+    let getenforce = values.get("getenforce").copied().unwrap_or("");
+
+    let (system, enforcing) = if !getenforce.is_empty() && getenforce != "Disabled" {
+        (MacSystem::SELinux, getenforce == "Enforcing")
+    } else if values.get("apparmor") == Some(&"Y") {
+        (MacSystem::AppArmor, label.ends_with("(enforce)"))
+    } else {
+        (MacSystem::None, false)
+    };
+
+    let mut bypass_vectors = Vec::new();
+    match system {
+        MacSystem::SELinux if !enforcing => bypass_vectors.push(String::from(
+            "SELinux is in permissive mode, denials are only logged",
+        )),
+        MacSystem::SELinux if label.contains("unconfined_t") => bypass_vectors.push(String::from(
+            "current process runs in the unconfined_t domain",
+        )),
+        MacSystem::AppArmor if label == "unconfined" || label.is_empty() => {
+            bypass_vectors.push(String::from("current process has no AppArmor profile"))
+        }
+        MacSystem::AppArmor if label.ends_with("(complain)") => bypass_vectors.push(String::from(
+            "current AppArmor profile is in complain mode, denials are only logged",
+        )),
+        _ => {}
+    }
+    if values.get("userns") == Some(&"1") {
+        bypass_vectors.push(String::from(
+            "unprivileged user namespaces are enabled (kernel.unprivileged_userns_clone=1)",
+        ));
+    }
+
+    return MandatoryAccessReport {
+        system,
+        enforcing,
+        current_label: label,
+        bypass_vectors,
+    };
+}
+
+impl Handle {
+    /// Works out which mandatory access control system confines the shell
+    pub async fn check_apparmor_selinux(&self) -> Result<MandatoryAccessReport, CrabTrapError> {
+        return match self.exec(MAC_PROBE, EXEC_TIMEOUT).await {
+            Some(output) => Ok(parse_mac_output(&output)),
+            None => Err(CrabTrapError::NoResponse),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mac_output() {
+        let report = parse_mac_output("label:unconfined\napparmor:Y\ngetenforce:\nuserns:1\n");
+        assert_eq!(report.system, MacSystem::AppArmor);
+        assert!(!report.enforcing);
+        assert_eq!(report.bypass_vectors.len(), 2);
+
+        let report = parse_mac_output(
+            "label:/usr/sbin/nginx (enforce)\napparmor:Y\ngetenforce:\nuserns:0\n",
+        );
+        assert!(report.enforcing);
+        assert!(report.bypass_vectors.is_empty());
+
+        let report = parse_mac_output(
+            "label:system_u:system_r:httpd_t:s0\napparmor:\ngetenforce:Permissive\nuserns:\n",
+        );
+        assert_eq!(report.system, MacSystem::SELinux);
+        assert!(!report.enforcing);
+        assert_eq!(report.current_label, "system_u:system_r:httpd_t:s0");
+
+        let report = parse_mac_output("label:\napparmor:N\ngetenforce:\nuserns:\n");
+        assert_eq!(report.system, MacSystem::None);
+    }
+}
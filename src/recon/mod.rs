@@ -0,0 +1,29 @@
+pub mod ad;
+pub mod caps;
+pub mod certs;
+pub mod cloud;
+pub mod cron;
+pub mod docker;
+pub mod egress;
+pub mod git_hooks;
+pub mod lxd;
+pub mod mac;
+pub mod nfs;
+pub mod packages;
+pub mod perms;
+pub mod polkit;
+pub mod preload;
+pub mod root_paths;
+pub mod ssh_config;
+pub mod suid;
+pub mod systemd;
+
+/// The value of a `name:` line in probe output, None when it's missing or empty
+pub(crate) fn field<'a>(output: &'a str, name: &str) -> Option<&'a str> {
+    let prefix = format!("{name}:");
+    return output
+        .lines()
+        .find_map(|line| line.strip_prefix(prefix.as_str()))
+        .map(|value| value.trim())
+        .filter(|value| !value.is_empty());
+}
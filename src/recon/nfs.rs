@@ -0,0 +1,166 @@
+use crate::socket::connection::Handle;
+use crate::socket::exec::EXEC_TIMEOUT;
+
+/// separates the exports file from the mount table in the probe output
+const MOUNTS_MARKER: &str = "--mounts--";
+
+const NFS_PROBE: &str =
+    "cat /etc/exports 2>/dev/null; echo --mou''nts--; mount 2>/dev/null | grep nfs";
+
+/// export options that let a remote root act as root on the share
+const RISKY_OPTIONS: [&str; 2] = ["no_root_squash", "no_all_squash"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NfsMountInfo {
+    pub export_path: String,
+    /// host, network or wildcard the export is shared with, the server for mounted shares
+    pub client: String,
+    pub options: Vec<String>,
+    pub vulnerable: bool,
+    /// where the share is mounted on the remote, if it is
+    pub mounted_on: Option<String>,
+}
+
+fn split_options(options: &str) -> Vec<String> {
+    return options
+        .split(',')
+        .map(|opt| String::from(opt.trim()))
+        .filter(|opt| !opt.is_empty())
+        .collect();
+}
+
+/// Parses /etc/exports into one entry per client
+pub fn parse_exports(content: &str) -> Vec<NfsMountInfo> {
+    let joined = content.replace("\\\n", " ");
+    let mut exports = Vec::new();
+    for line in joined.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut fields = line.split_whitespace();
+        let export_path = match fields.next() {
+            Some(val) => String::from(val.trim_matches('"')),
+            None => continue,
+        };
+        // `-opts` before the clients applies to all of them
+        let mut shared_options = Vec::new();
+        let mut clients: Vec<&str> = fields.collect();
+        if let Some(first) = clients.first() {
+            if let Some(opts) = first.strip_prefix('-') {
+                shared_options = split_options(opts);
+                clients.remove(0);
+            }
+        }
+        // an export without a client is shared with everyone
+        if clients.is_empty() {
+            clients.push("*");
+        }
+        for client in clients {
+            let (host, mut options) = match client.split_once('(') {
+                Some((host, opts)) => (host, split_options(opts.trim_end_matches(')'))),
+                None => (client, Vec::new()),
+            };
+            options.splice(0..0, shared_options.clone());
+            let vulnerable = options
+                .iter()
+                .any(|opt| RISKY_OPTIONS.contains(&opt.as_str()));
+            exports.push(NfsMountInfo {
+                export_path: export_path.clone(),
+                client: String::from(if host.is_empty() { "*" } else { host }),
+                options,
+                vulnerable,
+                mounted_on: None,
+            });
+        }
+    }
+    return exports;
+}
+
+/// Parses `server:/path on /mnt type nfs (opts)` lines from `mount`
+pub fn parse_nfs_mounts(content: &str) -> Vec<NfsMountInfo> {
+    let mut mounts = Vec::new();
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 5 || fields[1] != "on" || !fields[4].starts_with("nfs") {
+            continue;
+        }
+        let (server, export_path) = match fields[0].rsplit_once(':') {
+            Some(val) => val,
+            None => continue,
+        };
+        let options = match fields.get(5) {
+            Some(opts) => split_options(opts.trim_matches(|c| c == '(' || c == ')')),
+            None => Vec::new(),
+        };
+        mounts.push(NfsMountInfo {
+            export_path: String::from(export_path),
+            client: String::from(server),
+            options,
+            vulnerable: false,
+            mounted_on: Some(String::from(fields[2])),
+        });
+    }
+    return mounts;
+}
+
+/// Marks exports that are also mounted and adds the mounts that aren't exported here
+pub fn cross_reference(
+    mut exports: Vec<NfsMountInfo>,
+    mounts: Vec<NfsMountInfo>,
+) -> Vec<NfsMountInfo> {
+    for mount in mounts {
+        let mut matched = false;
+        for export in exports.iter_mut() {
+            if export.export_path == mount.export_path {
+                export.mounted_on = mount.mounted_on.clone();
+                matched = true;
+            }
+        }
+        if !matched {
+            exports.push(mount);
+        }
+    }
+    return exports;
+}
+
+impl Handle {
+    /// Lists the remote's nfs exports and mounted shares, flagging exports that don't
+    /// squash root. Empty if nothing is exported or mounted or the shell didn't answer
+    pub async fn check_nfs_mounts(&self) -> Vec<NfsMountInfo> {
+        let output = match self.exec(NFS_PROBE, EXEC_TIMEOUT).await {
+            Some(val) => val,
+            None => return Vec::new(),
+        };
+        let (exports, mounts) = output.split_once(MOUNTS_MARKER).unwrap_or((&output, ""));
+        return cross_reference(parse_exports(exports), parse_nfs_mounts(mounts));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_exports() {
+        let content = "# shared with the lab\n\
+            /srv/nfs 10.0.0.0/24(rw,sync,no_root_squash) *(ro)\n\
+            /home -sync \\\n    backup(rw)\n\
+            /pub\n";
+        let exports = parse_exports(content);
+        assert_eq!(exports.len(), 4);
+        assert_eq!(exports[0].client, "10.0.0.0/24");
+        assert!(exports[0].vulnerable);
+        assert!(!exports[1].vulnerable);
+        assert_eq!(exports[2].export_path, "/home");
+        assert_eq!(exports[2].options, vec!["sync", "rw"]);
+        assert_eq!(exports[3].client, "*");
+
+        let mounts = parse_nfs_mounts(
+            "10.0.0.5:/srv/nfs on /mnt/nfs type nfs4 (rw,relatime,vers=4.2)\n\
+             nas:/backups on /mnt/backups type nfs (ro)\n",
+        );
+        let all = cross_reference(exports, mounts);
+        assert_eq!(all.len(), 5);
+        assert_eq!(all[0].mounted_on, Some(String::from("/mnt/nfs")));
+        assert_eq!(all[4].client, "nas");
+        assert_eq!(all[4].options, vec!["ro"]);
+    }
+}
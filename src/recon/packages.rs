@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+
+use crate::socket::connection::Handle;
+use crate::socket::exec::EXEC_TIMEOUT;
+
+const GEM_DIRS: &str = "gem env gempath 2>/dev/null | tr ':' '\\n'";
+
+const PIP_DIRS: &str = "for py in python3 python; do \
+     $py -c 'import site; print(\"\\n\".join(site.getsitepackages()))' 2>/dev/null; done";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageManager {
+    Gem,
+    Pip,
+}
+
+impl PackageManager {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            PackageManager::Gem => "gem",
+            PackageManager::Pip => "pip",
+        };
+    }
+}
+
+/// A package directory someone else owns that the session user can write, what's
+/// installed there runs for everyone who loads it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WritablePackagePath {
+    pub manager: PackageManager,
+    pub dir: PathBuf,
+}
+
+/// `w:<dir>` for each directory `list` prints that exists, isn't the session user's and
+/// can be written, checked with `test -w`
+fn writable_probe(list: &str) -> String {
+    return format!(
+        "me=$(id -un); ({list}) | sort -u | while read -r d; do \
+         [ -d \"$d\" ] || continue; [ \"$(stat -c %U \"$d\" 2>/dev/null)\" = \"$me\" ] && continue; \
+         [ -w \"$d\" ] && echo \"w:$d\"; \
+         done"
+    );
+}
+
+pub fn parse_package_paths(output: &str, manager: PackageManager) -> Vec<WritablePackagePath> {
+    return output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("w:"))
+        .filter(|dir| dir.starts_with('/'))
+        .map(|dir| WritablePackagePath {
+            manager,
+            dir: PathBuf::from(dir),
+        })
+        .collect();
+}
+
+impl Handle {
+    async fn check_package_paths(
+        &self,
+        list: &str,
+        manager: PackageManager,
+    ) -> Vec<WritablePackagePath> {
+        return match self.exec(&writable_probe(list), EXEC_TIMEOUT).await {
+            Some(output) => parse_package_paths(&output, manager),
+            None => Vec::new(),
+        };
+    }
+
+    /// Directories on the gem path the session user can write but doesn't own
+    pub async fn check_ruby_gem_writable(&self) -> Vec<WritablePackagePath> {
+        return self
+            .check_package_paths(GEM_DIRS, PackageManager::Gem)
+            .await;
+    }
+
+    /// System site-packages directories the session user can write but doesn't own
+    pub async fn check_pip_writable(&self) -> Vec<WritablePackagePath> {
+        return self
+            .check_package_paths(PIP_DIRS, PackageManager::Pip)
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_package_paths() {
+        let output = "w:/usr/lib/python3/dist-packages\nw:lib\nTraceback\n";
+        assert_eq!(
+            parse_package_paths(output, PackageManager::Pip),
+            vec![WritablePackagePath {
+                manager: PackageManager::Pip,
+                dir: PathBuf::from("/usr/lib/python3/dist-packages"),
+            }]
+        );
+        assert!(parse_package_paths("", PackageManager::Gem).is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::socket::connection::Handle;
+use crate::socket::exec::EXEC_TIMEOUT;
+
+/// the writable file search walks the whole filesystem
+const PERMS_SCAN_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// separates the stat lines from the process list in the probe output
+const PROCS_MARKER: &str = "--procs--";
+
+const PERMS_PROBE: &str = "find $(echo $PATH | tr ':' ' ') -maxdepth 1 -type f -perm -o+w -exec stat -c '%A %U %n' {} + 2>/dev/null; \
+     find / -writable -executable -type f -not -path '/proc/*' -not -path '/sys/*' -exec stat -c '%A %U %n' {} + 2>/dev/null; \
+     echo --pro''cs--; ps -eo user=,args= 2>/dev/null";
+
+/// root trusts these to say who's who, nobody else should be able to write them
+const ACCOUNT_FILES: &[&str] = &["/etc/passwd", "/etc/shadow", "/etc/group", "/etc/sudoers"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WeakPermFile {
+    pub path: String,
+    /// mode like `-rwxrwxrwx`
+    pub permissions: String,
+    pub owner: String,
+    /// a root process is running this binary, so writing it runs code as root
+    pub high_severity: bool,
+}
+
+/// What root processes are running, their binary and any script paths passed to it.
+/// Binaries are bare names when ps didn't show a path
+fn root_binaries(procs: &str) -> Vec<&str> {
+    let mut binaries = Vec::new();
+    for line in procs.lines() {
+        let mut fields = line.split_whitespace();
+        if fields.next() != Some("root") {
+            continue;
+        }
+        // kernel threads show up as [kthreadd]
+        match fields.next() {
+            Some(binary) if !binary.starts_with('[') => binaries.push(binary),
+            _ => continue,
+        }
+        binaries.extend(fields.filter(|arg| arg.starts_with('/')));
+    }
+    return binaries;
+}
+
+/// Parses `mode owner path` stat lines and the process list after the marker
+pub fn parse_perms_output(output: &str) -> Vec<WeakPermFile> {
+    let (files, procs) = output.split_once(PROCS_MARKER).unwrap_or((output, ""));
+    let running = root_binaries(procs);
+    // both searches can find the same file
+    let mut found = BTreeMap::new();
+    for line in files.lines() {
+        let mut fields = line.trim().splitn(3, ' ');
+        let (permissions, owner, path) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(perms), Some(owner), Some(path)) if path.starts_with('/') => (perms, owner, path),
+            _ => continue,
+        };
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let high_severity = running
+            .iter()
+            .any(|binary| *binary == path || (!binary.contains('/') && *binary == name));
+        found.insert(
+            String::from(path),
+            WeakPermFile {
+                path: String::from(path),
+                permissions: String::from(permissions),
+                owner: String::from(owner),
+                high_severity,
+            },
+        );
+    }
+    return found.into_values().collect();
+}
+
+fn account_files_probe() -> String {
+    let files = ACCOUNT_FILES.join(" ");
+    return format!("for f in {files}; do [ -w $f ] && stat -c '%A %U %n' $f; done 2>/dev/null");
+}
+
+/// The account files in `mode owner path` lines, writing any of them is as good as root
+pub fn parse_account_files_output(output: &str) -> Vec<WeakPermFile> {
+    return output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.trim().splitn(3, ' ');
+            let (permissions, owner, path) = (fields.next()?, fields.next()?, fields.next()?);
+            if !ACCOUNT_FILES.contains(&path) {
+                return None;
+            }
+            return Some(WeakPermFile {
+                path: String::from(path),
+                permissions: String::from(permissions),
+                owner: String::from(owner),
+                high_severity: true,
+            });
+        })
+        .collect();
+}
+
+impl Handle {
+    /// Whether the session user can write /etc/passwd, None when it can't
+    pub async fn check_etc_passwd_writable(&self) -> Option<WeakPermFile> {
+        return self
+            .check_account_files_writable()
+            .await
+            .into_iter()
+            .find(|file| file.path == "/etc/passwd");
+    }
+
+    /// The account files, like /etc/passwd and /etc/shadow, the session user can write
+    pub async fn check_account_files_writable(&self) -> Vec<WeakPermFile> {
+        return match self.exec(&account_files_probe(), EXEC_TIMEOUT).await {
+            Some(output) => parse_account_files_output(&output),
+            None => Vec::new(),
+        };
+    }
+
+    /// Lists world writable files in PATH and executables the session user can write,
+    /// flagging any that root processes are running
+    pub async fn check_weak_file_permissions(&self) -> Vec<WeakPermFile> {
+        return match self.exec(PERMS_PROBE, PERMS_SCAN_TIMEOUT).await {
+            Some(output) => parse_perms_output(&output),
+            None => Vec::new(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_perms_output() {
+        let output = "\
+-rwxrwxrwx root /usr/local/bin/backup.sh
+-rwxrwxr-x bob /home/bob/tool
+-rwxrwxrwx root /usr/local/bin/backup.sh
+-rwxrwxrwx root /opt/agent/agentd
+bad line
+--procs--
+root     [kthreadd]
+root     /bin/sh /usr/local/bin/backup.sh
+root     agentd --daemon
+bob      /home/bob/tool
+";
+        let files = parse_perms_output(output);
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0].path, "/home/bob/tool");
+        assert_eq!(files[0].owner, "bob");
+        // run by bob, not root
+        assert!(!files[0].high_severity);
+        assert_eq!(files[1].path, "/opt/agent/agentd");
+        assert!(files[1].high_severity);
+        // scripts root passes to an interpreter count too
+        assert_eq!(files[2].permissions, "-rwxrwxrwx");
+        assert!(files[2].high_severity);
+    }
+
+    #[test]
+    fn test_parse_account_files_output() {
+        let output = "-rw-rw-rw- root /etc/passwd
+-rw-rw---- root /etc/shadow
+-rw-r--r-- bob /home/bob/.bashrc
+";
+        let files = parse_account_files_output(output);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, "/etc/passwd");
+        assert_eq!(files[0].permissions, "-rw-rw-rw-");
+        assert!(files.iter().all(|file| file.high_severity));
+        assert!(parse_account_files_output("").is_empty());
+    }
+}
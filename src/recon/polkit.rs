@@ -0,0 +1,126 @@
+use crate::recon::field;
+use crate::socket::connection::Handle;
+use crate::socket::exec::EXEC_TIMEOUT;
+
+const POLKIT_PROBE: &str = "p=$(command -v pkexec 2>/dev/null); echo path:$p; \
+     [ -n \"$p\" ] && stat -c 'mode:%a %U' \"$p\" 2>/dev/null; \
+     pkexec --version 2>/dev/null | sed 's/^/version:/'";
+
+/// pkexec's argv handling, every release before 121 (0.121)
+const PWNKIT_FIXED: u32 = 121;
+
+/// the dbus auth race, from 0.113 until 0.119
+const DBUS_RACE_INTRODUCED: u32 = 113;
+const DBUS_RACE_FIXED: u32 = 119;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PolkitReport {
+    pub pkexec: Option<String>,
+    /// as pkexec reports it, like `0.105` or `121`
+    pub version: Option<String>,
+    /// pkexec is setuid root, which PwnKit needs
+    pub setuid_root: bool,
+    /// CVE-2021-4034, by version. Distros backport the fix so check the package too
+    pub pwnkit: bool,
+    /// CVE-2021-3560, by version
+    pub dbus_race: bool,
+    pub references: Vec<String>,
+}
+
+/// Polkit numbered releases 0.x until it dropped the leading zero at 121, so both
+/// come out as the release number
+fn release_number(version: &str) -> Option<u32> {
+    let mut parts = version.trim().split('.');
+    let first: u32 = parts.next()?.parse().ok()?;
+    return match first {
+        0 => parts.next()?.parse().ok(),
+        n => Some(n),
+    };
+}
+
+pub fn parse_polkit_output(output: &str) -> PolkitReport {
+    let mut report = PolkitReport {
+        pkexec: field(output, "path").map(String::from),
+        version: field(output, "version")
+            .and_then(|line| line.split_whitespace().last())
+            .map(String::from),
+        ..PolkitReport::default()
+    };
+    // the setuid bit makes the mode four digits, like 4755
+    report.setuid_root = field(output, "mode").is_some_and(|mode| {
+        let mut fields = mode.split_whitespace();
+        let perms = fields.next().unwrap_or_default();
+        let owner = fields.next().unwrap_or_default();
+        return perms.len() == 4 && perms.starts_with(['4', '6']) && owner == "root";
+    });
+    let release = match report.version.as_deref().and_then(release_number) {
+        Some(val) => val,
+        None => return report,
+    };
+    if report.setuid_root && release < PWNKIT_FIXED {
+        report.pwnkit = true;
+        report.references.push(String::from(
+            "https://nvd.nist.gov/vuln/detail/CVE-2021-4034",
+        ));
+    }
+    if (DBUS_RACE_INTRODUCED..DBUS_RACE_FIXED).contains(&release) {
+        report.dbus_race = true;
+        report.references.push(String::from(
+            "https://nvd.nist.gov/vuln/detail/CVE-2021-3560",
+        ));
+    }
+    return report;
+}
+
+impl Handle {
+    /// Checks the installed polkit version against CVE-2021-4034 and CVE-2021-3560.
+    /// This only reads versions and permissions, nothing is exploited
+    pub async fn check_polkit(&self) -> PolkitReport {
+        return match self.exec(POLKIT_PROBE, EXEC_TIMEOUT).await {
+            Some(output) => parse_polkit_output(&output),
+            None => PolkitReport::default(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_release_number() {
+        assert_eq!(release_number("0.105"), Some(105));
+        assert_eq!(release_number("121"), Some(121));
+        assert_eq!(release_number("124.1"), Some(124));
+        assert_eq!(release_number("beta"), None);
+    }
+
+    #[test]
+    fn test_parse_polkit_output() {
+        let report = parse_polkit_output(
+            "path:/usr/bin/pkexec\nmode:4755 root\nversion:pkexec version 0.105\n",
+        );
+        assert_eq!(report.pkexec.as_deref(), Some("/usr/bin/pkexec"));
+        assert_eq!(report.version.as_deref(), Some("0.105"));
+        assert!(report.setuid_root);
+        assert!(report.pwnkit);
+        assert!(!report.dbus_race);
+
+        let report = parse_polkit_output(
+            "path:/usr/bin/pkexec\nmode:4755 root\nversion:pkexec version 0.117\n",
+        );
+        assert!(report.pwnkit && report.dbus_race);
+        assert_eq!(report.references.len(), 2);
+
+        // no setuid bit, pkexec can't be used to get root
+        let report = parse_polkit_output(
+            "path:/usr/bin/pkexec\nmode:755 root\nversion:pkexec version 0.105\n",
+        );
+        assert!(!report.pwnkit);
+        let report = parse_polkit_output(
+            "path:/usr/bin/pkexec\nmode:4755 root\nversion:pkexec version 121\n",
+        );
+        assert!(!report.pwnkit && !report.dbus_race);
+        assert_eq!(parse_polkit_output("path:\n"), PolkitReport::default());
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use crate::socket::connection::Handle;
+
+/// the suid search walks the whole filesystem and runs ldd on each hit
+const PRELOAD_SCAN_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// The loader ignores LD_PRELOAD for suid binaries, so what matters is where it does
+/// look: the directories their libraries resolve from, and the files that tell it
+/// where to look for every binary
+const PRELOAD_PROBE: &str = "for b in $(find / -perm -u=s -type f 2>/dev/null); do \
+     ldd $b 2>/dev/null | grep -q '=> /' || continue; echo suid:$b; \
+     for d in $(ldd $b 2>/dev/null | awk '$3 ~ /^\\// {print $3}' | sed 's|/[^/]*$||' | sort -u); do \
+     [ -w $d ] && echo lib:$b:$d; done; done; \
+     for p in /etc/ld.so.preload /etc/ld.so.conf /etc/ld.so.conf.d \
+     $(cat /etc/ld.so.conf /etc/ld.so.conf.d/*.conf 2>/dev/null | grep '^/'); do \
+     [ -w $p ] && echo global:$p; done";
+
+/// A suid binary loads libraries from somewhere the session user can write, so a
+/// library planted there runs as the binary's owner
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LdPreloadPath {
+    pub suid_binary: String,
+    /// the library directory, or a loader config file that applies to every binary
+    pub writable_path: String,
+    pub reference: String,
+}
+
+/// Parses the probe's `suid:`, `lib:` and `global:` lines. A directory one of the
+/// binary's own libraries comes from is the surer path, a writable loader config
+/// only pays off once ld.so reads it again
+pub fn parse_preload_output(output: &str) -> Option<LdPreloadPath> {
+    let mut suid = Vec::new();
+    let mut global = Vec::new();
+    let mut found = None;
+    for line in output.lines().map(|line| line.trim()) {
+        if let Some(binary) = line.strip_prefix("suid:") {
+            suid.push(binary);
+        } else if let Some(pair) = line.strip_prefix("lib:") {
+            // paths with a colon are rarer in library directories than in binaries
+            if let Some((binary, dir)) = pair.rsplit_once(':') {
+                found = found.or(Some((binary, dir)));
+            }
+        } else if let Some(path) = line.strip_prefix("global:") {
+            global.push(path);
+        }
+    }
+    let (binary, path) = match found {
+        Some(val) => val,
+        None => (*suid.first()?, *global.first()?),
+    };
+    return Some(LdPreloadPath {
+        suid_binary: String::from(binary),
+        writable_path: String::from(path),
+        reference: String::from(
+            "https://book.hacktricks.xyz/linux-hardening/privilege-escalation/ld.so.conf-example",
+        ),
+    });
+}
+
+impl Handle {
+    /// Looks for a dynamically linked suid binary whose library search path the
+    /// session user can write to. Only reports where it is, nothing is built or planted
+    pub async fn check_ld_preload_attack(&self) -> Option<LdPreloadPath> {
+        let output = self.exec(PRELOAD_PROBE, PRELOAD_SCAN_TIMEOUT).await?;
+        return parse_preload_output(&output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_preload_output() {
+        let output = "suid:/usr/bin/passwd\nsuid:/opt/app/bin/helper\nlib:/opt/app/bin/helper:/opt/app/lib\nglobal:/etc/ld.so.conf.d\n";
+        let path = parse_preload_output(output).unwrap();
+        assert_eq!(path.suid_binary, "/opt/app/bin/helper");
+        assert_eq!(path.writable_path, "/opt/app/lib");
+
+        // a writable loader config reaches every dynamic suid binary
+        let path =
+            parse_preload_output("suid:/usr/bin/passwd\nglobal:/etc/ld.so.preload\n").unwrap();
+        assert_eq!(path.suid_binary, "/usr/bin/passwd");
+        assert_eq!(path.writable_path, "/etc/ld.so.preload");
+
+        // nothing writable, or nothing dynamic to load it
+        assert_eq!(parse_preload_output("suid:/usr/bin/passwd\n"), None);
+        assert_eq!(parse_preload_output("global:/etc/ld.so.preload\n"), None);
+    }
+}
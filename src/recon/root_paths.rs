@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+
+use crate::socket::connection::Handle;
+use crate::socket::exec::EXEC_TIMEOUT;
+
+/// only root should be able to write these, a normal user that can is a misconfiguration
+const ROOT_PATHS: [&str; 9] = [
+    "/etc",
+    "/etc/cron.d",
+    "/etc/sudoers.d",
+    "/etc/systemd/system",
+    "/root",
+    "/usr/bin",
+    "/usr/local/bin",
+    "/usr/local/sbin",
+    "/usr/sbin",
+];
+
+/// `w:<dir>` for each directory the session user can write, checked with `test -w` so
+/// nothing is created. PATH entries the user owns are theirs to write and skipped
+fn root_paths_probe() -> String {
+    return format!(
+        "me=$(id -un); \
+         for d in {} $(echo \"$PATH\" | tr ':' ' '); do \
+         [ -d \"$d\" ] || continue; [ \"$(stat -c %U \"$d\" 2>/dev/null)\" = \"$me\" ] && continue; \
+         [ -w \"$d\" ] && echo \"w:$d\"; \
+         done",
+        ROOT_PATHS.join(" ")
+    );
+}
+
+pub fn parse_root_paths_output(output: &str) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for line in output.lines() {
+        let dir = match line.trim().strip_prefix("w:") {
+            Some(val) if val.starts_with('/') => PathBuf::from(val),
+            _ => continue,
+        };
+        // PATH usually repeats some of the fixed list
+        if !paths.contains(&dir) {
+            paths.push(dir);
+        }
+    }
+    return paths;
+}
+
+impl Handle {
+    /// Lists directories only root should be able to write that the session user can,
+    /// from a fixed list and every PATH entry owned by someone else. A writable PATH
+    /// directory lets anything dropped there shadow commands root runs
+    pub async fn check_write_access_to_root_paths(&self) -> Vec<PathBuf> {
+        return match self.exec(&root_paths_probe(), EXEC_TIMEOUT).await {
+            Some(output) => parse_root_paths_output(&output),
+            None => Vec::new(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    #[test]
+    fn test_parse_root_paths_output() {
+        let output = "w:/usr/local/bin\nw:/etc/cron.d\nw:/usr/local/bin\nnoise\nw:relative\n";
+        assert_eq!(
+            parse_root_paths_output(output),
+            vec![
+                PathBuf::from("/usr/local/bin"),
+                PathBuf::from("/etc/cron.d")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_write_access_to_root_paths() {
+        let handle = spawn_shell_session(32461).await;
+        let dir = std::env::temp_dir().join("crab_trap_test_root_paths");
+        std::fs::create_dir_all(&dir).unwrap();
+        // owned by whoever runs the tests, so it's never reported
+        handle
+            .exec(
+                &format!("export PATH=\"{}:$PATH\"", dir.display()),
+                EXEC_TIMEOUT,
+            )
+            .await
+            .unwrap();
+        let found = handle.check_write_access_to_root_paths().await;
+        assert!(!found.contains(&dir));
+        std::fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+}
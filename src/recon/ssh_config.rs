@@ -0,0 +1,152 @@
+use crate::socket::connection::Handle;
+use crate::socket::exec::EXEC_TIMEOUT;
+
+/// separates the config from the identity files that were found
+const KEYS_MARKER: &str = "--keys--";
+
+/// Only checks whether each identity file is there, the keys themselves aren't read
+const SSH_CONFIG_PROBE: &str = "cat ~/.ssh/config 2>/dev/null; echo --ke''ys--; \
+     for f in $(awk 'tolower($1) == \"identityfile\" {print $2}' ~/.ssh/config 2>/dev/null); do \
+     p=$(echo $f | sed \"s|^~|$HOME|\"); [ -f \"$p\" ] && echo $f; done";
+
+/// A `Host` entry from the remote user's ssh config, where that user goes from here
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SshConfigHost {
+    /// the name given after `Host`
+    pub alias: String,
+    pub host_name: Option<String>,
+    pub user: Option<String>,
+    /// as written in the config, `~` isn't expanded
+    pub identity_file: Option<String>,
+    /// the identity file is there on the remote
+    pub identity_exists: bool,
+    pub proxy_jump: Option<String>,
+}
+
+/// `Key value` or `Key=value`, keys are case insensitive
+fn split_option(line: &str) -> Option<(String, &str)> {
+    let split = line.find(|c: char| c.is_whitespace() || c == '=')?;
+    let (key, value) = line.split_at(split);
+    let value = value.trim_start_matches(|c: char| c.is_whitespace() || c == '=');
+    let value = value.trim().trim_matches('"');
+    return match value.is_empty() {
+        true => None,
+        false => Some((key.to_ascii_lowercase(), value)),
+    };
+}
+
+/// Parses the config and the identity files after the marker. Patterns with
+/// wildcards match many hosts rather than naming one, so they're left out, and
+/// `Match` blocks aren't followed
+pub fn parse_ssh_config_output(output: &str) -> Vec<SshConfigHost> {
+    let (config, keys) = output.split_once(KEYS_MARKER).unwrap_or((output, ""));
+    let keys: Vec<&str> = keys.lines().map(|line| line.trim()).collect();
+    let mut hosts: Vec<SshConfigHost> = Vec::new();
+    // the entries the current block's options go to
+    let mut block = 0..0;
+    for line in config.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let (key, value) = match split_option(line) {
+            Some(val) => val,
+            None => continue,
+        };
+        match key.as_str() {
+            "host" => {
+                let start = hosts.len();
+                for alias in value.split_whitespace() {
+                    if alias.contains(['*', '?', '!']) {
+                        continue;
+                    }
+                    hosts.push(SshConfigHost {
+                        alias: String::from(alias),
+                        ..SshConfigHost::default()
+                    });
+                }
+                block = start..hosts.len();
+            }
+            "match" => block = hosts.len()..hosts.len(),
+            key => {
+                for host in &mut hosts[block.clone()] {
+                    // like ssh, the first value given wins
+                    let field = match key {
+                        "hostname" => &mut host.host_name,
+                        "user" => &mut host.user,
+                        "identityfile" => &mut host.identity_file,
+                        "proxyjump" => &mut host.proxy_jump,
+                        _ => continue,
+                    };
+                    field.get_or_insert_with(|| String::from(value));
+                }
+            }
+        }
+    }
+    for host in &mut hosts {
+        host.identity_exists = host
+            .identity_file
+            .as_deref()
+            .is_some_and(|file| keys.contains(&file));
+    }
+    return hosts;
+}
+
+impl Handle {
+    /// Lists the hosts in the session user's `~/.ssh/config`, with whether the
+    /// identity file each one names is on the remote
+    pub async fn tunnel_via_ssh_config(&self) -> Vec<SshConfigHost> {
+        return match self.exec(SSH_CONFIG_PROBE, EXEC_TIMEOUT).await {
+            Some(output) => parse_ssh_config_output(&output),
+            None => Vec::new(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ssh_config_output() {
+        let output = "\
+# jump box first
+Host bastion
+    HostName 203.0.113.10
+    User ops
+    IdentityFile ~/.ssh/bastion_ed25519
+
+Host db01 db02
+    HostName=10.0.2.5
+    User root
+    User postgres
+    ProxyJump bastion
+    IdentityFile \"~/.ssh/old key\"
+
+Host *
+    ServerAliveInterval 30
+    User nobody
+Match host staging
+    User deploy
+--keys--
+~/.ssh/bastion_ed25519
+";
+        let hosts = parse_ssh_config_output(output);
+        assert_eq!(hosts.len(), 3);
+        assert_eq!(
+            hosts[0],
+            SshConfigHost {
+                alias: String::from("bastion"),
+                host_name: Some(String::from("203.0.113.10")),
+                user: Some(String::from("ops")),
+                identity_file: Some(String::from("~/.ssh/bastion_ed25519")),
+                identity_exists: true,
+                proxy_jump: None,
+            }
+        );
+        assert_eq!(hosts[2].alias, "db02");
+        assert_eq!(hosts[2].host_name.as_deref(), Some("10.0.2.5"));
+        assert_eq!(hosts[2].user.as_deref(), Some("root"));
+        assert_eq!(hosts[2].proxy_jump.as_deref(), Some("bastion"));
+        assert!(!hosts[2].identity_exists);
+
+        assert_eq!(parse_ssh_config_output("--keys--\n"), Vec::new());
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::socket::connection::Handle;
+
+/// walking the whole filesystem takes a while
+const SUID_SCAN_TIMEOUT: Duration = Duration::from_secs(120);
+
+const SUID_PROBE: &str = "find / -perm -u=s -type f -exec stat -c '%U %n' {} + 2>/dev/null";
+
+/// binary name to the gtfobins functions it supports
+static GTFOBINS_JSON: &[u8] = include_bytes!("gtfobins.json");
+
+static GTFOBINS: OnceLock<HashMap<String, Vec<String>>> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GtfobinsEntry {
+    pub name: String,
+    /// what the binary can be abused for, like `shell` or `file-read`
+    pub functions: Vec<String>,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuidBinary {
+    pub path: String,
+    pub owner: String,
+    pub gtfobins_entry: Option<GtfobinsEntry>,
+}
+
+fn gtfobins() -> &'static HashMap<String, Vec<String>> {
+    return GTFOBINS.get_or_init(|| serde_json::from_slice(GTFOBINS_JSON).unwrap_or_default());
+}
+
+/// Finds the gtfobins entry for a binary, `python3.11` matches `python3`
+pub fn lookup_gtfobins(path: &str) -> Option<GtfobinsEntry> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let db = gtfobins();
+    let minor_stripped = name.split('.').next().unwrap_or(name);
+    let unversioned = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    let (name, functions) = db
+        .get_key_value(name)
+        .or_else(|| db.get_key_value(minor_stripped))
+        .or_else(|| db.get_key_value(unversioned))?;
+    return Some(GtfobinsEntry {
+        name: name.clone(),
+        functions: functions.clone(),
+        url: format!("https://gtfobins.github.io/gtfobins/{name}/"),
+    });
+}
+
+/// Parses `owner path` lines from the suid probe
+pub fn parse_suid_output(output: &str) -> Vec<SuidBinary> {
+    let mut binaries = Vec::new();
+    for line in output.lines() {
+        let (owner, path) = match line.trim().split_once(' ') {
+            Some(val) => val,
+            None => continue,
+        };
+        if !path.starts_with('/') {
+            continue;
+        }
+        binaries.push(SuidBinary {
+            path: String::from(path),
+            owner: String::from(owner),
+            gtfobins_entry: lookup_gtfobins(path),
+        });
+    }
+    return binaries;
+}
+
+impl Handle {
+    /// Lists setuid binaries on the remote along with any matching gtfobins entry
+    pub async fn find_suid_binaries(&self) -> Vec<SuidBinary> {
+        return match self.exec(SUID_PROBE, SUID_SCAN_TIMEOUT).await {
+            Some(output) => parse_suid_output(&output),
+            None => Vec::new(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_suid_output() {
+        let binaries = parse_suid_output(
+            "root /usr/bin/passwd\nroot /usr/bin/find\nbob /opt/tools/python3.11\nnot a path\n",
+        );
+        assert_eq!(binaries.len(), 3);
+        assert_eq!(binaries[0].gtfobins_entry, None);
+        let find = binaries[1].gtfobins_entry.as_ref().unwrap();
+        assert!(find.functions.contains(&String::from("suid")));
+        assert_eq!(find.url, "https://gtfobins.github.io/gtfobins/find/");
+        assert_eq!(binaries[2].owner, "bob");
+        assert_eq!(binaries[2].gtfobins_entry.as_ref().unwrap().name, "python3");
+    }
+}
@@ -0,0 +1,258 @@
+use std::time::Duration;
+
+use crate::socket::connection::Handle;
+
+/// reads every unit file, which takes a while on a big install
+const UNITS_SCAN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// one `unit:` line per service file with `user:` and `exec:` lines for it after,
+/// `w` means the session user can write it. ExecStart prefixes like `-` and `!` are
+/// dropped to get at the binary
+const UNITS_PROBE: &str = "echo me:$(id -un); \
+     for f in /etc/systemd/system/*.service /etc/systemd/system/*/*.service /lib/systemd/system/*.service; do \
+     [ -f \"$f\" ] || continue; [ -w \"$f\" ] && w=w || w=-; echo \"unit:$w:$f\"; \
+     sed -n 's/^User=//p' \"$f\" | head -n 1 | sed 's/^/user:/'; \
+     sed -n 's/^ExecStart=[-@:+!]*//p' \"$f\" | while read -r b _; do [ -w \"$b\" ] && w=w || w=-; echo \"exec:$w:$b\"; done; \
+     done";
+
+/// a `timer:` line per timer with `unit:` for the service it starts, `trigger:` for
+/// each `On...=` line and `file:` and `exec:` for the service, `w` meaning writable
+const TIMERS_PROBE: &str = "for t in $(systemctl list-timers --all --no-legend --no-pager 2>/dev/null | grep -o '[^ ]*\\.timer'); do \
+     s=$(systemctl show -p Unit --value \"$t\" 2>/dev/null); \
+     f=$(systemctl show -p FragmentPath --value \"$s\" 2>/dev/null); \
+     echo \"timer:$t\"; echo \"unit:$s\"; \
+     systemctl cat \"$t\" 2>/dev/null | sed -n 's/^On[A-Za-z]*=.*/trigger:&/p'; \
+     [ -f \"$f\" ] || continue; [ -w \"$f\" ] && w=w || w=-; echo \"file:$w:$f\"; \
+     sed -n 's/^ExecStart=[-@:+!]*//p' \"$f\" | head -n 1 | while read -r b rest; do \
+     [ -w \"$b\" ] && w=w || w=-; echo \"exec:$w:$b $rest\"; done; \
+     done";
+
+/// A service whose unit file or binary the session user can change, so whatever
+/// it's changed to runs as `service_user` next time the service starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WritableUnit {
+    pub path: String,
+    pub unit_name: String,
+    /// from `User=`, services without one run as root
+    pub service_user: String,
+    pub unit_writable: bool,
+    /// an ExecStart binary the session user can write
+    pub writable_exec: Option<String>,
+}
+
+fn finish(units: &mut Vec<WritableUnit>, unit: Option<WritableUnit>, me: &str) {
+    let unit = match unit {
+        Some(val) => val,
+        None => return,
+    };
+    // the same file can show up under a symlinked directory
+    if units.iter().any(|other| other.path == unit.path) {
+        return;
+    }
+    // a writable unit can be given any User=, a binary only runs as the one it has
+    if unit.unit_writable || (unit.writable_exec.is_some() && unit.service_user != me) {
+        units.push(unit);
+    }
+}
+
+pub fn parse_units_output(output: &str) -> Vec<WritableUnit> {
+    let mut me = "";
+    let mut units = Vec::new();
+    let mut current: Option<WritableUnit> = None;
+    for line in output.lines() {
+        let (kind, rest) = match line.trim().split_once(':') {
+            Some(val) => val,
+            None => continue,
+        };
+        match kind {
+            "me" => me = rest,
+            "unit" => {
+                finish(&mut units, current.take(), me);
+                let (flag, path) = match rest.split_once(':') {
+                    Some(val) => val,
+                    None => continue,
+                };
+                current = Some(WritableUnit {
+                    path: String::from(path),
+                    unit_name: String::from(path.rsplit('/').next().unwrap_or(path)),
+                    service_user: String::from("root"),
+                    unit_writable: flag == "w",
+                    writable_exec: None,
+                });
+            }
+            "user" => {
+                if let Some(unit) = current.as_mut() {
+                    if !rest.trim().is_empty() {
+                        unit.service_user = String::from(rest.trim());
+                    }
+                }
+            }
+            "exec" => {
+                if let (Some(unit), Some(("w", binary))) = (current.as_mut(), rest.split_once(':'))
+                {
+                    unit.writable_exec
+                        .get_or_insert_with(|| String::from(binary));
+                }
+            }
+            _ => {}
+        }
+    }
+    finish(&mut units, current, me);
+    return units;
+}
+
+/// A timer and the service it starts. `writable` when the session user can change
+/// the service's unit file or the binary its ExecStart runs, so whatever it's changed
+/// to runs the next time the timer fires
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemdTimer {
+    pub name: String,
+    /// the timer's `On...=` lines, `OnCalendar=daily` or `OnBootSec=5min`
+    pub trigger: String,
+    pub service_unit: String,
+    pub service_command: String,
+    pub writable: bool,
+}
+
+pub fn parse_timers_output(output: &str) -> Vec<SystemdTimer> {
+    let mut timers: Vec<SystemdTimer> = Vec::new();
+    for line in output.lines() {
+        let (kind, rest) = match line.trim().split_once(':') {
+            Some(val) => val,
+            None => continue,
+        };
+        if kind == "timer" {
+            timers.push(SystemdTimer {
+                name: String::from(rest),
+                trigger: String::new(),
+                service_unit: String::new(),
+                service_command: String::new(),
+                writable: false,
+            });
+            continue;
+        }
+        let timer = match timers.last_mut() {
+            Some(val) => val,
+            None => continue,
+        };
+        match kind {
+            "unit" => timer.service_unit = String::from(rest),
+            "trigger" => {
+                if !timer.trigger.is_empty() {
+                    timer.trigger += ", ";
+                }
+                timer.trigger += rest;
+            }
+            "file" | "exec" => {
+                if let Some((flag, value)) = rest.split_once(':') {
+                    timer.writable |= flag == "w";
+                    if kind == "exec" {
+                        timer.service_command = String::from(value.trim());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    return timers;
+}
+
+impl Handle {
+    /// Finds services under /etc/systemd/system and /lib/systemd/system whose unit
+    /// file, or the binary they start, the session user can write
+    pub async fn check_writable_systemd_units(&self) -> Vec<WritableUnit> {
+        return match self.exec(UNITS_PROBE, UNITS_SCAN_TIMEOUT).await {
+            Some(output) => parse_units_output(&output),
+            None => Vec::new(),
+        };
+    }
+
+    /// Lists every systemd timer with the service it starts, flagging the ones whose
+    /// service the session user can change
+    pub async fn check_timers(&self) -> Vec<SystemdTimer> {
+        return match self.exec(TIMERS_PROBE, UNITS_SCAN_TIMEOUT).await {
+            Some(output) => parse_timers_output(&output),
+            None => Vec::new(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_units_output() {
+        let output = "\
+me:bob
+unit:-:/etc/systemd/system/backup.service
+exec:-:/usr/bin/env
+exec:w:/opt/backup/run.sh
+unit:w:/etc/systemd/system/app.service
+user:www-data
+exec:-:/usr/bin/node
+unit:-:/etc/systemd/system/bob-agent.service
+user:bob
+exec:w:/home/bob/agent
+unit:-:/lib/systemd/system/ssh.service
+exec:-:/usr/sbin/sshd
+unit:w:/etc/systemd/system/app.service
+";
+        let units = parse_units_output(output);
+        assert_eq!(
+            units,
+            vec![
+                WritableUnit {
+                    path: String::from("/etc/systemd/system/backup.service"),
+                    unit_name: String::from("backup.service"),
+                    service_user: String::from("root"),
+                    unit_writable: false,
+                    writable_exec: Some(String::from("/opt/backup/run.sh")),
+                },
+                WritableUnit {
+                    path: String::from("/etc/systemd/system/app.service"),
+                    unit_name: String::from("app.service"),
+                    service_user: String::from("www-data"),
+                    unit_writable: true,
+                    writable_exec: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_timers_output() {
+        let output = "\
+timer:backup.timer
+unit:backup.service
+trigger:OnCalendar=daily
+trigger:OnBootSec=15min
+file:-:/etc/systemd/system/backup.service
+exec:w:/opt/backup/run.sh --full
+timer:logrotate.timer
+unit:logrotate.service
+trigger:OnCalendar=daily
+file:-:/lib/systemd/system/logrotate.service
+exec:-:/usr/sbin/logrotate /etc/logrotate.conf
+";
+        assert_eq!(
+            parse_timers_output(output),
+            vec![
+                SystemdTimer {
+                    name: String::from("backup.timer"),
+                    trigger: String::from("OnCalendar=daily, OnBootSec=15min"),
+                    service_unit: String::from("backup.service"),
+                    service_command: String::from("/opt/backup/run.sh --full"),
+                    writable: true,
+                },
+                SystemdTimer {
+                    name: String::from("logrotate.timer"),
+                    trigger: String::from("OnCalendar=daily"),
+                    service_unit: String::from("logrotate.service"),
+                    service_command: String::from("/usr/sbin/logrotate /etc/logrotate.conf"),
+                    writable: false,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use futures_util::stream::{self, StreamExt};
+use serde::Serialize;
+use tokio::time::timeout;
+
+use crate::remote::watch::{diff_lines, parse_interval, DiffLine, MAX_DIFF_LINES};
+use crate::socket::connection::Handle;
+use crate::socket::exec::{ExecResult, EXEC_TIMEOUT};
+use crate::socket::ops::Priority;
+
+pub const RUN_ALL_USAGE: &str =
+    "usage: run-all '<command>' [--tag <tag>] [--timeout <duration>] [--json <path>] [--loot <name>]";
+
+/// shells a batch runs its command on at once, the rest wait for a free slot
+pub const MAX_PARALLEL: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunAllArgs {
+    pub command: String,
+    /// only shells with this tag
+    pub tag: Option<String>,
+    /// how long each shell gets, waiting for its turn included
+    pub timeout: Duration,
+    pub json: Option<PathBuf>,
+    pub loot: Option<String>,
+}
+
+/// Reads `'<command>' [--<option> <value>]...`. Without quotes the command runs up to
+/// the first option
+pub fn parse_run_all_args(args: &str) -> Option<RunAllArgs> {
+    let args = args.trim();
+    let (command, rest) = match args.chars().next()? {
+        quote @ ('\'' | '"') => {
+            let end = args[1..].find(quote)? + 1;
+            (String::from(&args[1..end]), &args[end + 1..])
+        }
+        _ => {
+            let end = args.find(" --").unwrap_or(args.len());
+            (String::from(&args[..end]), &args[end..])
+        }
+    };
+    if command.trim().is_empty() {
+        return None;
+    }
+    let mut parsed = RunAllArgs {
+        command,
+        tag: None,
+        timeout: EXEC_TIMEOUT,
+        json: None,
+        loot: None,
+    };
+    let mut words = rest.split_whitespace();
+    while let Some(option) = words.next() {
+        let value = words.next()?;
+        match option {
+            "--tag" => parsed.tag = Some(String::from(value)),
+            "--timeout" => parsed.timeout = parse_interval(value)?,
+            "--json" => parsed.json = Some(PathBuf::from(value)),
+            "--loot" => parsed.loot = Some(String::from(value)),
+            _ => return None,
+        }
+    }
+    return Some(parsed);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostOutcome {
+    Done(ExecResult),
+    /// no answer in time, the shell is still open
+    TimedOut(Duration),
+    Closed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostResult {
+    pub session: String,
+    pub outcome: HostOutcome,
+}
+
+/// Runs `command` framed on every shell, at most MAX_PARALLEL at a time, in name order
+pub async fn run_batch(
+    shells: Vec<(String, Handle)>,
+    command: &str,
+    wait: Duration,
+) -> Vec<HostResult> {
+    let mut results: Vec<HostResult> = stream::iter(shells)
+        .map(|(session, handle)| async move {
+            let run = async {
+                let kind = handle.shell_kind().await;
+                return handle
+                    .exec_result(Priority::Interactive, kind, command, wait)
+                    .await;
+            };
+            let outcome = match timeout(wait, run).await {
+                Ok(Some(result)) => HostOutcome::Done(result),
+                _ if handle.is_closed() => HostOutcome::Closed,
+                _ => HostOutcome::TimedOut(wait),
+            };
+            return HostResult { session, outcome };
+        })
+        .buffer_unordered(MAX_PARALLEL)
+        .collect()
+        .await;
+    results.sort_by(|a, b| a.session.cmp(&b.session));
+    return results;
+}
+
+/// Shells that printed the same thing and finished the same way
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputGroup {
+    pub output: String,
+    pub status: Option<i32>,
+    pub sessions: Vec<String>,
+}
+
+/// The biggest group first, ties in name order
+pub fn group_results(results: &[HostResult]) -> Vec<OutputGroup> {
+    let mut groups: Vec<OutputGroup> = Vec::new();
+    for result in results {
+        let done = match &result.outcome {
+            HostOutcome::Done(val) => val,
+            _ => continue,
+        };
+        let same = groups
+            .iter_mut()
+            .find(|group| group.output == done.output && group.status == done.status);
+        match same {
+            Some(group) => group.sessions.push(result.session.clone()),
+            None => groups.push(OutputGroup {
+                output: done.output.clone(),
+                status: done.status,
+                sessions: vec![result.session.clone()],
+            }),
+        }
+    }
+    // stable, so equal sizes keep the order their first shell came in
+    groups.sort_by_key(|group| std::cmp::Reverse(group.sessions.len()));
+    return groups;
+}
+
+fn group_heading(group: &OutputGroup) -> String {
+    let who = match group.sessions.len() {
+        1 => group.sessions[0].clone(),
+        n => format!("{n} shells ({})", group.sessions.join(", ")),
+    };
+    return match group.status {
+        Some(status) if status != 0 => format!("{who} returned, exit {status}:"),
+        _ => format!("{who} returned:"),
+    };
+}
+
+fn indent(output: &str) -> String {
+    if output.is_empty() {
+        return String::from("  (no output)\n");
+    }
+    return output.lines().map(|line| format!("  {line}\n")).collect();
+}
+
+/// The most common output in full, the others as what differs from it, then the
+/// shells that didn't answer
+pub fn render_batch(results: &[HostResult]) -> String {
+    let groups = group_results(results);
+    let mut text = String::new();
+    if let Some(common) = groups.first() {
+        text += &group_heading(common);
+        text += "\n";
+        text += &indent(&common.output);
+        for group in &groups[1..] {
+            text += &group_heading(group);
+            let too_long = [&common.output, &group.output]
+                .iter()
+                .any(|output| output.lines().count() > MAX_DIFF_LINES);
+            if too_long {
+                text += "\n";
+                text += &indent(&group.output);
+                continue;
+            }
+            text += " (compared to the first)\n";
+            for line in diff_lines(&common.output, &group.output) {
+                text += &match line {
+                    DiffLine::Added(line) => format!("  + {line}\n"),
+                    DiffLine::Removed(line) => format!("  - {line}\n"),
+                };
+            }
+        }
+    }
+    let failures: Vec<String> = results
+        .iter()
+        .filter_map(|result| match &result.outcome {
+            HostOutcome::Done(_) => None,
+            HostOutcome::TimedOut(wait) => Some(format!(
+                "  {} no answer within {}s\n",
+                result.session,
+                wait.as_secs_f32()
+            )),
+            HostOutcome::Closed => Some(format!("  {} closed\n", result.session)),
+        })
+        .collect();
+    if !failures.is_empty() {
+        text += "Failed:\n";
+        text += &failures.concat();
+    }
+    if text.is_empty() {
+        text += "No shells to run it on\n";
+    }
+    return text;
+}
+
+#[derive(Serialize)]
+struct JsonHost<'a> {
+    session: &'a str,
+    /// ok, timeout or closed
+    result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct JsonBatch<'a> {
+    command: &'a str,
+    hosts: Vec<JsonHost<'a>>,
+}
+
+/// Every shell's result, for `--json` and `--loot`
+pub fn batch_json(command: &str, results: &[HostResult]) -> String {
+    let hosts = results
+        .iter()
+        .map(|result| {
+            let mut host = JsonHost {
+                session: &result.session,
+                result: "ok",
+                output: None,
+                status: None,
+                duration_ms: None,
+            };
+            match &result.outcome {
+                HostOutcome::Done(done) => {
+                    host.output = Some(&done.output);
+                    host.status = done.status;
+                    host.duration_ms = Some(done.duration.as_millis() as u64);
+                }
+                HostOutcome::TimedOut(_) => host.result = "timeout",
+                HostOutcome::Closed => host.result = "closed",
+            }
+            return host;
+        })
+        .collect();
+    let batch = JsonBatch { command, hosts };
+    return serde_json::to_string_pretty(&batch).unwrap_or_default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_parse_run_all_args() {
+        let args = parse_run_all_args("'uname -a; id' --tag linux --timeout 20s").unwrap();
+        assert_eq!(args.command, "uname -a; id");
+        assert_eq!(args.tag.as_deref(), Some("linux"));
+        assert_eq!(args.timeout, Duration::from_secs(20));
+        let args = parse_run_all_args("cat /etc/os-release --json out.json").unwrap();
+        assert_eq!(args.command, "cat /etc/os-release");
+        assert_eq!(args.json, Some(PathBuf::from("out.json")));
+        assert_eq!(args.timeout, EXEC_TIMEOUT);
+        assert!(parse_run_all_args("").is_none());
+        assert!(parse_run_all_args("''").is_none());
+        assert!(parse_run_all_args("'id").is_none());
+        assert!(parse_run_all_args("id --tag").is_none());
+        assert!(parse_run_all_args("id --timeout soon").is_none());
+        assert!(parse_run_all_args("id --force yes").is_none());
+    }
+
+    /// a shell that connects and never says anything
+    async fn silent_session(port: u16) -> (Handle, TcpStream) {
+        let listener = TcpListener::bind(format!("127.0.0.1:{port}"))
+            .await
+            .unwrap();
+        let client = tokio::spawn(TcpStream::connect(format!("127.0.0.1:{port}")));
+        let (soc, _) = listener.accept().await.unwrap();
+        let (read, write) = soc.into_split();
+        return (
+            Handle::new_headless(read, write),
+            client.await.unwrap().unwrap(),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_batch() {
+        let mut shells = Vec::new();
+        for (name, port, value) in [
+            ("web1", 32482, "same"),
+            ("web2", 32483, "same"),
+            ("db", 32484, "other"),
+        ] {
+            let handle = spawn_shell_session(port).await;
+            handle.exec(&format!("H={value}"), EXEC_TIMEOUT).await;
+            shells.push((String::from(name), handle));
+        }
+        let (silent, _remote) = silent_session(32485).await;
+        shells.push((String::from("dead"), silent));
+        let cmd = "echo $H; echo common; [ \"$H\" = same ]";
+        let results = run_batch(shells, cmd, Duration::from_secs(1)).await;
+        let names: Vec<&str> = results.iter().map(|r| r.session.as_str()).collect();
+        assert_eq!(names, vec!["db", "dead", "web1", "web2"]);
+        assert_eq!(
+            results[1].outcome,
+            HostOutcome::TimedOut(Duration::from_secs(1))
+        );
+
+        let groups = group_results(&results);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].sessions, vec!["web1", "web2"]);
+        assert_eq!(groups[0].output, "same\ncommon\n");
+        assert_eq!(groups[1].status, Some(1));
+        assert_eq!(
+            render_batch(&results),
+            "2 shells (web1, web2) returned:\n  same\n  common\n\
+             db returned, exit 1: (compared to the first)\n  + other\n  - same\n\
+             Failed:\n  dead no answer within 1s\n"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&batch_json(cmd, &results)).unwrap();
+        assert_eq!(json["command"], cmd);
+        assert_eq!(json["hosts"][0]["status"], 1);
+        assert_eq!(json["hosts"][1]["result"], "timeout");
+        assert!(json["hosts"][1].get("output").is_none());
+        assert_eq!(json["hosts"][3]["output"], "same\ncommon\n");
+    }
+}
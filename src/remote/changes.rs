@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::sync::mpsc::{channel, Receiver};
+
+use crate::error::error::CrabTrapError;
+use crate::socket::connection::Handle;
+use crate::socket::exec::shell_quote;
+
+/// events buffered for a monitor before it backs up
+const EVENT_CHANNEL_SIZE: usize = 1024;
+
+/// how polling snapshots end
+const SNAPSHOT_END: &str = "END";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeType {
+    Modified,
+    Created,
+    Deleted,
+    Accessed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChangeEvent {
+    pub path: String,
+    pub event: ChangeType,
+}
+
+/// mtime, atime and md5 of a file at one poll
+type FileState = (String, String, String);
+
+/// Turns inotifywait events or polling snapshots into change events
+#[derive(Default)]
+pub struct ChangeTracker {
+    previous: Option<HashMap<String, FileState>>,
+    current: HashMap<String, FileState>,
+}
+
+/// Maps an inotifywait event list like `CREATE,ISDIR` to a change
+fn inotify_change(events: &str) -> Option<ChangeType> {
+    for event in events.split(',') {
+        let change = match event {
+            "MODIFY" | "CLOSE_WRITE" | "ATTRIB" => ChangeType::Modified,
+            "CREATE" | "MOVED_TO" => ChangeType::Created,
+            "DELETE" | "DELETE_SELF" | "MOVED_FROM" => ChangeType::Deleted,
+            "ACCESS" => ChangeType::Accessed,
+            _ => continue,
+        };
+        return Some(change);
+    }
+    return None;
+}
+
+impl ChangeTracker {
+    pub fn feed(&mut self, line: &str) -> Vec<FileChangeEvent> {
+        let fields: Vec<&str> = line.splitn(5, '|').collect();
+        match fields.as_slice() {
+            // inotifywait --format '%e|%w%f'
+            [events, path] => {
+                return match inotify_change(events) {
+                    Some(event) => vec![FileChangeEvent {
+                        path: String::from(*path),
+                        event,
+                    }],
+                    None => Vec::new(),
+                };
+            }
+            ["F", mtime, atime, md5, path] => {
+                let state = (
+                    String::from(*mtime),
+                    String::from(*atime),
+                    String::from(*md5),
+                );
+                self.current.insert(String::from(*path), state);
+                return Vec::new();
+            }
+            [SNAPSHOT_END] => {}
+            _ => return Vec::new(),
+        }
+        let current = std::mem::take(&mut self.current);
+        // the first snapshot is the baseline
+        let previous = match self.previous.replace(current.clone()) {
+            Some(val) => val,
+            None => return Vec::new(),
+        };
+        let mut events = Vec::new();
+        for (path, (mtime, atime, md5)) in &current {
+            let event = match previous.get(path) {
+                None => ChangeType::Created,
+                Some((old_mtime, _, old_md5)) if old_mtime != mtime || old_md5 != md5 => {
+                    ChangeType::Modified
+                }
+                Some((_, old_atime, _)) if old_atime != atime => ChangeType::Accessed,
+                Some(_) => continue,
+            };
+            events.push(FileChangeEvent {
+                path: path.clone(),
+                event,
+            });
+        }
+        for path in previous.keys().filter(|path| !current.contains_key(*path)) {
+            events.push(FileChangeEvent {
+                path: path.clone(),
+                event: ChangeType::Deleted,
+            });
+        }
+        events.sort_by(|a, b| a.path.cmp(&b.path));
+        return events;
+    }
+}
+
+/// Builds the remote watch command, inotifywait if it's there and polling if not
+fn monitor_command(path: &str, interval: Duration) -> String {
+    let path = shell_quote(path);
+    let secs = interval.as_secs_f64().max(0.1);
+    return format!(
+        "if command -v inotifywait >/dev/null 2>&1; then \
+         inotifywait -m -q -r -e modify,attrib,create,delete,moved_to,moved_from,access --format '%e|%w%f' {path}; \
+         else while :; do find {path} -type f 2>/dev/null | while IFS= read -r f; do \
+         echo \"F|$(stat -c '%Y|%X' \"$f\")|$(md5sum < \"$f\" | cut -c1-32)|$f\"; done; \
+         echo {SNAPSHOT_END}; sleep {secs:.1}; done; fi"
+    );
+}
+
+/// Changes to a path on the remote, reported as they happen
+pub struct FileMonitor {
+    handle: Handle,
+    job_id: u64,
+    pub events: Receiver<FileChangeEvent>,
+}
+
+impl FileMonitor {
+    pub async fn stop(&self) {
+        self.handle.stop_background(self.job_id).await;
+    }
+}
+
+impl Handle {
+    /// Watches a file or directory on the remote with inotifywait, falling back to
+    /// checking mtimes and hashes every `interval` when it isn't installed
+    pub async fn monitor_file_changes(
+        &self,
+        path: &str,
+        interval: Duration,
+    ) -> Result<FileMonitor, CrabTrapError> {
+        let mut job = match self
+            .spawn_background(&monitor_command(path, interval))
+            .await
+        {
+            Some(val) => val,
+            None => return Err(CrabTrapError::NoResponse),
+        };
+        let (tx, events) = channel::<FileChangeEvent>(EVENT_CHANNEL_SIZE);
+        tokio::spawn(async move {
+            let mut tracker = ChangeTracker::default();
+            while let Some(line) = job.lines.recv().await {
+                for event in tracker.feed(&line) {
+                    if tx.send(event).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        return Ok(FileMonitor {
+            handle: self.clone(),
+            job_id: job.id,
+            events,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::exec::EXEC_TIMEOUT;
+    use crate::socket::mock_shell::spawn_shell_session;
+    use tokio::time::timeout;
+
+    #[test]
+    fn test_change_tracker() {
+        let mut tracker = ChangeTracker::default();
+        assert_eq!(
+            tracker.feed("CREATE|/tmp/x/new"),
+            vec![FileChangeEvent {
+                path: String::from("/tmp/x/new"),
+                event: ChangeType::Created
+            }]
+        );
+        assert!(tracker.feed("OPEN|/tmp/x/new").is_empty());
+
+        tracker.feed("F|1|1|aaa|/tmp/x/a");
+        tracker.feed("F|1|1|bbb|/tmp/x/b");
+        assert!(tracker.feed("END").is_empty());
+        tracker.feed("F|2|1|abc|/tmp/x/a");
+        tracker.feed("F|1|1|ccc|/tmp/x/c");
+        let events = tracker.feed("END");
+        let changes: Vec<ChangeType> = events.iter().map(|event| event.event).collect();
+        assert_eq!(
+            changes,
+            vec![
+                ChangeType::Modified,
+                ChangeType::Deleted,
+                ChangeType::Created
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_monitor_file_changes() {
+        let handle = spawn_shell_session(32442).await;
+        let dir = std::env::temp_dir().join("crab_trap_test_monitor");
+        std::fs::remove_dir_all(&dir).unwrap_or_default();
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir_path = dir.to_str().unwrap();
+
+        let mut monitor = handle
+            .monitor_file_changes(dir_path, Duration::from_millis(200))
+            .await
+            .unwrap();
+        // let inotifywait or the first snapshot settle
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        handle
+            .exec(&format!("echo hi > {dir_path}/new.txt"), EXEC_TIMEOUT)
+            .await
+            .unwrap();
+        let event = timeout(EXEC_TIMEOUT, monitor.events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.path, format!("{dir_path}/new.txt"));
+        assert_eq!(event.event, ChangeType::Created);
+        monitor.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+}
@@ -0,0 +1,139 @@
+use crate::error::error::CrabTrapError;
+use crate::socket::connection::Handle;
+use crate::socket::exec::{cmd_quote, shell_quote, ShellKind, EXEC_TIMEOUT};
+use crate::socket::history::EventKind;
+
+/// printed after the copy so its exit status can be told apart from its output
+const STATUS_PREFIX: &str = "copy_status:";
+
+/// Collects md5 hashes from `md5sum` or `certutil -hashfile` output
+pub fn parse_md5_hashes(output: &str) -> Vec<String> {
+    let is_md5 = |s: &str| s.len() == 32 && s.chars().all(|c| c.is_ascii_hexdigit());
+    let mut hashes = Vec::new();
+    for line in output.lines() {
+        let first = line.split_whitespace().next().unwrap_or("");
+        // older certutil versions space separate the hash bytes
+        let joined = line.replace(' ', "");
+        if is_md5(first) {
+            hashes.push(first.to_lowercase());
+        } else if is_md5(&joined) {
+            hashes.push(joined.to_lowercase());
+        }
+    }
+    return hashes;
+}
+
+/// Splits the copy output into whether it succeeded and what it printed
+fn parse_copy_output(output: &str) -> (bool, String) {
+    let mut copied = false;
+    let mut messages = Vec::new();
+    for line in output.lines() {
+        match line.trim().strip_prefix(STATUS_PREFIX) {
+            Some(status) => copied = status == "0",
+            None => messages.push(line.trim()),
+        }
+    }
+    return (copied, messages.join("\n").trim().to_string());
+}
+
+impl Handle {
+    /// Copies a file on the remote and checks the copy hashes the same as the original
+    pub async fn copy_file_remote(&self, src: &str, dst: &str) -> Result<(), CrabTrapError> {
+        let kind = self.shell_kind().await;
+        let (copy_cmd, hash_cmd) = match kind {
+            ShellKind::Sh => (
+                format!(
+                    "cp -p {src} {dst} 2>&1 && echo {STATUS_PREFIX}0 || echo {STATUS_PREFIX}1",
+                    src = shell_quote(src),
+                    dst = shell_quote(dst)
+                ),
+                format!(
+                    "md5sum {src} {dst} 2>&1",
+                    src = shell_quote(src),
+                    dst = shell_quote(dst)
+                ),
+            ),
+            ShellKind::Cmd => (
+                format!(
+                    "copy /Y {src} {dst} >nul 2>&1 && (echo {STATUS_PREFIX}0) || (echo {STATUS_PREFIX}1)",
+                    src = cmd_quote(src),
+                    dst = cmd_quote(dst)
+                ),
+                format!(
+                    "certutil -hashfile {src} MD5 & certutil -hashfile {dst} MD5",
+                    src = cmd_quote(src),
+                    dst = cmd_quote(dst)
+                ),
+            ),
+        };
+
+        let output = match self.exec_with(kind, &copy_cmd, EXEC_TIMEOUT).await {
+            Some(val) => val,
+            None => return Err(CrabTrapError::NoResponse),
+        };
+        let (copied, reason) = parse_copy_output(&output);
+        if !copied {
+            return Err(CrabTrapError::RemoteCopyFailed { reason });
+        }
+
+        let hashes = match self.exec_with(kind, &hash_cmd, EXEC_TIMEOUT).await {
+            Some(val) => parse_md5_hashes(&val),
+            None => return Err(CrabTrapError::NoResponse),
+        };
+        if hashes.len() != 2 || hashes[0] != hashes[1] {
+            return Err(CrabTrapError::RemoteCopyFailed {
+                reason: String::from("copy does not match the original"),
+            });
+        }
+        self.record(EventKind::Transfer, &format!("copied {src} to {dst}"));
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    #[test]
+    fn test_parse_md5_hashes() {
+        let md5sum =
+            "d41d8cd98f00b204e9800998ecf8427e  /tmp/a\nd41d8cd98f00b204e9800998ecf8427e  /tmp/b\n";
+        assert_eq!(parse_md5_hashes(md5sum).len(), 2);
+        let certutil = "MD5 hash of a.txt:\nd4 1d 8c d9 8f 00 b2 04 e9 80 09 98 ec f8 42 7e\nCertUtil: -hashfile command completed successfully.\n";
+        assert_eq!(
+            parse_md5_hashes(certutil),
+            vec![String::from("d41d8cd98f00b204e9800998ecf8427e")]
+        );
+    }
+
+    #[test]
+    fn test_parse_copy_output() {
+        let (copied, reason) =
+            parse_copy_output("cp: cannot stat 'x': No such file or directory\ncopy_status:1\n");
+        assert!(!copied);
+        assert_eq!(reason, "cp: cannot stat 'x': No such file or directory");
+        assert!(parse_copy_output("copy_status:0\n").0);
+    }
+
+    #[tokio::test]
+    async fn test_copy_file_remote() {
+        let dir = std::env::temp_dir();
+        let src = dir.join("crab_trap_copy_src.txt");
+        let dst = dir.join("crab_trap_copy_dst.txt");
+        std::fs::write(&src, "some content").unwrap();
+        let handle = spawn_shell_session(32434).await;
+        let res = handle
+            .copy_file_remote(src.to_str().unwrap(), dst.to_str().unwrap())
+            .await;
+        assert_eq!(res, Ok(()));
+        assert_eq!(std::fs::read_to_string(&dst).unwrap(), "some content");
+
+        let res = handle
+            .copy_file_remote("/nonexistent/crab_trap", dst.to_str().unwrap())
+            .await;
+        assert!(matches!(res, Err(CrabTrapError::RemoteCopyFailed { .. })));
+        std::fs::remove_file(src).unwrap_or_default();
+        std::fs::remove_file(dst).unwrap_or_default();
+    }
+}
@@ -0,0 +1,214 @@
+use std::time::Duration;
+
+use crate::socket::connection::Handle;
+use crate::socket::exec::ShellKind;
+use crate::socket::ops::Priority;
+
+/// the probe runs as the operator goes back to the menu, so it can't hold them up long
+const CWD_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What's known about where the remote shell is
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoteCwd {
+    pub cwd: Option<String>,
+    /// the probe got no answer once, the shell isn't asked again
+    pub unsupported: bool,
+}
+
+/// The directory in a probe's output, nothing when it doesn't look like a path
+pub fn parse_cwd(output: &str, kind: ShellKind) -> Option<String> {
+    let line = output
+        .lines()
+        .map(str::trim)
+        .rfind(|line| !line.is_empty())?;
+    let is_path = match kind {
+        ShellKind::Sh => line.starts_with('/'),
+        // like C:\Users\bob
+        ShellKind::Cmd => line.get(1..3) == Some(":\\"),
+    };
+    return match is_path {
+        true => Some(String::from(line)),
+        false => None,
+    };
+}
+
+/// Whether a prompt is a shell's and not a repl's like `>>> ` or `mysql> `
+pub fn looks_like_shell_prompt(prompt: &str, kind: ShellKind) -> bool {
+    let prompt = prompt.trim_end();
+    return match kind {
+        // psql's `db=#` ends like a root prompt
+        ShellKind::Sh => {
+            prompt.ends_with(['$', '#', '%']) && !prompt.ends_with("=#") && !prompt.ends_with("-#")
+        }
+        // like C:\Users\bob> or PS C:\Users\bob>
+        ShellKind::Cmd => {
+            prompt.ends_with('>') && prompt.trim_start_matches("PS ").get(1..3) == Some(":\\")
+        }
+    };
+}
+
+impl Handle {
+    /// The remote's working directory as of the last refresh
+    pub fn remote_cwd(&self) -> Option<String> {
+        return self.remote_cwd.lock().ok()?.cwd.clone();
+    }
+
+    /// Asks the remote where it is, best effort. Skipped in raw mode, while anything
+    /// else is using the session, while a command is still running so the probe can't
+    /// end up typed into it, and for shells that didn't answer before. It's only sent
+    /// to a shell whose kind is known, sitting at its own prompt rather than a program
+    /// like python that it started
+    pub async fn refresh_remote_cwd(&self) -> Option<String> {
+        let unsupported = match self.remote_cwd.lock() {
+            Ok(known) => known.unsupported,
+            Err(_) => true,
+        };
+        let skip = self.raw_mode || self.is_closed() || self.session_busy();
+        if skip || unsupported || self.command_running() {
+            return self.remote_cwd();
+        }
+        // a cached os only, detecting it would send more probes
+        let kind = match *self.remote_os.lock().await {
+            Some(os) => os.shell_kind(),
+            None => return self.remote_cwd(),
+        };
+        let at_shell = self.timer.lock().is_ok_and(|timer| {
+            let prompt = timer.prompt().filter(|_| !timer.interactive());
+            prompt.is_some_and(|prompt| looks_like_shell_prompt(prompt, kind))
+        });
+        if !at_shell {
+            return self.remote_cwd();
+        }
+        let probe = match kind {
+            ShellKind::Sh => "pwd",
+            ShellKind::Cmd => "cd",
+        };
+        let output = self
+            .exec_quietly(Priority::Background, kind, probe, CWD_TIMEOUT)
+            .await;
+        let mut known = self.remote_cwd.lock().ok()?;
+        match output.as_deref().and_then(|output| parse_cwd(output, kind)) {
+            Some(cwd) => known.cwd = Some(cwd),
+            None => {
+                known.cwd = None;
+                known.unsupported = output.is_none();
+            }
+        }
+        return known.cwd.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::exec::{RemoteOs, EXEC_TIMEOUT};
+    use crate::socket::mock_shell::spawn_shell_session;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::timeout;
+
+    #[test]
+    fn test_parse_cwd() {
+        assert_eq!(
+            parse_cwd("/var/www\n", ShellKind::Sh),
+            Some(String::from("/var/www"))
+        );
+        assert_eq!(
+            parse_cwd("\r\nC:\\Users\\bob\r\n\r\n", ShellKind::Cmd),
+            Some(String::from("C:\\Users\\bob"))
+        );
+        assert_eq!(parse_cwd("pwd: not found\n", ShellKind::Sh), None);
+        assert_eq!(parse_cwd("", ShellKind::Sh), None);
+    }
+
+    #[test]
+    fn test_looks_like_shell_prompt() {
+        assert!(looks_like_shell_prompt("user@box:~$ ", ShellKind::Sh));
+        assert!(looks_like_shell_prompt("root@box:/# ", ShellKind::Sh));
+        assert!(looks_like_shell_prompt("box% ", ShellKind::Sh));
+        assert!(!looks_like_shell_prompt(">>> ", ShellKind::Sh));
+        assert!(!looks_like_shell_prompt("mysql> ", ShellKind::Sh));
+        assert!(!looks_like_shell_prompt("postgres=# ", ShellKind::Sh));
+        assert!(looks_like_shell_prompt("C:\\Users\\bob>", ShellKind::Cmd));
+        assert!(looks_like_shell_prompt(
+            "PS C:\\Users\\bob> ",
+            ShellKind::Cmd
+        ));
+        assert!(!looks_like_shell_prompt(">>> ", ShellKind::Cmd));
+    }
+
+    #[tokio::test]
+    async fn test_no_probe_in_repl() {
+        let listener = TcpListener::bind("127.0.0.1:32487").await.unwrap();
+        let client = tokio::spawn(TcpStream::connect("127.0.0.1:32487"));
+        let (soc, _) = listener.accept().await.unwrap();
+        let mut remote = client.await.unwrap().unwrap();
+        let (read, write) = soc.into_split();
+        let handle = Handle::new_headless(read, write);
+        *handle.remote_os.lock().await = Some(RemoteOs::Linux);
+
+        // python was started from the shell, and a line typed into it
+        handle.time_command("python3");
+        handle.time_output("Python 3.11.2\n>>> ");
+        assert_eq!(handle.refresh_remote_cwd().await, None);
+        handle.time_command("import os");
+        handle.time_output(">>> ");
+        assert_eq!(handle.refresh_remote_cwd().await, None);
+
+        let mut buf = [0; 64];
+        let read = timeout(Duration::from_millis(300), remote.read(&mut buf)).await;
+        assert!(read.is_err(), "the repl was sent {:?}", read);
+        assert!(!handle.remote_cwd.lock().unwrap().unsupported);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_remote_cwd() {
+        let handle = spawn_shell_session(32460).await;
+        // the shell hasn't been confirmed yet
+        handle.time_output("$ ");
+        assert_eq!(handle.refresh_remote_cwd().await, None);
+        handle.detect_os().await.unwrap();
+        handle.exec("cd /tmp", EXEC_TIMEOUT).await.unwrap();
+        {
+            let mut write_soc = handle.write_stream.lock().await;
+            write_soc.write_all(b"false\n").await.unwrap();
+        }
+        assert_eq!(
+            handle.refresh_remote_cwd().await,
+            Some(String::from("/tmp"))
+        );
+
+        // the operator's next command still sees the status of `false`
+        let mut write_soc = handle.write_stream.lock().await;
+        write_soc.write_all(b"echo status:$?\n").await.unwrap();
+        drop(write_soc);
+        let mut read_soc = handle.read_stream.lock().await;
+        let mut content = String::new();
+        let mut buf = [0; 1024];
+        while !content.contains("status:") || !content.ends_with('\n') {
+            let n = timeout(EXEC_TIMEOUT, read_soc.read(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            content += &String::from_utf8_lossy(&buf[..n]);
+        }
+        assert!(content.contains("status:1"));
+        assert!(!content.contains("__crab_trap_status"));
+        drop(read_soc);
+        assert_eq!(
+            handle
+                .exec("echo ${__crab_trap_status-unset}", EXEC_TIMEOUT)
+                .await,
+            Some(String::from("unset\n"))
+        );
+
+        // nothing's asked while a command could still be reading input
+        handle.time_command("sleep 1");
+        handle.exec("cd /", EXEC_TIMEOUT).await.unwrap();
+        assert_eq!(
+            handle.refresh_remote_cwd().await,
+            Some(String::from("/tmp"))
+        );
+        assert_eq!(handle.remote_cwd(), Some(String::from("/tmp")));
+    }
+}
@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::error::error::CrabTrapError;
+use crate::socket::connection::Handle;
+use crate::socket::exec::{shell_quote, ShellKind};
+use crate::socket::history::EventKind;
+use crate::socket::ops::Priority;
+
+/// Decodes base64 output from the remote, ignoring the line wrapping `base64` adds
+pub fn decode_base64_output(output: &str) -> Result<Vec<u8>, CrabTrapError> {
+    let cleaned: String = output.chars().filter(|c| !c.is_whitespace()).collect();
+    return STANDARD
+        .decode(cleaned)
+        .map_err(|err| CrabTrapError::RemoteCommandFailed {
+            reason: format!("invalid base64 from remote: {err}"),
+        });
+}
+
+impl Handle {
+    /// Fetches a url from the remote's point of view with curl, or wget if curl is missing.
+    /// Both honour the remote's proxy environment variables. `wait` covers the whole
+    /// download, base64 included, so give big files over slow links longer
+    pub async fn remote_http_get(
+        &self,
+        url: &str,
+        wait: Duration,
+    ) -> Result<Vec<u8>, CrabTrapError> {
+        let url = shell_quote(url);
+        // the body goes through base64 on fd 4 while the client's own status comes back
+        // on fd 3, so it's what the framed exec reports rather than base64's. A subshell
+        // keeps g and s out of the operator's shell
+        let cmd = format!(
+            "(g=; if command -v curl >/dev/null 2>&1; then g='curl -skf'; \
+             elif command -v wget >/dev/null 2>&1; then g='wget -qO- --no-check-certificate'; fi; \
+             [ -n \"$g\" ] || {{ echo no_http_client; exit 127; }}; \
+             {{ s=$( {{ {{ $g {url} 2>/dev/null; echo $? >&3; }} | base64 >&4; }} 3>&1 ); }} 4>&1; exit $s)"
+        );
+        let result = match self
+            .exec_result(Priority::Interactive, ShellKind::Sh, &cmd, wait)
+            .await
+        {
+            Some(val) => val,
+            None => return Err(CrabTrapError::NoResponse),
+        };
+        if result.output.contains("no_http_client") {
+            return Err(CrabTrapError::RemoteCommandFailed {
+                reason: String::from("neither curl nor wget is installed"),
+            });
+        }
+        if let Some(status) = result.status.filter(|status| *status != 0) {
+            return Err(CrabTrapError::RemoteCommandFailed {
+                reason: format!("request to {url} failed, exit {status}"),
+            });
+        }
+        let body = decode_base64_output(&result.output)?;
+        self.record(
+            EventKind::Transfer,
+            &format!("fetched {url}, {} bytes", body.len()),
+        );
+        return Ok(body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::socket::exec::EXEC_TIMEOUT;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    #[test]
+    fn test_decode_base64_output() {
+        assert_eq!(decode_base64_output("aGVs\nbG8=\n").unwrap(), b"hello");
+        assert!(decode_base64_output("not base64!").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_remote_http_get() {
+        let server = TcpListener::bind("127.0.0.1:32436").await.unwrap();
+        tokio::spawn(async move {
+            let responses: [&[u8]; 2] = [
+                b"HTTP/1.0 200 OK\r\nContent-Length: 4\r\n\r\n\x00\x01\xfe\xff",
+                b"HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n",
+            ];
+            for response in responses {
+                let (mut soc, _) = server.accept().await.unwrap();
+                let mut buf = [0; 1024];
+                let _request = soc.read(&mut buf).await.unwrap();
+                soc.write_all(response).await.unwrap();
+            }
+        });
+        let handle = spawn_shell_session(32435).await;
+        let body = handle
+            .remote_http_get("http://127.0.0.1:32436/tool", EXEC_TIMEOUT)
+            .await;
+        assert_eq!(body, Ok(vec![0, 1, 0xfe, 0xff]));
+        // an empty body is still a body
+        let body = handle
+            .remote_http_get("http://127.0.0.1:32436/empty", EXEC_TIMEOUT)
+            .await;
+        assert_eq!(body, Ok(Vec::new()));
+        let body = handle
+            .remote_http_get("http://127.0.0.1:1/nothing", EXEC_TIMEOUT)
+            .await;
+        assert!(matches!(
+            body,
+            Err(CrabTrapError::RemoteCommandFailed { reason }) if reason.contains("exit")
+        ));
+    }
+}
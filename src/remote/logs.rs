@@ -0,0 +1,109 @@
+use regex::Regex;
+use tokio::sync::mpsc::{channel, Receiver};
+
+use crate::error::error::CrabTrapError;
+use crate::socket::connection::Handle;
+use crate::socket::exec::shell_quote;
+
+/// lines buffered for a watcher before the oldest reader backs up
+const LOG_CHANNEL_SIZE: usize = 1024;
+
+/// A `tail -F` running in the background on the remote
+pub struct LogWatcher {
+    handle: Handle,
+    job_id: u64,
+    pub lines: Receiver<String>,
+}
+
+impl LogWatcher {
+    /// Kills the remote tail, the channel closes once the last lines are read
+    pub async fn stop(&self) {
+        self.handle.stop_background(self.job_id).await;
+    }
+}
+
+impl Handle {
+    /// Follows a log file on the remote, sending each new line that matches the filter.
+    /// The regex is applied locally so it behaves the same whatever grep the remote has
+    pub async fn watch_logs(
+        &self,
+        log_path: &str,
+        filter: Option<&Regex>,
+    ) -> Result<LogWatcher, CrabTrapError> {
+        let cmd = format!("tail -n 0 -F {} 2>/dev/null", shell_quote(log_path));
+        let mut job = match self.spawn_background(&cmd).await {
+            Some(val) => val,
+            None => return Err(CrabTrapError::NoResponse),
+        };
+        let (tx, lines) = channel::<String>(LOG_CHANNEL_SIZE);
+        let filter = filter.cloned();
+        tokio::spawn(async move {
+            while let Some(line) = job.lines.recv().await {
+                if let Some(re) = &filter {
+                    if !re.is_match(&line) {
+                        continue;
+                    }
+                }
+                if tx.send(line).await.is_err() {
+                    return;
+                }
+            }
+        });
+        return Ok(LogWatcher {
+            handle: self.clone(),
+            job_id: job.id,
+            lines,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::exec::EXEC_TIMEOUT;
+    use crate::socket::mock_shell::spawn_shell_session;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn test_watch_logs() {
+        let handle = spawn_shell_session(32441).await;
+        let log = std::env::temp_dir().join("crab_trap_test_watch_logs.log");
+        std::fs::write(&log, "old line\n").unwrap();
+        let log_path = log.to_str().unwrap();
+
+        let errors = Regex::new("^ERROR").unwrap();
+        let mut all = handle.watch_logs(log_path, None).await.unwrap();
+        let mut filtered = handle.watch_logs(log_path, Some(&errors)).await.unwrap();
+        // give tail a moment to open the file
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        handle
+            .exec(
+                &format!("echo 'INFO up' >> {log_path}; echo 'ERROR down' >> {log_path}"),
+                EXEC_TIMEOUT,
+            )
+            .await
+            .unwrap();
+
+        let first = timeout(EXEC_TIMEOUT, all.lines.recv()).await.unwrap();
+        assert_eq!(first, Some(String::from("INFO up")));
+        let second = timeout(EXEC_TIMEOUT, all.lines.recv()).await.unwrap();
+        assert_eq!(second, Some(String::from("ERROR down")));
+        let only = timeout(EXEC_TIMEOUT, filtered.lines.recv()).await.unwrap();
+        assert_eq!(only, Some(String::from("ERROR down")));
+
+        all.stop().await;
+        filtered.stop().await;
+        handle
+            .exec(&format!("echo 'ERROR late' >> {log_path}"), EXEC_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(
+            timeout(Duration::from_millis(500), all.lines.recv())
+                .await
+                .unwrap(),
+            None
+        );
+        std::fs::remove_file(&log).unwrap_or_default();
+    }
+}
@@ -0,0 +1,10 @@
+pub mod batch;
+pub mod changes;
+pub mod copy;
+pub mod cwd;
+pub mod http;
+pub mod logs;
+pub mod portscan;
+pub mod strace;
+pub mod upload;
+pub mod watch;
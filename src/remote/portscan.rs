@@ -0,0 +1,254 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use tokio::select;
+use tokio::sync::mpsc::{channel, Receiver};
+
+use crate::error::error::CrabTrapError;
+use crate::socket::connection::Handle;
+use crate::socket::exec::{shell_quote, EXEC_TIMEOUT};
+
+/// checked when no ports are given
+pub const COMMON_PORTS: &[u16] = &[
+    21, 22, 23, 25, 53, 80, 88, 110, 135, 139, 143, 389, 443, 445, 1433, 3306, 3389, 5432, 5900,
+    5985, 6379, 8080, 8443, 9200,
+];
+
+/// a scan bigger than this is better done with a proper scanner through a pivot
+pub const MAX_PROBES: usize = 65536;
+
+/// how long each connect gets before the port counts as closed
+const CONNECT_TIMEOUT_SECS: u32 = 1;
+
+const SCAN_DONE: &str = "scan:done";
+
+const RESULT_CHANNEL_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenPort {
+    pub ip: IpAddr,
+    pub port: u16,
+}
+
+/// The hosts and ports a scan covers, hosts as an inclusive range of addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanTarget {
+    pub first: Ipv4Addr,
+    pub last: Ipv4Addr,
+    pub ports: Vec<u16>,
+}
+
+impl ScanTarget {
+    pub fn hosts(&self) -> usize {
+        return (u32::from(self.last) - u32::from(self.first)) as usize + 1;
+    }
+}
+
+fn invalid(target: &str, reason: &str) -> CrabTrapError {
+    return CrabTrapError::InvalidScanTarget {
+        target: String::from(target),
+        reason: String::from(reason),
+    };
+}
+
+/// `22,80,443` or `1-1024` or a mix of them
+fn parse_ports(arg: &str) -> Option<Vec<u16>> {
+    let mut ports = Vec::new();
+    for part in arg.split(',') {
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) => (start.parse::<u16>().ok()?, end.parse::<u16>().ok()?),
+            None => {
+                let port = part.parse::<u16>().ok()?;
+                (port, port)
+            }
+        };
+        if start == 0 || start > end {
+            return None;
+        }
+        ports.extend(start..=end);
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    return Some(ports);
+}
+
+/// `10.0.0.5`, `10.0.0.10-20` for a run of the last octet or `10.0.0.0/24`, with
+/// `:<ports>` after any of them. CIDR ranges skip their network and broadcast addresses
+pub fn parse_scan_target(arg: &str) -> Result<ScanTarget, CrabTrapError> {
+    let (range, ports) = match arg.split_once(':') {
+        Some((range, ports)) => (
+            range,
+            parse_ports(ports).ok_or_else(|| invalid(arg, "bad port list"))?,
+        ),
+        None => (arg, COMMON_PORTS.to_vec()),
+    };
+    let (first, last) = if let Some((base, bits)) = range.split_once('/') {
+        let base: Ipv4Addr = base.parse().map_err(|_| invalid(arg, "bad address"))?;
+        let bits: u32 = match bits.parse() {
+            Ok(val) if (16..=32).contains(&val) => val,
+            _ => return Err(invalid(arg, "prefix must be between /16 and /32")),
+        };
+        let mask = u32::MAX.checked_shl(32 - bits).unwrap_or(0);
+        let network = u32::from(base) & mask;
+        let broadcast = network | !mask;
+        match bits {
+            31 | 32 => (network, broadcast),
+            _ => (network + 1, broadcast - 1),
+        }
+    } else if let Some((start, end)) = range.split_once('-') {
+        let start: Ipv4Addr = start.parse().map_err(|_| invalid(arg, "bad address"))?;
+        let end: u8 = end.parse().map_err(|_| invalid(arg, "bad end of range"))?;
+        let octets = start.octets();
+        if end < octets[3] {
+            return Err(invalid(arg, "range ends before it starts"));
+        }
+        let last = Ipv4Addr::new(octets[0], octets[1], octets[2], end);
+        (u32::from(start), u32::from(last))
+    } else {
+        let addr: Ipv4Addr = range.parse().map_err(|_| invalid(arg, "bad address"))?;
+        (u32::from(addr), u32::from(addr))
+    };
+    let target = ScanTarget {
+        first: Ipv4Addr::from(first),
+        last: Ipv4Addr::from(last),
+        ports,
+    };
+    if target.hosts() * target.ports.len() > MAX_PROBES {
+        return Err(invalid(arg, "too many hosts and ports for one scan"));
+    }
+    return Ok(target);
+}
+
+/// bash's /dev/tcp does the connecting, a host's ports are tried at once and the
+/// hosts one after another so the remote isn't flooded
+pub fn scan_script(target: &ScanTarget) -> String {
+    let ports: Vec<String> = target.ports.iter().map(|port| port.to_string()).collect();
+    let script = format!(
+        "for ((i={first}; i<={last}; i++)); do \
+         ip=$((i>>24&255)).$((i>>16&255)).$((i>>8&255)).$((i&255)); \
+         for p in {ports}; do \
+         (timeout {CONNECT_TIMEOUT_SECS} bash -c \"echo >/dev/tcp/$ip/$p\" 2>/dev/null && echo \"open:$ip:$p\") & \
+         done; wait; done; echo {SCAN_DONE}",
+        first = u32::from(target.first),
+        last = u32::from(target.last),
+        ports = ports.join(" "),
+    );
+    return format!("bash -c {}", shell_quote(&script));
+}
+
+pub fn parse_scan_line(line: &str) -> Option<OpenPort> {
+    let (ip, port) = line.trim().strip_prefix("open:")?.rsplit_once(':')?;
+    return Some(OpenPort {
+        ip: ip.parse().ok()?,
+        port: port.parse().ok()?,
+    });
+}
+
+impl Handle {
+    /// Scans hosts the remote can reach, see `parse_scan_target` for `target_range`.
+    /// Open ports come through as they're found and the receiver closes once the scan
+    /// is done. Dropping it stops the scan
+    pub async fn reverse_port_scan(
+        &self,
+        target_range: &str,
+    ) -> Result<Receiver<OpenPort>, CrabTrapError> {
+        let target = parse_scan_target(target_range)?;
+        let has_bash = self
+            .exec("command -v bash >/dev/null && echo yes", EXEC_TIMEOUT)
+            .await
+            .ok_or(CrabTrapError::NoResponse)?;
+        if has_bash.trim() != "yes" {
+            return Err(CrabTrapError::RemoteCommandFailed {
+                reason: String::from("the scan needs bash on the remote"),
+            });
+        }
+        let mut job = self
+            .spawn_background(&scan_script(&target))
+            .await
+            .ok_or(CrabTrapError::NoResponse)?;
+        let (tx, results) = channel::<OpenPort>(RESULT_CHANNEL_SIZE);
+        let handle = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let line = select! {
+                    line = job.lines.recv() => line,
+                    _ = tx.closed() => break,
+                };
+                let line = match line {
+                    Some(val) if val != SCAN_DONE => val,
+                    _ => break,
+                };
+                if let Some(open) = parse_scan_line(&line) {
+                    if tx.send(open).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            handle.stop_background(job.id).await;
+        });
+        return Ok(results);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+
+    #[test]
+    fn test_parse_scan_target() {
+        let target = parse_scan_target("10.0.0.0/24").unwrap();
+        assert_eq!(target.first, Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(target.last, Ipv4Addr::new(10, 0, 0, 254));
+        assert_eq!(target.ports, COMMON_PORTS);
+        let target = parse_scan_target("192.168.1.10-20:22,80-82").unwrap();
+        assert_eq!(target.hosts(), 11);
+        assert_eq!(target.ports, vec![22, 80, 81, 82]);
+        assert_eq!(parse_scan_target("10.0.0.7:443").unwrap().hosts(), 1);
+        assert!(parse_scan_target("10.0.0.0/8").is_err());
+        assert!(parse_scan_target("10.0.0.20-10").is_err());
+        assert!(parse_scan_target("10.0.0.1:0").is_err());
+        assert!(parse_scan_target("10.0.0.0/16:1-1024").is_err());
+        assert!(parse_scan_target("intranet").is_err());
+    }
+
+    #[test]
+    fn test_parse_scan_line() {
+        assert_eq!(
+            parse_scan_line("open:10.0.0.5:445"),
+            Some(OpenPort {
+                ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5)),
+                port: 445,
+            })
+        );
+        assert_eq!(parse_scan_line("open:10.0.0.5:http"), None);
+        assert_eq!(parse_scan_line("scan:done"), None);
+    }
+
+    #[tokio::test]
+    async fn test_reverse_port_scan() {
+        let handle = spawn_shell_session(32468).await;
+        let _open = TcpListener::bind("127.0.0.1:32469").await.unwrap();
+        let mut results = handle
+            .reverse_port_scan("127.0.0.1:32469-32470")
+            .await
+            .unwrap();
+        let found = timeout(Duration::from_secs(10), results.recv())
+            .await
+            .unwrap();
+        assert_eq!(
+            found,
+            Some(OpenPort {
+                ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                port: 32469,
+            })
+        );
+        // the closed port isn't reported and the scan finishes
+        let rest = timeout(Duration::from_secs(10), results.recv())
+            .await
+            .unwrap();
+        assert_eq!(rest, None);
+    }
+}
@@ -0,0 +1,204 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use regex::Regex;
+
+use crate::error::error::CrabTrapError;
+use crate::remote::http::decode_base64_output;
+use crate::socket::connection::Handle;
+use crate::socket::exec::{shell_quote, EXEC_TIMEOUT};
+
+/// how long the traced command gets to finish
+const STRACE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// printed when the remote has no strace to run
+const NO_STRACE: &str = "no_strace";
+
+/// printed before the trace file path so it can be told apart from the command output
+const TRACE_PREFIX: &str = "strace_file:";
+
+static SYSCALL_LINE: OnceLock<Regex> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Syscall {
+    pub name: String,
+    pub args: Vec<String>,
+    pub ret: String,
+    /// errno name like `ENOENT` when the call failed
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StraceOutput {
+    /// what the traced command printed
+    pub output: String,
+    pub syscalls: Vec<Syscall>,
+}
+
+fn syscall_line() -> &'static Regex {
+    return SYSCALL_LINE.get_or_init(|| {
+        Regex::new(r"^(?:\[pid\s+)?\d*\]?\s*(\w+)\((.*)\)\s+=\s+(\S+)(?:\s+([A-Z][A-Z0-9_]+)\b)?")
+            .unwrap()
+    });
+}
+
+/// Splits a syscall argument list on the commas that aren't inside quotes or brackets
+pub fn split_args(args: &str) -> Vec<String> {
+    let mut split = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for c in args.chars() {
+        if in_quotes {
+            current.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_quotes = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_quotes = true,
+            '[' | '{' | '(' => depth += 1,
+            ']' | '}' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                split.push(String::from(current.trim()));
+                current = String::new();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        split.push(String::from(current.trim()));
+    }
+    return split;
+}
+
+/// Parses strace's default output, signals, exits and unfinished calls are skipped
+pub fn parse_strace(trace: &str) -> Vec<Syscall> {
+    let mut syscalls = Vec::new();
+    for line in trace.lines() {
+        let caps = match syscall_line().captures(line.trim()) {
+            Some(val) => val,
+            None => continue,
+        };
+        syscalls.push(Syscall {
+            name: String::from(&caps[1]),
+            args: split_args(&caps[2]),
+            ret: String::from(&caps[3]),
+            error: caps.get(4).map(|error| String::from(error.as_str())),
+        });
+    }
+    return syscalls;
+}
+
+impl Handle {
+    /// Runs a command under strace on the remote, pulls the trace back and removes it
+    pub async fn run_with_strace(&self, cmd: &str) -> Result<StraceOutput, CrabTrapError> {
+        let run = format!(
+            "if command -v strace >/dev/null 2>&1; then f=/tmp/.strace.$$; \
+             strace -f -o \"$f\" sh -c {cmd} 2>&1; echo {TRACE_PREFIX}\"$f\"; \
+             else echo {NO_STRACE}; fi",
+            cmd = shell_quote(cmd)
+        );
+        let output = match self.exec(&run, STRACE_TIMEOUT).await {
+            Some(val) => val,
+            None => return Err(CrabTrapError::NoResponse),
+        };
+        let (output, path) = match output.rsplit_once(TRACE_PREFIX) {
+            Some((output, path)) => (output, path.trim()),
+            None if output.contains(NO_STRACE) => {
+                return Err(CrabTrapError::RemoteCommandFailed {
+                    reason: String::from("strace is not installed"),
+                });
+            }
+            None => {
+                return Err(CrabTrapError::RemoteCommandFailed {
+                    reason: String::from("strace didn't finish"),
+                });
+            }
+        };
+        let path = shell_quote(path);
+        let trace = self
+            .exec(&format!("base64 {path}"), EXEC_TIMEOUT)
+            .await
+            .map(|encoded| decode_base64_output(&encoded));
+        self.exec(&format!("rm -f {path}"), EXEC_TIMEOUT).await;
+        let trace = match trace {
+            Some(val) => val?,
+            None => return Err(CrabTrapError::NoResponse),
+        };
+        return Ok(StraceOutput {
+            output: String::from(output),
+            syscalls: parse_strace(&String::from_utf8_lossy(&trace)),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    #[test]
+    fn test_parse_strace() {
+        let trace = "\
+execve(\"/bin/cat\", [\"cat\", \"/nope\"], 0x7ffd /* 20 vars */) = 0
+openat(AT_FDCWD, \"/nope\", O_RDONLY) = -1 ENOENT (No such file or directory)
+1234  write(2, \"cat: /nope, gone\\n\", 17) = 17
+[pid  1235] read(3,  <unfinished ...>
+--- SIGCHLD {si_signo=SIGCHLD} ---
+exit_group(1)                           = ?
++++ exited with 1 +++
+";
+        let syscalls = parse_strace(trace);
+        assert_eq!(syscalls.len(), 4);
+        assert_eq!(syscalls[0].name, "execve");
+        assert_eq!(
+            syscalls[0].args,
+            vec![
+                "\"/bin/cat\"",
+                "[\"cat\", \"/nope\"]",
+                "0x7ffd /* 20 vars */"
+            ]
+        );
+        assert_eq!(syscalls[1].ret, "-1");
+        assert_eq!(syscalls[1].error.as_deref(), Some("ENOENT"));
+        assert_eq!(syscalls[2].name, "write");
+        assert_eq!(syscalls[2].args[1], "\"cat: /nope, gone\\n\"");
+        assert_eq!(syscalls[2].error, None);
+        assert_eq!(syscalls[3].ret, "?");
+    }
+
+    #[tokio::test]
+    async fn test_run_with_strace() {
+        let handle = spawn_shell_session(32444).await;
+        let has_strace = std::process::Command::new("sh")
+            .args(["-c", "command -v strace"])
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false);
+        let res = handle.run_with_strace("echo traced").await;
+        if !has_strace {
+            assert_eq!(
+                res,
+                Err(CrabTrapError::RemoteCommandFailed {
+                    reason: String::from("strace is not installed")
+                })
+            );
+            return;
+        }
+        let traced = res.unwrap();
+        assert_eq!(traced.output, "traced\n");
+        assert!(traced.syscalls.iter().any(|call| call.name == "execve"));
+        let leftover = handle
+            .exec("ls /tmp/.strace.* 2>/dev/null", EXEC_TIMEOUT)
+            .await;
+        assert_eq!(leftover, Some(String::new()));
+    }
+}
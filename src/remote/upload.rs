@@ -0,0 +1,403 @@
+use std::fs;
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha256::digest;
+
+use crate::error::error::CrabTrapError;
+use crate::socket::connection::Handle;
+use crate::socket::exec::{shell_quote, ShellKind, EXEC_TIMEOUT};
+use crate::socket::history::EventKind;
+use crate::socket::ops::Priority;
+
+pub const UPLOAD_USAGE: &str = "Usage: upload <name> <local path> <remote path>";
+
+/// a tty hands the shell at most 4096 bytes of a line, this leaves room for the framing
+pub const DEFAULT_MAX_CHUNK: usize = 3072;
+
+pub const DEFAULT_MIN_CHUNK: usize = 64;
+
+/// where a transfer starts before it knows what the link takes
+const START_CHUNK: usize = 1024;
+
+/// verified chunks in a row before the chunk size grows
+const GROW_AFTER: u32 = 3;
+
+/// failed verifications in a row before the upload gives up
+const MAX_FAILURES: u32 = 8;
+
+const CHUNK_OK: &str = "chunk:ok";
+
+/// Sizes are in base64 characters, which is what ends up on the line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkOptions {
+    pub min: usize,
+    pub max: usize,
+    /// chunks sent between each check the remote got them intact
+    pub verify_every: usize,
+}
+
+impl Default for ChunkOptions {
+    fn default() -> ChunkOptions {
+        return ChunkOptions {
+            min: DEFAULT_MIN_CHUNK,
+            max: DEFAULT_MAX_CHUNK,
+            verify_every: 1,
+        };
+    }
+}
+
+/// Picks the size of the next chunk from how the last ones went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkSizer {
+    size: usize,
+    min: usize,
+    max: usize,
+    streak: u32,
+    /// the smallest size that's been mangled, growing stays under it
+    failed_at: Option<usize>,
+}
+
+impl ChunkSizer {
+    pub fn new(options: &ChunkOptions) -> ChunkSizer {
+        let max = options.max.max(options.min);
+        return ChunkSizer {
+            size: START_CHUNK.clamp(options.min, max),
+            min: options.min,
+            max,
+            streak: 0,
+            failed_at: None,
+        };
+    }
+
+    pub fn size(&self) -> usize {
+        return self.size;
+    }
+
+    /// Doubles the size after a run of good chunks, or goes halfway to the size that
+    /// last failed so it settles just under what the shell can take
+    pub fn success(&mut self) {
+        self.streak += 1;
+        if self.streak < GROW_AFTER {
+            return;
+        }
+        self.streak = 0;
+        let mut next = (self.size * 2).min(self.max);
+        if let Some(failed) = self.failed_at {
+            next = next.min((self.size + failed) / 2);
+        }
+        self.size = next.max(self.size);
+    }
+
+    pub fn failure(&mut self) {
+        self.streak = 0;
+        self.failed_at = Some(self.failed_at.map_or(self.size, |f| f.min(self.size)));
+        self.size = (self.size / 4).max(self.min);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadProgress {
+    /// base64 characters the remote has verified
+    pub sent: usize,
+    pub total: usize,
+    pub chunk: usize,
+    /// chunks that had to be sent again
+    pub retries: usize,
+}
+
+impl UploadProgress {
+    pub fn line(&self) -> String {
+        let percent = match self.total {
+            0 => 100,
+            total => self.sent * 100 / total,
+        };
+        return format!(
+            "{percent:>3}% {}/{} chunk {} retries {}",
+            self.sent, self.total, self.chunk, self.retries
+        );
+    }
+}
+
+/// `upload <name> <local path> <remote path>`, paths can't have spaces
+pub fn parse_upload_args(args: &str) -> Option<(String, String, String)> {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    return match words.as_slice() {
+        [name, local, remote] => Some((
+            String::from(*name),
+            String::from(*local),
+            String::from(*remote),
+        )),
+        _ => None,
+    };
+}
+
+fn failed(reason: String) -> CrabTrapError {
+    return CrabTrapError::RemoteCopyFailed { reason };
+}
+
+impl Handle {
+    /// Sends a local file to the remote as base64 through the shell, for hosts with
+    /// nothing better. Chunks are staged next to `remote` and checked against their
+    /// sha256 every `verify_every` chunks, a window that doesn't match is sent again
+    /// in smaller chunks. `progress` is told after each verified window
+    pub async fn upload_file(
+        &self,
+        local: &Path,
+        remote: &str,
+        options: ChunkOptions,
+        mut progress: impl FnMut(&UploadProgress),
+    ) -> Result<UploadProgress, CrabTrapError> {
+        let content = fs::read(local)
+            .map_err(|err| failed(format!("couldn't read {}: {err}", local.display())))?;
+        let encoded = STANDARD.encode(&content);
+        let staged = shell_quote(&format!("{remote}.b64"));
+        let window = shell_quote(&format!("{remote}.part"));
+
+        let ready = self
+            .exec_quietly(
+                Priority::Interactive,
+                ShellKind::Sh,
+                &format!(
+                    "command -v base64 >/dev/null && command -v sha256sum >/dev/null && : > {staged} && rm -f {window} && echo ready"
+                ),
+                EXEC_TIMEOUT,
+            )
+            .await
+            .ok_or(CrabTrapError::NoResponse)?;
+        if ready.trim() != "ready" {
+            return Err(failed(String::from(
+                "the remote needs base64 and sha256sum and somewhere writable",
+            )));
+        }
+
+        let verify_every = options.verify_every.max(1);
+        let mut sizer = ChunkSizer::new(&options);
+        let mut state = UploadProgress {
+            sent: 0,
+            total: encoded.len(),
+            chunk: sizer.size(),
+            retries: 0,
+        };
+        let mut pos = 0;
+        let mut in_window = 0;
+        let mut failures = 0;
+        while state.sent < encoded.len() {
+            let end = (pos + sizer.size()).min(encoded.len());
+            in_window += 1;
+            let mut cmd = format!("printf '%s' '{}' >> {window}", &encoded[pos..end]);
+            let check = in_window == verify_every || end == encoded.len();
+            if check {
+                let hash = digest(&encoded[state.sent..end]);
+                cmd += &format!(
+                    "; [ \"$(sha256sum < {window} | cut -c1-64)\" = \"{hash}\" ] && cat {window} >> {staged} && echo {CHUNK_OK}; rm -f {window}"
+                );
+            }
+            let output = self
+                .exec_quietly(Priority::Interactive, ShellKind::Sh, &cmd, EXEC_TIMEOUT)
+                .await
+                .ok_or(CrabTrapError::NoResponse)?;
+            if !check {
+                pos = end;
+                continue;
+            }
+            in_window = 0;
+            if output.trim() == CHUNK_OK {
+                failures = 0;
+                state.sent = end;
+                sizer.success();
+            } else {
+                failures += 1;
+                if failures >= MAX_FAILURES {
+                    return Err(failed(format!(
+                        "chunks of {} still don't arrive intact",
+                        sizer.size()
+                    )));
+                }
+                state.retries += 1;
+                sizer.failure();
+            }
+            pos = state.sent;
+            state.chunk = sizer.size();
+            progress(&state);
+        }
+
+        let output = self
+            .exec_quietly(
+                Priority::Interactive,
+                ShellKind::Sh,
+                &format!(
+                    "base64 -d < {staged} > {dst} && rm -f {staged} && sha256sum < {dst}",
+                    dst = shell_quote(remote)
+                ),
+                EXEC_TIMEOUT,
+            )
+            .await
+            .ok_or(CrabTrapError::NoResponse)?;
+        if output.split_whitespace().next() != Some(digest(&content[..]).as_str()) {
+            return Err(failed(String::from(
+                "the upload doesn't match the local file",
+            )));
+        }
+        self.record(
+            EventKind::Transfer,
+            &format!(
+                "uploaded {} to {remote}, {} bytes",
+                local.display(),
+                content.len()
+            ),
+        );
+        return Ok(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Stdio;
+    use tokio::io::{copy, AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::process::Command;
+
+    /// A shell behind a line that lowercases letters past `threshold` columns, which
+    /// only ever lands in the base64 since everything else that's sent is lowercase
+    async fn mangling_shell_session(port: u16, threshold: usize) -> Handle {
+        let listener = TcpListener::bind(format!("127.0.0.1:{port}"))
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            let soc = TcpStream::connect(format!("127.0.0.1:{port}"))
+                .await
+                .unwrap();
+            let (soc_read, mut soc_write) = soc.into_split();
+            let mut child = Command::new("sh")
+                .arg("-c")
+                .arg("exec sh 2>&1")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .unwrap();
+            let mut stdin = child.stdin.take().unwrap();
+            let mut stdout = child.stdout.take().unwrap();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(soc_read).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let mangled: String = line
+                        .chars()
+                        .enumerate()
+                        .map(|(i, c)| match i < threshold {
+                            true => c,
+                            false => c.to_ascii_lowercase(),
+                        })
+                        .collect();
+                    stdin.write_all(format!("{mangled}\n").as_bytes()).await?;
+                }
+                return Ok::<(), std::io::Error>(());
+            });
+            copy(&mut stdout, &mut soc_write).await.unwrap_or_default();
+            child.wait().await.unwrap();
+        });
+        let (soc, _) = listener.accept().await.unwrap();
+        let (read, write) = soc.into_split();
+        return Handle::new_headless(read, write);
+    }
+
+    /// bytes that don't repeat and base64 to plenty of capitals
+    fn test_content(len: usize) -> Vec<u8> {
+        let mut state: u32 = 12345;
+        return (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+    }
+
+    #[test]
+    fn test_chunk_sizer() {
+        let mut sizer = ChunkSizer::new(&ChunkOptions::default());
+        assert_eq!(sizer.size(), START_CHUNK);
+        for _ in 0..GROW_AFTER {
+            sizer.success();
+        }
+        assert_eq!(sizer.size(), 2048);
+        sizer.failure();
+        assert_eq!(sizer.size(), 512);
+        for _ in 0..GROW_AFTER * 3 {
+            sizer.success();
+        }
+        // 1024, then halfway to the 2048 that failed each time
+        assert_eq!(sizer.size(), 1792);
+        for _ in 0..4 {
+            sizer.failure();
+        }
+        assert_eq!(sizer.size(), DEFAULT_MIN_CHUNK);
+        let options = ChunkOptions {
+            min: 16,
+            max: 100,
+            verify_every: 1,
+        };
+        assert_eq!(ChunkSizer::new(&options).size(), 100);
+    }
+
+    #[test]
+    fn test_progress_line() {
+        let progress = UploadProgress {
+            sent: 250,
+            total: 1000,
+            chunk: 512,
+            retries: 2,
+        };
+        assert_eq!(progress.line(), " 25% 250/1000 chunk 512 retries 2");
+        assert_eq!(
+            parse_upload_args("web ./linpeas.sh /tmp/l.sh"),
+            Some((
+                String::from("web"),
+                String::from("./linpeas.sh"),
+                String::from("/tmp/l.sh")
+            ))
+        );
+        assert_eq!(parse_upload_args("web ./linpeas.sh"), None);
+    }
+
+    #[tokio::test]
+    async fn test_upload_settles_under_mangling() {
+        let threshold = 600;
+        let handle = mangling_shell_session(32471, threshold).await;
+        let dir = std::env::temp_dir().join("crab_trap_test_upload");
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("src.bin");
+        let dst = dir.join("dst.bin");
+        let content = test_content(24 * 1024);
+        fs::write(&src, &content).unwrap();
+
+        let mut sizes = Vec::new();
+        let done = handle
+            .upload_file(&src, dst.to_str().unwrap(), ChunkOptions::default(), |p| {
+                sizes.push(p.chunk)
+            })
+            .await
+            .unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), content);
+        assert!(done.retries > 0);
+        // once it's found the limit it stays under it
+        assert!(done.retries < 10);
+        assert!(sizes[sizes.len() / 2..]
+            .iter()
+            .all(|size| *size < threshold));
+
+        let verified_less = ChunkOptions {
+            verify_every: 4,
+            ..ChunkOptions::default()
+        };
+        fs::remove_file(&dst).unwrap();
+        handle
+            .upload_file(&src, dst.to_str().unwrap(), verified_less, |_| {})
+            .await
+            .unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), content);
+        fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+}
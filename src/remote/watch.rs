@@ -0,0 +1,348 @@
+use std::time::Duration;
+
+use tokio::select;
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::socket::connection::Handle;
+use crate::socket::exec::{ShellKind, EXEC_TIMEOUT};
+use crate::socket::ops::Priority;
+
+/// a watch gives up after this many runs in a row get no answer
+pub const MAX_WATCH_FAILURES: u32 = 3;
+
+/// how soon a run put off because the session was busy is tried again
+const DEFER_RETRY: Duration = Duration::from_millis(250);
+
+/// reports buffered for a watch before it waits on the reader
+const REPORT_CHANNEL_SIZE: usize = 64;
+
+/// outputs longer than this many lines are shown whole instead of diffed
+pub const MAX_DIFF_LINES: usize = 2000;
+
+pub const WATCH_USAGE: &str =
+    "usage: watch-remote [--full] <name> <interval> <command> | watch-remote stop <id> | watch-remote";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Added(String),
+    Removed(String),
+}
+
+/// Lines added and removed going from `old` to `new`, in the order they appear
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // longest common subsequence, counted from the end
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = match old[i] == new[j] {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
+        }
+    }
+    let mut diff = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            diff.push(DiffLine::Added(String::from(new[j])));
+            j += 1;
+        } else {
+            diff.push(DiffLine::Removed(String::from(old[i])));
+            i += 1;
+        }
+    }
+    return diff;
+}
+
+/// Parses an interval like `30`, `30s`, `5m` or `500ms`
+pub fn parse_interval(text: &str) -> Option<Duration> {
+    let (digits, millis_per) = match text {
+        t if t.ends_with("ms") => (&t[..t.len() - 2], 1),
+        t if t.ends_with('s') => (&t[..t.len() - 1], 1000),
+        t if t.ends_with('m') => (&t[..t.len() - 1], 60_000),
+        t => (t, 1000),
+    };
+    let interval = Duration::from_millis(digits.parse::<u64>().ok()? * millis_per);
+    if interval.is_zero() {
+        return None;
+    }
+    return Some(interval);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchCommand {
+    List,
+    Stop(u64),
+    Start {
+        session: String,
+        interval: Duration,
+        command: String,
+        full: bool,
+    },
+}
+
+pub fn parse_watch_args(args: &str) -> Option<WatchCommand> {
+    let args = args.trim();
+    if args.is_empty() {
+        return Some(WatchCommand::List);
+    }
+    if let Some(id) = args.strip_prefix("stop ") {
+        return Some(WatchCommand::Stop(id.trim().parse::<u64>().ok()?));
+    }
+    let (full, args) = match args.strip_prefix("--full ") {
+        Some(rest) => (true, rest.trim_start()),
+        None => (false, args),
+    };
+    let mut parts = args.splitn(3, ' ');
+    let session = parts.next()?;
+    let interval = parse_interval(parts.next()?)?;
+    let command = parts.next()?.trim();
+    if command.is_empty() {
+        return None;
+    }
+    return Some(WatchCommand::Start {
+        session: String::from(session),
+        interval,
+        command: String::from(command),
+        full,
+    });
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchReport {
+    /// the whole output, for the first run, with `--full` or when it's too long to diff
+    Output(String),
+    Changed(Vec<DiffLine>),
+    /// the run got no answer, this many in a row now
+    Failed(u32),
+    Stopped(String),
+}
+
+/// What one run of a watch should report
+#[derive(Default)]
+pub struct WatchState {
+    previous: Option<String>,
+    failures: u32,
+    full: bool,
+}
+
+impl WatchState {
+    pub fn new(full: bool) -> WatchState {
+        return WatchState {
+            full,
+            ..WatchState::default()
+        };
+    }
+
+    /// Takes one run's output, or None if it didn't answer. Nothing to report when
+    /// the output is the same as last time
+    pub fn next(&mut self, output: Option<String>) -> Option<WatchReport> {
+        let output = match output {
+            Some(val) => val,
+            None => {
+                self.failures += 1;
+                if self.failures >= MAX_WATCH_FAILURES {
+                    return Some(WatchReport::Stopped(format!(
+                        "{} runs in a row failed",
+                        self.failures
+                    )));
+                }
+                return Some(WatchReport::Failed(self.failures));
+            }
+        };
+        self.failures = 0;
+        let previous = match self.previous.replace(output.clone()) {
+            Some(val) if val == output => return None,
+            Some(val) => val,
+            None => return Some(WatchReport::Output(output)),
+        };
+        let too_long = output.lines().count().max(previous.lines().count()) > MAX_DIFF_LINES;
+        if self.full || too_long {
+            return Some(WatchReport::Output(output));
+        }
+        return Some(WatchReport::Changed(diff_lines(&previous, &output)));
+    }
+}
+
+/// A command re-run on the remote every interval
+pub struct RemoteWatch {
+    cancel: CancellationToken,
+    pub reports: Receiver<WatchReport>,
+}
+
+impl RemoteWatch {
+    pub fn stop(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Stops the watch from somewhere that doesn't own it
+    pub fn stop_token(&self) -> CancellationToken {
+        return self.cancel.clone();
+    }
+}
+
+impl Handle {
+    /// Anything else holding the session, an attached terminal or another command
+    pub(crate) fn session_busy(&self) -> bool {
+        let running = self.operations.lock().is_ok_and(|queue| queue.is_busy());
+        return running || self.read_stream.try_lock().is_err();
+    }
+
+    /// Runs `cmd` every `interval` and reports how its output changed. A run is put
+    /// off while the session is attached or busy, so it never lands in the middle of
+    /// what the operator is typing
+    pub fn watch_remote(&self, cmd: &str, interval: Duration, full: bool) -> RemoteWatch {
+        let (tx, reports) = channel::<WatchReport>(REPORT_CHANNEL_SIZE);
+        let cancel = CancellationToken::new();
+        let handle = self.clone();
+        let cmd = String::from(cmd);
+        let token = cancel.clone();
+        tokio::spawn(async move {
+            let mut state = WatchState::new(full);
+            loop {
+                if handle.is_closed() {
+                    let report = WatchReport::Stopped(String::from("the session closed"));
+                    tx.send(report).await.unwrap_or_default();
+                    return;
+                }
+                if handle.session_busy() {
+                    select! {
+                        _ = sleep(DEFER_RETRY) => continue,
+                        _ = token.cancelled() => return,
+                    }
+                }
+                // a run is never cut off, its output would end up in the session
+                let output = handle
+                    .exec_at(Priority::Background, ShellKind::Sh, &cmd, EXEC_TIMEOUT)
+                    .await;
+                if token.is_cancelled() {
+                    return;
+                }
+                if let Some(report) = state.next(output) {
+                    let stopped = matches!(report, WatchReport::Stopped(_));
+                    if tx.send(report).await.is_err() || stopped {
+                        return;
+                    }
+                }
+                select! {
+                    _ = sleep(interval) => {}
+                    _ = token.cancelled() => return,
+                }
+            }
+        });
+        return RemoteWatch { cancel, reports };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+    use tokio::time::timeout;
+
+    fn added(line: &str) -> DiffLine {
+        return DiffLine::Added(String::from(line));
+    }
+
+    fn removed(line: &str) -> DiffLine {
+        return DiffLine::Removed(String::from(line));
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        assert_eq!(
+            diff_lines("a\nb\nc\n", "a\nc\nd\n"),
+            vec![removed("b"), added("d")]
+        );
+        assert_eq!(diff_lines("a\n", "a\n"), vec![]);
+        assert_eq!(diff_lines("", "x\n"), vec![added("x")]);
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_interval("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_interval("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_interval("0"), None);
+        assert_eq!(parse_interval("soon"), None);
+    }
+
+    #[test]
+    fn test_parse_watch_args() {
+        assert_eq!(parse_watch_args(""), Some(WatchCommand::List));
+        assert_eq!(parse_watch_args("stop 2"), Some(WatchCommand::Stop(2)));
+        assert_eq!(
+            parse_watch_args("--full web 10s ps aux | grep cron"),
+            Some(WatchCommand::Start {
+                session: String::from("web"),
+                interval: Duration::from_secs(10),
+                command: String::from("ps aux | grep cron"),
+                full: true,
+            })
+        );
+        assert_eq!(parse_watch_args("web 10s"), None);
+        assert_eq!(parse_watch_args("web often ls"), None);
+    }
+
+    #[test]
+    fn test_watch_state() {
+        let mut state = WatchState::new(false);
+        assert_eq!(
+            state.next(Some(String::from("a\n"))),
+            Some(WatchReport::Output(String::from("a\n")))
+        );
+        assert_eq!(state.next(Some(String::from("a\n"))), None);
+        assert_eq!(
+            state.next(Some(String::from("a\nb\n"))),
+            Some(WatchReport::Changed(vec![added("b")]))
+        );
+        assert_eq!(state.next(None), Some(WatchReport::Failed(1)));
+        // a good run resets the count
+        state.next(Some(String::from("a\nb\n")));
+        assert_eq!(state.next(None), Some(WatchReport::Failed(1)));
+        assert_eq!(state.next(None), Some(WatchReport::Failed(2)));
+        assert!(matches!(state.next(None), Some(WatchReport::Stopped(_))));
+
+        let mut state = WatchState::new(true);
+        state.next(Some(String::from("a\n")));
+        assert_eq!(
+            state.next(Some(String::from("b\n"))),
+            Some(WatchReport::Output(String::from("b\n")))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_remote() {
+        let handle = spawn_shell_session(32451).await;
+        let file = std::env::temp_dir().join("crab_trap_test_watch_remote");
+        std::fs::write(&file, "one\n").unwrap();
+        let cmd = format!("cat {}", file.display());
+        let mut watch = handle.watch_remote(&cmd, Duration::from_millis(50), false);
+        let first = timeout(EXEC_TIMEOUT, watch.reports.recv()).await.unwrap();
+        assert_eq!(first, Some(WatchReport::Output(String::from("one\n"))));
+
+        // runs wait while something else has the session
+        let busy = handle.read_stream.lock().await;
+        std::fs::write(&file, "one\ntwo\n").unwrap();
+        assert!(timeout(Duration::from_millis(300), watch.reports.recv())
+            .await
+            .is_err());
+        drop(busy);
+        let changed = timeout(EXEC_TIMEOUT, watch.reports.recv()).await.unwrap();
+        assert_eq!(changed, Some(WatchReport::Changed(vec![added("two")])));
+
+        watch.stop();
+        assert_eq!(
+            timeout(EXEC_TIMEOUT, watch.reports.recv()).await.unwrap(),
+            None
+        );
+        std::fs::remove_file(&file).unwrap_or_default();
+    }
+}
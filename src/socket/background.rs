@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::{sleep, timeout};
+
+use crate::socket::close::CloseReason;
+use crate::socket::connection::Handle;
+use crate::socket::exec::{shell_quote, EXEC_TIMEOUT};
+use crate::socket::spill::{take_overflow, PENDING_MEMORY_CAP};
+
+/// prefix put in front of every line a background job prints
+pub const JOB_TAG: &str = "CTBG";
+
+/// most of an unfinished tagged line held back waiting for its newline, past it
+/// the line is shown as ordinary output
+const MAX_PARTIAL: usize = 64 * 1024;
+
+/// how long the pump holds the session before letting a reader in
+const PUMP_SLICE: Duration = Duration::from_millis(100);
+
+static JOB_COUNT: AtomicU64 = AtomicU64::new(0);
+
+struct Job {
+    tx: UnboundedSender<String>,
+    pid: Option<u32>,
+}
+
+/// Splits tagged background job lines out of the shell output
+#[derive(Default)]
+pub struct OutputRouter {
+    jobs: HashMap<u64, Job>,
+    /// an unfinished line that may turn out to be tagged
+    partial: String,
+    pump_running: bool,
+}
+
+impl OutputRouter {
+    pub fn register(&mut self, id: u64, tx: UnboundedSender<String>) {
+        self.jobs.insert(id, Job { tx, pid: None });
+    }
+
+    pub fn unregister(&mut self, id: u64) {
+        self.jobs.remove(&id);
+    }
+
+    pub fn has_jobs(&self) -> bool {
+        return !self.jobs.is_empty();
+    }
+
+    pub fn pid(&self, id: u64) -> Option<u32> {
+        return self.jobs.get(&id).and_then(|job| job.pid);
+    }
+
+    /// Handles a `<id>:<line>` or `<id>pid:<pid>` line, false if it wasn't one of ours
+    fn deliver(&mut self, tagged: &str) -> bool {
+        let digits = tagged.chars().take_while(|c| c.is_ascii_digit()).count();
+        let id = match tagged[..digits].parse::<u64>() {
+            Ok(val) => val,
+            Err(_) => return false,
+        };
+        let rest = &tagged[digits..];
+        if let Some(line) = rest.strip_prefix(':') {
+            if let Some(job) = self.jobs.get(&id) {
+                if job.tx.send(String::from(line)).is_err() {
+                    // nobody is listening anymore
+                    self.jobs.remove(&id);
+                }
+            }
+            return true;
+        }
+        if let Some(pid) = rest.strip_prefix("pid:") {
+            if let Some(job) = self.jobs.get_mut(&id) {
+                job.pid = pid.trim().parse::<u32>().ok();
+            }
+            return true;
+        }
+        return false;
+    }
+
+    /// Delivers any complete tagged lines to their jobs and returns the rest of the output
+    pub fn route(&mut self, chunk: &str) -> String {
+        let content = std::mem::take(&mut self.partial) + chunk;
+        let mut out = String::new();
+        let mut rest = content.as_str();
+        while let Some(idx) = rest.find('\n') {
+            let line = &rest[..idx];
+            rest = &rest[idx + 1..];
+            match line.find(JOB_TAG) {
+                Some(tag_idx)
+                    if self.deliver(line[tag_idx + JOB_TAG.len()..].trim_end_matches('\r')) =>
+                {
+                    out += &line[..tag_idx];
+                }
+                _ => {
+                    out += line;
+                    out += "\n";
+                }
+            }
+        }
+        if !self.has_jobs() {
+            out += rest;
+            return out;
+        }
+        // what comes before a tag is shown either way, only the tag onwards or an end
+        // that could become one is held back
+        let hold_from = match rest.find(JOB_TAG) {
+            Some(idx) => idx,
+            None => (1..JOB_TAG.len())
+                .rev()
+                .find(|len| rest.ends_with(&JOB_TAG[..*len]))
+                .map_or(rest.len(), |len| rest.len() - len),
+        };
+        out += &rest[..hold_from];
+        let held = &rest[hold_from..];
+        // a line that long isn't a job's, whatever it holds
+        if held.len() > MAX_PARTIAL {
+            out += held;
+        } else {
+            self.partial = String::from(held);
+        }
+        return out;
+    }
+}
+
+/// Output from a command running in the background on the remote
+pub struct BackgroundJob {
+    pub id: u64,
+    pub lines: UnboundedReceiver<String>,
+}
+
+impl Handle {
+    /// Starts a posix command in the background on the remote, each line it prints is
+    /// sent to the returned job instead of the terminal
+    pub async fn spawn_background(&self, cmd: &str) -> Option<BackgroundJob> {
+        let id = JOB_COUNT.fetch_add(1, Ordering::Relaxed);
+        let (tx, lines) = unbounded_channel::<String>();
+        self.router.lock().ok()?.register(id, tx);
+
+        // setsid puts the job in its own process group so it can be stopped as a whole
+        // the tag is split so a tty echoing the command line can't be mistaken for job output
+        let (a, b) = JOB_TAG.split_at(2);
+        let script = format!(
+            "echo \"{a}\"\"{b}{id}pid:$$\"; {{ {cmd}; }} 2>&1 | while IFS= read -r l; do echo \"{a}\"\"{b}{id}:$l\"; done"
+        );
+        let launch = format!(
+            "(S=$(command -v setsid); $S sh -c {} &)",
+            shell_quote(&script)
+        );
+        if self.exec(&launch, EXEC_TIMEOUT).await.is_none() {
+            self.stop_background(id).await;
+            return None;
+        }
+        self.start_pump();
+        return Some(BackgroundJob { id, lines });
+    }
+
+    /// Kills a background job and everything it started
+    pub async fn stop_background(&self, id: u64) {
+        let mut pid = None;
+        for _ in 0..20 {
+            pid = self.router.lock().ok().and_then(|router| router.pid(id));
+            if pid.is_some() || self.is_closed() {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        if let Some(pid) = pid {
+            self.exec(
+                &format!("kill -TERM -{pid} 2>/dev/null || kill -TERM {pid}"),
+                EXEC_TIMEOUT,
+            )
+            .await;
+        }
+        if let Ok(mut router) = self.router.lock() {
+            router.unregister(id);
+        }
+    }
+
+    /// Runs shell output through the router, returning what should still be shown
+    pub fn route_output(&self, content: &str) -> String {
+        return match self.router.lock() {
+            Ok(mut router) => router.route(content),
+            Err(_) => String::from(content),
+        };
+    }
+
+    /// Output the pump read while nobody was attached to the session, including
+    /// anything that spilled to disk
+    pub async fn take_pending_output(&self) -> Vec<u8> {
+        let pending = match self.pending_output.lock() {
+            Ok(mut pending) => std::mem::take(&mut *pending),
+            Err(_) => Vec::new(),
+        };
+        let mut output = match &self.spill {
+            Some(spill) => spill.drain().await,
+            None => Vec::new(),
+        };
+        output.extend(pending);
+        return output;
+    }
+
+    /// Keeps background jobs flowing while the session isn't being read
+    fn start_pump(&self) {
+        match self.router.lock() {
+            Ok(mut router) if !router.pump_running => router.pump_running = true,
+            _ => return,
+        }
+        let handle = self.clone();
+        tokio::spawn(async move {
+            let mut read_buf: [u8; 4096] = [0; 4096];
+            loop {
+                let has_jobs = match handle.router.lock() {
+                    Ok(mut router) => {
+                        router.pump_running = router.has_jobs() && !handle.is_closed();
+                        router.pump_running
+                    }
+                    Err(_) => false,
+                };
+                if !has_jobs {
+                    return;
+                }
+                // anyone else reading the session routes the output themselves
+                if let Ok(mut read_soc) = handle.read_stream.try_lock() {
+                    match timeout(PUMP_SLICE, read_soc.read(&mut read_buf)).await {
+                        Ok(Ok(n)) if n > 0 => {
+                            handle.publish_output(&read_buf[..n]);
+                            let content = String::from_utf8_lossy(&read_buf[..n]);
+                            let rest = handle.route_output(&content);
+                            if let Ok(mut pending) = handle.pending_output.lock() {
+                                pending.extend_from_slice(rest.as_bytes());
+                                let overflow = take_overflow(&mut pending, PENDING_MEMORY_CAP);
+                                if let (Some(overflow), Some(spill)) = (overflow, &handle.spill) {
+                                    spill.write(overflow);
+                                }
+                            }
+                        }
+                        Ok(result) => handle.mark_closed(CloseReason::from_read(&result)),
+                        Err(_) => {}
+                    }
+                }
+                sleep(Duration::from_millis(20)).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    #[test]
+    fn test_route() {
+        let mut router = OutputRouter::default();
+        let (tx, mut rx) = unbounded_channel::<String>();
+        router.register(3, tx);
+        assert_eq!(router.route("$ ls\nfile\nCTBG3:hel"), "$ ls\nfile\n");
+        assert_eq!(router.route("lo\r\nCTBG3pid:42\n$ "), "$ ");
+        assert_eq!(rx.try_recv(), Ok(String::from("hello")));
+        assert_eq!(router.pid(3), Some(42));
+        // tags for jobs we don't know about are still dropped
+        assert_eq!(router.route("CTBG9:x\nCTBGnot a job\n"), "CTBGnot a job\n");
+        // only the tag onwards waits for the newline
+        assert_eq!(router.route("$ cat f CTBG3:a"), "$ cat f ");
+        assert_eq!(router.route("b\nlast CT"), "last ");
+        assert_eq!(rx.try_recv(), Ok(String::from("ab")));
+        assert_eq!(router.route("X\n"), "CTX\n");
+        // a dump with a tag in it and no newline isn't held past the cap
+        let dump = format!("CTBG3:{}", "x".repeat(MAX_PARTIAL));
+        assert_eq!(router.route(&dump), dump);
+        assert!(router.partial.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_spawn_background() {
+        let handle = spawn_shell_session(32440).await;
+        let mut job = handle.spawn_background("echo one; sleep 30").await.unwrap();
+        let line = timeout(EXEC_TIMEOUT, job.lines.recv()).await.unwrap();
+        assert_eq!(line, Some(String::from("one")));
+        // framed commands still work while the job is running
+        let output = handle.exec("echo two", EXEC_TIMEOUT).await;
+        assert_eq!(output, Some(String::from("two\n")));
+        let pid = handle.router.lock().unwrap().pid(job.id).unwrap();
+        handle.stop_background(job.id).await;
+        // the whole process group goes, not just the wrapper shell. zombies are skipped
+        // since nothing may reap the orphans
+        let cmd = format!("sleep 0.2; ps -eo pgid=,stat= | awk '$1 == {pid} && $2 !~ /Z/' | wc -l");
+        let output = handle.exec(&cmd, EXEC_TIMEOUT).await;
+        assert_eq!(
+            output.map(|out| String::from(out.trim())),
+            Some(String::from("0"))
+        );
+    }
+}
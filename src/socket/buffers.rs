@@ -0,0 +1,92 @@
+use std::io;
+
+use socket2::SockRef;
+
+use crate::error::error::CrabTrapError;
+use crate::socket::connection::Handle;
+use crate::socket::history::EventKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Buffer {
+    Recv,
+    Send,
+}
+
+impl Buffer {
+    fn option(&self) -> &'static str {
+        return match self {
+            Buffer::Recv => "SO_RCVBUF",
+            Buffer::Send => "SO_SNDBUF",
+        };
+    }
+}
+
+impl Handle {
+    /// Asks the kernel for a bigger receive buffer on the shell's socket. Returns the
+    /// size it actually gave, linux doubles the request and caps it at rmem_max
+    pub async fn set_socket_recv_buffer(&self, size_bytes: usize) -> Result<usize, CrabTrapError> {
+        return self.set_socket_buffer(Buffer::Recv, size_bytes).await;
+    }
+
+    /// Same as `set_socket_recv_buffer` for the send buffer, capped at wmem_max
+    pub async fn set_socket_send_buffer(&self, size_bytes: usize) -> Result<usize, CrabTrapError> {
+        return self.set_socket_buffer(Buffer::Send, size_bytes).await;
+    }
+
+    async fn set_socket_buffer(
+        &self,
+        buffer: Buffer,
+        size_bytes: usize,
+    ) -> Result<usize, CrabTrapError> {
+        // both halves are the same socket underneath
+        let write_soc = self.write_stream.lock().await;
+        let soc = SockRef::from(write_soc.as_ref());
+        let result: io::Result<usize> = match buffer {
+            Buffer::Recv => soc
+                .set_recv_buffer_size(size_bytes)
+                .and_then(|_| soc.recv_buffer_size()),
+            Buffer::Send => soc
+                .set_send_buffer_size(size_bytes)
+                .and_then(|_| soc.send_buffer_size()),
+        };
+        let actual = result.map_err(|err| CrabTrapError::SocketOption {
+            option: String::from(buffer.option()),
+            reason: err.to_string(),
+        })?;
+        self.record(
+            EventKind::Note,
+            &format!(
+                "{} set to {actual} bytes, asked for {size_bytes}",
+                buffer.option()
+            ),
+        );
+        return Ok(actual);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    #[tokio::test]
+    async fn test_set_socket_buffers() {
+        let handle = spawn_shell_session(32472).await;
+        let recv = handle.set_socket_recv_buffer(64 * 1024).await.unwrap();
+        let send = handle.set_socket_send_buffer(64 * 1024).await.unwrap();
+        assert!(recv > 0 && send > 0);
+        let logged: Vec<String> = handle
+            .history()
+            .into_iter()
+            .filter(|event| event.kind == EventKind::Note)
+            .map(|event| event.detail)
+            .collect();
+        assert_eq!(
+            logged,
+            vec![
+                format!("SO_RCVBUF set to {recv} bytes, asked for 65536"),
+                format!("SO_SNDBUF set to {send} bytes, asked for 65536"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use termion::terminal_size;
+use tokio::time::sleep;
+
+use crate::config::ephemeral::{is_ephemeral, refusal};
+use crate::input::input::display_notification;
+use crate::menu::output::{render_chunk, LineLimiter};
+use crate::remote::watch::parse_interval;
+use crate::socket::connection::Handle;
+use crate::socket::history::now_secs;
+
+pub const CAPTURE_USAGE: &str = "capture burst <duration> [<path>] | capture stop";
+
+/// a burst is for catching a glitch, transcripts are for keeping a whole session
+pub const MAX_BURST: Duration = Duration::from_secs(600);
+
+pub const BUNDLE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureAction {
+    Burst {
+        duration: Duration,
+        path: Option<PathBuf>,
+    },
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// from the remote
+    In,
+    /// typed or injected
+    Out,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureFrame {
+    /// since the capture started
+    pub at_ms: u64,
+    pub dir: Direction,
+    /// base64 of the bytes exactly as they went over the socket
+    pub data: String,
+}
+
+/// What the output rendering depends on besides the bytes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenderSettings {
+    pub raw_mode: bool,
+    pub max_line_width: usize,
+}
+
+/// Everything needed to replay a burst through the rendering without the session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureBundle {
+    pub version: u32,
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub peer: Option<String>,
+    pub cols: u16,
+    pub rows: u16,
+    pub settings: RenderSettings,
+    pub frames: Vec<CaptureFrame>,
+}
+
+/// A burst being recorded
+pub struct Capture {
+    started: Instant,
+    until: Instant,
+    path: PathBuf,
+    bundle: CaptureBundle,
+}
+
+pub fn parse_capture_args(args: &str) -> Result<CaptureAction, String> {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let (duration, path) = match words.as_slice() {
+        ["stop"] => return Ok(CaptureAction::Stop),
+        ["burst", duration] => (duration, None),
+        ["burst", duration, path] => (duration, Some(PathBuf::from(path))),
+        _ => return Err(String::from(CAPTURE_USAGE)),
+    };
+    let duration = parse_interval(duration).ok_or(format!("{duration} isn't a duration"))?;
+    if duration > MAX_BURST {
+        return Err(format!(
+            "bursts are at most {}m, use transcripts for longer",
+            MAX_BURST.as_secs() / 60
+        ));
+    }
+    return Ok(CaptureAction::Burst { duration, path });
+}
+
+pub fn load_bundle(path: &Path) -> Result<CaptureBundle, String> {
+    let content = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let bundle: CaptureBundle = serde_json::from_str(&content).map_err(|err| err.to_string())?;
+    if bundle.version != BUNDLE_VERSION {
+        return Err(format!("bundle version {} isn't supported", bundle.version));
+    }
+    return Ok(bundle);
+}
+
+/// What the terminal was sent for each inbound frame, replayed through the same
+/// rendering a shell's output goes through
+pub fn render_bundle(bundle: &CaptureBundle) -> Vec<(u64, String)> {
+    let mut limiter = LineLimiter::new(bundle.settings.max_line_width);
+    return bundle
+        .frames
+        .iter()
+        .filter(|frame| frame.dir == Direction::In)
+        .map(|frame| {
+            let bytes = STANDARD.decode(&frame.data).unwrap_or_default();
+            let content = String::from_utf8_lossy(&bytes);
+            let shown = render_chunk(&content, bundle.settings.raw_mode, &mut limiter);
+            (frame.at_ms, shown)
+        })
+        .collect();
+}
+
+impl Handle {
+    /// Records what goes each way for `duration`, then writes the bundle to `path`
+    pub fn start_capture(&self, duration: Duration, path: PathBuf) -> Result<(), String> {
+        if is_ephemeral() {
+            return Err(refusal("A capture"));
+        }
+        let mut capture = self.capture.lock().map_err(|err| err.to_string())?;
+        if let Some(current) = capture.as_ref() {
+            return Err(format!("already capturing to {}", current.path.display()));
+        }
+        let (cols, rows) = terminal_size().unwrap_or((80, 24));
+        let started = Instant::now();
+        *capture = Some(Capture {
+            started,
+            until: started + duration,
+            path,
+            bundle: CaptureBundle {
+                version: BUNDLE_VERSION,
+                started_at: now_secs(),
+                duration_ms: 0,
+                peer: self.peer_addr.map(|addr| addr.to_string()),
+                cols,
+                rows,
+                settings: RenderSettings {
+                    raw_mode: self.raw_mode,
+                    max_line_width: self.max_line_width,
+                },
+                frames: Vec::new(),
+            },
+        });
+        let handle = self.clone();
+        tokio::spawn(async move {
+            sleep(duration).await;
+            // a burst stopped and started again in the meantime isn't this one
+            let ours = match handle.capture.lock() {
+                Ok(capture) => capture.as_ref().is_some_and(|cur| cur.started == started),
+                Err(_) => false,
+            };
+            if ours {
+                display_notification(handle.stop_capture());
+            }
+        });
+        return Ok(());
+    }
+
+    pub fn capture(&self, dir: Direction, data: &[u8]) {
+        let mut capture = match self.capture.lock() {
+            Ok(val) => val,
+            Err(_) => return,
+        };
+        if let Some(current) = capture.as_mut() {
+            let now = Instant::now();
+            if now <= current.until {
+                current.bundle.frames.push(CaptureFrame {
+                    at_ms: now.duration_since(current.started).as_millis() as u64,
+                    dir,
+                    data: STANDARD.encode(data),
+                });
+            }
+        }
+    }
+
+    /// Writes out the burst being recorded and says where it went
+    pub fn stop_capture(&self) -> String {
+        let current = match self.capture.lock() {
+            Ok(mut capture) => capture.take(),
+            Err(_) => None,
+        };
+        let mut current = match current {
+            Some(val) => val,
+            None => return String::from("Not capturing"),
+        };
+        let ended = Instant::now().min(current.until);
+        current.bundle.duration_ms = ended.duration_since(current.started).as_millis() as u64;
+        let written = serde_json::to_string_pretty(&current.bundle)
+            .map_err(|err| err.to_string())
+            .and_then(|json| fs::write(&current.path, json).map_err(|err| err.to_string()));
+        return match written {
+            Ok(_) => format!(
+                "Captured {} frames to {}",
+                current.bundle.frames.len(),
+                current.path.display()
+            ),
+            Err(err) => format!("Couldn't write {}: {err}", current.path.display()),
+        };
+    }
+
+    /// What the `capture` session command prints. Relative paths are from the local
+    /// directory, bundles go there as `capture-<time>.json` without one
+    pub fn capture_command(&self, action: CaptureAction) -> String {
+        let (duration, path) = match action {
+            CaptureAction::Stop => return self.stop_capture(),
+            CaptureAction::Burst { duration, path } => (duration, path),
+        };
+        let path = self
+            .local_dir()
+            .join(path.unwrap_or_else(|| PathBuf::from(format!("capture-{}.json", now_secs()))));
+        return match self.start_capture(duration, path.clone()) {
+            Ok(_) => format!("Capturing for {duration:?} to {}", path.display()),
+            Err(err) => err,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    #[test]
+    fn test_parse_capture_args() {
+        assert_eq!(
+            parse_capture_args("burst 30s"),
+            Ok(CaptureAction::Burst {
+                duration: Duration::from_secs(30),
+                path: None,
+            })
+        );
+        assert_eq!(
+            parse_capture_args("burst 2m glitch.json"),
+            Ok(CaptureAction::Burst {
+                duration: Duration::from_secs(120),
+                path: Some(PathBuf::from("glitch.json")),
+            })
+        );
+        assert_eq!(parse_capture_args("stop"), Ok(CaptureAction::Stop));
+        assert!(parse_capture_args("burst soon").is_err());
+        assert!(parse_capture_args("burst 20m").is_err());
+        assert!(parse_capture_args("").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_capture_and_render() {
+        let mut handle = spawn_shell_session(32467).await;
+        handle.max_line_width = 8;
+        let dir = std::env::temp_dir().join("crab_trap_test_capture");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("burst.json");
+        handle
+            .start_capture(Duration::from_secs(5), path.clone())
+            .unwrap();
+        assert!(handle
+            .start_capture(Duration::from_secs(5), path.clone())
+            .is_err());
+        handle.capture(Direction::Out, b"ls\n");
+        handle.capture(Direction::In, b"a_long_file_name\n$ ");
+        assert!(handle.stop_capture().starts_with("Captured 2 frames"));
+        assert_eq!(handle.stop_capture(), "Not capturing");
+
+        let bundle = load_bundle(&path).unwrap();
+        assert_eq!(bundle.frames.len(), 2);
+        assert_eq!(bundle.frames[0].dir, Direction::Out);
+        assert!(!bundle.settings.raw_mode);
+        let rendered = render_bundle(&bundle);
+        assert_eq!(rendered.len(), 1);
+        assert_eq!(
+            rendered[0].1,
+            format!(
+                "\r{}a_long_f ... [8 bytes truncated]\n$ ",
+                termion::clear::CurrentLine
+            )
+        );
+        fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+}
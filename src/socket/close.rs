@@ -0,0 +1,124 @@
+use std::fmt;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+/// Why a session ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    /// the remote closed the connection
+    RemoteEof,
+    /// the connection was reset or aborted
+    RemoteReset,
+    /// closed from the menu with `kill`
+    OperatorKill,
+    /// it never answered like a shell when it connected
+    HandshakeFailure,
+    /// crab_trap exited with the session still open
+    ListenerShutdown,
+    /// sending to the remote failed
+    WriteError,
+}
+
+impl CloseReason {
+    pub fn code(&self) -> &'static str {
+        return match self {
+            CloseReason::RemoteEof => "remote_eof",
+            CloseReason::RemoteReset => "remote_reset",
+            CloseReason::OperatorKill => "operator_kill",
+            CloseReason::HandshakeFailure => "handshake_failure",
+            CloseReason::ListenerShutdown => "listener_shutdown",
+            CloseReason::WriteError => "write_error",
+        };
+    }
+
+    fn description(&self) -> &'static str {
+        return match self {
+            CloseReason::RemoteEof => "the remote hung up",
+            CloseReason::RemoteReset => "the connection was reset",
+            CloseReason::OperatorKill => "killed from the menu",
+            CloseReason::HandshakeFailure => "it didn't answer like a shell",
+            CloseReason::ListenerShutdown => "crab_trap exited",
+            CloseReason::WriteError => "sending to the remote failed",
+        };
+    }
+
+    /// Why a read that didn't return any data ended the session
+    pub fn from_read(result: &io::Result<usize>) -> CloseReason {
+        return match result {
+            Err(_) => CloseReason::RemoteReset,
+            Ok(_) => CloseReason::RemoteEof,
+        };
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{} ({})", self.code(), self.description());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::history::EventKind;
+    use crate::socket::mock_shell::spawn_shell_session;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn wait_closed(handle: &crate::socket::connection::Handle) -> Option<CloseReason> {
+        for _ in 0..100 {
+            if handle.is_closed() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        return handle.close_reason();
+    }
+
+    #[test]
+    fn test_from_read() {
+        assert_eq!(CloseReason::from_read(&Ok(0)), CloseReason::RemoteEof);
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert_eq!(
+            CloseReason::from_read(&Err(reset)),
+            CloseReason::RemoteReset
+        );
+        assert_eq!(
+            CloseReason::OperatorKill.to_string(),
+            "operator_kill (killed from the menu)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_close_reasons() {
+        // the remote shell exits
+        let handle = spawn_shell_session(32453).await;
+        handle.exec("exit", Duration::from_millis(500)).await;
+        assert_eq!(wait_closed(&handle).await, Some(CloseReason::RemoteEof));
+        let history = handle.history();
+        let closed = history.iter().find(|event| event.kind == EventKind::Closed);
+        assert!(closed.unwrap().detail.starts_with("remote_eof"));
+
+        // the remote resets, dropping a socket with unread data sends a rst
+        let listener = TcpListener::bind("127.0.0.1:32454").await.unwrap();
+        let client = tokio::spawn(TcpStream::connect("127.0.0.1:32454"));
+        let (soc, _) = listener.accept().await.unwrap();
+        let remote = client.await.unwrap().unwrap();
+        let (read, mut write) = soc.into_split();
+        write.write_all(b"unread\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(remote);
+        let handle = crate::socket::connection::Handle::new_headless(read, write);
+        assert!(!handle.check_alive().await);
+        assert_eq!(wait_closed(&handle).await, Some(CloseReason::RemoteReset));
+
+        // the first reason sticks
+        let handle = spawn_shell_session(32455).await;
+        handle.kill().await;
+        handle.mark_closed(CloseReason::RemoteEof);
+        assert_eq!(handle.close_reason(), Some(CloseReason::OperatorKill));
+    }
+}
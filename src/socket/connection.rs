@@ -1,42 +1,299 @@
 use std::collections::HashMap;
+use std::io::stdin;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicIsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use rustyline::error::ReadlineError;
 use rustyline::history::MemHistory;
-use rustyline::{Config, Editor};
+use rustyline::{Config, Editor, EventHandler, KeyEvent};
 use sha256::digest;
 use tokio::io::AsyncWriteExt;
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
-use tokio::time::sleep;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::{sleep, timeout};
+use tokio_util::sync::CancellationToken;
 
+use crate::input::chord::SwitchKey;
 use crate::input::input::InputHelper;
+use crate::menu::output::DEFAULT_MAX_LINE_WIDTH;
+use crate::remote::cwd::RemoteCwd;
+use crate::socket::background::OutputRouter;
+use crate::socket::capture::Capture;
+use crate::socket::close::CloseReason;
+use crate::socket::dial::{DialTarget, RedialStatus};
+use crate::socket::exec::RemoteOs;
+use crate::socket::history::{EventKind, SessionEvent};
+use crate::socket::local_dir::startup_dir;
+use crate::socket::marks::SessionMark;
+use crate::socket::notes::SessionNote;
+use crate::socket::ops::OpQueue;
+use crate::socket::origin::InputOrigin;
+use crate::socket::sniff::ProtocolHint;
+use crate::socket::spill::SpillFile;
+use crate::socket::tee::Tees;
+use crate::socket::timing::CommandTimer;
+use crate::socket::transcript::Transcript;
+
+/// buffered output chunks kept for subscribers that fall behind
+const OUTPUT_CHANNEL_SIZE: usize = 256;
 
 #[derive(Clone)]
 pub struct Handle {
-    pub readline: Arc<Mutex<Editor<InputHelper, MemHistory>>>,
+    /// None when there is no terminal to read from, input must be injected instead
+    pub readline: Option<Arc<Mutex<Editor<InputHelper, MemHistory>>>>,
     pub read_stream: Arc<Mutex<OwnedReadHalf>>,
     pub write_stream: Arc<Mutex<OwnedWriteHalf>>,
     pub raw_mode: bool,
+    /// lines longer than this are truncated when not in raw mode
+    pub max_line_width: usize,
+    /// cancelled once the remote end of the socket is gone
+    pub soc_kill_token: CancellationToken,
+    /// filled in the first time the remote os is detected
+    pub remote_os: Arc<Mutex<Option<RemoteOs>>>,
+    pub peer_addr: Option<SocketAddr>,
+    /// when the session closed and why
+    closed: Arc<std::sync::Mutex<Option<(Instant, CloseReason)>>>,
+    /// the closed session this one carried on from, if it was restored
+    pub restored_from: Option<String>,
+    /// what its first bytes looked like it speaks
+    pub sniffed_protocol: ProtocolHint,
+    /// from `set protocol`, when the guess was wrong
+    pub protocol_override: Option<ProtocolHint>,
+    pub(crate) input_tx: UnboundedSender<(InputOrigin, String)>,
+    pub input_rx: Arc<Mutex<UnboundedReceiver<(InputOrigin, String)>>>,
+    output_tx: broadcast::Sender<Vec<u8>>,
+    /// every event as it's recorded, for control socket subscribers
+    pub(crate) event_tx: broadcast::Sender<SessionEvent>,
+    /// sends background job output to the jobs instead of the terminal
+    pub(crate) router: Arc<std::sync::Mutex<OutputRouter>>,
+    /// read by the background pump while nobody was attached
+    pub(crate) pending_output: Arc<std::sync::Mutex<Vec<u8>>>,
+    /// where pending output goes once it outgrows memory, dropped when this is None
+    pub(crate) spill: Option<SpillFile>,
+    /// set by alt-n and alt-p in line mode, the step to the shell to switch to
+    pub(crate) switch_request: Arc<AtomicIsize>,
+    /// structured command and output log, when transcripts are on
+    pub(crate) transcript: Option<Transcript>,
+    /// what happened to the session, for the timeline
+    pub(crate) history: Arc<std::sync::Mutex<Vec<SessionEvent>>>,
+    /// the operator's notes, kept across restarts in the state file
+    pub(crate) notes: Arc<std::sync::Mutex<Vec<SessionNote>>>,
+    /// labelled points in the transcript, kept in the state file too
+    pub(crate) marks: Arc<std::sync::Mutex<Vec<SessionMark>>>,
+    /// times line mode commands until their prompt comes back
+    pub(crate) timer: Arc<std::sync::Mutex<CommandTimer>>,
+    /// where relative local paths are resolved, changed with `lcd`
+    pub(crate) local_dir: Arc<std::sync::Mutex<PathBuf>>,
+    /// where the remote shell was last seen to be
+    pub(crate) remote_cwd: Arc<std::sync::Mutex<RemoteCwd>>,
+    /// the bind shell it was dialed from, none for shells that connected in
+    pub dial_target: Option<DialTarget>,
+    pub(crate) redial_status: Arc<std::sync::Mutex<RedialStatus>>,
+    /// a `capture burst` being recorded
+    pub(crate) capture: Arc<std::sync::Mutex<Option<Capture>>>,
+    /// files and processes getting a copy of the output as it arrives
+    pub(crate) tees: Arc<std::sync::Mutex<Tees>>,
+    /// framed commands take turns through this
+    pub(crate) operations: Arc<std::sync::Mutex<OpQueue>>,
+    /// the operator's labels for the session, shown in the dashboard
+    pub(crate) tags: Arc<std::sync::Mutex<Vec<String>>>,
+    /// answers to the remote's terminal queries, written by whoever is attached
+    pub(crate) replies_tx: UnboundedSender<Vec<u8>>,
+    pub(crate) replies_rx: Arc<Mutex<UnboundedReceiver<Vec<u8>>>>,
 }
 
 impl Handle {
-    pub fn new(read_stream: OwnedReadHalf, write_stream: OwnedWriteHalf) -> Handle {
+    /// Creates a handle with an interactive readline, falling back to a headless
+    /// handle when stdin isn't a terminal
+    pub fn new(
+        read_stream: OwnedReadHalf,
+        write_stream: OwnedWriteHalf,
+    ) -> Result<Handle, ReadlineError> {
+        if !termion::is_tty(&stdin()) {
+            return Ok(Handle::new_headless(read_stream, write_stream));
+        }
         let history = MemHistory::new();
         let mut builder = Config::builder();
         builder = builder.check_cursor_position(false);
         let config = builder.build();
-        let mut rl = Editor::with_history(config, history).unwrap();
+        let mut rl = Editor::with_history(config, history)?;
         rl.set_helper(Some(InputHelper::new_only_hinter()));
+        let mut handle = Handle::new_headless(read_stream, write_stream);
+        for (key, step) in [('n', 1), ('p', -1)] {
+            let switch = SwitchKey {
+                request: handle.switch_request.clone(),
+                step,
+            };
+            rl.bind_sequence(
+                KeyEvent::alt(key),
+                EventHandler::Conditional(Box::new(switch)),
+            );
+        }
+        handle.readline = Some(Arc::new(Mutex::new(rl)));
+        return Ok(handle);
+    }
+
+    /// Creates a handle without readline, driven by `inject_input` and `subscribe_output`
+    pub fn new_headless(read_stream: OwnedReadHalf, write_stream: OwnedWriteHalf) -> Handle {
+        let (input_tx, input_rx) = unbounded_channel::<(InputOrigin, String)>();
+        let (output_tx, _) = broadcast::channel::<Vec<u8>>(OUTPUT_CHANNEL_SIZE);
+        let (event_tx, _) = broadcast::channel::<SessionEvent>(OUTPUT_CHANNEL_SIZE);
+        let (replies_tx, replies_rx) = unbounded_channel::<Vec<u8>>();
+        let peer_addr = read_stream.peer_addr().ok();
         let handle = Handle {
-            readline: Arc::new(Mutex::new(rl)),
+            readline: None,
             read_stream: Arc::new(Mutex::new(read_stream)),
             write_stream: Arc::new(Mutex::new(write_stream)),
             raw_mode: false,
+            max_line_width: DEFAULT_MAX_LINE_WIDTH,
+            soc_kill_token: CancellationToken::new(),
+            remote_os: Arc::new(Mutex::new(None)),
+            peer_addr,
+            closed: Arc::new(std::sync::Mutex::new(None)),
+            restored_from: None,
+            sniffed_protocol: ProtocolHint::Shell,
+            protocol_override: None,
+            input_tx,
+            input_rx: Arc::new(Mutex::new(input_rx)),
+            output_tx,
+            event_tx,
+            router: Arc::new(std::sync::Mutex::new(OutputRouter::default())),
+            pending_output: Arc::new(std::sync::Mutex::new(Vec::new())),
+            spill: None,
+            switch_request: Arc::new(AtomicIsize::new(0)),
+            transcript: None,
+            history: Arc::new(std::sync::Mutex::new(Vec::new())),
+            notes: Arc::new(std::sync::Mutex::new(Vec::new())),
+            marks: Arc::new(std::sync::Mutex::new(Vec::new())),
+            timer: Arc::new(std::sync::Mutex::new(CommandTimer::default())),
+            local_dir: Arc::new(std::sync::Mutex::new(startup_dir())),
+            remote_cwd: Arc::new(std::sync::Mutex::new(RemoteCwd::default())),
+            dial_target: None,
+            redial_status: Arc::new(std::sync::Mutex::new(RedialStatus::Idle)),
+            capture: Arc::new(std::sync::Mutex::new(None)),
+            tees: Arc::new(std::sync::Mutex::new(Tees::default())),
+            operations: Arc::new(std::sync::Mutex::new(OpQueue::default())),
+            tags: Arc::new(std::sync::Mutex::new(Vec::new())),
+            replies_tx,
+            replies_rx: Arc::new(Mutex::new(replies_rx)),
+        };
+        let from = match peer_addr {
+            Some(addr) => format!("from {addr}"),
+            None => String::from("from an unknown address"),
         };
+        handle.record(EventKind::Connected, &from);
         return handle;
     }
+
+    pub fn is_headless(&self) -> bool {
+        return self.readline.is_none();
+    }
+
+    /// Queues a line to be sent to the remote as if it had been typed
+    pub fn inject_input(&self, line: &str) -> bool {
+        return self.send_input(InputOrigin::Injected, line);
+    }
+
+    /// Receives everything the remote sends while the shell is being read
+    pub fn subscribe_output(&self) -> broadcast::Receiver<Vec<u8>> {
+        return self.output_tx.subscribe();
+    }
+
+    /// Receives the session's events as they're recorded
+    pub fn subscribe_events(&self) -> broadcast::Receiver<SessionEvent> {
+        return self.event_tx.subscribe();
+    }
+
+    /// Passes what the remote sent to the subscribers and tees, byte for byte
+    pub fn publish_output(&self, content: &[u8]) {
+        self.tee_output(content);
+        // no subscribers is the normal case
+        self.output_tx.send(content.to_vec()).unwrap_or_default();
+    }
+
+    /// The shell switch asked for by a key since the last check
+    pub fn take_switch_request(&self) -> Option<isize> {
+        return match self.switch_request.swap(0, Ordering::SeqCst) {
+            0 => None,
+            step => Some(step),
+        };
+    }
+
+    pub fn is_closed(&self) -> bool {
+        return self.soc_kill_token.is_cancelled();
+    }
+
+    /// Records that the session ended and wakes anything waiting on it. Only the first
+    /// reason is kept, whatever notices the end first knows best why
+    pub fn mark_closed(&self, reason: CloseReason) {
+        if let Ok(mut closed) = self.closed.lock() {
+            if closed.is_none() {
+                *closed = Some((Instant::now(), reason));
+                self.record(EventKind::Closed, &reason.to_string());
+                // nothing more is coming, the tees finish what they have
+                self.stop_tee(None);
+            }
+        }
+        self.soc_kill_token.cancel();
+    }
+
+    pub fn closed_at(&self) -> Option<Instant> {
+        return self
+            .closed
+            .lock()
+            .ok()
+            .and_then(|closed| closed.map(|(at, _)| at));
+    }
+
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        return self
+            .closed
+            .lock()
+            .ok()
+            .and_then(|closed| closed.map(|(_, reason)| reason));
+    }
+
+    /// Whether both handles are clones of the same connection
+    pub fn same_session(&self, other: &Handle) -> bool {
+        return Arc::ptr_eq(&self.closed, &other.closed);
+    }
+
+    /// Closes the session from this end
+    pub async fn kill(&self) {
+        self.mark_closed(CloseReason::OperatorKill);
+        self.write_stream
+            .lock()
+            .await
+            .shutdown()
+            .await
+            .unwrap_or_default();
+    }
+
+    /// Peeks at a session nobody is reading to see if the remote hung up
+    pub async fn check_alive(&self) -> bool {
+        if self.is_closed() {
+            return false;
+        }
+        // someone is reading the session, they will notice it closing
+        let mut read_soc = match self.read_stream.try_lock() {
+            Ok(val) => val,
+            Err(_) => return true,
+        };
+        let mut buf: [u8; 1] = [0; 1];
+        match timeout(Duration::from_millis(50), read_soc.peek(&mut buf)).await {
+            Ok(Ok(n)) if n > 0 => return true,
+            Ok(result) => {
+                self.mark_closed(CloseReason::from_read(&result));
+                return false;
+            }
+            Err(_) => return true,
+        }
+    }
 }
 
 pub async fn soc_is_shell(
@@ -47,17 +304,19 @@ pub async fn soc_is_shell(
     let read_soc = read_stream.lock().await;
     let mut write_soc = write_stream.lock().await;
     write_soc
-        .write(format!("echo {}\r\n", soc_key).as_bytes())
+        .write_all(format!("echo {}\r\n", soc_key).as_bytes())
         .await
         .unwrap();
     let mut buf: [u8; 4096] = [0; 4096];
     for _ in 0..10 {
-        let len = read_soc.try_read(&mut buf);
-        if len.is_ok() {
-            let content: String = String::from_utf8_lossy(&buf[..len.unwrap()]).into();
+        if let Ok(len) = read_soc.try_read(&mut buf) {
+            let content: String = String::from_utf8_lossy(&buf[..len]).into();
             if content.contains(&soc_key) {
                 // add a new line so we get our prompt back, also check to make sure the socket did not just close
-                write_soc.write("\n".as_bytes()).await.unwrap_or_default();
+                write_soc
+                    .write_all("\n".as_bytes())
+                    .await
+                    .unwrap_or_default();
                 return true;
             }
         }
@@ -66,14 +325,21 @@ pub async fn soc_is_shell(
     return false;
 }
 
+/// Validates and stores a new connection, returning its key if it was a shell
 pub async fn handle_new_shell(
     soc: TcpStream,
     connected_shells: Arc<Mutex<HashMap<String, Handle>>>,
     skip_validation: Option<bool>,
-) -> bool {
+) -> Option<String> {
     let soc_addr = soc.peer_addr();
     let (soc_read, soc_write) = soc.into_split();
-    let handle = Handle::new(soc_read, soc_write);
+    let handle = match Handle::new(soc_read, soc_write) {
+        Ok(val) => val,
+        Err(err) => {
+            eprintln!("Error setting up shell input: {err}");
+            return None;
+        }
+    };
 
     let soc_key: String;
     match soc_addr {
@@ -92,14 +358,15 @@ pub async fn handle_new_shell(
             };
             if is_shell {
                 let mut shells = connected_shells.lock().await;
-                shells.insert(soc_key, handle);
+                shells.insert(soc_key.clone(), handle);
             } else {
-                return false;
+                handle.mark_closed(CloseReason::HandshakeFailure);
+                return None;
             }
         }
-        Err(_) => return false,
+        Err(_) => return None,
     }
-    return true;
+    return Some(soc_key);
 }
 
 #[cfg(test)]
@@ -115,7 +382,7 @@ mod test {
         let listener = listener_res.unwrap();
         tokio::spawn(async move {
             let (mut soc, _) = listener.accept().await.unwrap();
-            soc.write("test123\n".as_bytes()).await.unwrap();
+            soc.write_all("test123\n".as_bytes()).await.unwrap();
         });
         let (read, write) = TcpStream::connect("127.0.0.1:32423")
             .await
@@ -135,9 +402,28 @@ mod test {
             let (soc, _) = listener.accept().await.unwrap();
             let connected_shells = Arc::new(Mutex::new(HashMap::<String, Handle>::new()));
             handle_new_shell(soc, connected_shells.clone(), Some(true)).await;
-            assert!(connected_shells.lock().await.len() > 0);
+            assert!(!connected_shells.lock().await.is_empty());
         });
         TcpStream::connect("127.0.0.1:32424").await.unwrap();
         handle_init.await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_handle_new_without_tty() {
+        let listener = TcpListener::bind("127.0.0.1:32431").await.unwrap();
+        let client = tokio::spawn(TcpStream::connect("127.0.0.1:32431"));
+        let (soc, _) = listener.accept().await.unwrap();
+        client.await.unwrap().unwrap();
+        let (read, write) = soc.into_split();
+        // what Handle::new builds when stdin isn't a tty
+        let handle = Handle::new_headless(read, write);
+        assert!(handle.is_headless());
+        assert!(handle.readline.is_none());
+        assert!(handle.inject_input("id"));
+        let injected = handle.input_rx.lock().await.recv().await;
+        assert_eq!(
+            injected,
+            Some((InputOrigin::Injected, String::from("id\n")))
+        );
+    }
 }
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::ephemeral::{is_ephemeral, track};
+use crate::socket::connection::Handle;
+use crate::socket::history::SessionEvent;
+
+/// messages queued for a client before its subscriptions start dropping chunks
+const CLIENT_QUEUE: usize = 64;
+
+/// What a client can ask for. There's nothing that sends to a session, the socket
+/// is only ever a tap
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ControlRequest {
+    List,
+    Subscribe { session: String },
+    Unsubscribe { session: String },
+}
+
+/// One json line sent to a client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ControlMessage {
+    Sessions {
+        names: Vec<String>,
+    },
+    Subscribed {
+        session: String,
+    },
+    Unsubscribed {
+        session: String,
+    },
+    Output {
+        session: String,
+        /// milliseconds since the unix epoch
+        at_ms: u64,
+        /// base64 of the bytes the shell sent
+        data: String,
+    },
+    Event {
+        session: String,
+        event: SessionEvent,
+    },
+    /// chunks this client missed because it fell behind
+    Dropped {
+        session: String,
+        count: u64,
+    },
+    /// the shell closed, nothing more comes for it
+    Ended {
+        session: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+fn now_ms() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+}
+
+pub fn parse_request(line: &str) -> Result<ControlRequest, String> {
+    return serde_json::from_str(line).map_err(|_| {
+        String::from("the control socket is read only, it takes list, subscribe and unsubscribe")
+    });
+}
+
+/// How a consumer shows a message, one line for each line of output
+pub fn render_message(message: &ControlMessage) -> Vec<String> {
+    return match message {
+        ControlMessage::Sessions { names } => vec![format!("sessions: {}", names.join(" "))],
+        ControlMessage::Subscribed { session } => vec![format!("[{session}] subscribed")],
+        ControlMessage::Unsubscribed { session } => vec![format!("[{session}] unsubscribed")],
+        ControlMessage::Output { session, data, .. } => {
+            let bytes = STANDARD.decode(data).unwrap_or_default();
+            String::from_utf8_lossy(&bytes)
+                .lines()
+                .map(|line| format!("[{session}] {}", line.trim_end_matches('\r')))
+                .collect()
+        }
+        ControlMessage::Event { session, event } => {
+            vec![format!(
+                "[{session}] {} {}",
+                event.kind.name(),
+                event.detail
+            )]
+        }
+        ControlMessage::Dropped { session, count } => {
+            vec![format!("[{session}] missed {count} chunks")]
+        }
+        ControlMessage::Ended { session } => vec![format!("[{session}] closed")],
+        ControlMessage::Error { message } => vec![format!("error: {message}")],
+    };
+}
+
+/// Passes a session's output and events to one client until it unsubscribes. The
+/// session only ever publishes, a slow client lags its own receivers and is told
+/// how much it missed
+async fn forward(
+    handle: Handle,
+    session: String,
+    tx: Sender<ControlMessage>,
+    stop: CancellationToken,
+) {
+    let mut output = handle.subscribe_output();
+    let mut events = handle.subscribe_events();
+    // told once it's listening, so nothing published after this is missed
+    let subscribed = ControlMessage::Subscribed {
+        session: session.clone(),
+    };
+    if tx.send(subscribed).await.is_err() {
+        return;
+    }
+    loop {
+        let message = select! {
+            biased;
+            _ = stop.cancelled() => return,
+            chunk = output.recv() => match chunk {
+                Ok(data) => ControlMessage::Output {
+                    session: session.clone(),
+                    at_ms: now_ms(),
+                    data: STANDARD.encode(data),
+                },
+                Err(RecvError::Lagged(count)) => ControlMessage::Dropped {
+                    session: session.clone(),
+                    count,
+                },
+                Err(RecvError::Closed) => return,
+            },
+            event = events.recv() => match event {
+                Ok(event) => ControlMessage::Event {
+                    session: session.clone(),
+                    event,
+                },
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            },
+            _ = handle.soc_kill_token.cancelled() => ControlMessage::Ended {
+                session: session.clone(),
+            },
+        };
+        let ended = matches!(message, ControlMessage::Ended { .. });
+        if tx.send(message).await.is_err() || ended {
+            return;
+        }
+    }
+}
+
+async fn serve_client(stream: UnixStream, shells: Arc<Mutex<HashMap<String, Handle>>>) {
+    let (read, mut write) = stream.into_split();
+    let (tx, mut rx) = channel::<ControlMessage>(CLIENT_QUEUE);
+    let writer = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            let mut line = serde_json::to_string(&message).unwrap_or_default();
+            line += "\n";
+            if write.write_all(line.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    });
+    let mut subscriptions: HashMap<String, CancellationToken> = HashMap::new();
+    let mut lines = BufReader::new(read).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match parse_request(&line) {
+            Err(message) => ControlMessage::Error { message },
+            Ok(ControlRequest::List) => {
+                let mut names: Vec<String> = shells.lock().await.keys().cloned().collect();
+                names.sort();
+                ControlMessage::Sessions { names }
+            }
+            Ok(ControlRequest::Subscribe { session }) => {
+                let handle = shells.lock().await.get(&session).cloned();
+                match handle {
+                    _ if subscriptions.contains_key(&session) => ControlMessage::Error {
+                        message: format!("already subscribed to {session}"),
+                    },
+                    Some(handle) => {
+                        let stop = CancellationToken::new();
+                        subscriptions.insert(session.clone(), stop.clone());
+                        tokio::spawn(forward(handle, session, tx.clone(), stop));
+                        continue;
+                    }
+                    None => ControlMessage::Error {
+                        message: format!("no shell called {session}"),
+                    },
+                }
+            }
+            Ok(ControlRequest::Unsubscribe { session }) => match subscriptions.remove(&session) {
+                Some(stop) => {
+                    stop.cancel();
+                    ControlMessage::Unsubscribed { session }
+                }
+                None => ControlMessage::Error {
+                    message: format!("not subscribed to {session}"),
+                },
+            },
+        };
+        if tx.send(reply).await.is_err() {
+            break;
+        }
+    }
+    for stop in subscriptions.values() {
+        stop.cancel();
+    }
+    drop(tx);
+    writer.await.unwrap_or_default();
+}
+
+/// Serves the control socket at `path` until crab_trap exits. Only the user running
+/// crab_trap can connect, what the shells print is as sensitive as the shells
+pub async fn serve_control(
+    path: &Path,
+    shells: Arc<Mutex<HashMap<String, Handle>>>,
+) -> io::Result<()> {
+    // a socket file left by a crab_trap that didn't exit cleanly
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    if is_ephemeral() {
+        track(path);
+    }
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(serve_client(stream, shells.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::history::EventKind;
+    use crate::socket::mock_shell::spawn_shell_session;
+    use std::time::Duration;
+    use tokio::io::{Lines, ReadHalf};
+    use tokio::time::timeout;
+
+    async fn next_message(lines: &mut Lines<BufReader<ReadHalf<UnixStream>>>) -> ControlMessage {
+        let line = timeout(Duration::from_secs(5), lines.next_line())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        return serde_json::from_str(&line).unwrap();
+    }
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(
+            parse_request(r#"{"type":"subscribe","session":"web"}"#),
+            Ok(ControlRequest::Subscribe {
+                session: String::from("web")
+            })
+        );
+        assert_eq!(
+            parse_request(r#"{"type":"list"}"#),
+            Ok(ControlRequest::List)
+        );
+        assert!(parse_request(r#"{"type":"input","session":"web","data":"id"}"#).is_err());
+        assert!(parse_request(r#"{"type":"subscribe","session":"web","data":"id"}"#).is_err());
+        let output = ControlMessage::Output {
+            session: String::from("web"),
+            at_ms: 0,
+            data: STANDARD.encode("uid=0(root)\r\n$ "),
+        };
+        assert_eq!(
+            render_message(&output),
+            vec!["[web] uid=0(root)", "[web] $ "]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_control_tap() {
+        let handle = spawn_shell_session(32473).await;
+        let shells = Arc::new(Mutex::new(HashMap::new()));
+        shells
+            .lock()
+            .await
+            .insert(String::from("web"), handle.clone());
+        let path = std::env::temp_dir().join("crab_trap_test_control.sock");
+        let server_path = path.clone();
+        tokio::spawn(async move { serve_control(&server_path, shells).await });
+        let mut client = None;
+        for _ in 0..50 {
+            if let Ok(stream) = UnixStream::connect(&path).await {
+                client = Some(stream);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let (read, mut write) = tokio::io::split(client.unwrap());
+        let mut lines = BufReader::new(read).lines();
+
+        write
+            .write_all(b"{\"type\":\"input\",\"session\":\"web\",\"data\":\"id\\n\"}\n")
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_message(&mut lines).await,
+            ControlMessage::Error { .. }
+        ));
+        write
+            .write_all(b"{\"type\":\"subscribe\",\"session\":\"web\"}\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            next_message(&mut lines).await,
+            ControlMessage::Subscribed {
+                session: String::from("web")
+            }
+        );
+        handle.publish_output(b"hello\n");
+        handle.record(EventKind::Note, "from the test");
+        match next_message(&mut lines).await {
+            ControlMessage::Output { session, data, .. } => {
+                assert_eq!(session, "web");
+                assert_eq!(STANDARD.decode(data).unwrap(), b"hello\n");
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        match next_message(&mut lines).await {
+            ControlMessage::Event { event, .. } => assert_eq!(event.detail, "from the test"),
+            other => panic!("unexpected {other:?}"),
+        }
+
+        // publishing never waits on the client, it just misses what it couldn't keep up with
+        for i in 0..1000 {
+            handle.publish_output(format!("line {i}\n").as_bytes());
+        }
+        let mut dropped = 0;
+        let mut last = String::new();
+        while last != "line 999\n" {
+            match next_message(&mut lines).await {
+                ControlMessage::Dropped { count, .. } => dropped += count,
+                ControlMessage::Output { data, .. } => {
+                    last = String::from_utf8(STANDARD.decode(data).unwrap()).unwrap()
+                }
+                other => panic!("unexpected {other:?}"),
+            }
+        }
+        assert!(dropped > 0);
+        write
+            .write_all(b"{\"type\":\"unsubscribe\",\"session\":\"web\"}\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            next_message(&mut lines).await,
+            ControlMessage::Unsubscribed {
+                session: String::from("web")
+            }
+        );
+        fs::remove_file(&path).unwrap_or_default();
+    }
+}
@@ -0,0 +1,239 @@
+use std::io;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::{sleep, timeout};
+
+use crate::socket::connection::Handle;
+use crate::socket::history::EventKind;
+
+pub const CONNECT_USAGE: &str =
+    "usage: connect <host> <port> [--redial <seconds>[:<max-attempts>]]";
+
+/// a bind shell that's up answers well within this
+const DIAL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// the wait between redials doubles until it gets to this
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Redial {
+    /// how long to wait before the first attempt
+    pub interval: Duration,
+    /// none keeps trying until the operator kills the session
+    pub max_attempts: Option<u32>,
+}
+
+/// A bind shell crab_trap connected out to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialTarget {
+    pub host: String,
+    pub port: u16,
+    pub redial: Option<Redial>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RedialStatus {
+    #[default]
+    Idle,
+    Reconnecting {
+        attempt: u32,
+    },
+    /// killed from the menu while it was waiting to redial
+    Stopped,
+    GaveUp {
+        attempts: u32,
+    },
+}
+
+/// A connection made from the menu, handed to the listener loop to be set up like any
+/// other shell
+pub struct Dialed {
+    pub soc: TcpStream,
+    pub target: DialTarget,
+    /// the session it was redialed for, it takes over that session's name
+    pub previous: Option<String>,
+}
+
+pub type DialSender = UnboundedSender<Dialed>;
+
+/// `5` or `5:10`, seconds before the first attempt and at most how many to make
+pub fn parse_redial(arg: &str) -> Option<Redial> {
+    let (secs, max_attempts) = match arg.split_once(':') {
+        Some((secs, max)) => (secs, Some(max.parse().ok().filter(|max| *max > 0)?)),
+        None => (arg, None),
+    };
+    let secs: u64 = secs.parse().ok().filter(|secs| *secs > 0)?;
+    return Some(Redial {
+        interval: Duration::from_secs(secs),
+        max_attempts,
+    });
+}
+
+pub fn parse_connect_args(args: &str) -> Option<DialTarget> {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let (host, port, redial) = match words.as_slice() {
+        [host, port] => (host, port, None),
+        [host, port, "--redial", redial] => (host, port, Some(parse_redial(redial)?)),
+        _ => return None,
+    };
+    return Some(DialTarget {
+        host: String::from(*host),
+        port: port.parse().ok()?,
+        redial,
+    });
+}
+
+/// How long to wait before redial `attempt`, counting from 1
+pub fn backoff(redial: &Redial, attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    return redial.interval.saturating_mul(factor).min(MAX_BACKOFF);
+}
+
+pub async fn dial(target: &DialTarget) -> io::Result<TcpStream> {
+    let addr = format!("{}:{}", target.host, target.port);
+    return match timeout(DIAL_TIMEOUT, TcpStream::connect(addr)).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::from(io::ErrorKind::TimedOut)),
+    };
+}
+
+impl Handle {
+    pub fn redial_status(&self) -> RedialStatus {
+        return match self.redial_status.lock() {
+            Ok(status) => *status,
+            Err(_) => RedialStatus::Idle,
+        };
+    }
+
+    pub fn is_redialing(&self) -> bool {
+        return matches!(self.redial_status(), RedialStatus::Reconnecting { .. });
+    }
+
+    fn set_redial_status(&self, status: RedialStatus) {
+        if let Ok(mut current) = self.redial_status.lock() {
+            *current = status;
+        }
+    }
+
+    /// Stops a redial that's waiting for its next attempt, false when there isn't one
+    pub fn stop_redial(&self) -> bool {
+        let mut status = match self.redial_status.lock() {
+            Ok(val) => val,
+            Err(_) => return false,
+        };
+        if !matches!(*status, RedialStatus::Reconnecting { .. }) {
+            return false;
+        }
+        *status = RedialStatus::Stopped;
+        return true;
+    }
+
+    /// Dials the shell this session came from again after it dropped, backing off
+    /// between attempts. Gives up after `max_attempts` or once stopped from the menu
+    pub async fn redial(&self, target: &DialTarget, redial: &Redial) -> Option<TcpStream> {
+        self.set_redial_status(RedialStatus::Reconnecting { attempt: 0 });
+        let mut attempt = 0;
+        while redial.max_attempts.is_none_or(|max| attempt < max) {
+            attempt += 1;
+            sleep(backoff(redial, attempt)).await;
+            if !self.is_redialing() {
+                return None;
+            }
+            self.set_redial_status(RedialStatus::Reconnecting { attempt });
+            if let Ok(soc) = dial(target).await {
+                // a kill that came in while the dial was in flight still wins
+                if self.stop_redial() {
+                    self.set_redial_status(RedialStatus::Idle);
+                    return Some(soc);
+                }
+                return None;
+            }
+        }
+        self.set_redial_status(RedialStatus::GaveUp { attempts: attempt });
+        self.record(
+            EventKind::Closed,
+            &format!("gave up redialing after {attempt} attempts"),
+        );
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_parse_connect_args() {
+        assert_eq!(
+            parse_connect_args("10.0.0.5 4444"),
+            Some(DialTarget {
+                host: String::from("10.0.0.5"),
+                port: 4444,
+                redial: None,
+            })
+        );
+        assert_eq!(
+            parse_connect_args("box 4444 --redial 5:10").unwrap().redial,
+            Some(Redial {
+                interval: Duration::from_secs(5),
+                max_attempts: Some(10),
+            })
+        );
+        assert_eq!(parse_redial("5").unwrap().max_attempts, None);
+        assert_eq!(parse_redial("0"), None);
+        assert_eq!(parse_redial("5:0"), None);
+        assert_eq!(parse_connect_args("box"), None);
+        assert_eq!(parse_connect_args("box http"), None);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let redial = Redial {
+            interval: Duration::from_secs(5),
+            max_attempts: None,
+        };
+        assert_eq!(backoff(&redial, 1), Duration::from_secs(5));
+        assert_eq!(backoff(&redial, 3), Duration::from_secs(20));
+        assert_eq!(backoff(&redial, 40), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn test_redial() {
+        let handle = spawn_shell_session(32463).await;
+        let redial = Redial {
+            interval: Duration::from_millis(10),
+            max_attempts: Some(2),
+        };
+        // nothing listening gives up after the attempts run out
+        let target = DialTarget {
+            host: String::from("127.0.0.1"),
+            port: 32464,
+            redial: Some(redial),
+        };
+        assert!(handle.redial(&target, &redial).await.is_none());
+        assert_eq!(handle.redial_status(), RedialStatus::GaveUp { attempts: 2 });
+
+        let listener = TcpListener::bind("127.0.0.1:32464").await.unwrap();
+        let accepted = tokio::spawn(async move { listener.accept().await.unwrap() });
+        assert!(handle.redial(&target, &redial).await.is_some());
+        accepted.await.unwrap();
+        assert_eq!(handle.redial_status(), RedialStatus::Idle);
+
+        // a kill while it waits stops it
+        let slow = Redial {
+            interval: Duration::from_millis(200),
+            max_attempts: None,
+        };
+        let waiting = handle.clone();
+        let target_again = target.clone();
+        let task = tokio::spawn(async move { waiting.redial(&target_again, &slow).await });
+        sleep(Duration::from_millis(50)).await;
+        assert!(handle.stop_redial());
+        assert!(task.await.unwrap().is_none());
+        assert_eq!(handle.redial_status(), RedialStatus::Stopped);
+    }
+}
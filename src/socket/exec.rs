@@ -0,0 +1,568 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use sha256::digest;
+use tokio::io::AsyncReadExt;
+use tokio::time::{timeout, timeout_at};
+
+use crate::socket::close::CloseReason;
+use crate::socket::connection::Handle;
+use crate::socket::history::EventKind;
+use crate::socket::ops::Priority;
+use crate::socket::write::{write_sliced, WriteOutcome};
+
+static MARKER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// default time to wait for a framed command to finish
+pub const EXEC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// output a framed command can print before it's dropped, a bit over what
+/// remote_http_get needs for a 90 MB file in base64
+pub const MAX_FRAMED_OUTPUT: usize = 128 * 1024 * 1024;
+
+/// how long a command that timed out gets to finish before its output is left for
+/// whoever reads next
+const EXEC_DRAIN_GRACE: Duration = Duration::from_millis(500);
+
+/// how long a framed command waits for the operations ahead of it
+pub const OPERATION_QUEUE_WAIT: Duration = Duration::from_secs(60);
+
+/// Generates a marker that is unlikely to show up in normal command output
+fn new_marker() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let count = MARKER_COUNT.fetch_add(1, Ordering::Relaxed);
+    return digest(format!("{nanos}{count}"))[0..16].to_string();
+}
+
+/// holds the remote's `$?` while a quiet command runs
+const STATUS_VAR: &str = "__crab_trap_status";
+
+/// time a shell gets to answer the os probe before we try the next one
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellKind {
+    Sh,
+    Cmd,
+}
+
+/// What a framed command printed and how it finished
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecResult {
+    pub output: String,
+    /// the command's exit status, None where the shell's framing can't report it
+    pub status: Option<i32>,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteOs {
+    Linux,
+    /// any other uname answering shell
+    Unix,
+    Windows,
+}
+
+impl RemoteOs {
+    pub fn shell_kind(&self) -> ShellKind {
+        return match self {
+            RemoteOs::Windows => ShellKind::Cmd,
+            _ => ShellKind::Sh,
+        };
+    }
+}
+
+/// Builds an echo command that prints the marker without the marker itself
+/// appearing in the echoed command line, so a tty echo can't be mistaken for output
+fn split_echo(marker: &str, kind: ShellKind) -> String {
+    let (a, b) = marker.split_at(marker.len() / 2);
+    return match kind {
+        ShellKind::Sh => format!("echo {a}''{b}"),
+        // the parentheses stop cmd from echoing the space before the next &
+        ShellKind::Cmd => format!("(echo {a}^{b})"),
+    };
+}
+
+/// Wraps a command so it runs between a start and end marker. A posix shell prints
+/// the command's `$?` right after the end marker. cmd expands `%errorlevel%` when it
+/// reads the line, before the command has run, so it gets no status
+fn frame(cmd: &str, start: &str, end: &str, kind: ShellKind) -> String {
+    return match kind {
+        ShellKind::Sh => format!(
+            "{}; {cmd}; {}$?\n",
+            split_echo(start, kind),
+            split_echo(end, kind)
+        ),
+        ShellKind::Cmd => format!(
+            "{} & {cmd} & {}\r\n",
+            split_echo(start, kind),
+            split_echo(end, kind)
+        ),
+    };
+}
+
+/// Saves `$?` before a framed command and puts it back after, so a probe can't change
+/// what the operator's next `echo $?` says. cmd's echo leaves ERRORLEVEL alone
+fn keep_status(framed: &str, kind: ShellKind) -> String {
+    return match kind {
+        // eval expands the saved status before unset clears it
+        ShellKind::Sh => format!(
+            "{STATUS_VAR}=$?; {}; eval \"unset {STATUS_VAR}; (exit ${STATUS_VAR})\"\n",
+            framed.trim_end()
+        ),
+        ShellKind::Cmd => String::from(framed),
+    };
+}
+
+/// Quotes an argument for a posix shell
+pub fn shell_quote(arg: &str) -> String {
+    return format!("'{}'", arg.replace('\'', "'\\''"));
+}
+
+/// Quotes an argument for cmd.exe
+pub fn cmd_quote(arg: &str) -> String {
+    return format!("\"{}\"", arg.replace('"', ""));
+}
+
+/// Pulls the output between the start and end markers out of the raw socket content
+pub fn extract_framed(content: &str, start: &str, end: &str) -> Option<String> {
+    // without a status to wait for the output is done at the end marker
+    return match FrameScanner::new(start, end, ShellKind::Cmd).feed(content) {
+        FrameScan::Done(output, _) => Some(output),
+        _ => None,
+    };
+}
+
+/// Pulls the output and the exit status out of the raw socket content, waiting for
+/// the rest of the end marker's line when the framing reports a status
+pub fn extract_result(
+    content: &str,
+    start: &str,
+    end: &str,
+    kind: ShellKind,
+) -> Option<(String, Option<i32>)> {
+    return match FrameScanner::new(start, end, kind).feed(content) {
+        FrameScan::Done(output, status) => Some((output, status)),
+        _ => None,
+    };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameScan {
+    /// the end marker and its status line haven't come yet
+    Pending,
+    Done(String, Option<i32>),
+    /// the command finished but printed more than the scanner holds
+    TooLarge,
+}
+
+/// The nearest char boundary at or before `by` bytes back from `at`
+fn back_off(content: &str, at: usize, by: usize) -> usize {
+    let mut idx = at.saturating_sub(by);
+    while !content.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    return idx;
+}
+
+/// Finds a framed command's result as its output arrives. Each read is only searched
+/// from where the last one stopped, less a marker in case one is split between reads,
+/// and no more than `limit` bytes of output are held
+pub struct FrameScanner<'a> {
+    start: &'a str,
+    end: &'a str,
+    kind: ShellKind,
+    /// the output from just after the start marker's line, or the tail that might
+    /// hold a split marker before it's seen
+    content: String,
+    started: bool,
+    /// where the next search starts
+    scanned: usize,
+    end_at: Option<usize>,
+    limit: usize,
+    overflowed: bool,
+}
+
+impl<'a> FrameScanner<'a> {
+    pub fn new(start: &'a str, end: &'a str, kind: ShellKind) -> FrameScanner<'a> {
+        return FrameScanner::with_limit(start, end, kind, MAX_FRAMED_OUTPUT);
+    }
+
+    pub fn with_limit(
+        start: &'a str,
+        end: &'a str,
+        kind: ShellKind,
+        limit: usize,
+    ) -> FrameScanner<'a> {
+        return FrameScanner {
+            start,
+            end,
+            kind,
+            content: String::new(),
+            started: false,
+            scanned: 0,
+            end_at: None,
+            limit,
+            overflowed: false,
+        };
+    }
+
+    pub fn feed(&mut self, chunk: &str) -> FrameScan {
+        self.content.push_str(chunk);
+        if !self.started {
+            // the printed marker is followed by a newline, the echoed command line isn't
+            let marker = format!("{}\n", self.start);
+            let from = back_off(&self.content, self.scanned, marker.len());
+            match self.content[from..].find(&marker) {
+                Some(idx) => {
+                    self.content.drain(..from + idx + marker.len());
+                    self.started = true;
+                    self.scanned = 0;
+                }
+                None => {
+                    let keep = back_off(&self.content, self.content.len(), marker.len());
+                    self.content.drain(..keep);
+                    self.scanned = self.content.len();
+                    return FrameScan::Pending;
+                }
+            }
+        }
+        let end_at = match self.end_at {
+            Some(val) => val,
+            None => {
+                let from = back_off(&self.content, self.scanned, self.end.len());
+                match self.content[from..].find(self.end) {
+                    Some(idx) => {
+                        self.end_at = Some(from + idx);
+                        from + idx
+                    }
+                    None => {
+                        if self.content.len() > self.limit {
+                            // past saving, only the end marker is still looked for
+                            let keep = back_off(&self.content, self.content.len(), self.end.len());
+                            self.content.drain(..keep);
+                            self.overflowed = true;
+                        }
+                        self.scanned = self.content.len();
+                        return FrameScan::Pending;
+                    }
+                }
+            }
+        };
+        let status = match self.kind {
+            ShellKind::Cmd => None,
+            ShellKind::Sh => match self.content[end_at + self.end.len()..].split_once('\n') {
+                // anything but a number means the shell didn't expand $?, it isn't guessed at
+                Some((status, _)) => status.trim().parse().ok(),
+                None => return FrameScan::Pending,
+            },
+        };
+        if self.overflowed || end_at > self.limit {
+            return FrameScan::TooLarge;
+        }
+        return FrameScan::Done(String::from(&self.content[..end_at]), status);
+    }
+}
+
+impl Handle {
+    /// Runs a command on the remote posix shell and returns its output, or None if the
+    /// session closed or the command did not finish in time
+    pub async fn exec(&self, cmd: &str, wait: Duration) -> Option<String> {
+        return self.exec_with(ShellKind::Sh, cmd, wait).await;
+    }
+
+    /// Runs a command using the framing for the given kind of shell
+    pub async fn exec_with(&self, kind: ShellKind, cmd: &str, wait: Duration) -> Option<String> {
+        return self.exec_at(Priority::Interactive, kind, cmd, wait).await;
+    }
+
+    /// Runs a command once the operations ahead of it at `priority` are done
+    pub async fn exec_at(
+        &self,
+        priority: Priority,
+        kind: ShellKind,
+        cmd: &str,
+        wait: Duration,
+    ) -> Option<String> {
+        let result = self.exec_result(priority, kind, cmd, wait).await?;
+        return Some(result.output);
+    }
+
+    /// Runs a command like `exec_at` and also returns its exit status and how long
+    /// it took
+    pub async fn exec_result(
+        &self,
+        priority: Priority,
+        kind: ShellKind,
+        cmd: &str,
+        wait: Duration,
+    ) -> Option<ExecResult> {
+        let start = new_marker();
+        let end = new_marker();
+        let framed = frame(cmd, &start, &end, kind);
+        let (scan, took) = self
+            .run_framed(priority, kind, &framed, &start, &end, wait)
+            .await?;
+        let finished = match scan {
+            FrameScan::Done(output, status) => Some((output, status)),
+            FrameScan::TooLarge => {
+                let note = format!(
+                    "output of {cmd} passed {} MB and was dropped",
+                    MAX_FRAMED_OUTPUT / (1024 * 1024)
+                );
+                self.record(EventKind::Note, &note);
+                self.transcribe_note(&note);
+                None
+            }
+            FrameScan::Pending => None,
+        };
+        let output = finished.as_ref().map(|(output, _)| output.as_str());
+        let status = finished.as_ref().and_then(|(_, status)| *status);
+        self.transcribe_framed(cmd, output, status, took);
+        self.record_framed(cmd, output, status);
+        let (output, status) = finished?;
+        return Some(ExecResult {
+            output,
+            status,
+            duration: took,
+        });
+    }
+
+    /// Runs a command the operator never asked for, like a probe crab_trap makes on
+    /// its own. The remote's `$?` is the same afterwards and nothing is recorded in
+    /// the transcript or timeline
+    pub async fn exec_quietly(
+        &self,
+        priority: Priority,
+        kind: ShellKind,
+        cmd: &str,
+        wait: Duration,
+    ) -> Option<String> {
+        let start = new_marker();
+        let end = new_marker();
+        let framed = keep_status(&frame(cmd, &start, &end, kind), kind);
+        let (scan, _) = self
+            .run_framed(priority, kind, &framed, &start, &end, wait)
+            .await?;
+        return match scan {
+            FrameScan::Done(output, _) => Some(output),
+            _ => None,
+        };
+    }
+
+    /// Sends an already framed command and reads until its end marker. None when it
+    /// couldn't be sent, otherwise how the read ended, Pending if it didn't finish in
+    /// time, and how long it took.
+    /// The session is held for just this command, the sending and the reading each get
+    /// `wait` so a stuck one lets the next in
+    async fn run_framed(
+        &self,
+        priority: Priority,
+        kind: ShellKind,
+        framed: &str,
+        start: &str,
+        end: &str,
+        wait: Duration,
+    ) -> Option<(FrameScan, Duration)> {
+        if self.is_closed() {
+            return None;
+        }
+        let _turn = self.begin_operation(priority, OPERATION_QUEUE_WAIT).await?;
+        let mut read_soc = self.read_stream.lock().await;
+        let mut write_soc = self.write_stream.lock().await;
+        let cancel = [&self.soc_kill_token];
+        let sent = timeout(
+            wait,
+            write_sliced(&mut *write_soc, framed.as_bytes(), &cancel),
+        )
+        .await;
+        drop(write_soc);
+        match sent {
+            Ok(WriteOutcome::Done) => {}
+            Ok(WriteOutcome::Failed(_)) => {
+                self.mark_closed(CloseReason::WriteError);
+                return None;
+            }
+            Ok(WriteOutcome::Cancelled(_)) | Err(_) => return None,
+        }
+
+        let sent_at = Instant::now();
+        let mut scanner = FrameScanner::new(start, end, kind);
+        let mut read_buf: [u8; 4096] = [0; 4096];
+        let mut deadline = sent_at + wait;
+        let mut timed_out = false;
+        let output = loop {
+            let n = match timeout_at(deadline.into(), read_soc.read(&mut read_buf)).await {
+                Ok(Ok(n)) if n > 0 => n,
+                Ok(result) => {
+                    self.mark_closed(CloseReason::from_read(&result));
+                    break FrameScan::Pending;
+                }
+                // the command may still finish, whatever it prints then isn't for
+                // the next reader
+                Err(_) if !timed_out => {
+                    timed_out = true;
+                    deadline = Instant::now() + EXEC_DRAIN_GRACE;
+                    continue;
+                }
+                Err(_) => break FrameScan::Pending,
+            };
+            let chunk = self.route_output(&String::from_utf8_lossy(&read_buf[..n]));
+            match scanner.feed(&chunk.replace('\r', "")) {
+                FrameScan::Pending => {}
+                // finished in the grace, too late for whoever asked
+                FrameScan::Done(..) if timed_out => break FrameScan::Pending,
+                finished => break finished,
+            }
+        };
+        return Some((output, sent_at.elapsed().min(wait)));
+    }
+
+    /// Works out what the remote is running, the answer is cached on the handle
+    pub async fn detect_os(&self) -> Option<RemoteOs> {
+        let mut cached = self.remote_os.lock().await;
+        if cached.is_some() {
+            return *cached;
+        }
+        let detected = match self.exec("uname -s", PROBE_TIMEOUT).await {
+            Some(output) if output.trim() == "Linux" => Some(RemoteOs::Linux),
+            Some(output) if !output.trim().is_empty() => Some(RemoteOs::Unix),
+            _ => match self.exec_with(ShellKind::Cmd, "ver", PROBE_TIMEOUT).await {
+                Some(output) if output.contains("Windows") => Some(RemoteOs::Windows),
+                _ => None,
+            },
+        };
+        *cached = detected;
+        return detected;
+    }
+
+    /// The shell framing to use for this remote, posix unless it looks like windows
+    pub async fn shell_kind(&self) -> ShellKind {
+        return match self.detect_os().await {
+            Some(os) => os.shell_kind(),
+            None => ShellKind::Sh,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    #[test]
+    fn test_extract_framed() {
+        let content = "$ echo st''art; ls; echo e''nd\nstart\nfile_a\nfile_b\nend\n$ ";
+        assert_eq!(
+            extract_framed(content, "start", "end"),
+            Some(String::from("file_a\nfile_b\n"))
+        );
+        assert_eq!(extract_framed("start\nstill running", "start", "end"), None);
+
+        let content = "$ echo st''art; false; echo e''nd$?\nstart\nbad\nend1\n$ ";
+        assert_eq!(
+            extract_result(content, "start", "end", ShellKind::Sh),
+            Some((String::from("bad\n"), Some(1)))
+        );
+        // the status line isn't finished yet
+        assert_eq!(
+            extract_result("start\nend12", "start", "end", ShellKind::Sh),
+            None
+        );
+        assert_eq!(
+            extract_result("start\nend$?\n", "start", "end", ShellKind::Sh),
+            Some((String::new(), None))
+        );
+        assert_eq!(
+            extract_result("start\nout\r\nend \r\n", "start", "end", ShellKind::Cmd),
+            Some((String::from("out\r\n"), None))
+        );
+    }
+
+    #[test]
+    fn test_frame_scanner() {
+        // markers split across reads are still found
+        let mut scanner = FrameScanner::new("start", "end", ShellKind::Sh);
+        for piece in [
+            "$ echo st''art; id; echo e''nd$?\nst",
+            "art\nuid=0\ne",
+            "nd",
+            "0",
+        ] {
+            assert_eq!(scanner.feed(piece), FrameScan::Pending);
+        }
+        assert_eq!(
+            scanner.feed("\n$ "),
+            FrameScan::Done(String::from("uid=0\n"), Some(0))
+        );
+
+        // past the limit nothing more is held, the end is still waited for
+        let mut scanner = FrameScanner::with_limit("start", "end", ShellKind::Sh, 1024);
+        assert_eq!(scanner.feed("start\n"), FrameScan::Pending);
+        for _ in 0..64 {
+            assert_eq!(scanner.feed(&"x".repeat(512)), FrameScan::Pending);
+            assert!(scanner.content.len() <= 1024 + 512);
+        }
+        assert_eq!(scanner.feed("end0\n"), FrameScan::TooLarge);
+    }
+
+    #[test]
+    fn test_frame() {
+        assert_eq!(
+            frame("ver", "abcd", "efgh", ShellKind::Cmd),
+            "(echo ab^cd) & ver & (echo ef^gh)\r\n"
+        );
+        assert_eq!(
+            frame("id", "abcd", "efgh", ShellKind::Sh),
+            "echo ab''cd; id; echo ef''gh$?\n"
+        );
+        assert_eq!(
+            keep_status(&frame("pwd", "abcd", "efgh", ShellKind::Sh), ShellKind::Sh),
+            "__crab_trap_status=$?; echo ab''cd; pwd; echo ef''gh$?; eval \"unset __crab_trap_status; (exit $__crab_trap_status)\"\n"
+        );
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[tokio::test]
+    async fn test_exec() {
+        let handle = spawn_shell_session(32428).await;
+        let output = handle.exec("echo hello; echo world", EXEC_TIMEOUT).await;
+        assert_eq!(output, Some(String::from("hello\nworld\n")));
+        let output = handle.exec("true", EXEC_TIMEOUT).await;
+        assert_eq!(output, Some(String::new()));
+        let result = handle
+            .exec_result(
+                Priority::Interactive,
+                ShellKind::Sh,
+                "echo no; (exit 3)",
+                EXEC_TIMEOUT,
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.output, "no\n");
+        assert_eq!(result.status, Some(3));
+        let event = handle.history().pop().unwrap();
+        assert_eq!(event.detail, "echo no; (exit 3) -> no [exit 3]");
+        assert_eq!(handle.detect_os().await, Some(RemoteOs::Linux));
+    }
+
+    #[tokio::test]
+    async fn test_exec_timeout_drains_late_output() {
+        let handle = spawn_shell_session(32486).await;
+        let output = handle
+            .exec("sleep 0.3; echo late", Duration::from_millis(100))
+            .await;
+        assert_eq!(output, None);
+        // the late output and its end marker were read with the timed out command
+        let mut read_buf = [0; 64];
+        let mut read_soc = handle.read_stream.lock().await;
+        let late = timeout(Duration::from_millis(300), read_soc.read(&mut read_buf)).await;
+        assert!(late.is_err());
+        drop(read_soc);
+        let output = handle.exec("echo next", EXEC_TIMEOUT).await;
+        assert_eq!(output, Some(String::from("next\n")));
+    }
+}
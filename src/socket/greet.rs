@@ -0,0 +1,218 @@
+use std::time::Duration;
+
+use regex::Regex;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::{timeout, timeout_at, Instant};
+
+use crate::config::profiles::{match_profile, DeviceProfile, BANNER_WAIT};
+use crate::socket::close::CloseReason;
+use crate::socket::connection::{soc_is_shell, Handle};
+
+/// What a new connection turned out to be
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Greeting {
+    /// no profile for it, it answered like a shell
+    Shell,
+    Ready(DeviceProfile),
+    /// the profile's steps didn't all finish, the operator takes it from there
+    Manual(DeviceProfile, String),
+}
+
+impl Handle {
+    /// Everything the shell sends in the first `wait` after it connects
+    pub async fn read_banner(&self, wait: Duration) -> String {
+        let mut read_soc = self.read_stream.lock().await;
+        let deadline = Instant::now() + wait;
+        let mut content = Vec::new();
+        let mut buf = [0; 4096];
+        while let Ok(result) = timeout_at(deadline, read_soc.read(&mut buf)).await {
+            match result {
+                Ok(n) if n > 0 => content.extend_from_slice(&buf[..n]),
+                _ => {
+                    self.mark_closed(CloseReason::from_read(&result));
+                    break;
+                }
+            }
+        }
+        return String::from_utf8_lossy(&content).into_owned();
+    }
+
+    /// Goes through a profile's steps, `banner` is what the shell has sent so far.
+    /// What the device printed is left out of the session, unless a step fails and
+    /// the operator needs to see where it got to
+    pub async fn run_profile(&self, profile: &DeviceProfile, banner: &str) -> Result<(), String> {
+        let mut content = String::from(banner);
+        let mut received = String::from(banner);
+        for (i, step) in profile.steps.iter().enumerate() {
+            let expect = Regex::new(&step.expect).map_err(|err| err.to_string())?;
+            let wait = Duration::from_millis(step.timeout_ms);
+            let found = {
+                let mut read_soc = self.read_stream.lock().await;
+                let mut buf = [0; 4096];
+                let read_fut = async {
+                    loop {
+                        if let Some(found) = expect.find(&content) {
+                            return Some(found.end());
+                        }
+                        let result = read_soc.read(&mut buf).await;
+                        match result {
+                            Ok(n) if n > 0 => {
+                                let chunk = String::from_utf8_lossy(&buf[..n]);
+                                content += &chunk;
+                                received += &chunk;
+                            }
+                            _ => {
+                                self.mark_closed(CloseReason::from_read(&result));
+                                return None;
+                            }
+                        }
+                    }
+                };
+                timeout(wait, read_fut).await
+            };
+            let reason = match found {
+                Ok(Some(end)) => {
+                    content = content.split_off(end);
+                    let mut write_soc = self.write_stream.lock().await;
+                    match write_soc.write_all(step.send.as_bytes()).await {
+                        Ok(_) => continue,
+                        Err(_) => {
+                            self.mark_closed(CloseReason::WriteError);
+                            format!("step {} couldn't be sent", i + 1)
+                        }
+                    }
+                }
+                Ok(None) => format!("the shell closed at step {}", i + 1),
+                Err(_) => format!(
+                    "step {} didn't see {} within {}ms",
+                    i + 1,
+                    step.expect,
+                    step.timeout_ms
+                ),
+            };
+            if let Ok(mut pending) = self.pending_output.lock() {
+                pending.extend_from_slice(received.as_bytes());
+            }
+            return Err(reason);
+        }
+        return Ok(());
+    }
+
+    /// Works out what a new connection is, a device one of the profiles knows or a
+    /// shell. `selected` is used whatever the banner says. None when it's neither
+    pub async fn greet(
+        &self,
+        soc_key: &str,
+        profiles: &[DeviceProfile],
+        selected: Option<&DeviceProfile>,
+    ) -> Option<Greeting> {
+        let banner = self.read_banner(BANNER_WAIT).await;
+        let profile = match selected.or_else(|| match_profile(profiles, &banner)) {
+            Some(val) => val.clone(),
+            None => {
+                // a plain shell's banner, like a `no job control` warning, is still shown
+                if let Ok(mut pending) = self.pending_output.lock() {
+                    pending.extend_from_slice(banner.as_bytes());
+                }
+                let is_shell = soc_is_shell(
+                    self.read_stream.clone(),
+                    self.write_stream.clone(),
+                    String::from(soc_key),
+                )
+                .await;
+                return match is_shell {
+                    true => Some(Greeting::Shell),
+                    false => None,
+                };
+            }
+        };
+        return match self.run_profile(&profile, &banner).await {
+            Ok(()) => Some(Greeting::Ready(profile)),
+            Err(reason) => Some(Greeting::Manual(profile, reason)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::profiles::parse_profile;
+    use tokio::net::{TcpListener, TcpStream};
+
+    const DEVICE: &str = "match = 'Router OS'\n\
+        [[steps]]\n\
+        expect = 'Terminal type\\?'\n\
+        send = \"vt100\\r\"\n\
+        [[steps]]\n\
+        expect = 'Press RETURN'\n\
+        send = \"\\r\"\n\
+        timeout_ms = 500\n";
+
+    async fn device_session(port: u16) -> (Handle, TcpStream) {
+        let listener = TcpListener::bind(format!("127.0.0.1:{port}"))
+            .await
+            .unwrap();
+        let client = tokio::spawn(TcpStream::connect(format!("127.0.0.1:{port}")));
+        let (soc, _) = listener.accept().await.unwrap();
+        let (read, write) = soc.into_split();
+        return (
+            Handle::new_headless(read, write),
+            client.await.unwrap().unwrap(),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_greet_runs_profile() {
+        let (handle, mut device) = device_session(32465).await;
+        let profiles = vec![parse_profile("router", DEVICE).unwrap()];
+        device
+            .write_all(b"Router OS 4.2\r\nTerminal type? ")
+            .await
+            .unwrap();
+        let device_task = tokio::spawn(async move {
+            let mut buf = [0; 64];
+            let n = device.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"vt100\r");
+            device
+                .write_all(b"\r\nPress RETURN to continue")
+                .await
+                .unwrap();
+            let n = device.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"\r");
+            return device;
+        });
+        let greeting = handle.greet("key", &profiles, None).await;
+        assert_eq!(greeting, Some(Greeting::Ready(profiles[0].clone())));
+        device_task.await.unwrap();
+        // the banner and the dance aren't shown
+        assert!(handle.take_pending_output().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_greet_leaves_failed_profile_to_operator() {
+        let (handle, mut device) = device_session(32466).await;
+        let profiles = vec![parse_profile("router", DEVICE).unwrap()];
+        device
+            .write_all(b"Router OS 4.2\r\nTerminal type? ")
+            .await
+            .unwrap();
+        let device_task = tokio::spawn(async move {
+            let mut buf = [0; 6];
+            device.read_exact(&mut buf).await.unwrap();
+            device.write_all(b"\r\nLogin: ").await.unwrap();
+            return device;
+        });
+        let greeting = handle.greet("key", &profiles, None).await;
+        let _device = device_task.await.unwrap();
+        match greeting {
+            Some(Greeting::Manual(profile, reason)) => {
+                assert_eq!(profile.name, "router");
+                assert_eq!(reason, "step 2 didn't see Press RETURN within 500ms");
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        assert!(!handle.is_closed());
+        let shown = String::from_utf8(handle.take_pending_output().await).unwrap();
+        assert!(shown.ends_with("Login: "));
+    }
+}
@@ -0,0 +1,169 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::socket::connection::Handle;
+use crate::socket::origin::InputOrigin;
+
+/// command details longer than this are cut short in the history
+const MAX_DETAIL_LEN: usize = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Connected,
+    Attached,
+    Detached,
+    Command,
+    Transfer,
+    Note,
+    Mark,
+    Loot,
+    Closed,
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            EventKind::Connected => "connected",
+            EventKind::Attached => "attached",
+            EventKind::Detached => "detached",
+            EventKind::Command => "command",
+            EventKind::Transfer => "transfer",
+            EventKind::Note => "note",
+            EventKind::Mark => "mark",
+            EventKind::Loot => "loot",
+            EventKind::Closed => "closed",
+        };
+    }
+}
+
+/// Something that happened to a session, `at` is seconds since the unix epoch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionEvent {
+    pub at: u64,
+    pub kind: EventKind,
+    pub detail: String,
+    /// for commands, where the input came from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<InputOrigin>,
+}
+
+pub fn now_secs() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+}
+
+/// Cuts a detail down to one line that fits in the history
+fn one_line(detail: &str) -> String {
+    let line = detail.lines().next().unwrap_or("").trim();
+    if line.chars().count() <= MAX_DETAIL_LEN {
+        return String::from(line);
+    }
+    let cut: String = line.chars().take(MAX_DETAIL_LEN - 3).collect();
+    return cut + "...";
+}
+
+/// Sums up a framed command and what it printed in one line, with its exit status
+/// when it failed
+pub fn command_summary(cmd: &str, output: Option<&str>, status: Option<i32>) -> String {
+    let result = match output {
+        None => String::from("no response"),
+        Some(output) => match output
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .count()
+        {
+            0 => String::from("no output"),
+            1 => one_line(output.trim()),
+            n => format!("{} (+{} lines)", one_line(output.trim()), n - 1),
+        },
+    };
+    let result = match status {
+        Some(status) if status != 0 => format!("{result} [exit {status}]"),
+        _ => result,
+    };
+    return one_line(&format!("{} -> {result}", one_line(cmd)));
+}
+
+impl Handle {
+    /// Adds an event to the session's history
+    pub fn record(&self, kind: EventKind, detail: &str) {
+        self.record_with_origin(kind, detail, None);
+    }
+
+    pub fn record_with_origin(&self, kind: EventKind, detail: &str, origin: Option<InputOrigin>) {
+        let event = SessionEvent {
+            at: now_secs(),
+            kind,
+            detail: one_line(detail),
+            origin,
+        };
+        // no subscribers is the normal case
+        self.event_tx.send(event.clone()).unwrap_or_default();
+        if let Ok(mut history) = self.history.lock() {
+            history.push(event);
+        }
+    }
+
+    /// Everything recorded for the session, oldest first
+    pub fn history(&self) -> Vec<SessionEvent> {
+        return match self.history.lock() {
+            Ok(history) => history.clone(),
+            Err(_) => Vec::new(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::close::CloseReason;
+    use crate::socket::exec::EXEC_TIMEOUT;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    #[test]
+    fn test_command_summary() {
+        assert_eq!(
+            command_summary("id", Some("uid=0(root)\n"), Some(0)),
+            "id -> uid=0(root)"
+        );
+        assert_eq!(
+            command_summary("ls", Some("a\nb\n\nc\n"), None),
+            "ls -> a (+2 lines)"
+        );
+        assert_eq!(
+            command_summary("true", Some(""), Some(0)),
+            "true -> no output"
+        );
+        assert_eq!(
+            command_summary("grep x", Some(""), Some(1)),
+            "grep x -> no output [exit 1]"
+        );
+        assert_eq!(
+            command_summary("sleep 99", None, None),
+            "sleep 99 -> no response"
+        );
+        let long = "x".repeat(300);
+        assert_eq!(
+            command_summary(&long, None, None).chars().count(),
+            MAX_DETAIL_LEN
+        );
+    }
+
+    #[tokio::test]
+    async fn test_history() {
+        let handle = spawn_shell_session(32443).await;
+        handle.exec("echo hello", EXEC_TIMEOUT).await;
+        handle.mark_closed(CloseReason::RemoteEof);
+        handle.mark_closed(CloseReason::RemoteEof);
+        let kinds: Vec<EventKind> = handle.history().iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![EventKind::Connected, EventKind::Command, EventKind::Closed]
+        );
+        assert_eq!(handle.history()[1].detail, "echo hello -> hello");
+    }
+}
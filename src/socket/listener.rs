@@ -1,18 +1,278 @@
+use std::cell::Cell;
+use std::net::SocketAddr;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::io;
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::select;
+use tokio::time::{sleep, timeout_at, Instant};
 
-use async_stream::try_stream;
-use futures_core::stream::Stream;
+/// as much of the first bytes as an authenticator is shown
+const AUTH_PEEK_BYTES: usize = 1024;
 
+/// how long a connection has to send what its authenticator asks for
+pub const DEFAULT_AUTH_WINDOW: Duration = Duration::from_millis(300);
 
-pub fn catch_sockets(addr: String, port: u16) -> impl Stream<Item = io::Result<TcpStream>> {
-    try_stream! {
-        let listener = TcpListener::bind(addr + ":" + &port.to_string()).await?;
+thread_local! {
+    // set while an authenticator runs, its panics are caught and aren't crab_trap's
+    static IN_AUTHENTICATOR: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether a panic happening now is inside an authenticator and will be caught
+pub fn panic_is_caught() -> bool {
+    return IN_AUTHENTICATOR.with(|on| on.get());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnInfo {
+    pub peer: SocketAddr,
+    /// the address the connection came in on
+    pub listener: SocketAddr,
+    /// this is the last chance to decide, the window is up, the client hung up or
+    /// sent more than is peeked. Deferring now turns the connection away
+    pub window_closed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthDecision {
+    /// becomes a session, with anything worth noting in its timeline
+    Accept(Option<String>),
+    /// turned away, the banner is sent first unless it's empty
+    Reject(String),
+    /// ask again once more bytes have arrived
+    Defer,
+}
+
+/// Decides from the connection and the bytes it sent so far whether it becomes a
+/// session. The bytes are peeked, a session still reads them from the start
+pub type Authenticator = Arc<dyn Fn(ConnInfo, &[u8]) -> AuthDecision + Send + Sync>;
+
+pub struct Accepted {
+    pub soc: TcpStream,
+    pub info: ConnInfo,
+    /// what the authenticator attached when it accepted
+    pub metadata: Option<String>,
+    /// what the authenticator was shown, it's still there to be read
+    pub first_bytes: Vec<u8>,
+}
+
+type Admission = Pin<Box<dyn std::future::Future<Output = Option<Accepted>> + Send>>;
+
+/// Accepts connections and runs each through the authenticator, if one is set,
+/// before handing it out. Connections are decided side by side, so one that's slow
+/// to speak doesn't hold up the rest
+pub struct Listener {
+    inner: TcpListener,
+    authenticator: Option<Authenticator>,
+    window: Duration,
+    pending: FuturesUnordered<Admission>,
+}
+
+/// The authenticator's answer, a panic in it turns the connection away
+fn decide(authenticator: &Authenticator, info: ConnInfo, bytes: &[u8]) -> AuthDecision {
+    IN_AUTHENTICATOR.with(|on| on.set(true));
+    let decision = catch_unwind(AssertUnwindSafe(|| authenticator(info, bytes)));
+    IN_AUTHENTICATOR.with(|on| on.set(false));
+    return decision.unwrap_or(AuthDecision::Reject(String::new()));
+}
+
+/// Closes a connection that wasn't let in. What it sent is read off first, closing
+/// with unread bytes resets the connection and the banner could be lost
+async fn turn_away(mut soc: TcpStream, seen: usize, banner: &str) {
+    let mut sent = vec![0u8; seen];
+    soc.read_exact(&mut sent).await.unwrap_or_default();
+    if !banner.is_empty() {
+        soc.write_all(banner.as_bytes()).await.unwrap_or_default();
+    }
+    soc.shutdown().await.unwrap_or_default();
+}
+
+/// Peeks at what the client sends until the authenticator stops deferring or the
+/// window is up
+async fn admit(
+    soc: TcpStream,
+    info: ConnInfo,
+    authenticator: Authenticator,
+    window: Duration,
+) -> Option<Accepted> {
+    let deadline = Instant::now() + window;
+    let mut buf = vec![0u8; AUTH_PEEK_BYTES];
+    let mut asked = None;
+    loop {
+        let (seen, window_closed) = match timeout_at(deadline, soc.peek(&mut buf)).await {
+            // a closed connection peeks as 0 bytes forever
+            Ok(Ok(0)) | Ok(Err(_)) => (0, true),
+            Ok(Ok(n)) => (n, n == buf.len() || Instant::now() >= deadline),
+            Err(_) => (asked.unwrap_or(0), true),
+        };
+        // peek only looks at what's already arrived, nothing new is worth asking about
+        if asked != Some(seen) || window_closed {
+            asked = Some(seen);
+            let info = ConnInfo {
+                window_closed,
+                ..info
+            };
+            match decide(&authenticator, info, &buf[..seen]) {
+                AuthDecision::Accept(metadata) => {
+                    return Some(Accepted {
+                        soc,
+                        info,
+                        metadata,
+                        first_bytes: buf[..seen].to_vec(),
+                    })
+                }
+                AuthDecision::Reject(banner) => {
+                    turn_away(soc, seen, &banner).await;
+                    return None;
+                }
+                AuthDecision::Defer if window_closed => {
+                    turn_away(soc, seen, "").await;
+                    return None;
+                }
+                AuthDecision::Defer => {}
+            }
+        }
+        // past the deadline the next peek is the last look
+        timeout_at(deadline, sleep(Duration::from_millis(10)))
+            .await
+            .unwrap_or_default();
+    }
+}
+
+impl Listener {
+    pub async fn bind(addr: &str, port: u16) -> io::Result<Listener> {
+        return Ok(Listener {
+            inner: TcpListener::bind(format!("{addr}:{port}")).await?,
+            authenticator: None,
+            window: DEFAULT_AUTH_WINDOW,
+            pending: FuturesUnordered::new(),
+        });
+    }
+
+    pub fn set_authenticator<F>(&mut self, authenticator: F)
+    where
+        F: Fn(ConnInfo, &[u8]) -> AuthDecision + Send + Sync + 'static,
+    {
+        self.authenticator = Some(Arc::new(authenticator));
+    }
+
+    /// Applies to connections accepted from now on
+    pub fn set_auth_window(&mut self, window: Duration) {
+        self.window = window;
+    }
 
+    /// The next connection its authenticator accepted. Cancel safe, connections
+    /// still being decided stay with the listener
+    pub async fn accept(&mut self) -> io::Result<Accepted> {
         loop {
-            let (socket, _) = listener.accept().await?;
-            yield socket;
+            select! {
+                accepted = self.inner.accept() => {
+                    let (soc, peer) = accepted?;
+                    let info = ConnInfo {
+                        peer,
+                        listener: soc.local_addr()?,
+                        window_closed: false,
+                    };
+                    match &self.authenticator {
+                        Some(authenticator) => self.pending.push(Box::pin(admit(
+                            soc,
+                            info,
+                            authenticator.clone(),
+                            self.window,
+                        ))),
+                        None => {
+                            return Ok(Accepted {
+                                soc,
+                                info,
+                                metadata: None,
+                                first_bytes: Vec::new(),
+                            })
+                        }
+                    }
+                }
+                Some(admitted) = self.pending.next(), if !self.pending.is_empty() => {
+                    if let Some(accepted) = admitted {
+                        return Ok(accepted);
+                    }
+                }
+            }
         }
     }
 }
+
+/// Lets in peers from the given addresses and turns everyone else away unanswered
+pub fn allow_peers(allowed: Vec<std::net::IpAddr>) -> impl Fn(ConnInfo, &[u8]) -> AuthDecision {
+    return move |info: ConnInfo, _: &[u8]| match allowed.contains(&info.peer.ip()) {
+        true => AuthDecision::Accept(None),
+        false => AuthDecision::Reject(String::new()),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn connect(port: u16, send: &'static [u8]) -> tokio::task::JoinHandle<TcpStream> {
+        return tokio::spawn(async move {
+            let mut soc = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            soc.write_all(send).await.unwrap();
+            soc
+        });
+    }
+
+    #[tokio::test]
+    async fn test_authenticator() {
+        let mut listener = Listener::bind("127.0.0.1", 32477).await.unwrap();
+        listener.set_auth_window(Duration::from_millis(300));
+        listener.set_authenticator(|info, bytes| {
+            if bytes.starts_with(b"boom") {
+                panic!("authenticator bug");
+            }
+            return match bytes.strip_prefix(b"token:") {
+                Some(rest) if rest.starts_with(b"good\n") => {
+                    AuthDecision::Accept(Some(String::from("token ok")))
+                }
+                Some(rest) if !info.window_closed && rest.len() < 5 => AuthDecision::Defer,
+                _ if !info.window_closed && b"token:".starts_with(bytes) => AuthDecision::Defer,
+                _ => AuthDecision::Reject(String::from("go away\n")),
+            };
+        });
+
+        // turned away with a banner, a panic doesn't stop the listener, and nothing
+        // said before the window is up is a refusal
+        let mut refused = connect(32477, b"token:bad!\n").await.await.unwrap();
+        let panics = connect(32477, b"boom").await;
+        let silent = connect(32477, b"").await;
+        // the token in two pieces is decided once the rest arrives
+        let split = tokio::spawn(async {
+            let mut soc = TcpStream::connect("127.0.0.1:32477").await.unwrap();
+            soc.write_all(b"tok").await.unwrap();
+            sleep(Duration::from_millis(50)).await;
+            soc.write_all(b"en:good\nid\n").await.unwrap();
+            soc
+        });
+
+        let accepted = listener.accept().await.unwrap();
+        assert_eq!(accepted.metadata.as_deref(), Some("token ok"));
+        let mut sent = vec![0u8; 14];
+        let mut soc = accepted.soc;
+        soc.read_exact(&mut sent).await.unwrap();
+        assert_eq!(sent, b"token:good\nid\n");
+
+        let mut banner = String::new();
+        refused.read_to_string(&mut banner).await.unwrap();
+        assert_eq!(banner, "go away\n");
+        let (_panics, _silent, _split) = (panics.await, silent.await, split.await);
+
+        // the listener still takes connections after all that
+        let allowed = "127.0.0.1".parse().unwrap();
+        listener.set_authenticator(allow_peers(vec![allowed]));
+        let _client = connect(32477, b"").await;
+        let accepted = listener.accept().await.unwrap();
+        assert_eq!(accepted.info.peer.ip(), allowed);
+    }
+}
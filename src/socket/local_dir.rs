@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+
+use crate::socket::connection::Handle;
+
+/// `path` the way a shell started in `dir` would see it, `~` is the local home
+pub fn resolve(dir: &Path, path: &str) -> PathBuf {
+    let expanded = match (path.strip_prefix('~'), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => {
+            PathBuf::from(format!("{home}{rest}"))
+        }
+        _ => PathBuf::from(path),
+    };
+    return dir.join(expanded);
+}
+
+/// The directory crab_trap started in, where sessions start out
+pub fn startup_dir() -> PathBuf {
+    return std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+}
+
+impl Handle {
+    /// Where relative local paths for this session are resolved from
+    pub fn local_dir(&self) -> PathBuf {
+        return match self.local_dir.lock() {
+            Ok(dir) => dir.clone(),
+            Err(_) => startup_dir(),
+        };
+    }
+
+    /// Moves the session's local directory like `cd` would, relative to where it is now
+    pub fn change_local_dir(&self, path: &str) -> Result<PathBuf, String> {
+        let mut dir = self.local_dir.lock().map_err(|err| err.to_string())?;
+        let target = resolve(&dir, path.trim());
+        let target = target
+            .canonicalize()
+            .map_err(|err| format!("Can't change to {}: {err}", target.display()))?;
+        if !target.is_dir() {
+            return Err(format!("{} isn't a directory", target.display()));
+        }
+        *dir = target.clone();
+        return Ok(target);
+    }
+
+    /// What `lcd` and `lpwd` print, `lpwd` passes no path
+    pub fn local_dir_command(&self, path: Option<&str>) -> String {
+        let dir = match path {
+            Some(path) => match self.change_local_dir(path) {
+                Ok(val) => val,
+                Err(err) => return err,
+            },
+            None => self.local_dir(),
+        };
+        return format!("local dir: {}", dir.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    #[test]
+    fn test_resolve() {
+        let dir = Path::new("/work/box");
+        assert_eq!(
+            resolve(dir, "loot.txt"),
+            PathBuf::from("/work/box/loot.txt")
+        );
+        assert_eq!(resolve(dir, "/tmp/x"), PathBuf::from("/tmp/x"));
+        let home = std::env::var("HOME").unwrap();
+        assert_eq!(resolve(dir, "~/x"), PathBuf::from(format!("{home}/x")));
+        // only a leading `~/` is the home directory
+        assert_eq!(resolve(dir, "~bob"), PathBuf::from("/work/box/~bob"));
+    }
+
+    #[tokio::test]
+    async fn test_change_local_dir() {
+        let handle = spawn_shell_session(32459).await;
+        assert_eq!(handle.local_dir(), startup_dir());
+        let base = std::env::temp_dir().join("crab_trap_test_local_dir");
+        std::fs::create_dir_all(base.join("loot")).unwrap();
+        let base = base.canonicalize().unwrap();
+
+        let moved = handle.change_local_dir(base.to_str().unwrap()).unwrap();
+        assert_eq!(moved, base);
+        assert_eq!(handle.change_local_dir("loot").unwrap(), base.join("loot"));
+        assert_eq!(handle.change_local_dir("..").unwrap(), base);
+        assert!(handle.change_local_dir("missing").is_err());
+        assert_eq!(handle.local_dir(), base);
+        assert_eq!(
+            handle.local_dir_command(None),
+            format!("local dir: {}", base.display())
+        );
+        // clones share it, the menu and the session see the same directory
+        assert_eq!(handle.clone().local_dir(), base);
+        std::fs::remove_dir_all(&base).unwrap_or_default();
+    }
+}
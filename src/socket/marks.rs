@@ -0,0 +1,247 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ephemeral::{is_ephemeral, refusal};
+use crate::socket::connection::Handle;
+use crate::socket::history::{now_secs, EventKind};
+
+pub const MARK_USAGE: &str = "usage: mark <name> <label>";
+
+pub const SAVE_USAGE: &str =
+    "usage: save <name> --between <from> <to> <file> | save <name> --since <from> <file>";
+
+/// A labelled point in a session's transcript
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionMark {
+    /// seconds since the unix epoch
+    pub at: u64,
+    pub label: String,
+    /// seq of the transcript record written for the mark, none without a transcript
+    pub seq: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveArgs {
+    pub session: String,
+    pub from: String,
+    /// the mark to stop at, or everything after `from`
+    pub to: Option<String>,
+    pub file: PathBuf,
+}
+
+pub fn parse_save_args(args: &str) -> Option<SaveArgs> {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let (session, from, to, file) = match words.as_slice() {
+        [session, "--between", from, to, file] => (session, from, Some(to), file),
+        [session, "--since", from, file] => (session, from, None, file),
+        _ => return None,
+    };
+    return Some(SaveArgs {
+        session: String::from(*session),
+        from: String::from(*from),
+        to: to.map(|to| String::from(*to)),
+        file: PathBuf::from(file),
+    });
+}
+
+fn find_seq(marks: &[SessionMark], label: &str) -> Result<u64, String> {
+    let mark = marks
+        .iter()
+        .find(|mark| mark.label == label)
+        .ok_or(format!("No mark called {label}"))?;
+    return mark
+        .seq
+        .ok_or(format!("{label} was marked without a transcript"));
+}
+
+/// Pulls the commands and output between two transcript records out of a jsonl
+/// transcript, commands are written the way they were typed after a `$ `
+pub fn extract_between(transcript: &str, from: u64, to: Option<u64>) -> String {
+    let mut text = String::new();
+    for line in transcript.lines() {
+        let record: serde_json::Value = match serde_json::from_str(line) {
+            Ok(val) => val,
+            Err(_) => continue,
+        };
+        let seq = match record["seq"].as_u64() {
+            Some(val) => val,
+            None => continue,
+        };
+        if seq <= from || to.is_some_and(|to| seq >= to) {
+            continue;
+        }
+        let body = record["text"].as_str().unwrap_or_default();
+        match record["kind"].as_str() {
+            Some("command") => text += &format!("$ {body}\n"),
+            Some("output") => {
+                text += body;
+                if !body.ends_with('\n') {
+                    text += "\n";
+                }
+            }
+            _ => {}
+        }
+    }
+    return text;
+}
+
+/// Writes what the transcript has between the marks in `args` to its file, returns
+/// how many bytes were saved
+pub fn save_marked(
+    transcript: &Path,
+    marks: &[SessionMark],
+    args: &SaveArgs,
+) -> Result<usize, String> {
+    if is_ephemeral() {
+        return Err(refusal("Saved output"));
+    }
+    let from = find_seq(marks, &args.from)?;
+    let to = match &args.to {
+        Some(label) => Some(find_seq(marks, label)?),
+        None => None,
+    };
+    if to.is_some_and(|to| to < from) {
+        return Err(format!(
+            "{} comes before {}",
+            args.to.as_deref().unwrap_or_default(),
+            args.from
+        ));
+    }
+    let contents = fs::read_to_string(transcript)
+        .map_err(|err| format!("Couldn't read {}: {err}", transcript.display()))?;
+    let text = extract_between(&contents, from, to);
+    fs::write(&args.file, &text)
+        .map_err(|err| format!("Couldn't write {}: {err}", args.file.display()))?;
+    return Ok(text.len());
+}
+
+impl Handle {
+    /// Marks where the session's transcript is up to so the output after it can be
+    /// saved later, labels are one word and unique in the session
+    pub fn add_mark(&self, label: &str) -> Result<SessionMark, String> {
+        let label = label.trim();
+        if label.is_empty() || label.contains(char::is_whitespace) {
+            return Err(String::from("a mark's label is one word"));
+        }
+        let mut marks = self.marks.lock().map_err(|err| err.to_string())?;
+        if marks.iter().any(|mark| mark.label == label) {
+            return Err(format!("{label} is already marked"));
+        }
+        let mark = SessionMark {
+            at: now_secs(),
+            label: String::from(label),
+            seq: self
+                .transcript
+                .as_ref()
+                .and_then(|transcript| transcript.note_seq(&format!("mark: {label}"))),
+        };
+        marks.push(mark.clone());
+        self.record(EventKind::Mark, label);
+        return Ok(mark);
+    }
+
+    pub fn marks(&self) -> Vec<SessionMark> {
+        return match self.marks.lock() {
+            Ok(marks) => marks.clone(),
+            Err(_) => Vec::new(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::exec::EXEC_TIMEOUT;
+    use crate::socket::mock_shell::spawn_shell_session;
+    use crate::socket::transcript::DEFAULT_FSYNC_INTERVAL;
+
+    #[test]
+    fn test_parse_save_args() {
+        assert_eq!(
+            parse_save_args("web --between creds-found done loot.txt"),
+            Some(SaveArgs {
+                session: String::from("web"),
+                from: String::from("creds-found"),
+                to: Some(String::from("done")),
+                file: PathBuf::from("loot.txt"),
+            })
+        );
+        assert_eq!(
+            parse_save_args("web --since creds-found loot.txt").map(|args| args.to),
+            Some(None)
+        );
+        assert_eq!(parse_save_args("web --between a loot.txt"), None);
+        assert_eq!(parse_save_args("web loot.txt"), None);
+    }
+
+    #[test]
+    fn test_extract_between() {
+        let transcript = [
+            r#"{"seq":0,"kind":"command","text":"id"}"#,
+            r#"{"seq":1,"kind":"output","text":"uid=0(root)\n"}"#,
+            r#"{"seq":2,"kind":"note","text":"mark: start"}"#,
+            r#"{"seq":3,"kind":"command","text":"cat /etc/shadow"}"#,
+            r#"{"seq":4,"kind":"output","text":"root:x"}"#,
+            r#"{"seq":5,"kind":"note","text":"mark: done"}"#,
+            r#"{"seq":6,"kind":"command","text":"exit"}"#,
+        ]
+        .join("\n");
+        assert_eq!(
+            extract_between(&transcript, 2, Some(5)),
+            "$ cat /etc/shadow\nroot:x\n"
+        );
+        assert_eq!(
+            extract_between(&transcript, 2, None),
+            "$ cat /etc/shadow\nroot:x\n$ exit\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_between_marks() {
+        let dir = std::env::temp_dir().join("crab_trap_test_marks");
+        std::fs::remove_dir_all(&dir).unwrap_or_default();
+        let mut handle = spawn_shell_session(32456).await;
+        let transcript = dir.join("test.jsonl");
+        handle
+            .transcribe_to(transcript.clone(), "test", DEFAULT_FSYNC_INTERVAL)
+            .unwrap();
+        handle.exec("echo before", EXEC_TIMEOUT).await;
+        handle.add_mark("start").unwrap();
+        handle.exec("echo inside", EXEC_TIMEOUT).await;
+        handle.add_mark("done").unwrap();
+        handle.exec("echo after", EXEC_TIMEOUT).await;
+        assert!(handle.add_mark("start").is_err());
+        assert!(handle.add_mark("two words").is_err());
+        handle.flush_transcript().await;
+
+        let marks = handle.marks();
+        assert_eq!(marks.len(), 2);
+        let history = handle.history();
+        let marked: Vec<&str> = history
+            .iter()
+            .filter(|event| event.kind == EventKind::Mark)
+            .map(|event| event.detail.as_str())
+            .collect();
+        assert_eq!(marked, vec!["start", "done"]);
+        let args = parse_save_args(&format!(
+            "test --between start done {}",
+            dir.join("loot.txt").display()
+        ))
+        .unwrap();
+        save_marked(&transcript, &marks, &args).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&args.file).unwrap(),
+            "$ echo inside\ninside\n"
+        );
+
+        let backwards = SaveArgs {
+            from: String::from("done"),
+            to: Some(String::from("start")),
+            ..args
+        };
+        assert!(save_marked(&transcript, &marks, &backwards).is_err());
+        std::fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+}
@@ -0,0 +1,37 @@
+use std::process::Stdio;
+
+use tokio::io::copy;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::Command;
+
+use crate::socket::connection::Handle;
+
+/// Connects a local `sh` to a listener the way a reverse shell would and returns
+/// the Handle for the caught end
+pub async fn spawn_shell_session(port: u16) -> Handle {
+    let listener = TcpListener::bind(format!("127.0.0.1:{port}"))
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        let soc = TcpStream::connect(format!("127.0.0.1:{port}"))
+            .await
+            .unwrap();
+        let (mut soc_read, mut soc_write) = soc.into_split();
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("exec sh 2>&1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+        tokio::spawn(async move { copy(&mut soc_read, &mut stdin).await });
+        copy(&mut stdout, &mut soc_write).await.unwrap_or_default();
+        child.wait().await.unwrap();
+    });
+    let (soc, _) = listener.accept().await.unwrap();
+    let (read, write) = soc.into_split();
+    return Handle::new_headless(read, write);
+}
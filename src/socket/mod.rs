@@ -1,2 +1,29 @@
+pub mod background;
+pub mod buffers;
+pub mod capture;
+pub mod close;
+pub mod connection;
+pub mod control;
+pub mod dial;
+pub mod exec;
+pub mod greet;
+pub mod history;
 pub mod listener;
-pub mod connection;
\ No newline at end of file
+pub mod local_dir;
+pub mod marks;
+#[cfg(test)]
+pub mod mock_shell;
+pub mod notes;
+pub mod ops;
+pub mod origin;
+pub mod pipe;
+pub mod queries;
+pub mod reconnect;
+pub mod retention;
+pub mod sniff;
+pub mod spill;
+pub mod tags;
+pub mod tee;
+pub mod timing;
+pub mod transcript;
+pub mod write;
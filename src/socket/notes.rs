@@ -0,0 +1,218 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ephemeral::{is_ephemeral, shred, track, untrack};
+use crate::socket::connection::Handle;
+use crate::socket::exec::shell_quote;
+use crate::socket::history::{now_secs, EventKind};
+
+pub const NOTE_USAGE: &str = "usage: note <name> [text] | note edit <name>";
+
+/// explains the block opened by `note edit`, lines like this are dropped on save
+const BLOCK_HEADER: &str = "# one note per line, lines starting with # are ignored\n";
+
+/// Something the operator wrote down about a session. Notes stay local, nothing
+/// here is ever written to the remote
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionNote {
+    /// seconds since the unix epoch
+    pub at: u64,
+    pub text: String,
+}
+
+/// Turns notes into the block opened in the editor
+pub fn notes_to_block(notes: &[SessionNote]) -> String {
+    let mut block = String::from(BLOCK_HEADER);
+    for note in notes {
+        block += &format!("{}\n", note.text);
+    }
+    return block;
+}
+
+/// Reads an edited block back, notes that weren't changed keep when they were written
+pub fn notes_from_block(block: &str, old: &[SessionNote], now: u64) -> Vec<SessionNote> {
+    let mut unused: Vec<&SessionNote> = old.iter().collect();
+    let mut notes = Vec::new();
+    for line in block.lines() {
+        let text = line.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let at = match unused.iter().position(|note| note.text == text) {
+            Some(idx) => unused.remove(idx).at,
+            None => now,
+        };
+        notes.push(SessionNote {
+            at,
+            text: String::from(text),
+        });
+    }
+    return notes;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NoteAction {
+    List,
+    Add(String),
+    Edit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoteArgs {
+    pub session: String,
+    pub action: NoteAction,
+}
+
+pub fn parse_note_args(args: &str) -> Option<NoteArgs> {
+    let args = args.trim();
+    if let Some(rest) = args.strip_prefix("edit ") {
+        let session = rest.trim();
+        if session.is_empty() || session.contains(' ') {
+            return None;
+        }
+        return Some(NoteArgs {
+            session: String::from(session),
+            action: NoteAction::Edit,
+        });
+    }
+    let (session, text) = args.split_once(' ').unwrap_or((args, ""));
+    if session.is_empty() {
+        return None;
+    }
+    let action = match text.trim() {
+        "" => NoteAction::List,
+        text => NoteAction::Add(String::from(text)),
+    };
+    return Some(NoteArgs {
+        session: String::from(session),
+        action,
+    });
+}
+
+/// Opens the notes in `$EDITOR` as one block and reads them back
+pub fn edit_notes(notes: &[SessionNote]) -> io::Result<Vec<SessionNote>> {
+    let path = std::env::temp_dir().join(format!("crab_trap_notes_{}.txt", std::process::id()));
+    // the editor needs a file, it's shredded even if crab_trap dies while it's open
+    track(&path);
+    // only the operator should be able to read them
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)?
+        .write_all(notes_to_block(notes).as_bytes())?;
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| String::from("vi"));
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} {}", shell_quote(&path.to_string_lossy())))
+        .status();
+    let block = fs::read_to_string(&path);
+    match is_ephemeral() {
+        true => shred(&path).unwrap_or_default(),
+        false => fs::remove_file(&path).unwrap_or_default(),
+    }
+    untrack(&path);
+    if !status?.success() {
+        return Err(io::Error::other("the editor exited with an error"));
+    }
+    return Ok(notes_from_block(&block?, notes, now_secs()));
+}
+
+impl Handle {
+    /// Attaches a note to the session, it shows up in the timeline and transcript too
+    pub fn add_note(&self, text: &str) {
+        let text = text.trim();
+        if let Ok(mut notes) = self.notes.lock() {
+            notes.push(SessionNote {
+                at: now_secs(),
+                text: String::from(text),
+            });
+        }
+        self.record(EventKind::Note, text);
+        self.transcribe_note(&format!("note: {text}"));
+    }
+
+    pub fn notes(&self) -> Vec<SessionNote> {
+        return match self.notes.lock() {
+            Ok(notes) => notes.clone(),
+            Err(_) => Vec::new(),
+        };
+    }
+
+    /// Swaps in an edited set of notes
+    pub fn replace_notes(&self, new_notes: Vec<SessionNote>) {
+        if let Ok(mut notes) = self.notes.lock() {
+            *notes = new_notes;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    fn note(at: u64, text: &str) -> SessionNote {
+        return SessionNote {
+            at,
+            text: String::from(text),
+        };
+    }
+
+    #[test]
+    fn test_notes_block() {
+        let old = vec![
+            note(10, "creds in /opt/app/.env"),
+            note(20, "cron runs as root"),
+        ];
+        let block = notes_to_block(&old);
+        assert_eq!(
+            block,
+            format!("{BLOCK_HEADER}creds in /opt/app/.env\ncron runs as root\n")
+        );
+        let edited = "# header\ncron runs as root\n\nnginx 1.18\ncreds in /opt/app/.env\n";
+        assert_eq!(
+            notes_from_block(edited, &old, 99),
+            vec![
+                note(20, "cron runs as root"),
+                note(99, "nginx 1.18"),
+                note(10, "creds in /opt/app/.env"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_note_args() {
+        let args = |session: &str, action| NoteArgs {
+            session: String::from(session),
+            action,
+        };
+        assert_eq!(parse_note_args("web"), Some(args("web", NoteAction::List)));
+        assert_eq!(
+            parse_note_args("web  found a key "),
+            Some(args("web", NoteAction::Add(String::from("found a key"))))
+        );
+        assert_eq!(
+            parse_note_args("edit web"),
+            Some(args("web", NoteAction::Edit))
+        );
+        assert_eq!(parse_note_args("edit web db"), None);
+        assert_eq!(parse_note_args(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_add_note() {
+        let handle = spawn_shell_session(32450).await;
+        handle.add_note("  box has docker  ");
+        assert_eq!(handle.notes()[0].text, "box has docker");
+        let history = handle.history();
+        let last = history.last().unwrap();
+        assert_eq!(last.kind, EventKind::Note);
+        assert_eq!(last.detail, "box has docker");
+    }
+}
@@ -0,0 +1,227 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+
+use crate::socket::connection::Handle;
+
+/// Who's asking for the session, the operator's own commands go first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// watches, cwd probes and anything else crab_trap runs on its own
+    Background,
+    /// something the operator asked for and is waiting on
+    Interactive,
+}
+
+struct Waiter {
+    priority: Priority,
+    /// when it started waiting, earlier goes first at the same priority
+    seq: u64,
+    tx: oneshot::Sender<OpGuard>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Waiter) -> bool {
+        return self.priority == other.priority && self.seq == other.seq;
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Waiter) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Waiter) -> Ordering {
+        return self
+            .priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq));
+    }
+}
+
+/// Framed operations waiting for a session, one runs at a time so their markers
+/// and output never mix
+#[derive(Default)]
+pub struct OpQueue {
+    busy: bool,
+    next_seq: u64,
+    waiting: BinaryHeap<Waiter>,
+}
+
+impl OpQueue {
+    /// An operation has the session
+    pub fn is_busy(&self) -> bool {
+        return self.busy;
+    }
+}
+
+/// Holds the session for one operation, the next in line gets it when this drops
+pub struct OpGuard {
+    /// None once it's been handed back unused
+    queue: Option<Arc<Mutex<OpQueue>>>,
+}
+
+impl Drop for OpGuard {
+    fn drop(&mut self) {
+        let queue = match self.queue.take() {
+            Some(val) => val,
+            None => return,
+        };
+        loop {
+            let waiter = match queue.lock() {
+                Ok(mut queue) => match queue.waiting.pop() {
+                    Some(val) => val,
+                    None => {
+                        queue.busy = false;
+                        return;
+                    }
+                },
+                Err(_) => return,
+            };
+            let guard = OpGuard {
+                queue: Some(queue.clone()),
+            };
+            match waiter.tx.send(guard) {
+                Ok(_) => return,
+                // it gave up waiting, the next one in line gets it instead
+                Err(mut guard) => guard.queue = None,
+            }
+        }
+    }
+}
+
+/// Waits up to `wait` for the session. A waiter that gives up after it was handed
+/// the guard drops it unopened, which passes the session on
+pub async fn acquire(
+    queue: &Arc<Mutex<OpQueue>>,
+    priority: Priority,
+    wait: Duration,
+) -> Option<OpGuard> {
+    let rx = {
+        let mut locked = queue.lock().ok()?;
+        if !locked.busy {
+            locked.busy = true;
+            return Some(OpGuard {
+                queue: Some(queue.clone()),
+            });
+        }
+        let (tx, rx) = oneshot::channel();
+        locked.next_seq += 1;
+        let seq = locked.next_seq;
+        locked.waiting.push(Waiter { priority, seq, tx });
+        rx
+    };
+    return timeout(wait, rx).await.ok()?.ok();
+}
+
+impl Handle {
+    /// Takes the session for a framed operation, waiting behind anything running and
+    /// anything of a higher priority. None if it isn't free within `wait`
+    pub async fn begin_operation(&self, priority: Priority, wait: Duration) -> Option<OpGuard> {
+        return acquire(&self.operations, priority, wait).await;
+    }
+
+    /// How many operations are waiting for the session
+    pub fn pending_operations(&self) -> usize {
+        return match self.operations.lock() {
+            Ok(queue) => queue.waiting.iter().filter(|w| !w.tx.is_closed()).count(),
+            Err(_) => 0,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::exec::{ShellKind, EXEC_TIMEOUT};
+    use crate::socket::mock_shell::spawn_shell_session;
+    use tokio::time::sleep;
+
+    #[tokio::test]
+    async fn test_priority_order() {
+        let queue = Arc::new(Mutex::new(OpQueue::default()));
+        let held = acquire(&queue, Priority::Background, EXEC_TIMEOUT)
+            .await
+            .unwrap();
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+        for (name, priority) in [
+            ("watch", Priority::Background),
+            ("probe", Priority::Background),
+            ("upload", Priority::Interactive),
+        ] {
+            let queue = queue.clone();
+            let order_tx = order_tx.clone();
+            tokio::spawn(async move {
+                let _guard = acquire(&queue, priority, EXEC_TIMEOUT).await.unwrap();
+                order_tx.send(name).unwrap();
+                sleep(Duration::from_millis(20)).await;
+            });
+            sleep(Duration::from_millis(20)).await;
+        }
+        // one that gives up doesn't keep the session when its turn comes
+        assert!(
+            acquire(&queue, Priority::Interactive, Duration::from_millis(20))
+                .await
+                .is_none()
+        );
+        drop(held);
+        let mut order = Vec::new();
+        for _ in 0..3 {
+            order.push(order_rx.recv().await.unwrap());
+        }
+        assert_eq!(order, vec!["upload", "watch", "probe"]);
+        assert!(
+            acquire(&queue, Priority::Background, Duration::from_millis(100))
+                .await
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_overlapping_operations() {
+        let handle = spawn_shell_session(32479).await;
+        // a stuck command gives the session up once its time is up
+        let stuck = handle.clone();
+        let stuck = tokio::spawn(async move {
+            return stuck
+                .exec_at(
+                    Priority::Background,
+                    ShellKind::Sh,
+                    "sleep 1; echo late",
+                    Duration::from_millis(200),
+                )
+                .await;
+        });
+        sleep(Duration::from_millis(50)).await;
+        let mut running = Vec::new();
+        for i in 0..8 {
+            let handle = handle.clone();
+            let priority = match i % 2 {
+                0 => Priority::Interactive,
+                _ => Priority::Background,
+            };
+            running.push(tokio::spawn(async move {
+                let cmd = format!("echo start{i}; echo end{i}");
+                return handle
+                    .exec_at(priority, ShellKind::Sh, &cmd, EXEC_TIMEOUT)
+                    .await;
+            }));
+        }
+        sleep(Duration::from_millis(50)).await;
+        assert!(handle.pending_operations() > 0);
+        assert_eq!(stuck.await.unwrap(), None);
+        for (i, op) in running.into_iter().enumerate() {
+            let output = op.await.unwrap();
+            assert_eq!(output, Some(format!("start{i}\nend{i}\n")));
+        }
+        assert_eq!(handle.pending_operations(), 0);
+    }
+}
@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use termion::style;
+
+use crate::socket::connection::Handle;
+use crate::socket::history::{command_summary, EventKind};
+
+/// Where a line sent to the remote came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputOrigin {
+    /// typed at the terminal
+    Operator,
+    /// queued with `inject_input`, by the api or a headless caller
+    Injected,
+    /// a framed command crab_trap ran itself
+    Internal,
+}
+
+impl InputOrigin {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            InputOrigin::Operator => "operator",
+            InputOrigin::Injected => "injected",
+            InputOrigin::Internal => "internal",
+        };
+    }
+
+    pub fn parse(name: &str) -> Option<InputOrigin> {
+        return [
+            InputOrigin::Operator,
+            InputOrigin::Injected,
+            InputOrigin::Internal,
+        ]
+        .into_iter()
+        .find(|origin| origin.name() == name);
+    }
+
+    /// What's shown before input that wasn't typed, so it stands out on the terminal
+    pub fn prefix(&self) -> String {
+        return match self {
+            InputOrigin::Operator => String::new(),
+            _ => format!("{}[{}]{} ", style::Faint, self.name(), style::Reset),
+        };
+    }
+}
+
+impl Handle {
+    /// Queues a line for the remote, everything that isn't typed goes through here
+    pub fn send_input(&self, origin: InputOrigin, line: &str) -> bool {
+        let mut content = String::from(line);
+        if !content.ends_with('\n') {
+            content += "\n";
+        }
+        return self.input_tx.send((origin, content)).is_ok();
+    }
+
+    /// Writes down a line that's about to go to the remote in the transcript and
+    /// the session's history
+    pub fn record_input(&self, origin: InputOrigin, line: &str) {
+        self.transcribe_command(origin, line);
+        self.record_command(origin, line.trim_end());
+    }
+
+    pub fn record_command(&self, origin: InputOrigin, detail: &str) {
+        self.record_with_origin(EventKind::Command, detail, Some(origin));
+    }
+
+    /// Records a framed command crab_trap ran with what it printed
+    pub fn record_framed(&self, cmd: &str, output: Option<&str>, status: Option<i32>) {
+        self.record_command(InputOrigin::Internal, &command_summary(cmd, output, status));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::exec::EXEC_TIMEOUT;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    #[test]
+    fn test_origin_names() {
+        assert_eq!(InputOrigin::parse("injected"), Some(InputOrigin::Injected));
+        assert_eq!(InputOrigin::parse("script"), None);
+        assert_eq!(InputOrigin::Operator.prefix(), "");
+        assert!(InputOrigin::Internal.prefix().contains("[internal]"));
+    }
+
+    #[tokio::test]
+    async fn test_origins_recorded() {
+        let handle = spawn_shell_session(32470).await;
+        assert!(handle.send_input(InputOrigin::Injected, "id"));
+        let queued = handle.input_rx.lock().await.recv().await;
+        assert_eq!(queued, Some((InputOrigin::Injected, String::from("id\n"))));
+        handle.record_input(InputOrigin::Operator, "whoami\n");
+        handle.exec("true", EXEC_TIMEOUT).await;
+        let origins: Vec<(Option<InputOrigin>, String)> = handle
+            .history()
+            .into_iter()
+            .filter(|event| event.kind == EventKind::Command)
+            .map(|event| (event.origin, event.detail))
+            .collect();
+        assert_eq!(
+            origins,
+            vec![
+                (Some(InputOrigin::Operator), String::from("whoami")),
+                (
+                    Some(InputOrigin::Internal),
+                    String::from("true -> no output")
+                ),
+            ]
+        );
+    }
+}
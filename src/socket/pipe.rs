@@ -0,0 +1,146 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::unix::pipe::{OpenOptions, Receiver, Sender};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, timeout};
+
+/// how long a pipe session waits for the other tool to open its end again before closing
+pub const PIPE_REOPEN_WINDOW: Duration = Duration::from_secs(30);
+
+/// a closed input fifo reads as eof straight away, so this is how often it's checked again
+const PIPE_POLL: Duration = Duration::from_millis(200);
+
+/// `--pipe <in-fifo>:<out-fifo>`, the shell's output is read from the first and its
+/// input written to the second
+pub fn parse_pipe_arg(arg: &str) -> Result<(PathBuf, PathBuf), String> {
+    return match arg.split_once(':') {
+        Some((input, output)) if !input.is_empty() && !output.is_empty() => {
+            Ok((PathBuf::from(input), PathBuf::from(output)))
+        }
+        _ => Err(format!("{arg} isn't <in-fifo>:<out-fifo>")),
+    };
+}
+
+/// Opens a fifo pair and returns a socket that carries it, for `handle_new_shell` to
+/// take like any other connection. Neither open waits for the other tool so the order
+/// it opens its ends in doesn't matter. The session closes once the input fifo has had
+/// no writer for `reopen_window`, a writer coming back before then carries on
+pub async fn adopt_pipe(
+    input: &Path,
+    output: &Path,
+    reopen_window: Duration,
+) -> io::Result<TcpStream> {
+    // non blocking, reads wait until a writer turns up
+    let receiver = OpenOptions::new().open_receiver(input)?;
+    // opened for reading as well so there doesn't have to be a reader yet, what's
+    // written waits in the pipe until there is
+    let sender = OpenOptions::new().read_write(true).open_sender(output)?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let bridge = TcpStream::connect(listener.local_addr()?).await?;
+    let (session, _) = listener.accept().await?;
+    tokio::spawn(bridge_pipe(receiver, sender, bridge, reopen_window));
+    return Ok(session);
+}
+
+/// Copies between the fifos and the session's socket until either side is done,
+/// dropping the socket is the session's eof
+async fn bridge_pipe(
+    mut receiver: Receiver,
+    mut sender: Sender,
+    bridge: TcpStream,
+    reopen_window: Duration,
+) {
+    let (mut from_session, mut to_session) = bridge.into_split();
+    tokio::select! {
+        _ = tokio::io::copy(&mut from_session, &mut sender) => {}
+        _ = relay_output(&mut receiver, &mut to_session, reopen_window) => {}
+    }
+}
+
+async fn relay_output(receiver: &mut Receiver, to_session: &mut OwnedWriteHalf, window: Duration) {
+    let mut buf = [0; 4096];
+    let mut eof_since: Option<Instant> = None;
+    loop {
+        match timeout(PIPE_POLL * 2, receiver.read(&mut buf)).await {
+            Ok(Ok(0)) => {
+                if eof_since.get_or_insert_with(Instant::now).elapsed() >= window {
+                    return;
+                }
+                sleep(PIPE_POLL).await;
+            }
+            Ok(Ok(len)) => {
+                eof_since = None;
+                if to_session.write_all(&buf[..len]).await.is_err() {
+                    return;
+                }
+            }
+            Ok(Err(_)) => return,
+            // a read only waits while there's a writer, it's back
+            Err(_) => eof_since = None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_parse_pipe_arg() {
+        assert_eq!(
+            parse_pipe_arg("/tmp/in:/tmp/out"),
+            Ok((PathBuf::from("/tmp/in"), PathBuf::from("/tmp/out")))
+        );
+        assert!(parse_pipe_arg("/tmp/in").is_err());
+        assert!(parse_pipe_arg(":/tmp/out").is_err());
+    }
+
+    async fn read_some(session: &mut TcpStream) -> String {
+        let mut buf = [0; 1024];
+        let len = timeout(Duration::from_secs(5), session.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        return String::from_utf8_lossy(&buf[..len]).into_owned();
+    }
+
+    #[tokio::test]
+    async fn test_adopt_pipe() {
+        let dir = std::env::temp_dir().join("crab_trap_test_pipe");
+        std::fs::remove_dir_all(&dir).unwrap_or_default();
+        std::fs::create_dir_all(&dir).unwrap();
+        let (input, output) = (dir.join("in"), dir.join("out"));
+        for fifo in [&input, &output] {
+            assert!(Command::new("mkfifo").arg(fifo).status().unwrap().success());
+        }
+        // nothing has the other ends open yet
+        let mut session = adopt_pipe(&input, &output, Duration::from_secs(1))
+            .await
+            .unwrap();
+        session.write_all(b"id\n").await.unwrap();
+        let mut tool_out = OpenOptions::new().open_receiver(&output).unwrap();
+        let mut tool_in = OpenOptions::new().open_sender(&input).unwrap();
+        tool_in.write_all(b"uid=0(root)\n").await.unwrap();
+        assert_eq!(read_some(&mut session).await, "uid=0(root)\n");
+        let mut buf = [0; 16];
+        let len = tool_out.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"id\n");
+
+        // the writer going away and coming back doesn't end the session
+        drop(tool_in);
+        sleep(PIPE_POLL * 2).await;
+        let mut tool_in = OpenOptions::new().open_sender(&input).unwrap();
+        tool_in.write_all(b"back\n").await.unwrap();
+        assert_eq!(read_some(&mut session).await, "back\n");
+
+        // staying away does
+        drop(tool_in);
+        assert_eq!(read_some(&mut session).await, "");
+        std::fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+}
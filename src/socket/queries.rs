@@ -0,0 +1,265 @@
+use crate::socket::connection::Handle;
+
+/// a sequence still unfinished past this many bytes isn't a query, it's shown as is
+const MAX_HELD: usize = 256;
+
+/// what `answer` mode reports for XTGETTCAP, anything else is reported as unknown
+const CAPABILITIES: &[(&str, &str)] = &[("TN", "xterm-256color"), ("Co", "256"), ("colors", "256")];
+
+/// How queries the remote sends to its terminal are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryMode {
+    /// answered with fixed values and kept off the local terminal
+    Answer,
+    /// shown as they are, the local terminal answers and in raw mode that reaches
+    /// the remote like a typed key
+    Terminal,
+    /// kept off the local terminal and never answered
+    Ignore,
+}
+
+impl QueryMode {
+    pub fn parse(name: &str) -> QueryMode {
+        return match name {
+            "terminal" => QueryMode::Terminal,
+            "ignore" => QueryMode::Ignore,
+            _ => QueryMode::Answer,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TerminalQuery {
+    /// DSR 6, where the cursor is
+    CursorPosition,
+    /// DSR 5, whether the terminal is ok
+    Status,
+    /// DA1
+    PrimaryAttributes,
+    /// DA2, the terminal's type and version
+    SecondaryAttributes,
+    /// XTGETTCAP, the terminfo capabilities asked for, hex encoded as sent
+    Capabilities(Vec<String>),
+}
+
+/// What a CSI sequence with these parameters and final byte asks, None for the
+/// ones that aren't queries
+fn csi_query(params: &[u8], last: u8) -> Option<TerminalQuery> {
+    return match (params, last) {
+        (b"6", b'n') => Some(TerminalQuery::CursorPosition),
+        (b"5", b'n') => Some(TerminalQuery::Status),
+        (b"" | b"0", b'c') => Some(TerminalQuery::PrimaryAttributes),
+        (b">" | b">0", b'c') => Some(TerminalQuery::SecondaryAttributes),
+        _ => None,
+    };
+}
+
+/// How a sequence starting at the front of `bytes` ends
+enum Scan {
+    /// not a query, this many bytes are shown as they are
+    Shown(usize),
+    /// a query this many bytes long
+    Query(usize, TerminalQuery),
+    /// it might be a query once the rest arrives
+    Unfinished,
+}
+
+/// Looks at the escape sequence at the start of `bytes`
+fn scan(bytes: &[u8]) -> Scan {
+    match bytes.get(1) {
+        None => return Scan::Unfinished,
+        Some(b'[') => {
+            let params = bytes[2..]
+                .iter()
+                .take_while(|b| (0x20..=0x3f).contains(*b))
+                .count();
+            return match bytes.get(2 + params) {
+                None => Scan::Unfinished,
+                Some(&last) => match csi_query(&bytes[2..2 + params], last) {
+                    Some(query) => Scan::Query(3 + params, query),
+                    None => Scan::Shown(3 + params),
+                },
+            };
+        }
+        Some(b'P') if b"P+q".starts_with(&bytes[1..bytes.len().min(4)]) => {
+            if bytes.len() < 4 {
+                return Scan::Unfinished;
+            }
+            let end = match bytes[4..].windows(2).position(|pair| pair == b"\x1b\\") {
+                Some(val) => val + 4,
+                None => return Scan::Unfinished,
+            };
+            let names = String::from_utf8_lossy(&bytes[4..end]);
+            let names = names.split(';').map(String::from).collect();
+            return Scan::Query(end + 2, TerminalQuery::Capabilities(names));
+        }
+        Some(_) => return Scan::Shown(1),
+    }
+}
+
+/// Takes the queries out of a session's output as it streams through. A sequence
+/// split across reads is held until the rest of it arrives
+#[derive(Debug, Default)]
+pub struct QueryFilter {
+    held: Vec<u8>,
+}
+
+impl QueryFilter {
+    /// The bytes to show and the queries that were taken out of them
+    pub fn feed(&mut self, chunk: &[u8]) -> (Vec<u8>, Vec<TerminalQuery>) {
+        let mut bytes = std::mem::take(&mut self.held);
+        bytes.extend_from_slice(chunk);
+        let mut shown = Vec::with_capacity(bytes.len());
+        let mut queries = Vec::new();
+        let mut at = 0;
+        while at < bytes.len() {
+            let next = match bytes[at..].iter().position(|b| *b == 0x1b) {
+                Some(val) => at + val,
+                None => {
+                    shown.extend_from_slice(&bytes[at..]);
+                    break;
+                }
+            };
+            shown.extend_from_slice(&bytes[at..next]);
+            match scan(&bytes[next..]) {
+                Scan::Shown(len) => {
+                    shown.extend_from_slice(&bytes[next..next + len]);
+                    at = next + len;
+                }
+                Scan::Query(len, query) => {
+                    queries.push(query);
+                    at = next + len;
+                }
+                Scan::Unfinished if bytes.len() - next > MAX_HELD => {
+                    shown.push(0x1b);
+                    at = next + 1;
+                }
+                Scan::Unfinished => {
+                    self.held = bytes[next..].to_vec();
+                    break;
+                }
+            }
+        }
+        return (shown, queries);
+    }
+}
+
+fn hex(text: &str) -> String {
+    return text.bytes().map(|b| format!("{b:02X}")).collect();
+}
+
+fn unhex(hex: &str) -> Option<String> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect();
+    return String::from_utf8(bytes?).ok();
+}
+
+/// The fixed answer to a query, what a plain xterm with 256 colours would say
+pub fn answer(query: &TerminalQuery) -> Vec<u8> {
+    let answer = match query {
+        TerminalQuery::CursorPosition => String::from("\x1b[1;1R"),
+        TerminalQuery::Status => String::from("\x1b[0n"),
+        TerminalQuery::PrimaryAttributes => String::from("\x1b[?1;2c"),
+        TerminalQuery::SecondaryAttributes => String::from("\x1b[>0;95;0c"),
+        TerminalQuery::Capabilities(names) => names
+            .iter()
+            .map(|name| {
+                let value = unhex(name).and_then(|cap| {
+                    CAPABILITIES
+                        .iter()
+                        .find(|(known, _)| *known == cap)
+                        .map(|(_, value)| hex(value))
+                });
+                match value {
+                    Some(value) => format!("\x1bP1+r{name}={value}\x1b\\"),
+                    None => format!("\x1bP0+r{name}\x1b\\"),
+                }
+            })
+            .collect(),
+    };
+    return answer.into_bytes();
+}
+
+impl Handle {
+    /// Sends the answer to a query down to the remote, ahead of anything typed after it
+    pub(crate) fn answer_query(&self, query: &TerminalQuery) {
+        // nobody attached to write it means nobody is waiting on the answer either
+        self.replies_tx.send(answer(query)).unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the start of vim's output in an xterm, t_RV and the XTGETTCAP for colours
+    const VIM_STARTUP: &[u8] =
+        b"\x1b[?1049h\x1b[22;0;0t\x1b[>4;2m\x1b[?1h\x1b=\x1b[H\x1b[2J\x1b[>c\x1bP+q436f\x1b\\\x1b[?25l~\r\n";
+
+    /// tmux asking its outer terminal what it is before drawing
+    const TMUX_STARTUP: &[u8] = b"\x1b[?1049h\x1b[c\x1b[>c\x1bP+q544e;524742\x1b\\\x1b[6n\x1b[1;1H";
+
+    #[test]
+    fn test_query_filter() {
+        let mut filter = QueryFilter::default();
+        let (shown, queries) = filter.feed(VIM_STARTUP);
+        assert_eq!(
+            shown,
+            b"\x1b[?1049h\x1b[22;0;0t\x1b[>4;2m\x1b[?1h\x1b=\x1b[H\x1b[2J\x1b[?25l~\r\n"
+        );
+        assert_eq!(
+            queries,
+            vec![
+                TerminalQuery::SecondaryAttributes,
+                TerminalQuery::Capabilities(vec![String::from("436f")]),
+            ]
+        );
+
+        // the same bytes a few at a time come out the same
+        let mut filter = QueryFilter::default();
+        let mut shown = Vec::new();
+        let mut queries = Vec::new();
+        for piece in TMUX_STARTUP.chunks(3) {
+            let (bytes, found) = filter.feed(piece);
+            shown.extend(bytes);
+            queries.extend(found);
+        }
+        assert_eq!(shown, b"\x1b[?1049h\x1b[1;1H");
+        assert_eq!(
+            queries,
+            vec![
+                TerminalQuery::PrimaryAttributes,
+                TerminalQuery::SecondaryAttributes,
+                TerminalQuery::Capabilities(vec![String::from("544e"), String::from("524742")]),
+                TerminalQuery::CursorPosition,
+            ]
+        );
+
+        // other DCS strings and a lone escape at the end aren't held up or taken
+        let mut filter = QueryFilter::default();
+        assert_eq!(filter.feed(b"a\x1bPq#0\x1b\\b").0, b"a\x1bPq#0\x1b\\b");
+        assert_eq!(filter.feed(b"c\x1b").0, b"c");
+        assert_eq!(filter.feed(b"[5n").1, vec![TerminalQuery::Status]);
+        let mut long = b"\x1bP+q".to_vec();
+        long.extend(vec![b'4'; MAX_HELD * 2]);
+        assert_eq!(filter.feed(&long).0, long);
+    }
+
+    #[test]
+    fn test_answer() {
+        assert_eq!(answer(&TerminalQuery::CursorPosition), b"\x1b[1;1R");
+        assert_eq!(answer(&TerminalQuery::PrimaryAttributes), b"\x1b[?1;2c");
+        let caps = TerminalQuery::Capabilities(vec![String::from("544e"), String::from("524742")]);
+        assert_eq!(
+            String::from_utf8(answer(&caps)).unwrap(),
+            "\x1bP1+r544e=787465726D2D323536636F6C6F72\x1b\\\x1bP0+r524742\x1b\\"
+        );
+        assert_eq!(QueryMode::parse("ignore"), QueryMode::Ignore);
+        assert_eq!(QueryMode::parse("answer"), QueryMode::Answer);
+    }
+}
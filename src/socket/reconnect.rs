@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::sync::MutexGuard;
+
+use crate::socket::connection::Handle;
+
+/// sessions that closed longer ago than this aren't offered for a restore
+pub const RECONNECT_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// Finds the most recently closed session from the same host as `key`
+pub async fn find_previous_session(
+    shells: &MutexGuard<'_, HashMap<String, Handle>>,
+    key: &str,
+) -> Option<String> {
+    let peer_ip = shells.get(key)?.peer_addr?.ip();
+    let mut previous: Option<(String, Handle)> = None;
+    for (other_key, other) in shells.iter() {
+        if other_key == key || other.peer_addr.map(|addr| addr.ip()) != Some(peer_ip) {
+            continue;
+        }
+        // a session that already carried on elsewhere has nothing left to restore
+        let restored = shells
+            .values()
+            .any(|handle| handle.restored_from.as_deref() == Some(other_key.as_str()));
+        if restored || other.check_alive().await {
+            continue;
+        }
+        let closed_at = match other.closed_at() {
+            Some(val) if val.elapsed() < RECONNECT_WINDOW => val,
+            _ => continue,
+        };
+        let is_newer = match &previous {
+            Some((_, prev)) => prev.closed_at().is_some_and(|prev_at| prev_at < closed_at),
+            None => true,
+        };
+        if is_newer {
+            previous = Some((other_key.clone(), other.clone()));
+        }
+    }
+    return previous.map(|(prev_key, _)| prev_key);
+}
+
+/// Picks a free name for the closed session so the restored one can take over its name
+fn retired_name(shells: &MutexGuard<'_, HashMap<String, Handle>>, key: &str) -> String {
+    let mut n = 1;
+    loop {
+        let name = format!("{key}~{n}");
+        if !shells.contains_key(&name) {
+            return name;
+        }
+        n += 1;
+    }
+}
+
+/// Carries the closed session's name, mode, local directory and queued input over to
+/// the new session, keeping both in the list. Returns the restored session's name
+pub async fn restore_session(
+    shells: &mut MutexGuard<'_, HashMap<String, Handle>>,
+    new_key: &str,
+    old_key: &str,
+) -> Option<String> {
+    let mut new_handle = shells.remove(new_key)?;
+    let old_handle = match shells.remove(old_key) {
+        Some(val) => val,
+        None => {
+            shells.insert(String::from(new_key), new_handle);
+            return None;
+        }
+    };
+
+    new_handle.raw_mode = old_handle.raw_mode;
+    new_handle.max_line_width = old_handle.max_line_width;
+    *new_handle.remote_os.lock().await = *old_handle.remote_os.lock().await;
+    if let Ok(mut dir) = new_handle.local_dir.lock() {
+        *dir = old_handle.local_dir();
+    }
+    {
+        let mut queued = old_handle.input_rx.lock().await;
+        while let Ok((origin, line)) = queued.try_recv() {
+            new_handle.send_input(origin, &line);
+        }
+    }
+
+    let retired = retired_name(shells, old_key);
+    new_handle.restored_from = Some(retired.clone());
+    shells.insert(retired, old_handle);
+    shells.insert(String::from(old_key), new_handle);
+    return Some(String::from(old_key));
+}
+
+/// Restores every live session that looks like a reconnect of a closed one
+pub async fn restore_all(shells: &mut MutexGuard<'_, HashMap<String, Handle>>) -> Vec<String> {
+    let mut restored = Vec::new();
+    let live_keys: Vec<String> = shells
+        .iter()
+        .filter(|(_, handle)| !handle.is_closed() && handle.restored_from.is_none())
+        .map(|(key, _)| key.clone())
+        .collect();
+    for key in live_keys {
+        if let Some(previous) = find_previous_session(shells, &key).await {
+            if let Some(name) = restore_session(shells, &key, &previous).await {
+                restored.push(name);
+            }
+        }
+    }
+    return restored;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::Mutex;
+
+    use super::*;
+    use crate::socket::connection::handle_new_shell;
+    use crate::socket::origin::InputOrigin;
+
+    #[tokio::test]
+    async fn test_restore_session() {
+        let listener = TcpListener::bind("127.0.0.1:32437").await.unwrap();
+        let shells = Arc::new(Mutex::new(HashMap::<String, Handle>::new()));
+
+        let first_client = TcpStream::connect("127.0.0.1:32437").await.unwrap();
+        let (soc, _) = listener.accept().await.unwrap();
+        let first_key = handle_new_shell(soc, shells.clone(), Some(true))
+            .await
+            .unwrap();
+        {
+            let mut guard = shells.lock().await;
+            let first = guard.remove(&first_key).unwrap();
+            let mut first = first;
+            first.raw_mode = true;
+            first.inject_input("whoami");
+            guard.insert(String::from("web01"), first);
+        }
+        drop(first_client);
+
+        let _second_client = TcpStream::connect("127.0.0.1:32437").await.unwrap();
+        let (soc, _) = listener.accept().await.unwrap();
+        let second_key = handle_new_shell(soc, shells.clone(), Some(true))
+            .await
+            .unwrap();
+
+        let mut guard = shells.lock().await;
+        let previous = find_previous_session(&guard, &second_key).await;
+        assert_eq!(previous.as_deref(), Some("web01"));
+        assert_eq!(restore_all(&mut guard).await, vec![String::from("web01")]);
+
+        let restored = guard.get("web01").unwrap();
+        assert!(!restored.is_closed());
+        assert!(restored.raw_mode);
+        assert_eq!(restored.restored_from.as_deref(), Some("web01~1"));
+        assert_eq!(
+            restored.input_rx.lock().await.try_recv().ok(),
+            Some((InputOrigin::Injected, String::from("whoami\n")))
+        );
+        assert!(guard.get("web01~1").unwrap().is_closed());
+        assert_eq!(guard.len(), 2);
+        // nothing left to restore
+        assert!(restore_all(&mut guard).await.is_empty());
+    }
+}
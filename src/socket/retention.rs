@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::socket::connection::Handle;
+
+/// how often closed sessions are swept when nothing else triggers it
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long closed sessions stay in the list before they are pruned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub max_age: Duration,
+    pub max_closed: usize,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        return RetentionPolicy {
+            max_age: Duration::from_secs(60 * 60),
+            max_closed: 20,
+        };
+    }
+}
+
+/// Drops closed sessions that are past the retention policy, oldest first.
+/// Returns the names of the pruned sessions
+pub fn prune_closed(shells: &mut HashMap<String, Handle>, policy: &RetentionPolicy) -> Vec<String> {
+    let mut closed: Vec<(String, Duration)> = shells
+        .iter()
+        // one waiting to be redialed isn't done with yet
+        .filter(|(_, handle)| !handle.is_redialing())
+        .filter_map(|(key, handle)| {
+            handle
+                .closed_at()
+                .map(|closed_at| (key.clone(), closed_at.elapsed()))
+        })
+        .collect();
+    // newest first, so everything past the count limit is the oldest
+    closed.sort_by_key(|(_, age)| *age);
+
+    let mut pruned = Vec::new();
+    for (i, (key, age)) in closed.into_iter().enumerate() {
+        if i >= policy.max_closed || age >= policy.max_age {
+            shells.remove(&key);
+            pruned.push(key);
+        }
+    }
+    return pruned;
+}
+
+/// Removes every closed session right away
+pub fn purge_closed(shells: &mut HashMap<String, Handle>) -> Vec<String> {
+    let closed: Vec<String> = shells
+        .iter()
+        .filter(|(_, handle)| handle.is_closed())
+        .map(|(key, _)| key.clone())
+        .collect();
+    for key in &closed {
+        shells.remove(key);
+    }
+    return closed;
+}
+
+/// Checks detached sessions for remotes that hung up, then applies the policy
+pub async fn sweep(shells: &mut HashMap<String, Handle>, policy: &RetentionPolicy) -> Vec<String> {
+    for handle in shells.values() {
+        handle.check_alive().await;
+    }
+    return prune_closed(shells, policy);
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::socket::close::CloseReason;
+
+    async fn new_handle(listener: &TcpListener, port: u16) -> Handle {
+        let client = tokio::spawn(TcpStream::connect(format!("127.0.0.1:{port}")));
+        let (soc, _) = listener.accept().await.unwrap();
+        client.await.unwrap().unwrap();
+        let (read, write) = soc.into_split();
+        return Handle::new_headless(read, write);
+    }
+
+    #[tokio::test]
+    async fn test_prune_closed() {
+        let listener = TcpListener::bind("127.0.0.1:32438").await.unwrap();
+        let mut shells = HashMap::new();
+        for name in ["live", "closed_first", "closed_second", "closed_last"] {
+            let handle = new_handle(&listener, 32438).await;
+            if name != "live" {
+                handle.mark_closed(CloseReason::RemoteEof);
+            }
+            shells.insert(String::from(name), handle);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        let policy = RetentionPolicy {
+            max_age: Duration::from_secs(60),
+            max_closed: 1,
+        };
+        let mut pruned = prune_closed(&mut shells, &policy);
+        pruned.sort();
+        assert_eq!(
+            pruned,
+            vec![String::from("closed_first"), String::from("closed_second")]
+        );
+        assert!(shells.contains_key("live"));
+
+        let policy = RetentionPolicy {
+            max_age: Duration::ZERO,
+            max_closed: 10,
+        };
+        assert_eq!(
+            prune_closed(&mut shells, &policy),
+            vec![String::from("closed_last")]
+        );
+        assert!(purge_closed(&mut shells).is_empty());
+        assert_eq!(shells.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sweep_notices_hangups() {
+        let listener = TcpListener::bind("127.0.0.1:32439").await.unwrap();
+        let client = tokio::spawn(TcpStream::connect("127.0.0.1:32439"));
+        let (soc, _) = listener.accept().await.unwrap();
+        drop(client.await.unwrap().unwrap());
+        let (read, write) = soc.into_split();
+        let mut shells = HashMap::new();
+        shells.insert(String::from("gone"), Handle::new_headless(read, write));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        sweep(&mut shells, &RetentionPolicy::default()).await;
+        assert!(shells.get("gone").unwrap().is_closed());
+        assert_eq!(purge_closed(&mut shells), vec![String::from("gone")]);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::socket::connection::Handle;
+use crate::socket::listener::{AuthDecision, ConnInfo};
+
+/// enough for the record header and the handshake type after it
+const SNIFF_BYTES: usize = 6;
+
+/// how an HTTP request line starts, the method and the space after it
+const HTTP_METHODS: &[&str] = &[
+    "GET ", "POST ", "HEAD ", "PUT ", "DELETE ", "OPTIONS ", "PATCH ", "CONNECT ", "TRACE ", "PRI ",
+];
+
+/// What a new connection's first bytes look like
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// a TLS ClientHello, a stager that expected a TLS listener
+    Tls,
+    /// anything else, or nothing before the timeout, the way crab_trap always took it
+    Plain,
+}
+
+/// A TLS record starts with the handshake content type and a 3.x version, and a
+/// ClientHello is handshake type 1
+pub fn classify(bytes: &[u8]) -> Protocol {
+    let is_tls = match bytes {
+        [0x16, 0x03, minor, _, _, kind, ..] => *minor <= 0x04 && *kind == 0x01,
+        // short of the handshake type, the header alone has to do
+        [0x16, 0x03, minor, ..] => *minor <= 0x04,
+        _ => false,
+    };
+    return match is_tls {
+        true => Protocol::Tls,
+        false => Protocol::Plain,
+    };
+}
+
+/// What a connection seems to speak, from the first bytes it sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProtocolHint {
+    #[default]
+    Shell,
+    Http,
+    Ssh,
+    Tls,
+    /// control characters and NULs a shell wouldn't print
+    UnknownBinary,
+}
+
+impl ProtocolHint {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ProtocolHint::Shell => "shell",
+            ProtocolHint::Http => "http",
+            ProtocolHint::Ssh => "ssh",
+            ProtocolHint::Tls => "tls",
+            ProtocolHint::UnknownBinary => "unknown-binary",
+        };
+    }
+
+    /// None for `auto` and anything else that isn't a protocol
+    pub fn parse(name: &str) -> Option<ProtocolHint> {
+        return match name {
+            "shell" => Some(ProtocolHint::Shell),
+            "http" => Some(ProtocolHint::Http),
+            "ssh" => Some(ProtocolHint::Ssh),
+            "tls" => Some(ProtocolHint::Tls),
+            "unknown-binary" => Some(ProtocolHint::UnknownBinary),
+            _ => None,
+        };
+    }
+}
+
+/// A guess from the first bytes, a shell unless they look like something else. Plenty
+/// of shells send nothing until spoken to, and prompts can be full of escapes
+pub fn guess_protocol(bytes: &[u8]) -> ProtocolHint {
+    if classify(bytes) == Protocol::Tls {
+        return ProtocolHint::Tls;
+    }
+    if bytes.starts_with(b"SSH-") {
+        return ProtocolHint::Ssh;
+    }
+    if HTTP_METHODS
+        .iter()
+        .any(|method| bytes.starts_with(method.as_bytes()))
+    {
+        return ProtocolHint::Http;
+    }
+    let control = bytes
+        .iter()
+        .filter(|b| (**b < 0x20 && !b"\t\r\n\x07\x08\x1b".contains(b)) || **b == 0x7f)
+        .count();
+    // more than a quarter, a stray ctrl character in a prompt isn't enough
+    if bytes.contains(&0) || control * 4 > bytes.len() {
+        return ProtocolHint::UnknownBinary;
+    }
+    return ProtocolHint::Shell;
+}
+
+impl Handle {
+    /// What the session speaks, `set protocol` wins over the guess from its first bytes
+    pub fn protocol(&self) -> ProtocolHint {
+        return self.protocol_override.unwrap_or(self.sniffed_protocol);
+    }
+}
+
+/// Whether these first bytes could still turn out to be a ClientHello
+fn could_be_tls(bytes: &[u8]) -> bool {
+    let header = [Some(0x16), Some(0x03), None];
+    return bytes.iter().zip(header).all(|(byte, want)| match want {
+        Some(want) => *byte == want,
+        None => *byte <= 0x04,
+    });
+}
+
+/// The TLS check as an authenticator, a ClientHello is turned away and anything
+/// else, or nothing by the end of the window, is a plain shell
+pub fn reject_tls(info: ConnInfo, bytes: &[u8]) -> AuthDecision {
+    if !info.window_closed && bytes.len() < SNIFF_BYTES && could_be_tls(bytes) {
+        return AuthDecision::Defer;
+    }
+    return match classify(bytes) {
+        Protocol::Tls => AuthDecision::Reject(String::new()),
+        Protocol::Plain => AuthDecision::Accept(None),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::listener::Listener;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::time::{sleep, Instant};
+
+    #[test]
+    fn test_classify() {
+        let hello = [
+            0x16, 0x03, 0x01, 0x02, 0x00, 0x01, 0x00, 0x01, 0xfc, 0x03, 0x03,
+        ];
+        assert_eq!(classify(&hello), Protocol::Tls);
+        assert_eq!(classify(&hello[..3]), Protocol::Tls);
+        // a server hello isn't something a stager sends
+        assert_eq!(
+            classify(&[0x16, 0x03, 0x03, 0x00, 0x5a, 0x02]),
+            Protocol::Plain
+        );
+        assert_eq!(classify(b"bash-5.1$ "), Protocol::Plain);
+        assert_eq!(classify(b""), Protocol::Plain);
+        assert!(could_be_tls(&[0x16]));
+        assert!(!could_be_tls(b"$ "));
+    }
+
+    #[test]
+    fn test_guess_protocol() {
+        assert_eq!(
+            guess_protocol(b"GET / HTTP/1.1\r\nHost: 10.0.0.1\r\n\r\n"),
+            ProtocolHint::Http
+        );
+        assert_eq!(
+            guess_protocol(b"SSH-2.0-OpenSSH_9.6\r\n"),
+            ProtocolHint::Ssh
+        );
+        assert_eq!(
+            guess_protocol(&[0x16, 0x03, 0x01, 0x02, 0x00, 0x01]),
+            ProtocolHint::Tls
+        );
+        assert_eq!(
+            guess_protocol(b"\x00\x00\x00\x2c\xff\x53\x4d\x42"),
+            ProtocolHint::UnknownBinary
+        );
+        assert_eq!(
+            guess_protocol(b"\x1b]0;root@box\x07\x1b[01;31mroot@box\x1b[0m:~# "),
+            ProtocolHint::Shell
+        );
+        // a shell's own name for its prompt isn't a request
+        assert_eq!(guess_protocol(b"GETTY> "), ProtocolHint::Shell);
+        assert_eq!(guess_protocol(b""), ProtocolHint::Shell);
+        assert_eq!(
+            ProtocolHint::parse("unknown-binary"),
+            Some(ProtocolHint::UnknownBinary)
+        );
+        assert_eq!(ProtocolHint::parse("auto"), None);
+    }
+
+    #[tokio::test]
+    async fn test_reject_tls() {
+        let mut listener = Listener::bind("127.0.0.1", 32476).await.unwrap();
+        let wait = Duration::from_millis(300);
+        listener.set_auth_window(wait);
+        listener.set_authenticator(reject_tls);
+
+        // split over two writes, the header arrives before the handshake type
+        let hello = tokio::spawn(async {
+            let mut soc = TcpStream::connect("127.0.0.1:32476").await.unwrap();
+            soc.write_all(&[0x16, 0x03]).await.unwrap();
+            sleep(Duration::from_millis(50)).await;
+            soc.write_all(&[0x01, 0x02, 0x00, 0x01]).await.unwrap();
+            soc
+        });
+        let mut hello = hello.await.unwrap();
+
+        // nothing is taken from a plain shell
+        let client = tokio::spawn(async {
+            let mut soc = TcpStream::connect("127.0.0.1:32476").await.unwrap();
+            soc.write_all(b"$ ").await.unwrap();
+            soc
+        });
+        let mut accepted = listener.accept().await.unwrap();
+        let mut prompt = [0u8; 2];
+        assert!(!accepted.first_bytes.is_empty());
+        assert!(b"$ ".starts_with(&accepted.first_bytes));
+        accepted.soc.read_exact(&mut prompt).await.unwrap();
+        assert_eq!(&prompt, b"$ ");
+        let _client = client.await.unwrap();
+        // the handshake was hung up on
+        assert_eq!(hello.read(&mut prompt).await.unwrap(), 0);
+
+        // a client that waits to be spoken to is plain once the wait is up
+        let client = tokio::spawn(TcpStream::connect("127.0.0.1:32476"));
+        let started = Instant::now();
+        let _accepted = listener.accept().await.unwrap();
+        assert!(started.elapsed() >= wait);
+        let _client = client.await.unwrap();
+    }
+}
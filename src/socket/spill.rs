@@ -0,0 +1,171 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tokio::fs::{self, File};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+
+use crate::config::ephemeral::is_ephemeral;
+use crate::socket::connection::Handle;
+
+/// output kept in memory for a detached session before older output spills to disk
+pub const PENDING_MEMORY_CAP: usize = 1024 * 1024;
+
+enum SpillMessage {
+    Write(Vec<u8>),
+    /// read back everything spilled so far and start over
+    Drain(oneshot::Sender<Vec<u8>>),
+}
+
+/// Keeps overflowing session output on disk, writes happen on their own task so the
+/// socket reader never waits on the disk. The files are removed once every handle
+/// to the session is gone
+#[derive(Clone)]
+pub struct SpillFile {
+    tx: UnboundedSender<SpillMessage>,
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".old");
+    return PathBuf::from(rotated);
+}
+
+async fn open_spill(path: &Path) -> io::Result<BufWriter<File>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    return Ok(BufWriter::new(File::create(path).await?));
+}
+
+/// Reads a spill file, a missing one just means nothing spilled
+async fn read_spill(path: &Path) -> Vec<u8> {
+    return fs::read(path).await.unwrap_or_default();
+}
+
+/// Writes spilled chunks to `path`, moving it to `path.old` once it holds half
+/// of `max_bytes` so about `max_bytes` of the newest output is kept
+async fn run_spill(path: PathBuf, max_bytes: u64, mut rx: UnboundedReceiver<SpillMessage>) {
+    let rotated = rotated_path(&path);
+    let mut writer = open_spill(&path).await.ok();
+    let mut written: u64 = 0;
+    while let Some(message) = rx.recv().await {
+        match message {
+            SpillMessage::Write(chunk) => {
+                let file = match writer.as_mut() {
+                    Some(val) => val,
+                    None => continue,
+                };
+                if file.write_all(&chunk).await.is_err() {
+                    continue;
+                }
+                written += chunk.len() as u64;
+                if written >= max_bytes / 2 {
+                    file.flush().await.unwrap_or_default();
+                    fs::rename(&path, &rotated).await.unwrap_or_default();
+                    writer = open_spill(&path).await.ok();
+                    written = 0;
+                }
+            }
+            SpillMessage::Drain(reply) => {
+                if let Some(file) = writer.as_mut() {
+                    file.flush().await.unwrap_or_default();
+                }
+                let mut spilled = read_spill(&rotated).await;
+                spilled.extend(read_spill(&path).await);
+                fs::remove_file(&rotated).await.unwrap_or_default();
+                writer = open_spill(&path).await.ok();
+                written = 0;
+                reply.send(spilled).unwrap_or_default();
+            }
+        }
+    }
+    drop(writer);
+    fs::remove_file(&rotated).await.unwrap_or_default();
+    fs::remove_file(&path).await.unwrap_or_default();
+}
+
+impl SpillFile {
+    pub fn new(path: PathBuf, max_bytes: u64) -> SpillFile {
+        let (tx, rx) = unbounded_channel::<SpillMessage>();
+        tokio::spawn(run_spill(path, max_bytes, rx));
+        return SpillFile { tx };
+    }
+
+    /// Queues a chunk to be written, never blocks
+    pub fn write(&self, chunk: Vec<u8>) {
+        self.tx.send(SpillMessage::Write(chunk)).unwrap_or_default();
+    }
+
+    /// Everything spilled since the last drain, oldest first
+    pub async fn drain(&self) -> Vec<u8> {
+        let (reply, rx) = oneshot::channel::<Vec<u8>>();
+        if self.tx.send(SpillMessage::Drain(reply)).is_err() {
+            return Vec::new();
+        }
+        return rx.await.unwrap_or_default();
+    }
+}
+
+impl Handle {
+    /// Sends output that overflows the in-memory buffer to `path` instead of dropping it.
+    /// In ephemeral mode the overflow is dropped
+    pub fn spill_to(&mut self, path: PathBuf, max_bytes: u64) {
+        if is_ephemeral() {
+            return;
+        }
+        self.spill = Some(SpillFile::new(path, max_bytes));
+    }
+}
+
+/// Trims the in-memory buffer to the cap, returning the oldest bytes that didn't fit
+pub fn take_overflow(pending: &mut Vec<u8>, cap: usize) -> Option<Vec<u8>> {
+    if pending.len() <= cap {
+        return None;
+    }
+    return Some(pending.drain(..pending.len() - cap).collect());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_take_overflow() {
+        let mut pending = b"abcdef".to_vec();
+        assert_eq!(take_overflow(&mut pending, 10), None);
+        assert_eq!(take_overflow(&mut pending, 4), Some(b"ab".to_vec()));
+        assert_eq!(pending, b"cdef".to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_spill_file() {
+        let dir = std::env::temp_dir().join("crab_trap_test_spill");
+        std::fs::remove_dir_all(&dir).unwrap_or_default();
+        let path = dir.join("session.out");
+
+        let spill = SpillFile::new(path.clone(), 8);
+        spill.write(b"ab".to_vec());
+        assert_eq!(spill.drain().await, b"ab".to_vec());
+        assert_eq!(spill.drain().await, Vec::<u8>::new());
+
+        // rotating at half the cap keeps the newest output and drops the oldest
+        for chunk in ["1111", "2222", "33"] {
+            spill.write(chunk.as_bytes().to_vec());
+        }
+        assert_eq!(spill.drain().await, b"222233".to_vec());
+
+        drop(spill);
+        for _ in 0..50 {
+            if !path.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!path.exists());
+        assert!(!rotated_path(&path).exists());
+        std::fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+}
@@ -0,0 +1,55 @@
+use crate::socket::connection::Handle;
+
+/// Cleans up a tag as typed, tags are single lowercase words
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() || tag.contains(char::is_whitespace) {
+        return None;
+    }
+    return Some(tag);
+}
+
+impl Handle {
+    /// Adds the tag, or takes it off if the session already has it. Returns whether
+    /// the session has it now
+    pub fn toggle_tag(&self, tag: &str) -> bool {
+        let mut tags = match self.tags.lock() {
+            Ok(val) => val,
+            Err(_) => return false,
+        };
+        if let Some(idx) = tags.iter().position(|have| have == tag) {
+            tags.remove(idx);
+            return false;
+        }
+        tags.push(String::from(tag));
+        return true;
+    }
+
+    /// The session's tags in the order they were added
+    pub fn tags(&self) -> Vec<String> {
+        return match self.tags.lock() {
+            Ok(tags) => tags.clone(),
+            Err(_) => Vec::new(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+
+    #[tokio::test]
+    async fn test_toggle_tag() {
+        assert_eq!(normalize_tag(" DC01 "), Some(String::from("dc01")));
+        assert_eq!(normalize_tag("two words"), None);
+        assert_eq!(normalize_tag(""), None);
+
+        let handle = spawn_shell_session(32480).await;
+        assert!(handle.toggle_tag("dc01"));
+        assert!(handle.toggle_tag("pivot"));
+        // clones share them
+        assert!(!handle.clone().toggle_tag("dc01"));
+        assert_eq!(handle.tags(), vec![String::from("pivot")]);
+    }
+}
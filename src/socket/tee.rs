@@ -0,0 +1,328 @@
+use std::fmt::{self, Display};
+use std::fs::OpenOptions;
+use std::io;
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::process::Command;
+use tokio::select;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::config::ephemeral::check_write;
+use crate::input::input::display_notification;
+use crate::socket::connection::Handle;
+use crate::socket::local_dir::resolve;
+
+pub const TEE_USAGE: &str = "ltee <path> | ltee !<command> | ltee off [<id>] | ltee";
+
+/// chunks held for a target that's slow to take them, past this it's dropped
+const TEE_BACKLOG: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TeeTarget {
+    /// appended to, relative paths are from the session's local directory
+    File(PathBuf),
+    /// run with `sh -c` in the local directory, the output goes on its stdin
+    Command(String),
+}
+
+impl Display for TeeTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            TeeTarget::File(path) => write!(f, "{}", path.display()),
+            TeeTarget::Command(cmd) => write!(f, "!{cmd}"),
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TeeAction {
+    Start(TeeTarget),
+    /// one tee by id, or all of them
+    Off(Option<usize>),
+    List,
+}
+
+pub fn parse_tee_args(args: &str) -> Result<TeeAction, String> {
+    let args = args.trim();
+    if args.is_empty() {
+        return Ok(TeeAction::List);
+    }
+    if let Some(rest) = args.strip_prefix("off") {
+        let rest = rest.trim();
+        if rest.is_empty() {
+            return Ok(TeeAction::Off(None));
+        }
+        // a file called offsite.log is still a file
+        if let Ok(id) = rest.parse() {
+            return Ok(TeeAction::Off(Some(id)));
+        }
+        if !args.starts_with("off ") {
+            return Ok(TeeAction::Start(TeeTarget::File(PathBuf::from(args))));
+        }
+        return Err(format!("{rest} isn't a tee id"));
+    }
+    return match args.strip_prefix('!') {
+        Some(cmd) if cmd.trim().is_empty() => Err(String::from("missing a command")),
+        Some(cmd) => Ok(TeeAction::Start(TeeTarget::Command(String::from(
+            cmd.trim(),
+        )))),
+        None => Ok(TeeAction::Start(TeeTarget::File(PathBuf::from(args)))),
+    };
+}
+
+/// A copy of the session's output on its way to a file or process
+pub(crate) struct Tee {
+    pub id: usize,
+    pub target: TeeTarget,
+    tx: Sender<Vec<u8>>,
+}
+
+#[derive(Default)]
+pub(crate) struct Tees {
+    next_id: usize,
+    pub active: Vec<Tee>,
+}
+
+/// Writes everything sent until the tee is turned off
+async fn drain<W>(mut out: W, rx: &mut Receiver<Vec<u8>>) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    while let Some(chunk) = rx.recv().await {
+        out.write_all(&chunk).await?;
+        out.flush().await?;
+    }
+    return Ok(());
+}
+
+impl Handle {
+    /// Starts copying everything the remote sends from now on to `target`, returning
+    /// the tee's id. Opening the target fails here, later failures stop just this tee
+    pub fn start_tee(&self, target: TeeTarget) -> Result<usize, String> {
+        let (tx, mut rx) = channel::<Vec<u8>>(TEE_BACKLOG);
+        let local_dir = self.local_dir();
+        let target = match target {
+            TeeTarget::File(path) => TeeTarget::File(resolve(&local_dir, &path.to_string_lossy())),
+            command => command,
+        };
+        let writer = match &target {
+            TeeTarget::File(path) => {
+                check_write("A tee to a file").map_err(|err| err.to_string())?;
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|err| format!("Couldn't open {}: {err}", path.display()))?;
+                let file = tokio::fs::File::from_std(file);
+                tokio::spawn(async move { drain(file, &mut rx).await })
+            }
+            TeeTarget::Command(cmd) => {
+                let mut child = Command::new("sh")
+                    .arg("-c")
+                    .arg(cmd)
+                    .current_dir(&local_dir)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .map_err(|err| format!("Couldn't run {cmd}: {err}"))?;
+                let stdin = child.stdin.take();
+                tokio::spawn(async move {
+                    let stdin = match stdin {
+                        Some(val) => val,
+                        None => return Err(io::Error::other("no stdin to write to")),
+                    };
+                    select! {
+                        drained = drain(stdin, &mut rx) => drained?,
+                        status = child.wait() => {
+                            return Err(io::Error::other(format!("exited with {}", status?)));
+                        }
+                    }
+                    // turned off, the closed stdin lets the command finish on its own
+                    child.wait().await?;
+                    return Ok(());
+                })
+            }
+        };
+        let mut tees = self.tees.lock().map_err(|err| err.to_string())?;
+        tees.next_id += 1;
+        let id = tees.next_id;
+        tees.active.push(Tee {
+            id,
+            target: target.clone(),
+            tx,
+        });
+        let registry = self.tees.clone();
+        tokio::spawn(async move {
+            let err = match writer.await {
+                Ok(Ok(_)) => return,
+                Ok(Err(err)) => err.to_string(),
+                Err(err) => err.to_string(),
+            };
+            if let Ok(mut tees) = registry.lock() {
+                tees.active.retain(|tee| tee.id != id);
+            }
+            display_notification(format!("tee {id} to {target} stopped: {err}"));
+        });
+        return Ok(id);
+    }
+
+    /// Turns off one tee, or all of them with no id
+    pub fn stop_tee(&self, id: Option<usize>) -> String {
+        let mut tees = match self.tees.lock() {
+            Ok(val) => val,
+            Err(_) => return String::from("No tees"),
+        };
+        let before = tees.active.len();
+        tees.active.retain(|tee| id.is_some_and(|id| tee.id != id));
+        return match (before - tees.active.len(), id) {
+            (0, Some(id)) => format!("No tee {id}"),
+            (0, None) => String::from("No tees"),
+            (_, Some(id)) => format!("Stopped tee {id}"),
+            (n, None) => format!("Stopped {n} tees"),
+        };
+    }
+
+    /// The active tees' ids and targets
+    pub fn tees(&self) -> Vec<(usize, String)> {
+        return match self.tees.lock() {
+            Ok(tees) => tees
+                .active
+                .iter()
+                .map(|tee| (tee.id, tee.target.to_string()))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+    }
+
+    /// Hands received bytes to each tee. A tee whose target has fallen too far behind
+    /// is dropped rather than holding up the session
+    pub(crate) fn tee_output(&self, data: &[u8]) {
+        let mut behind = Vec::new();
+        if let Ok(mut tees) = self.tees.lock() {
+            tees.active
+                .retain(|tee| match tee.tx.try_send(data.to_vec()) {
+                    Ok(_) => true,
+                    Err(TrySendError::Full(_)) => {
+                        behind.push(format!(
+                            "tee {} to {} fell behind and was stopped",
+                            tee.id, tee.target
+                        ));
+                        false
+                    }
+                    // its writer already gave up and says why
+                    Err(TrySendError::Closed(_)) => false,
+                });
+        }
+        for notice in behind {
+            display_notification(notice);
+        }
+    }
+
+    /// What the `ltee` session command prints
+    pub fn tee_command(&self, action: TeeAction) -> String {
+        return match action {
+            TeeAction::Start(target) => match self.start_tee(target.clone()) {
+                Ok(id) => format!("tee {id} started, `ltee off {id}` stops it"),
+                Err(err) => err,
+            },
+            TeeAction::Off(id) => self.stop_tee(id),
+            TeeAction::List => {
+                let tees = self.tees();
+                if tees.is_empty() {
+                    return String::from("No tees");
+                }
+                let lines: Vec<String> = tees
+                    .iter()
+                    .map(|(id, target)| format!("{id:<4}{target}"))
+                    .collect();
+                lines.join("\n")
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock_shell::spawn_shell_session;
+    use std::fs;
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    #[test]
+    fn test_parse_tee_args() {
+        assert_eq!(parse_tee_args(""), Ok(TeeAction::List));
+        assert_eq!(
+            parse_tee_args("out.log"),
+            Ok(TeeAction::Start(TeeTarget::File(PathBuf::from("out.log"))))
+        );
+        assert_eq!(
+            parse_tee_args("!grep --line-buffered root"),
+            Ok(TeeAction::Start(TeeTarget::Command(String::from(
+                "grep --line-buffered root"
+            ))))
+        );
+        assert_eq!(parse_tee_args("off"), Ok(TeeAction::Off(None)));
+        assert_eq!(parse_tee_args("off 2"), Ok(TeeAction::Off(Some(2))));
+        assert_eq!(
+            parse_tee_args("offsite.log"),
+            Ok(TeeAction::Start(TeeTarget::File(PathBuf::from(
+                "offsite.log"
+            ))))
+        );
+        assert!(parse_tee_args("off two").is_err());
+        assert!(parse_tee_args("!").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tees() {
+        let handle = spawn_shell_session(32478).await;
+        let dir = std::env::temp_dir().join("crab_trap_test_tee");
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        fs::create_dir_all(&dir).unwrap();
+        let dir = handle.change_local_dir(&dir.display().to_string()).unwrap();
+
+        let to_file = handle
+            .start_tee(TeeTarget::File(PathBuf::from("a.log")))
+            .unwrap();
+        let to_cmd = handle
+            .start_tee(TeeTarget::Command(String::from("cat > b.log")))
+            .unwrap();
+        // exits at once, it's detached without touching the others
+        handle
+            .start_tee(TeeTarget::Command(String::from("exit 3")))
+            .unwrap();
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(handle.tees().len(), 2);
+
+        let raw = b"\x1b[1mroot\x1b[0m\r\n\xff\xfe";
+        handle.publish_output(raw);
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            handle.stop_tee(Some(to_cmd)),
+            format!("Stopped tee {to_cmd}")
+        );
+        handle.publish_output(b"more\n");
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(fs::read(dir.join("b.log")).unwrap(), raw);
+        let mut both = raw.to_vec();
+        both.extend_from_slice(b"more\n");
+        assert_eq!(fs::read(dir.join("a.log")).unwrap(), both);
+
+        assert_eq!(
+            handle.tees(),
+            vec![(to_file, dir.join("a.log").display().to_string())]
+        );
+        assert_eq!(handle.stop_tee(None), "Stopped 1 tees");
+        assert_eq!(handle.stop_tee(Some(to_file)), format!("No tee {to_file}"));
+        assert!(handle
+            .start_tee(TeeTarget::File(PathBuf::from("missing/c.log")))
+            .is_err());
+        fs::remove_dir_all(&dir).unwrap_or_default();
+    }
+}
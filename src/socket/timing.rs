@@ -0,0 +1,186 @@
+use std::time::{Duration, Instant};
+
+use crate::menu::output::{tail, PROMPT_WINDOW};
+use crate::socket::connection::Handle;
+use crate::socket::transcript::looks_like_prompt;
+
+/// Times line mode commands from when they're sent until the remote's next prompt
+#[derive(Debug, Default)]
+pub struct CommandTimer {
+    started: Option<Instant>,
+    /// another command went out before this one's prompt came back
+    overlapped: bool,
+    /// the last command started a program that reads lines of its own, like python
+    interactive: bool,
+    last_line: String,
+}
+
+/// programs that take the session over with a prompt of their own
+const INTERACTIVE_PROGRAMS: &[&str] = &[
+    "bc",
+    "ftp",
+    "gdb",
+    "ipython",
+    "irb",
+    "lua",
+    "mongo",
+    "mysql",
+    "nc",
+    "node",
+    "perl",
+    "php",
+    "psql",
+    "python",
+    "python2",
+    "python3",
+    "redis-cli",
+    "sftp",
+    "sqlite3",
+    "ssh",
+    "telnet",
+];
+
+/// Whether a command line starts one of the programs above
+pub fn starts_interactive(line: &str) -> bool {
+    let program = match line.split_whitespace().next() {
+        Some(word) => word.rsplit('/').next().unwrap_or(word),
+        None => return false,
+    };
+    return INTERACTIVE_PROGRAMS.contains(&program);
+}
+
+impl CommandTimer {
+    pub fn command(&mut self, now: Instant, line: &str) {
+        self.overlapped = self.started.is_some();
+        self.started = Some(now);
+        self.interactive = starts_interactive(line);
+        self.last_line.clear();
+    }
+
+    /// A command went out and its prompt hasn't come back yet
+    pub fn running(&self) -> bool {
+        return self.started.is_some();
+    }
+
+    /// The last line the remote printed, when it looks like it's waiting for input
+    pub fn prompt(&self) -> Option<&str> {
+        if self.running() || !looks_like_prompt(&self.last_line) {
+            return None;
+        }
+        return Some(&self.last_line);
+    }
+
+    pub fn interactive(&self) -> bool {
+        return self.interactive;
+    }
+
+    /// How long the running command took once a prompt ends it. Nothing when there's
+    /// no telling which command the prompt belongs to
+    pub fn output(&mut self, chunk: &str, now: Instant) -> Option<Duration> {
+        let chunk = chunk.replace('\r', "");
+        match chunk.rsplit_once('\n') {
+            Some((_, last)) => self.last_line = String::from(last),
+            None => self.last_line += &chunk,
+        }
+        self.last_line = String::from(tail(&self.last_line, PROMPT_WINDOW));
+        if !looks_like_prompt(&self.last_line) {
+            return None;
+        }
+        let started = self.started.take()?;
+        if std::mem::take(&mut self.overlapped) {
+            return None;
+        }
+        return Some(now.saturating_duration_since(started));
+    }
+}
+
+/// Like `[took 4.2s]` or `[took 2m05s]`
+pub fn format_took(took: Duration) -> String {
+    let secs = took.as_secs();
+    if secs < 60 {
+        return format!("[took {:.1}s]", took.as_secs_f64());
+    }
+    return format!("[took {}m{:02}s]", secs / 60, secs % 60);
+}
+
+impl Handle {
+    /// Starts timing a command that was just sent in line mode
+    pub fn time_command(&self, line: &str) {
+        if let Ok(mut timer) = self.timer.lock() {
+            timer.command(Instant::now(), line);
+        }
+    }
+
+    /// Whether the last line mode command is still going, or its shell has no prompt
+    /// to tell
+    pub fn command_running(&self) -> bool {
+        return self.timer.lock().is_ok_and(|timer| timer.running());
+    }
+
+    pub fn time_output(&self, chunk: &str) -> Option<Duration> {
+        return match self.timer.lock() {
+            Ok(mut timer) => timer.output(chunk, Instant::now()),
+            Err(_) => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_timer() {
+        let now = Instant::now();
+        let mut timer = CommandTimer::default();
+        // no command sent, the first prompt isn't timed
+        assert_eq!(timer.output("user@box:~$ ", now), None);
+
+        timer.command(now, "sleep 4");
+        assert!(timer.running());
+        assert_eq!(timer.output("sleep 4\r\n", now), None);
+        assert_eq!(
+            timer.output("user@box:~", now + Duration::from_millis(4200)),
+            None
+        );
+        // the prompt split over two reads
+        assert_eq!(
+            timer.output("$ ", now + Duration::from_millis(4200)),
+            Some(Duration::from_millis(4200))
+        );
+        assert!(!timer.running());
+
+        // typed ahead before the prompt came back, the split is a guess
+        timer.command(now, "ls");
+        timer.command(now + Duration::from_secs(1), "ls");
+        assert_eq!(timer.output("done\nuser@box:~$ ", now), None);
+        timer.command(now, "ls");
+        assert_eq!(
+            timer.output("ok\nuser@box:~$ ", now + Duration::from_secs(1)),
+            Some(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn test_starts_interactive() {
+        assert!(starts_interactive("python3"));
+        assert!(starts_interactive("  /usr/bin/mysql -u root"));
+        assert!(!starts_interactive("ls -la"));
+        assert!(!starts_interactive("pythonista"));
+        assert!(!starts_interactive(""));
+
+        let mut timer = CommandTimer::default();
+        timer.command(Instant::now(), "python3");
+        assert_eq!(timer.prompt(), None);
+        timer.output("Python 3.11\n>>> ", Instant::now());
+        assert!(timer.interactive());
+        assert_eq!(timer.prompt(), Some(">>> "));
+    }
+
+    #[test]
+    fn test_format_took() {
+        assert_eq!(format_took(Duration::from_millis(4230)), "[took 4.2s]");
+        assert_eq!(format_took(Duration::from_millis(50)), "[took 0.1s]");
+        assert_eq!(format_took(Duration::from_secs(125)), "[took 2m05s]");
+    }
+}
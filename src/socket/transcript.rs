@@ -0,0 +1,676 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use crate::config::ephemeral::check_write;
+use crate::socket::connection::Handle;
+use crate::socket::history::now_secs;
+use crate::socket::origin::InputOrigin;
+
+/// endings that make the last line of a chunk look like a shell prompt
+const PROMPT_ENDINGS: [&str; 4] = ["$", "#", ">", "%"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordKind {
+    Command,
+    Output,
+    Note,
+}
+
+/// One line of a structured transcript
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TranscriptRecord {
+    pub seq: u64,
+    pub ts: u64,
+    pub kind: RecordKind,
+    pub session: String,
+    pub text: String,
+    /// seq of the command this output answers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<u64>,
+    /// the split between command and output was a guess
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub low_confidence: bool,
+    /// how long the command this output answers took to run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// for commands, where the input came from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<InputOrigin>,
+    /// the exit status of the framed command this output answers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_status: Option<i32>,
+}
+
+/// Whether the last line of some output looks like the remote waiting for input
+pub fn looks_like_prompt(line: &str) -> bool {
+    let line = line.trim_end();
+    return !line.is_empty() && PROMPT_ENDINGS.iter().any(|end| line.ends_with(end));
+}
+
+/// output held waiting for a prompt past this is written out as a guess
+const MAX_SEGMENT_BYTES: usize = 1024 * 1024;
+
+/// Splits what was typed and what came back into command and output records
+pub struct Segmenter {
+    session: String,
+    next_seq: u64,
+    /// the command still waiting on its output
+    last_command: Option<(u64, String)>,
+    output: String,
+    /// where the last line of `output` starts, so a chunk only has to be searched once
+    line_start: usize,
+}
+
+impl Segmenter {
+    pub fn new(session: &str) -> Segmenter {
+        return Segmenter {
+            session: String::from(session),
+            next_seq: 0,
+            last_command: None,
+            output: String::new(),
+            line_start: 0,
+        };
+    }
+
+    fn record(
+        &mut self,
+        kind: RecordKind,
+        text: &str,
+        reply_to: Option<u64>,
+        low_confidence: bool,
+    ) -> TranscriptRecord {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        return TranscriptRecord {
+            seq,
+            ts: now_secs(),
+            kind,
+            session: self.session.clone(),
+            text: String::from(text),
+            reply_to,
+            low_confidence,
+            duration_ms: None,
+            origin: None,
+            exit_status: None,
+        };
+    }
+
+    /// Turns the buffered output into a record, `confident` when a prompt ended it
+    fn flush_output(
+        &mut self,
+        confident: bool,
+        took: Option<Duration>,
+    ) -> Option<TranscriptRecord> {
+        let mut output = std::mem::take(&mut self.output);
+        self.line_start = 0;
+        let command = self.last_command.take();
+        // a tty echoes the command back before its output
+        if let Some((_, text)) = &command {
+            if let Some((first, rest)) = output.split_once('\n') {
+                if !text.is_empty() && first.trim_end().ends_with(text.as_str()) {
+                    output = String::from(rest);
+                }
+            }
+        }
+        if output.trim().is_empty() {
+            return None;
+        }
+        let reply_to = command.as_ref().map(|(seq, _)| *seq);
+        // output nobody asked for can't be paired with anything
+        let low_confidence = !confident || reply_to.is_none();
+        let mut record = self.record(RecordKind::Output, &output, reply_to, low_confidence);
+        if !low_confidence {
+            record.duration_ms = took.map(|took| took.as_millis() as u64);
+        }
+        return Some(record);
+    }
+
+    /// A line the operator sent to the remote
+    pub fn command(&mut self, text: &str, origin: InputOrigin) -> Vec<TranscriptRecord> {
+        let text = text.trim_end_matches(['\r', '\n']);
+        let mut records = Vec::new();
+        // no prompt showed up since the last command so where its output ends is a guess
+        records.extend(self.flush_output(false, None));
+        let mut record = self.record(RecordKind::Command, text, None, false);
+        record.origin = Some(origin);
+        self.last_command = Some((record.seq, String::from(text)));
+        records.push(record);
+        return records;
+    }
+
+    /// Output read from the remote, split off as soon as a prompt ends it. `took` is
+    /// how long the command ran when the prompt timed it
+    pub fn output(&mut self, chunk: &str, took: Option<Duration>) -> Vec<TranscriptRecord> {
+        let searched = self.output.len();
+        self.output += &chunk.replace('\r', "");
+        if let Some(end) = self.output[searched..].rfind('\n') {
+            self.line_start = searched + end + 1;
+        }
+        if looks_like_prompt(&self.output[self.line_start..]) {
+            self.output.truncate(self.line_start);
+            return self.flush_output(true, took).into_iter().collect();
+        }
+        // a prompt may never come, raw mode or a huge dump, so don't hold on to it all
+        if self.output.len() > MAX_SEGMENT_BYTES {
+            return self.flush_output(false, None).into_iter().collect();
+        }
+        return Vec::new();
+    }
+
+    /// A framed exec, the markers say exactly where its output starts and ends
+    pub fn framed(
+        &mut self,
+        cmd: &str,
+        output: Option<&str>,
+        status: Option<i32>,
+        took: Duration,
+    ) -> Vec<TranscriptRecord> {
+        let mut command = self.record(RecordKind::Command, cmd, None, false);
+        command.origin = Some(InputOrigin::Internal);
+        let seq = command.seq;
+        let mut records = vec![command];
+        match output {
+            Some(output) => {
+                let mut record = self.record(RecordKind::Output, output, Some(seq), false);
+                record.duration_ms = Some(took.as_millis() as u64);
+                record.exit_status = status;
+                records.push(record);
+            }
+            None => records.push(self.record(RecordKind::Note, "no response", Some(seq), false)),
+        }
+        return records;
+    }
+
+    pub fn note(&mut self, text: &str) -> Vec<TranscriptRecord> {
+        return vec![self.record(RecordKind::Note, text, None, false)];
+    }
+}
+
+/// records waiting to be written before new ones are dropped
+pub const TRANSCRIPT_QUEUE_SIZE: usize = 4096;
+
+pub const DEFAULT_FSYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+type SharedWriter = Arc<std::sync::Mutex<BufWriter<File>>>;
+
+/// every open transcript file, so a panic can flush them on the way down
+static OPEN_WRITERS: std::sync::Mutex<Vec<Weak<std::sync::Mutex<BufWriter<File>>>>> =
+    std::sync::Mutex::new(Vec::new());
+
+enum TranscriptMessage {
+    Record(TranscriptRecord),
+    /// write and sync everything before this, then reply
+    Flush(oneshot::Sender<()>),
+}
+
+/// How a transcript is keeping up with the session
+#[derive(Debug, Default)]
+pub struct TranscriptStats {
+    pub queued: AtomicU64,
+    pub written: AtomicU64,
+    /// records thrown away because the queue was full
+    pub dropped: AtomicU64,
+}
+
+impl TranscriptStats {
+    /// Records queued but not written yet
+    pub fn backlog(&self) -> u64 {
+        let written = self.written.load(Ordering::SeqCst);
+        return self.queued.load(Ordering::SeqCst).saturating_sub(written);
+    }
+}
+
+/// Appends records to a jsonl file from its own thread so readers never wait on the
+/// disk. Written records are flushed to the file and synced to disk `fsync_interval`
+/// after the first of them, or when asked
+#[derive(Clone)]
+pub struct Transcript {
+    tx: SyncSender<TranscriptMessage>,
+    segmenter: Arc<std::sync::Mutex<Segmenter>>,
+    pub stats: Arc<TranscriptStats>,
+    pub path: PathBuf,
+}
+
+fn open_transcript(path: &PathBuf) -> io::Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    return OpenOptions::new().create(true).append(true).open(path);
+}
+
+fn write_record(writer: &SharedWriter, record: &TranscriptRecord) -> io::Result<()> {
+    let line = serde_json::to_string(record).unwrap_or_default() + "\n";
+    return match writer.lock() {
+        Ok(mut writer) => writer.write_all(line.as_bytes()),
+        Err(_) => Ok(()),
+    };
+}
+
+fn sync_writer(writer: &SharedWriter) {
+    if let Ok(mut writer) = writer.lock() {
+        writer.flush().unwrap_or_default();
+        writer.get_ref().sync_data().unwrap_or_default();
+    }
+}
+
+fn run_transcript(
+    writer: SharedWriter,
+    rx: Receiver<TranscriptMessage>,
+    stats: Arc<TranscriptStats>,
+    fsync_interval: Duration,
+) {
+    let mut last_sync = Instant::now();
+    let mut unsynced = false;
+    loop {
+        // with nothing waiting to be synced there's no deadline, so just wait for a record
+        let message = match unsynced {
+            true => {
+                let wait = fsync_interval.saturating_sub(last_sync.elapsed());
+                match rx.recv_timeout(wait) {
+                    Ok(val) => Some(val),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            false => match rx.recv() {
+                Ok(val) => Some(val),
+                Err(_) => break,
+            },
+        };
+        match message {
+            Some(TranscriptMessage::Record(record)) => {
+                write_record(&writer, &record).unwrap_or_default();
+                stats.written.fetch_add(1, Ordering::SeqCst);
+                if !unsynced {
+                    // the interval counts from the first record that isn't on disk yet
+                    last_sync = Instant::now();
+                }
+                unsynced = true;
+            }
+            Some(TranscriptMessage::Flush(reply)) => {
+                sync_writer(&writer);
+                unsynced = false;
+                last_sync = Instant::now();
+                reply.send(()).unwrap_or_default();
+            }
+            None => {}
+        }
+        if unsynced && last_sync.elapsed() >= fsync_interval {
+            sync_writer(&writer);
+            unsynced = false;
+            last_sync = Instant::now();
+        }
+    }
+    sync_writer(&writer);
+}
+
+/// Flushes and syncs every open transcript without waiting on their threads, for
+/// the panic hook
+pub fn flush_open_transcripts() {
+    let writers = match OPEN_WRITERS.try_lock() {
+        Ok(val) => val,
+        Err(_) => return,
+    };
+    for writer in writers.iter().filter_map(|writer| writer.upgrade()) {
+        // the panicking thread may be the one holding it
+        if let Ok(mut writer) = writer.try_lock() {
+            writer.flush().unwrap_or_default();
+            writer.get_ref().sync_data().unwrap_or_default();
+        }
+    }
+}
+
+impl Transcript {
+    pub fn new(path: PathBuf, session: &str, fsync_interval: Duration) -> io::Result<Transcript> {
+        let writer = Arc::new(std::sync::Mutex::new(BufWriter::new(open_transcript(
+            &path,
+        )?)));
+        return Ok(Transcript::with_writer(
+            path,
+            writer,
+            session,
+            fsync_interval,
+            TRANSCRIPT_QUEUE_SIZE,
+        ));
+    }
+
+    fn with_writer(
+        path: PathBuf,
+        writer: SharedWriter,
+        session: &str,
+        fsync_interval: Duration,
+        queue_size: usize,
+    ) -> Transcript {
+        if let Ok(mut writers) = OPEN_WRITERS.lock() {
+            writers.retain(|writer| writer.strong_count() > 0);
+            writers.push(Arc::downgrade(&writer));
+        }
+        let (tx, rx) = sync_channel::<TranscriptMessage>(queue_size);
+        let stats = Arc::new(TranscriptStats::default());
+        let thread_stats = stats.clone();
+        std::thread::spawn(move || run_transcript(writer, rx, thread_stats, fsync_interval));
+        return Transcript {
+            tx,
+            segmenter: Arc::new(std::sync::Mutex::new(Segmenter::new(session))),
+            stats,
+            path,
+        };
+    }
+
+    fn with_segmenter(&self, segment: impl FnOnce(&mut Segmenter) -> Vec<TranscriptRecord>) {
+        let records = match self.segmenter.lock() {
+            Ok(mut segmenter) => segment(&mut segmenter),
+            Err(_) => return,
+        };
+        for record in records {
+            // never hold up the session for the disk, count what couldn't be kept instead
+            match self.tx.try_send(TranscriptMessage::Record(record)) {
+                Ok(_) => self.stats.queued.fetch_add(1, Ordering::SeqCst),
+                Err(_) => self.stats.dropped.fetch_add(1, Ordering::SeqCst),
+            };
+        }
+    }
+
+    /// Writes a note, returning its seq
+    pub fn note_seq(&self, text: &str) -> Option<u64> {
+        let mut seq = None;
+        self.with_segmenter(|segmenter| {
+            let records = segmenter.note(text);
+            seq = records.first().map(|record| record.seq);
+            return records;
+        });
+        return seq;
+    }
+
+    /// Waits until everything queued so far is written and synced to disk
+    pub async fn flush(&self) {
+        let (reply, done) = oneshot::channel::<()>();
+        let tx = self.tx.clone();
+        // the queue may be full, wait for room off the runtime
+        let sent = tokio::task::spawn_blocking(move || tx.send(TranscriptMessage::Flush(reply)))
+            .await
+            .is_ok_and(|sent| sent.is_ok());
+        if sent {
+            done.await.unwrap_or_default();
+        }
+    }
+}
+
+impl Handle {
+    /// Starts writing a structured transcript of the session to `path`
+    pub fn transcribe_to(
+        &mut self,
+        path: PathBuf,
+        session: &str,
+        fsync_interval: Duration,
+    ) -> io::Result<()> {
+        check_write("A transcript")?;
+        self.transcript = Some(Transcript::new(path, session, fsync_interval)?);
+        return Ok(());
+    }
+
+    pub fn transcript_path(&self) -> Option<PathBuf> {
+        return self
+            .transcript
+            .as_ref()
+            .map(|transcript| transcript.path.clone());
+    }
+
+    pub fn transcript_stats(&self) -> Option<Arc<TranscriptStats>> {
+        return self
+            .transcript
+            .as_ref()
+            .map(|transcript| transcript.stats.clone());
+    }
+
+    /// Writes out and syncs the session's transcript, if it has one
+    pub async fn flush_transcript(&self) {
+        if let Some(transcript) = &self.transcript {
+            transcript.flush().await;
+        }
+    }
+
+    pub fn transcribe_command(&self, origin: InputOrigin, text: &str) {
+        if let Some(transcript) = &self.transcript {
+            transcript.with_segmenter(|segmenter| segmenter.command(text, origin));
+        }
+    }
+
+    pub fn transcribe_output(&self, chunk: &str, took: Option<Duration>) {
+        if let Some(transcript) = &self.transcript {
+            transcript.with_segmenter(|segmenter| segmenter.output(chunk, took));
+        }
+    }
+
+    pub fn transcribe_framed(
+        &self,
+        cmd: &str,
+        output: Option<&str>,
+        status: Option<i32>,
+        took: Duration,
+    ) {
+        if let Some(transcript) = &self.transcript {
+            transcript.with_segmenter(|segmenter| segmenter.framed(cmd, output, status, took));
+        }
+    }
+
+    pub fn transcribe_note(&self, text: &str) {
+        if let Some(transcript) = &self.transcript {
+            transcript.with_segmenter(|segmenter| segmenter.note(text));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::exec::EXEC_TIMEOUT;
+    use crate::socket::mock_shell::spawn_shell_session;
+    use std::time::Duration;
+
+    fn summary(records: &[TranscriptRecord]) -> Vec<(RecordKind, &str, Option<u64>, bool)> {
+        return records
+            .iter()
+            .map(|r| (r.kind, r.text.as_str(), r.reply_to, r.low_confidence))
+            .collect();
+    }
+
+    #[test]
+    fn test_segmenter() {
+        let mut segmenter = Segmenter::new("web");
+        let mut records = segmenter.command("id\n", InputOrigin::Operator);
+        // tty echo then output split over two reads, ended by the prompt
+        records.extend(segmenter.output("id\r\nuid=0(ro", None));
+        records.extend(segmenter.output("ot)\r\nroot@web:~# ", None));
+        // no prompt comes back before the next command
+        records.extend(segmenter.command("cat", InputOrigin::Operator));
+        records.extend(segmenter.output("hello\n", None));
+        records.extend(segmenter.command("^C", InputOrigin::Operator));
+        records.extend(segmenter.note("detached"));
+        assert_eq!(
+            summary(&records),
+            vec![
+                (RecordKind::Command, "id", None, false),
+                (RecordKind::Output, "uid=0(root)\n", Some(0), false),
+                (RecordKind::Command, "cat", None, false),
+                (RecordKind::Output, "hello\n", Some(2), true),
+                (RecordKind::Command, "^C", None, false),
+                (RecordKind::Note, "detached", None, false),
+            ]
+        );
+        assert_eq!(records[5].seq, 5);
+
+        // output before any command has nothing to pair with
+        let mut segmenter = Segmenter::new("web");
+        let records = segmenter.output("motd\n$ ", None);
+        assert_eq!(
+            summary(&records),
+            vec![(RecordKind::Output, "motd\n", None, true)]
+        );
+
+        // a dump with no prompt is cut into guesses instead of piling up
+        let mut segmenter = Segmenter::new("web");
+        let chunk = "x".repeat(64 * 1024);
+        let mut records = Vec::new();
+        for _ in 0..(MAX_SEGMENT_BYTES / chunk.len() + 1) {
+            records.extend(segmenter.output(&chunk, None));
+        }
+        assert_eq!(records.len(), 1);
+        assert!(records[0].low_confidence);
+        assert!(segmenter.output.len() <= MAX_SEGMENT_BYTES);
+        assert!(!looks_like_prompt("100% done\n"));
+        assert!(looks_like_prompt("C:\\Users\\bob>"));
+    }
+
+    #[test]
+    fn test_record_json() {
+        let mut segmenter = Segmenter::new("web");
+        let records =
+            segmenter.framed("uname", Some("Linux\n"), Some(0), Duration::from_millis(40));
+        assert_eq!(records[0].origin, Some(InputOrigin::Internal));
+        assert_eq!(records[1].origin, None);
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&records[1]).unwrap()).unwrap();
+        assert_eq!(json["kind"], "output");
+        assert_eq!(json["session"], "web");
+        assert_eq!(json["reply_to"], 0);
+        assert!(json.get("low_confidence").is_none());
+        assert_eq!(json["duration_ms"], 40);
+        assert_eq!(json["exit_status"], 0);
+        // commands are never timed, only the output that answers them
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&records[0]).unwrap()).unwrap();
+        assert!(json.get("duration_ms").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_transcribe_framed() {
+        let mut handle = spawn_shell_session(32445).await;
+        let path = std::env::temp_dir().join("crab_trap_test_transcript.jsonl");
+        std::fs::remove_file(&path).unwrap_or_default();
+        handle
+            .transcribe_to(path.clone(), "test", DEFAULT_FSYNC_INTERVAL)
+            .unwrap();
+        handle.exec("echo hi", EXEC_TIMEOUT).await;
+        handle.flush_transcript().await;
+        let mut contents = String::new();
+        for _ in 0..50 {
+            contents = std::fs::read_to_string(&path).unwrap_or_default();
+            if contents.lines().count() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let records: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["text"], "echo hi");
+        assert_eq!(records[1]["text"], "hi\n");
+        std::fs::remove_file(&path).unwrap_or_default();
+    }
+
+    fn test_writer(name: &str) -> (PathBuf, SharedWriter) {
+        let path = std::env::temp_dir().join(name);
+        std::fs::remove_file(&path).unwrap_or_default();
+        let file = open_transcript(&path).unwrap();
+        return (path, Arc::new(std::sync::Mutex::new(BufWriter::new(file))));
+    }
+
+    fn read_notes(path: &PathBuf) -> Vec<String> {
+        return std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let record: serde_json::Value = serde_json::from_str(line).unwrap();
+                return String::from(record["text"].as_str().unwrap());
+            })
+            .collect();
+    }
+
+    #[tokio::test]
+    async fn test_slow_disk() {
+        let (path, writer) = test_writer("crab_trap_test_transcript_slow.jsonl");
+        let transcript = Transcript::with_writer(
+            path.clone(),
+            writer.clone(),
+            "test",
+            Duration::from_secs(60),
+            8,
+        );
+        // holding the writer stalls the thread like a disk that can't keep up
+        let stalled = writer.lock().unwrap();
+        for n in 0..100 {
+            transcript.with_segmenter(|segmenter| segmenter.note(&n.to_string()));
+        }
+        let queued = transcript.stats.queued.load(Ordering::SeqCst);
+        let dropped = transcript.stats.dropped.load(Ordering::SeqCst);
+        assert_eq!(queued + dropped, 100);
+        assert!(queued <= 9);
+        assert!(dropped > 0);
+        drop(stalled);
+
+        transcript.flush().await;
+        assert_eq!(transcript.stats.backlog(), 0);
+        // everything that was accepted is on disk, in order
+        let expected: Vec<String> = (0..queued).map(|n| n.to_string()).collect();
+        assert_eq!(read_notes(&path), expected);
+        std::fs::remove_file(&path).unwrap_or_default();
+    }
+
+    #[tokio::test]
+    async fn test_drained_on_close() {
+        let (path, writer) = test_writer("crab_trap_test_transcript_close.jsonl");
+        let transcript =
+            Transcript::with_writer(path.clone(), writer, "test", Duration::from_secs(60), 256);
+        for n in 0..200 {
+            transcript.with_segmenter(|segmenter| segmenter.note(&n.to_string()));
+        }
+        assert_eq!(transcript.stats.dropped.load(Ordering::SeqCst), 0);
+        // the last sender going away ends the thread once the queue is written
+        let stats = transcript.stats.clone();
+        drop(transcript);
+        for _ in 0..100 {
+            if stats.written.load(Ordering::SeqCst) == 200 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let expected: Vec<String> = (0..200).map(|n| n.to_string()).collect();
+        assert_eq!(read_notes(&path), expected);
+        std::fs::remove_file(&path).unwrap_or_default();
+    }
+
+    #[tokio::test]
+    async fn test_flush_open_transcripts() {
+        let (path, writer) = test_writer("crab_trap_test_transcript_panic.jsonl");
+        let transcript = Transcript::with_writer(
+            path.clone(),
+            writer.clone(),
+            "test",
+            Duration::from_secs(60),
+            16,
+        );
+        transcript.with_segmenter(|segmenter| segmenter.note("before"));
+        transcript.flush().await;
+        // something still in the buffer when crab_trap panics
+        writer
+            .lock()
+            .unwrap()
+            .write_all(b"{\"text\":\"buffered\"}\n")
+            .unwrap();
+        flush_open_transcripts();
+        assert_eq!(read_notes(&path), vec!["before", "buffered"]);
+        std::fs::remove_file(&path).unwrap_or_default();
+    }
+}
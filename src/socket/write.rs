@@ -0,0 +1,386 @@
+use std::collections::VecDeque;
+use std::future::pending;
+use std::time::Duration;
+
+use futures_util::future::select_all;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::select;
+use tokio::time::{sleep_until, Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::error::CrabTrapError;
+use crate::socket::connection::Handle;
+
+/// how much is written to the socket between checks for a cancel
+pub const WRITE_SLICE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOutcome {
+    Done,
+    /// stopped by a token after this many bytes
+    Cancelled(usize),
+    /// the socket errored after this many bytes
+    Failed(usize),
+}
+
+/// Resolves once any of the tokens is cancelled
+async fn any_cancelled(tokens: &[&CancellationToken]) {
+    if tokens.is_empty() {
+        return pending().await;
+    }
+    select_all(tokens.iter().map(|token| Box::pin(token.cancelled()))).await;
+}
+
+/// Writes `data` in slices, stopping as soon as any of the tokens is cancelled even
+/// if the remote has stopped reading
+pub async fn write_sliced<W>(
+    writer: &mut W,
+    data: &[u8],
+    cancel: &[&CancellationToken],
+) -> WriteOutcome
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut written = 0;
+    for slice in data.chunks(WRITE_SLICE) {
+        if cancel.iter().any(|token| token.is_cancelled()) {
+            return WriteOutcome::Cancelled(written);
+        }
+        select! {
+            biased;
+            _ = any_cancelled(cancel) => return WriteOutcome::Cancelled(written),
+            res = writer.write_all(slice) => {
+                if res.is_err() {
+                    return WriteOutcome::Failed(written);
+                }
+            }
+        }
+        written += slice.len();
+    }
+    if writer.flush().await.is_err() {
+        return WriteOutcome::Failed(written);
+    }
+    return WriteOutcome::Done;
+}
+
+/// paced sends shorter than this don't show their progress
+pub const PACE_PROGRESS_MIN: usize = 256;
+
+/// How fast input goes to a remote that drops characters when it gets them too fast,
+/// like a serial console without flow control. Zero is off for both
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pace {
+    pub chars_per_sec: u64,
+    /// extra wait after each line
+    pub newline_delay: Duration,
+}
+
+impl Pace {
+    pub fn is_off(&self) -> bool {
+        return self.chars_per_sec == 0 && self.newline_delay.is_zero();
+    }
+}
+
+/// Holds input back and lets it out at the session's pace. Bytes are queued as they're
+/// typed or pasted and taken off one at a time, or a line at a time when only the
+/// newline delay is set
+pub struct Pacer {
+    pace: Pace,
+    queue: VecDeque<u8>,
+    next: Instant,
+    /// bytes queued since it was last idle, sent or not
+    burst: usize,
+}
+
+impl Pacer {
+    pub fn new(pace: Pace) -> Pacer {
+        return Pacer {
+            pace,
+            queue: VecDeque::new(),
+            next: Instant::now(),
+            burst: 0,
+        };
+    }
+
+    pub fn is_off(&self) -> bool {
+        return self.pace.is_off();
+    }
+
+    pub fn is_idle(&self) -> bool {
+        return self.queue.is_empty();
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        if self.is_idle() {
+            self.burst = 0;
+        }
+        self.queue.extend(data);
+        self.burst += data.len();
+    }
+
+    /// Throws away what hasn't been sent, returning how much that was
+    pub fn clear(&mut self) -> usize {
+        let dropped = self.queue.len();
+        self.queue.clear();
+        return dropped;
+    }
+
+    /// How much of the current burst has been sent, out of how much
+    pub fn progress(&self) -> (usize, usize) {
+        return (self.burst - self.queue.len(), self.burst);
+    }
+
+    /// a `\r\n` is one line ending, not two
+    fn line_end(&self, idx: usize) -> bool {
+        return match self.queue[idx] {
+            b'\n' => true,
+            b'\r' => self.queue.get(idx + 1) != Some(&b'\n'),
+            _ => false,
+        };
+    }
+
+    fn take_piece(&mut self) -> Vec<u8> {
+        let len = match self.pace.chars_per_sec {
+            0 => (0..self.queue.len())
+                .find(|idx| self.line_end(*idx))
+                .map_or(self.queue.len(), |idx| idx + 1),
+            _ => 1,
+        };
+        let ends_line = len > 0 && self.line_end(len - 1);
+        let piece: Vec<u8> = self.queue.drain(..len).collect();
+        let mut wait = match self.pace.chars_per_sec {
+            0 => Duration::ZERO,
+            cps => Duration::from_secs(1) / cps.min(u32::MAX as u64) as u32,
+        };
+        if ends_line {
+            wait += self.pace.newline_delay;
+        }
+        // a write that took longer than the wait doesn't earn a burst after it
+        self.next = self.next.max(Instant::now()) + wait;
+        return piece;
+    }
+
+    /// Waits until the next piece is due and takes it off the queue. Cancel safe,
+    /// nothing is taken until the wait is over
+    pub async fn next_piece(&mut self) -> Vec<u8> {
+        sleep_until(self.next).await;
+        return self.take_piece();
+    }
+}
+
+/// Sends everything queued in `pacer` at its pace, stopping when any of the tokens is
+/// cancelled. `progress` is told the burst's progress after each piece
+pub async fn write_paced<W, F>(
+    writer: &mut W,
+    pacer: &mut Pacer,
+    cancel: &[&CancellationToken],
+    mut progress: F,
+) -> WriteOutcome
+where
+    W: AsyncWrite + Unpin,
+    F: FnMut(usize, usize),
+{
+    let mut written = 0;
+    while !pacer.is_idle() {
+        let piece = select! {
+            biased;
+            _ = any_cancelled(cancel) => return WriteOutcome::Cancelled(written),
+            piece = pacer.next_piece() => piece,
+        };
+        select! {
+            biased;
+            _ = any_cancelled(cancel) => return WriteOutcome::Cancelled(written),
+            res = writer.write_all(&piece) => {
+                if res.is_err() || writer.flush().await.is_err() {
+                    return WriteOutcome::Failed(written);
+                }
+            }
+        }
+        written += piece.len();
+        let (sent, total) = pacer.progress();
+        progress(sent, total);
+    }
+    return WriteOutcome::Done;
+}
+
+impl Handle {
+    /// Sends raw bytes to the remote, giving up part way through when `cancel` or the
+    /// session's kill token fires. Returns how much was sent
+    pub async fn send_bytes(
+        &self,
+        data: &[u8],
+        cancel: &CancellationToken,
+    ) -> Result<usize, CrabTrapError> {
+        let mut write_soc = self.write_stream.lock().await;
+        return match write_sliced(&mut *write_soc, data, &[cancel, &self.soc_kill_token]).await {
+            WriteOutcome::Done => Ok(data.len()),
+            WriteOutcome::Cancelled(written) => Err(CrabTrapError::RemoteCopyFailed {
+                reason: format!("cancelled after {written} of {} bytes", data.len()),
+            }),
+            WriteOutcome::Failed(written) => Err(CrabTrapError::RemoteCopyFailed {
+                reason: format!("the socket failed after {written} of {} bytes", data.len()),
+            }),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::timeout;
+
+    /// Takes a little at a time like a slow socket, cancelling once `cancel_at` bytes are in
+    struct SlowSocket {
+        received: Vec<u8>,
+        cancel_at: usize,
+        token: CancellationToken,
+    }
+
+    impl AsyncWrite for SlowSocket {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let n = buf.len().min(4096);
+            self.received.extend_from_slice(&buf[..n]);
+            if self.received.len() >= self.cancel_at {
+                self.token.cancel();
+            }
+            return Poll::Ready(Ok(n));
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            return Poll::Ready(Ok(()));
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            return Poll::Ready(Ok(()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_sliced_cancel() {
+        let data = vec![b'x'; 100 * WRITE_SLICE];
+        let token = CancellationToken::new();
+        let mut socket = SlowSocket {
+            received: Vec::new(),
+            cancel_at: data.len() / 10,
+            token: token.clone(),
+        };
+        let outcome = write_sliced(&mut socket, &data, &[&token]).await;
+        // the slice that was going when it was cancelled is finished, nothing after it
+        assert_eq!(outcome, WriteOutcome::Cancelled(10 * WRITE_SLICE));
+        assert_eq!(socket.received.len(), 10 * WRITE_SLICE);
+
+        let mut socket = SlowSocket {
+            received: Vec::new(),
+            cancel_at: usize::MAX,
+            token: CancellationToken::new(),
+        };
+        assert_eq!(
+            write_sliced(&mut socket, b"short", &[]).await,
+            WriteOutcome::Done
+        );
+        assert_eq!(socket.received, b"short");
+    }
+
+    #[tokio::test]
+    async fn test_write_paced() {
+        let mut socket = SlowSocket {
+            received: Vec::new(),
+            cancel_at: usize::MAX,
+            token: CancellationToken::new(),
+        };
+        let mut pacer = Pacer::new(Pace {
+            chars_per_sec: 200,
+            newline_delay: Duration::ZERO,
+        });
+        pacer.push(b"0123456789");
+        let mut seen = Vec::new();
+        let started = Instant::now();
+        let outcome = write_paced(&mut socket, &mut pacer, &[], |sent, total| {
+            seen.push((sent, total))
+        })
+        .await;
+        assert_eq!(outcome, WriteOutcome::Done);
+        assert_eq!(socket.received, b"0123456789");
+        // the first goes straight away, each after it waits 5ms
+        assert!(started.elapsed() >= Duration::from_millis(45));
+        assert_eq!(seen.first(), Some(&(1, 10)));
+        assert_eq!(seen.last(), Some(&(10, 10)));
+
+        // only the newline delay, a line at a time with `\r\n` as one ending
+        let mut pacer = Pacer::new(Pace {
+            chars_per_sec: 0,
+            newline_delay: Duration::from_millis(40),
+        });
+        pacer.push(b"ls\r\nid\nwhoami");
+        assert_eq!(pacer.next_piece().await, b"ls\r\n");
+        let started = Instant::now();
+        assert_eq!(pacer.next_piece().await, b"id\n");
+        assert_eq!(pacer.next_piece().await, b"whoami");
+        assert!(started.elapsed() >= Duration::from_millis(75));
+        assert!(pacer.is_idle());
+
+        // cancelled part way, what's left is still queued for the caller to drop
+        let token = CancellationToken::new();
+        let stopper = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            stopper.cancel();
+        });
+        let mut pacer = Pacer::new(Pace {
+            chars_per_sec: 100,
+            newline_delay: Duration::ZERO,
+        });
+        pacer.push(&[b'x'; 100]);
+        let outcome = write_paced(&mut socket, &mut pacer, &[&token], |_, _| {}).await;
+        assert!(matches!(outcome, WriteOutcome::Cancelled(sent) if sent < 20));
+        assert!(pacer.clear() > 80);
+    }
+
+    #[tokio::test]
+    async fn test_send_bytes_stalled() {
+        let listener = TcpListener::bind("127.0.0.1:32452").await.unwrap();
+        let client = tokio::spawn(async { TcpStream::connect("127.0.0.1:32452").await.unwrap() });
+        let (soc, _) = listener.accept().await.unwrap();
+        // the remote never reads so the socket buffers fill up and the write blocks
+        let mut remote = client.await.unwrap();
+        let (read, write) = soc.into_split();
+        let handle = Handle::new_headless(read, write);
+
+        let data = vec![b'x'; 256 * 1024 * 1024];
+        let cancel = CancellationToken::new();
+        let stopper = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            stopper.cancel();
+        });
+        let started = Instant::now();
+        let result = timeout(Duration::from_secs(5), handle.send_bytes(&data, &cancel))
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(matches!(
+            result,
+            Err(CrabTrapError::RemoteCopyFailed { .. })
+        ));
+
+        // only what was already sent gets through, far short of the whole thing
+        let mut received = 0;
+        let mut buf = vec![0; 64 * 1024];
+        while let Ok(Ok(n)) = timeout(Duration::from_millis(200), remote.read(&mut buf)).await {
+            if n == 0 {
+                break;
+            }
+            received += n;
+        }
+        assert!(received < data.len() / 10);
+    }
+}